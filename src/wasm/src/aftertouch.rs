@@ -0,0 +1,129 @@
+//! Channel aftertouch vibrato
+//!
+//! Pressing into a held key (channel pressure) adds vibrato to the voices
+//! of keys with an aftertouch depth, so a sustained loop can be made to
+//! waver by hand. MIDI voices follow the pressure of their own channel;
+//! keyboard and device voices follow the latest pressure from any channel.
+//!
+//! The engine has no modulation matrix or filter yet, so vibrato is the
+//! only destination. Its pitch is updated once per block, which is fine
+//! for rates of a few hertz.
+
+use std::f32::consts::TAU;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::history::ConfigChange;
+use crate::performance::Performed;
+use crate::{DspEngine, VoiceSource};
+
+/// Largest per-key vibrato depth in semitones
+const MAX_DEPTH_SEMITONES: f32 = 2.0;
+
+pub(crate) struct Aftertouch {
+    /// Pressure per MIDI channel (0.0 to 1.0)
+    channel_pressure: [f32; 16],
+    /// Latest pressure from any channel, for non-MIDI voices
+    latest: f32,
+    /// Vibrato rate in Hz
+    rate: f32,
+    /// Vibrato LFO phase (0.0 to 1.0)
+    phase: f32,
+}
+
+impl Aftertouch {
+    pub(crate) const fn new() -> Self {
+        Self { channel_pressure: [0.0; 16], latest: 0.0, rate: 5.5, phase: 0.0 }
+    }
+
+    /// Release the pressure of every channel
+    pub(crate) fn reset(&mut self) {
+        self.channel_pressure = [0.0; 16];
+        self.latest = 0.0;
+    }
+}
+
+impl DspEngine {
+    /// Set each voice's vibrato for the coming block and advance the LFO
+    /// (called at the start of each block)
+    #[inline]
+    pub(crate) fn update_vibrato(&mut self, frames: usize) {
+        let aftertouch = &mut self.aftertouch;
+        let lfo = (aftertouch.phase * TAU).sin();
+        aftertouch.phase = (aftertouch.phase + aftertouch.rate * frames as f32 / self.sample_rate).fract();
+        for voice in self.voices.iter_mut().filter(|voice| voice.active) {
+            let pressure = match voice.source {
+                VoiceSource::Midi(channel, _) => aftertouch.channel_pressure[channel as usize],
+                _ => aftertouch.latest,
+            };
+            voice.vibrato = 2.0_f32.powf(lfo * voice.aftertouch_depth * pressure / 12.0);
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Handle MIDI channel pressure (aftertouch) for one channel
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_channel_pressure(&mut self, channel: u8, pressure: u8) {
+        if channel > 15 {
+            return;
+        }
+        self.record_performance(Performed::Pressure { channel, pressure });
+        let pressure = pressure.min(127) as f32 / 127.0;
+        self.aftertouch.channel_pressure[channel as usize] = pressure;
+        self.aftertouch.latest = pressure;
+    }
+
+    /// Set how far aftertouch bends a key's voices, in semitones (0-2)
+    ///
+    /// 0 (the default) leaves the key unaffected by pressure.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_aftertouch_depth(&mut self, key_code: u8, semitones: f32) {
+        self.record_change(ConfigChange::KeyMapping(key_code));
+        let depth = if semitones.is_finite() { semitones.clamp(0.0, MAX_DEPTH_SEMITONES) } else { 0.0 };
+        self.key_mappings[key_code as usize].aftertouch_depth = depth;
+    }
+
+    /// Aftertouch vibrato depth of a key in semitones
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_aftertouch_depth(&self, key_code: u8) -> f32 {
+        self.key_mappings[key_code as usize].aftertouch_depth
+    }
+
+    /// Set the aftertouch vibrato rate in Hz (0.5-12)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_vibrato_rate(&mut self, hz: f32) {
+        if hz.is_finite() {
+            self.aftertouch.rate = hz.clamp(0.5, 12.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_pressure_adds_vibrato_to_deep_keys() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_key_mapping(66, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_key_aftertouch_depth(65, 1.0);
+        engine.note_on(65);
+        engine.note_on(66);
+
+        // Skip the LFO's zero crossing, then press
+        engine.process(&mut [0.0; 2 * 40]);
+        engine.midi_channel_pressure(0, 127);
+        engine.process(&mut [0.0; 2 * 8]);
+        assert!(engine.voices[0].vibrato > 1.0, "vibrato bends the key with depth");
+        assert_eq!(engine.voices[1].vibrato, 1.0);
+
+        engine.midi_channel_pressure(0, 0);
+        engine.process(&mut [0.0; 2 * 8]);
+        assert_eq!(engine.voices[0].vibrato, 1.0);
+    }
+}
@@ -0,0 +1,534 @@
+//! Load-time sound analysis
+//!
+//! Everything here runs when a sound is loaded (or on explicit request),
+//! never from the audio callback, so it is free to allocate.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::fft::{fft, hann};
+use crate::DspEngine;
+
+// ============================================================================
+// ONSET DETECTION - Energy-flux transient detector
+// ============================================================================
+
+/// Analysis frame length in samples
+const ONSET_FRAME: usize = 512;
+
+/// Hop between analysis frames in samples
+const ONSET_HOP: usize = 256;
+
+/// Frames on either side used for the adaptive threshold
+const ONSET_THRESHOLD_RADIUS: usize = 8;
+
+/// Minimum spacing between two onsets (seconds)
+const ONSET_MIN_GAP_SECONDS: f32 = 0.03;
+
+/// Frames quieter than this mean-square energy (-60 dBFS) never trigger
+const ONSET_SILENCE_FLOOR: f32 = 1.0e-6;
+
+/// Detect transient onsets in `samples`, writing sample positions to `onsets`
+///
+/// `sensitivity` ranges from 0.0 (only strong hits) to 1.0 (every bump).
+pub(crate) fn detect_onsets(samples: &[f32], sample_rate: f32, sensitivity: f32, onsets: &mut Vec<usize>) {
+    onsets.clear();
+    if samples.is_empty() {
+        return;
+    }
+
+    let sensitivity = sensitivity.clamp(0.0, 1.0);
+    let frame_count = samples.len().div_ceil(ONSET_HOP);
+
+    // Log energy per frame
+    let energies: Vec<f32> = (0..frame_count)
+        .map(|frame| {
+            let start = frame * ONSET_HOP;
+            let end = (start + ONSET_FRAME).min(samples.len());
+            let sum: f32 = samples[start..end].iter().map(|s| s * s).sum();
+            sum / ONSET_FRAME as f32
+        })
+        .collect();
+
+    // Positive log-energy difference (rises only)
+    let mut previous = ONSET_SILENCE_FLOOR.ln();
+    let flux: Vec<f32> = energies
+        .iter()
+        .map(|&energy| {
+            let level = (energy + 1.0e-10).ln();
+            let rise = (level - previous).max(0.0);
+            previous = level;
+            if energy < ONSET_SILENCE_FLOOR { 0.0 } else { rise }
+        })
+        .collect();
+
+    // Adaptive threshold: local mean scaled by sensitivity plus a fixed offset
+    let multiplier = 1.5 + (1.0 - sensitivity) * 2.0;
+    let offset = 0.2 + (1.0 - sensitivity) * 1.0;
+    let min_gap = (ONSET_MIN_GAP_SECONDS * sample_rate) as usize;
+
+    for frame in 0..frame_count {
+        let lo = frame.saturating_sub(ONSET_THRESHOLD_RADIUS);
+        let hi = (frame + ONSET_THRESHOLD_RADIUS + 1).min(frame_count);
+        let local_mean = flux[lo..hi].iter().sum::<f32>() / (hi - lo) as f32;
+        let threshold = local_mean * multiplier + offset;
+
+        let value = flux[frame];
+        let is_peak = value > threshold
+            && (frame == 0 || value >= flux[frame - 1])
+            && (frame + 1 == frame_count || value >= flux[frame + 1]);
+        if !is_peak {
+            continue;
+        }
+
+        let position = refine_onset(samples, frame);
+        if onsets.last().is_none_or(|&last| position >= last + min_gap) {
+            onsets.push(position);
+        }
+    }
+}
+
+/// Locate the attack inside the samples a frame added over its predecessor
+fn refine_onset(samples: &[f32], frame: usize) -> usize {
+    let start = if frame == 0 { 0 } else { frame * ONSET_HOP + ONSET_FRAME - ONSET_HOP };
+    let start = start.min(samples.len().saturating_sub(1));
+    let end = (frame * ONSET_HOP + ONSET_FRAME).min(samples.len());
+    let region = &samples[start..end];
+
+    let peak = region.iter().fold(0.0_f32, |m, s| m.max(s.abs()));
+    let offset = region.iter().position(|s| s.abs() >= peak * 0.5).unwrap_or(0);
+    start + offset
+}
+
+// ============================================================================
+// PITCH DETECTION - YIN fundamental estimator
+// ============================================================================
+
+/// YIN integration window in samples
+const PITCH_WINDOW: usize = 2048;
+
+/// Lowest detectable fundamental (Hz)
+const PITCH_MIN_HZ: f32 = 40.0;
+
+/// Highest detectable fundamental (Hz)
+const PITCH_MAX_HZ: f32 = 2000.0;
+
+/// Dip in the normalized difference function that counts as periodic
+const PITCH_YIN_THRESHOLD: f32 = 0.15;
+
+/// Skip past the attack before analyzing (seconds after the peak)
+const PITCH_ATTACK_SKIP_SECONDS: f32 = 0.01;
+
+/// Estimate the fundamental frequency of a monophonic sound
+///
+/// Returns `None` for unpitched or too-short material.
+pub(crate) fn detect_pitch(samples: &[f32], sample_rate: f32) -> Option<f32> {
+    let min_lag = (sample_rate / PITCH_MAX_HZ).floor().max(2.0) as usize;
+    let max_lag = (sample_rate / PITCH_MIN_HZ).ceil() as usize;
+
+    // Analyze just after the loudest point, where tonal content is strongest
+    let peak_index = samples
+        .iter()
+        .enumerate()
+        .fold((0, 0.0_f32), |(best, max), (i, s)| if s.abs() > max { (i, s.abs()) } else { (best, max) })
+        .0;
+    let needed = PITCH_WINDOW + max_lag;
+    if samples.len() < needed {
+        return None;
+    }
+    let start = (peak_index + (PITCH_ATTACK_SKIP_SECONDS * sample_rate) as usize).min(samples.len() - needed);
+    let frame = &samples[start..start + needed];
+
+    // Cumulative mean normalized difference function
+    let mut cmnd = vec![1.0_f32; max_lag + 1];
+    let mut running_sum = 0.0;
+    for lag in 1..=max_lag {
+        let diff: f32 = (0..PITCH_WINDOW)
+            .map(|i| {
+                let d = frame[i] - frame[i + lag];
+                d * d
+            })
+            .sum();
+        running_sum += diff;
+        cmnd[lag] = if running_sum > 0.0 { diff * lag as f32 / running_sum } else { 1.0 };
+    }
+
+    // First dip below threshold, walked down to its local minimum
+    let mut lag = (min_lag..max_lag).find(|&lag| cmnd[lag] < PITCH_YIN_THRESHOLD)?;
+    while lag + 1 < max_lag && cmnd[lag + 1] < cmnd[lag] {
+        lag += 1;
+    }
+
+    // Parabolic interpolation around the minimum for sub-sample accuracy
+    let (a, b, c) = (cmnd[lag - 1], cmnd[lag], cmnd[lag + 1]);
+    let denominator = a - 2.0 * b + c;
+    let shift = if denominator.abs() > f32::EPSILON { 0.5 * (a - c) / denominator } else { 0.0 };
+
+    Some(sample_rate / (lag as f32 + shift.clamp(-1.0, 1.0)))
+}
+
+/// Convert a frequency to a fractional MIDI note number (A4 = 69)
+pub(crate) fn frequency_to_midi_note(frequency: f32) -> f32 {
+    69.0 + 12.0 * (frequency / 440.0).log2()
+}
+
+// ============================================================================
+// LEVELS - Sample peak and RMS
+// ============================================================================
+
+/// Compute the absolute sample peak and RMS level of `samples`
+pub(crate) fn measure_levels(samples: &[f32]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    let peak = samples.iter().fold(0.0_f32, |m, s| m.max(s.abs()));
+    let sum_squares: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+    let rms = (sum_squares / samples.len() as f64).sqrt() as f32;
+    (peak, rms)
+}
+
+// ============================================================================
+// SPECTRAL CENTROID - Rough brightness measure
+// ============================================================================
+
+/// FFT size for centroid analysis
+const CENTROID_FFT_SIZE: usize = 1024;
+
+/// Most frames analyzed per sound (spread evenly over long sounds)
+const CENTROID_MAX_FRAMES: usize = 64;
+
+/// Compute the magnitude-weighted average spectral centroid in Hz
+///
+/// Each frame's centroid is weighted by its spectral energy so quiet
+/// tails do not drag the result around. Returns 0.0 for silence.
+pub(crate) fn measure_spectral_centroid(samples: &[f32], sample_rate: f32) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let available = samples.len().saturating_sub(CENTROID_FFT_SIZE) / (CENTROID_FFT_SIZE / 2) + 1;
+    let frames = available.min(CENTROID_MAX_FRAMES);
+    let stride = if frames > 1 { samples.len().saturating_sub(CENTROID_FFT_SIZE) / (frames - 1) } else { 0 };
+    let bin_hz = sample_rate / CENTROID_FFT_SIZE as f32;
+
+    let mut re = vec![0.0_f32; CENTROID_FFT_SIZE];
+    let mut im = vec![0.0_f32; CENTROID_FFT_SIZE];
+    let mut weighted_sum = 0.0_f64;
+    let mut total_weight = 0.0_f64;
+
+    for frame in 0..frames {
+        let start = frame * stride;
+        for (i, (r, m)) in re.iter_mut().zip(im.iter_mut()).enumerate() {
+            *r = samples.get(start + i).copied().unwrap_or(0.0) * hann(i, CENTROID_FFT_SIZE);
+            *m = 0.0;
+        }
+        fft(&mut re, &mut im);
+
+        let mut magnitude_sum = 0.0_f64;
+        let mut moment = 0.0_f64;
+        for bin in 1..CENTROID_FFT_SIZE / 2 {
+            let magnitude = (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() as f64;
+            magnitude_sum += magnitude;
+            moment += magnitude * (bin as f32 * bin_hz) as f64;
+        }
+        if magnitude_sum > 0.0 {
+            weighted_sum += moment;
+            total_weight += magnitude_sum;
+        }
+    }
+
+    if total_weight > 0.0 { (weighted_sum / total_weight) as f32 } else { 0.0 }
+}
+
+// ============================================================================
+// LOUDNESS - ITU-R BS.1770 integrated loudness (mono)
+// ============================================================================
+
+/// Gating block length (seconds)
+const LOUDNESS_BLOCK_SECONDS: f32 = 0.4;
+
+/// Absolute gate (LUFS)
+const LOUDNESS_ABSOLUTE_GATE: f32 = -70.0;
+
+/// Relative gate below the ungated loudness (LU)
+const LOUDNESS_RELATIVE_GATE: f32 = -10.0;
+
+/// Direct form I biquad used for K-weighting
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self { b0, b1, b2, a1, a2, x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0 }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Build the two K-weighting stages (high shelf + high pass) for a sample rate
+fn k_weighting(sample_rate: f32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    // Stage 1: +4 dB high shelf around 1.7 kHz
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10.0_f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    );
+
+    // Stage 2: high pass around 38 Hz
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(1.0, -2.0, 1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0);
+
+    [shelf, high_pass]
+}
+
+/// Measure integrated loudness in LUFS
+///
+/// Sounds shorter than one gating block are measured as a single block.
+/// Returns `None` for silence (everything below the absolute gate).
+pub(crate) fn measure_loudness(samples: &[f32], sample_rate: f32) -> Option<f32> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let [mut shelf, mut high_pass] = k_weighting(sample_rate);
+    let weighted: Vec<f64> = samples
+        .iter()
+        .map(|&s| {
+            let y = high_pass.process(shelf.process(s as f64));
+            y * y
+        })
+        .collect();
+
+    // 400 ms blocks with 75% overlap
+    let block = ((LOUDNESS_BLOCK_SECONDS * sample_rate) as usize).clamp(1, weighted.len());
+    let step = (block / 4).max(1);
+    let powers: Vec<f64> = (0..=(weighted.len() - block) / step)
+        .map(|i| weighted[i * step..i * step + block].iter().sum::<f64>() / block as f64)
+        .collect();
+
+    let lufs = |power: f64| (-0.691 + 10.0 * power.log10()) as f32;
+    let gated_mean = |gate: f32| {
+        let kept: Vec<f64> = powers.iter().copied().filter(|&p| p > 0.0 && lufs(p) > gate).collect();
+        (!kept.is_empty()).then(|| kept.iter().sum::<f64>() / kept.len() as f64)
+    };
+
+    let ungated = gated_mean(LOUDNESS_ABSOLUTE_GATE)?;
+    let relative_gate = lufs(ungated) + LOUDNESS_RELATIVE_GATE;
+    gated_mean(relative_gate.max(LOUDNESS_ABSOLUTE_GATE)).map(lufs)
+}
+
+// ============================================================================
+// ENGINE API
+// ============================================================================
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Enable or disable transient detection when sounds are loaded
+    ///
+    /// `sensitivity` ranges from 0.0 (only strong hits) to 1.0 (every bump).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_onset_detection(&mut self, enabled: bool, sensitivity: f32) {
+        self.onset_detection_enabled = enabled;
+        self.onset_sensitivity = sensitivity.clamp(0.0, 1.0);
+    }
+
+    /// Run transient detection on an already loaded sound
+    ///
+    /// Returns the number of onsets found.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn detect_sound_onsets(&mut self, sound_index: usize) -> u32 {
+        if sound_index >= self.sounds.len() || !self.sounds[sound_index].loaded {
+            return 0;
+        }
+        let sound = &mut self.sounds[sound_index];
+        detect_onsets(&sound.samples[..sound.length], self.sample_rate, self.onset_sensitivity, &mut sound.onsets);
+        sound.onsets.len() as u32
+    }
+
+    /// Number of detected onsets for a sound
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_onset_count(&self, sound_index: usize) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        self.sounds[sound_index].onsets.len() as u32
+    }
+
+    /// Copy detected onset positions (in samples) into `out`
+    ///
+    /// Returns the number of positions written.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_onsets(&self, sound_index: usize, out: &mut [u32]) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        let onsets = &self.sounds[sound_index].onsets;
+        let count = onsets.len().min(out.len());
+        for (dst, &src) in out.iter_mut().zip(onsets.iter()) {
+            *dst = src as u32;
+        }
+        count as u32
+    }
+
+    /// Detected fundamental frequency of a sound in Hz, or 0.0 if unpitched
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_detected_frequency(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return 0.0;
+        }
+        self.sounds[sound_index].detected_frequency.unwrap_or(0.0)
+    }
+
+    /// Detected root note of a sound as the nearest MIDI note number,
+    /// or -1 if no stable pitch was found
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_detected_note(&self, sound_index: usize) -> i32 {
+        if sound_index >= self.sounds.len() {
+            return -1;
+        }
+        self.sounds[sound_index]
+            .detected_frequency
+            .map_or(-1, |frequency| frequency_to_midi_note(frequency).round() as i32)
+    }
+
+    /// Absolute sample peak of a sound (linear, 0.0 if not loaded)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_peak(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return 0.0;
+        }
+        self.sounds[sound_index].peak
+    }
+
+    /// RMS level of a sound (linear, 0.0 if not loaded)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_rms(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return 0.0;
+        }
+        self.sounds[sound_index].rms
+    }
+
+    /// Spectral centroid of a sound in Hz (higher = brighter, 0.0 if silent)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_spectral_centroid(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return 0.0;
+        }
+        self.sounds[sound_index].spectral_centroid
+    }
+}
+
+impl DspEngine {
+    /// Run the enabled load-time analyses on a freshly loaded sound
+    ///
+    /// Every path that changes a sound's audio ends here, so this also marks
+    /// the slot for the next incremental export.
+    pub(crate) fn analyze_sound(&mut self, sound_index: usize) {
+        self.mark_audio_dirty(sound_index);
+        let sound = &mut self.sounds[sound_index];
+        sound.detected_frequency = detect_pitch(&sound.samples[..sound.length], self.sample_rate);
+        sound.loudness = measure_loudness(&sound.samples[..sound.length], self.sample_rate);
+        (sound.peak, sound.rms) = measure_levels(&sound.samples[..sound.length]);
+        sound.spectral_centroid = measure_spectral_centroid(&sound.samples[..sound.length], self.sample_rate);
+        if self.onset_detection_enabled {
+            detect_onsets(&sound.samples[..sound.length], self.sample_rate, self.onset_sensitivity, &mut sound.onsets);
+        } else {
+            sound.onsets.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_onsets_finds_hits() {
+        let sample_rate = 48000.0;
+        let hits = [4800, 19200, 33600];
+        let mut samples = vec![0.0_f32; 48000];
+        for &hit in &hits {
+            for (i, s) in samples[hit..hit + 2400].iter_mut().enumerate() {
+                *s = (i as f32 * 0.3).sin() * (1.0 - i as f32 / 2400.0);
+            }
+        }
+
+        let mut onsets = Vec::new();
+        detect_onsets(&samples, sample_rate, 0.5, &mut onsets);
+
+        assert_eq!(onsets.len(), hits.len());
+        for (&found, &expected) in onsets.iter().zip(hits.iter()) {
+            assert!(found.abs_diff(expected) < 32, "onset {found} far from {expected}");
+        }
+    }
+
+    #[test]
+    fn test_detect_pitch_of_sine() {
+        let sample_rate = 48000.0;
+        let samples: Vec<f32> = (0..24000)
+            .map(|i| (i as f32 * 220.0 * std::f32::consts::TAU / sample_rate).sin() * 0.8)
+            .collect();
+
+        let frequency = detect_pitch(&samples, sample_rate).unwrap();
+        assert!((frequency - 220.0).abs() < 1.0, "detected {frequency}");
+        assert_eq!(frequency_to_midi_note(frequency).round() as i32, 57);
+    }
+
+    #[test]
+    fn test_spectral_centroid_orders_brightness() {
+        let sample_rate = 48000.0;
+        let tone = |hz: f32| -> Vec<f32> {
+            (0..8192).map(|i| (i as f32 * hz * std::f32::consts::TAU / sample_rate).sin()).collect()
+        };
+
+        let low = measure_spectral_centroid(&tone(200.0), sample_rate);
+        let high = measure_spectral_centroid(&tone(5000.0), sample_rate);
+        assert!((high - 5000.0).abs() < 200.0, "centroid {high}");
+        assert!(low < 400.0, "centroid {low}");
+    }
+
+    #[test]
+    fn test_measure_loudness_of_sine() {
+        // A full-scale 1 kHz sine reads about -3 LUFS (K-weighting is ~0 dB at 1 kHz)
+        let sample_rate = 48000.0;
+        let samples: Vec<f32> = (0..48000)
+            .map(|i| (i as f32 * 1000.0 * std::f32::consts::TAU / sample_rate).sin())
+            .collect();
+
+        let loudness = measure_loudness(&samples, sample_rate).unwrap();
+        assert!((loudness + 3.0).abs() < 0.3, "measured {loudness}");
+        assert!(measure_loudness(&[0.0; 4800], sample_rate).is_none());
+    }
+}
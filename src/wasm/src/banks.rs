@@ -0,0 +1,102 @@
+//! Key mapping banks
+//!
+//! The engine holds several complete sets of 256 key mappings. The active
+//! bank lives in `key_mappings`, where every setter and `note_on` use it;
+//! the others wait in `key_banks`. Switching copies banks in and out at the
+//! start of the next `process()` block. Voices already playing keep sounding.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::{DspEngine, KeyMapping};
+
+/// A full set of key mappings
+pub(crate) type KeyBank = [KeyMapping; 256];
+
+impl DspEngine {
+    /// Switch to a requested bank (called at the start of each block)
+    #[inline]
+    pub(crate) fn apply_pending_bank(&mut self) {
+        let Some(bank) = self.pending_bank.take() else {
+            return;
+        };
+        if bank == self.active_bank {
+            return;
+        }
+        self.key_banks[self.active_bank] = self.key_mappings;
+        self.key_mappings = self.key_banks[bank];
+        self.active_bank = bank;
+    }
+
+    /// Mutable mappings of a bank, wherever it currently lives
+    pub(crate) fn bank_mut(&mut self, bank: usize) -> &mut KeyBank {
+        if bank == self.active_bank {
+            &mut self.key_mappings
+        } else {
+            &mut self.key_banks[bank]
+        }
+    }
+
+    /// Mappings of a bank, wherever it currently lives
+    pub(crate) fn bank(&self, bank: usize) -> &KeyBank {
+        if bank == self.active_bank {
+            &self.key_mappings
+        } else {
+            &self.key_banks[bank]
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Make another key mapping bank active at the next block boundary
+    ///
+    /// Returns false if `bank` is out of range.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_active_bank(&mut self, bank: usize) -> bool {
+        if bank >= self.key_banks.len() {
+            return false;
+        }
+        // Queued changes belong to the bank that is active now
+        self.apply_commands();
+        self.pending_bank = Some(bank);
+        true
+    }
+
+    /// Bank that is active, or will be after the next block
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_active_bank(&self) -> usize {
+        self.pending_bank.unwrap_or(self.active_bank)
+    }
+
+    /// Number of key mapping banks
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_bank_count(&self) -> usize {
+        self.key_banks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_bank_switch() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 64]);
+        engine.load_sound(1, &[0.25; 64]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+
+        assert!(engine.set_active_bank(1));
+        assert!(!engine.set_active_bank(engine.get_bank_count()));
+        assert!(engine.key_mappings[65].has_sound, "switches at the next block");
+        engine.process(&mut [0.0; 2]);
+        assert!(!engine.key_mappings[65].has_sound);
+        engine.set_key_mapping(65, 1, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+
+        engine.set_active_bank(0);
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.key_mappings[65].sound_index, 0);
+        assert_eq!(engine.bank(1)[65].sound_index, 1);
+    }
+}
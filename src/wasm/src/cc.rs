@@ -0,0 +1,293 @@
+//! MIDI CC learn and mapping
+//!
+//! Control change messages drive engine parameters through a table of
+//! (channel, controller) bindings. Each binding scales the 0-127 CC value
+//! through a curve into a range of the target parameter. Bindings are made
+//! by learning: `begin_cc_learn` arms a target and the next control change
+//! that arrives is bound to it.
+//!
+//! The mod wheel (CC 1) sets the modulation depth unless it is bound to
+//! something else.
+//!
+//! CC moves are performance gestures, so like morphing they bypass the
+//! undo history.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::performance::Performed;
+use crate::release::DEFAULT_RELEASE_VELOCITY;
+use crate::{DspEngine, VoiceSource};
+
+/// Most CC bindings kept
+const MAX_CC_MAPPINGS: usize = 128;
+
+/// Controller number of the mod wheel
+const MOD_WHEEL: u8 = 1;
+
+/// Channel mode message: stop every sound on the channel at once
+const ALL_SOUND_OFF: u8 = 120;
+
+/// Channel mode message: return controllers to rest
+const RESET_ALL_CONTROLLERS: u8 = 121;
+
+/// Channel mode message: release every note on the channel
+const ALL_NOTES_OFF: u8 = 123;
+
+/// Parameter a controller can drive
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum CcTarget {
+    /// Master volume (0.0 to 1.0)
+    MasterVolume = 0,
+    /// Metronome volume (0.0 to 1.0)
+    MetronomeVolume = 1,
+    /// Volume of one group's channel (0.0 to 1.0)
+    GroupVolume = 2,
+    /// Volume of one key (0.0 to 1.0)
+    KeyVolume = 3,
+    /// Pitch of one key in semitones (-24 to 24)
+    KeyPitch = 4,
+    /// Morph amount between scenes A and B (0.0 to 1.0)
+    Morph = 5,
+}
+
+impl CcTarget {
+    /// Full range of the parameter, used for newly learned bindings
+    const fn default_range(self) -> (f32, f32) {
+        match self {
+            Self::KeyPitch => (-24.0, 24.0),
+            _ => (0.0, 1.0),
+        }
+    }
+}
+
+/// How a CC value is shaped before it is scaled into the target range
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum CcCurve {
+    /// Straight line
+    Linear = 0,
+    /// Fine control at the low end (squared)
+    Exponential = 1,
+    /// Fine control at the high end (square root)
+    Logarithmic = 2,
+}
+
+impl CcCurve {
+    #[inline]
+    fn shape(self, x: f32) -> f32 {
+        match self {
+            Self::Linear => x,
+            Self::Exponential => x * x,
+            Self::Logarithmic => x.sqrt(),
+        }
+    }
+}
+
+/// A controller bound to a parameter
+#[derive(Clone, Copy)]
+pub(crate) struct CcMapping {
+    pub(crate) channel: u8,
+    pub(crate) controller: u8,
+    pub(crate) target: CcTarget,
+    /// Group or key the target refers to (unused for global targets)
+    pub(crate) target_index: u8,
+    /// Parameter value at CC 0
+    pub(crate) min: f32,
+    /// Parameter value at CC 127
+    pub(crate) max: f32,
+    pub(crate) curve: CcCurve,
+}
+
+impl CcMapping {
+    /// Parameter value for a CC value
+    fn value(&self, cc_value: u8) -> f32 {
+        let x = self.curve.shape(cc_value.min(127) as f32 / 127.0);
+        self.min + (self.max - self.min) * x
+    }
+}
+
+pub(crate) struct CcMap {
+    pub(crate) mappings: Vec<CcMapping>,
+    /// Target waiting for the next control change
+    learning: Option<(CcTarget, u8)>,
+}
+
+impl CcMap {
+    pub(crate) const fn new() -> Self {
+        Self { mappings: Vec::new(), learning: None }
+    }
+
+    /// Index of the binding for a controller
+    fn position(&self, channel: u8, controller: u8) -> Option<usize> {
+        self.mappings.iter().position(|mapping| mapping.channel == channel && mapping.controller == controller)
+    }
+}
+
+impl DspEngine {
+    fn apply_cc(&mut self, mapping: CcMapping, cc_value: u8) {
+        // A controller moved after a setter wins over it
+        self.apply_commands();
+        self.set_parameter(mapping.target, mapping.target_index, mapping.value(cc_value));
+    }
+
+    /// Set a parameter directly, bypassing the undo history (real-time safe)
+    ///
+    /// `index` picks the group or key for per-group and per-key targets.
+    pub(crate) fn set_parameter(&mut self, target: CcTarget, index: u8, value: f32) {
+        let index = index as usize;
+        match target {
+            CcTarget::MasterVolume => self.master_volume = value.clamp(0.0, 1.0),
+            CcTarget::MetronomeVolume => self.metronome_volume = value.clamp(0.0, 1.0),
+            CcTarget::GroupVolume => self.mixer.volumes[index] = value.clamp(0.0, 1.0),
+            CcTarget::KeyVolume => self.key_mappings[index].volume = value.clamp(0.0, 1.0),
+            CcTarget::KeyPitch => self.key_mappings[index].pitch_semitones = value.round().clamp(-24.0, 24.0) as i8,
+            CcTarget::Morph => self.blend_morph(value),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Bind the next incoming control change to a parameter
+    ///
+    /// `target_index` picks the group or key for per-group and per-key
+    /// targets. The binding starts with the parameter's full range and a
+    /// linear curve. Learning a controller that is already bound rebinds it.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn begin_cc_learn(&mut self, target: CcTarget, target_index: u8) {
+        self.cc_map.learning = Some((target, target_index));
+    }
+
+    /// Stop waiting for a control change to learn
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn cancel_cc_learn(&mut self) {
+        self.cc_map.learning = None;
+    }
+
+    /// Whether a learn is waiting for a control change
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_cc_learning(&self) -> bool {
+        self.cc_map.learning.is_some()
+    }
+
+    /// Handle a MIDI control change
+    ///
+    /// Completes a pending learn, then drives the parameter bound to the
+    /// controller, if any. An unbound mod wheel sets the modulation depth.
+    /// Channel mode messages (120-127) are never learned: All Sound Off
+    /// fades out the channel's voices, All Notes Off releases them and
+    /// Reset All Controllers returns the wheels and aftertouch to rest.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn control_change(&mut self, channel: u8, controller: u8, value: u8) {
+        if channel > 15 || controller > 127 {
+            return;
+        }
+        self.record_performance(Performed::Control { channel, controller, value });
+        match controller {
+            ALL_SOUND_OFF => {
+                self.fade_out_voices(|voice| matches!(voice.source, VoiceSource::Midi(c, _) if c == channel));
+            }
+            RESET_ALL_CONTROLLERS => self.reset_controllers(),
+            ALL_NOTES_OFF => {
+                for note in 0..=127 {
+                    self.release_voices(VoiceSource::Midi(channel, note), note as u16, DEFAULT_RELEASE_VELOCITY);
+                }
+            }
+            _ => {}
+        }
+        if controller >= ALL_SOUND_OFF {
+            return;
+        }
+        if let Some((target, target_index)) = self.cc_map.learning.take() {
+            let (min, max) = target.default_range();
+            let learned = CcMapping { channel, controller, target, target_index, min, max, curve: CcCurve::Linear };
+            let bound = self.cc_map.position(channel, controller);
+            let mappings = &mut self.cc_map.mappings;
+            match bound {
+                Some(index) => mappings[index] = learned,
+                None if mappings.len() < MAX_CC_MAPPINGS => mappings.push(learned),
+                None => {}
+            }
+        }
+        match self.cc_map.position(channel, controller) {
+            Some(index) => self.apply_cc(self.cc_map.mappings[index], value),
+            None if controller == MOD_WHEEL => self.set_modulation_depth(value.min(127) as f32 / 127.0),
+            None => {}
+        }
+    }
+
+    /// Set the range and curve of a bound controller
+    ///
+    /// `min` is the parameter value at CC 0 and `max` at CC 127; `min` may
+    /// exceed `max` to invert the control. Returns false if the controller
+    /// is not bound.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_cc_mapping_range(&mut self, channel: u8, controller: u8, min: f32, max: f32, curve: CcCurve) -> bool {
+        let Some(index) = self.cc_map.position(channel, controller) else {
+            return false;
+        };
+        if !min.is_finite() || !max.is_finite() {
+            return false;
+        }
+        let mapping = &mut self.cc_map.mappings[index];
+        (mapping.min, mapping.max, mapping.curve) = (min, max, curve);
+        true
+    }
+
+    /// Unbind a controller; returns false if it was not bound
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn remove_cc_mapping(&mut self, channel: u8, controller: u8) -> bool {
+        let count = self.cc_map.mappings.len();
+        self.cc_map.mappings.retain(|mapping| mapping.channel != channel || mapping.controller != controller);
+        self.cc_map.mappings.len() != count
+    }
+
+    /// Unbind every controller
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_cc_mappings(&mut self) {
+        self.cc_map.mappings.clear();
+    }
+
+    /// Number of bound controllers
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_cc_mapping_count(&self) -> usize {
+        self.cc_map.mappings.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cc_learn_and_curves() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.control_change(0, 7, 0);
+        assert_eq!(engine.master_volume, 1.0, "unbound controllers do nothing");
+
+        engine.begin_cc_learn(CcTarget::MasterVolume, 0);
+        assert!(engine.is_cc_learning());
+        engine.control_change(0, 7, 0);
+        assert!(!engine.is_cc_learning());
+        assert_eq!(engine.master_volume, 0.0, "the learning message already applies");
+
+        engine.begin_cc_learn(CcTarget::KeyPitch, 65);
+        engine.control_change(1, 74, 127);
+        assert_eq!(engine.key_mappings[65].pitch_semitones, 24);
+        assert!(engine.set_cc_mapping_range(1, 74, 12.0, 0.0, CcCurve::Linear));
+        engine.control_change(1, 74, 127);
+        assert_eq!(engine.key_mappings[65].pitch_semitones, 0, "inverted range");
+
+        assert!(engine.set_cc_mapping_range(0, 7, 0.0, 1.0, CcCurve::Exponential));
+        engine.control_change(0, 7, 64);
+        assert!((engine.master_volume - (64.0_f32 / 127.0).powi(2)).abs() < 1e-6);
+        assert_eq!(engine.get_cc_mapping_count(), 2);
+
+        engine.control_change(4, 1, 0);
+        assert_eq!(engine.get_modulation_depth(), 0.0, "unbound mod wheel");
+    }
+}
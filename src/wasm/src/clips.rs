@@ -0,0 +1,296 @@
+//! Clip launcher
+//!
+//! Each key is a lane holding up to eight clips, as in a session view. A
+//! clip is either a sequencer pattern or a sound slot played as a loop.
+//! Launching a clip replaces whatever its lane is playing and stopping
+//! silences the lane; both wait for the next launch boundary (a whole
+//! number of bars, or straight away with quantizing off) and happen on its
+//! exact sample.
+//!
+//! The sequencer has one playhead, so only one pattern clip plays at a
+//! time: launching one stops the pattern clip of any other lane.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::events::EngineEventKind;
+use crate::release::DEFAULT_RELEASE_VELOCITY;
+use crate::sequencer::MAX_PATTERNS;
+use crate::{DspEngine, KeyMapping, PlaybackMode, VoiceSource};
+
+/// Clips per lane
+pub(crate) const MAX_CLIPS_PER_LANE: usize = 8;
+
+/// What a clip plays
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum ClipKind {
+    /// A sequencer pattern
+    Pattern = 0,
+    /// A sound slot, looped
+    Loop = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Clip {
+    pub(crate) kind: ClipKind,
+    /// Pattern or sound slot
+    pub(crate) index: u8,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Lane {
+    pub(crate) clips: [Option<Clip>; MAX_CLIPS_PER_LANE],
+    /// Slot playing and the clip it held at launch
+    playing: Option<(u8, Clip)>,
+    /// Clip to launch (None to stop) and the sample to do it on
+    queued: Option<(Option<u8>, u64)>,
+}
+
+impl Lane {
+    const fn new() -> Self {
+        Self { clips: [None; MAX_CLIPS_PER_LANE], playing: None, queued: None }
+    }
+}
+
+pub(crate) struct ClipLauncher {
+    pub(crate) lanes: [Lane; 256],
+    /// Bars between launch boundaries (0 = launch straight away)
+    pub(crate) quantize_bars: u32,
+    /// Earliest queued launch or stop
+    next_change: Option<u64>,
+}
+
+impl ClipLauncher {
+    pub(crate) const fn new() -> Self {
+        Self { lanes: [Lane::new(); 256], quantize_bars: 1, next_change: None }
+    }
+
+    #[inline]
+    pub(crate) fn is_pending(&self) -> bool {
+        self.next_change.is_some()
+    }
+
+    /// Whether a clip launches or stops at sample `now`
+    #[inline]
+    pub(crate) fn is_due(&self, now: u64) -> bool {
+        self.next_change.is_some_and(|at| at <= now)
+    }
+
+    fn update_next_change(&mut self) {
+        self.next_change = self.lanes.iter().filter_map(|lane| lane.queued.map(|(_, at)| at)).min();
+    }
+}
+
+impl DspEngine {
+    /// Launch and stop the clips due at the current sample (called once per
+    /// frame from `process()` while a change is queued)
+    #[inline]
+    pub(crate) fn advance_clips(&mut self) {
+        let now = self.global_sample_position;
+        if self.clips.next_change.is_none_or(|at| at > now) {
+            return;
+        }
+        for key_code in 0..=255u8 {
+            match self.clips.lanes[key_code as usize].queued {
+                Some((clip, at)) if at <= now => {
+                    self.clips.lanes[key_code as usize].queued = None;
+                    self.switch_clip(key_code, clip);
+                }
+                _ => {}
+            }
+        }
+        self.clips.update_next_change();
+    }
+
+    /// Stop a lane's clip and start another (or none) now
+    fn switch_clip(&mut self, key_code: u8, clip: Option<u8>) {
+        let now = self.global_sample_position;
+        let lane = self.clips.lanes[key_code as usize];
+        if let Some((slot, playing)) = lane.playing {
+            match playing.kind {
+                ClipKind::Loop => self.release_voices(VoiceSource::Clip, key_code as u16, DEFAULT_RELEASE_VELOCITY),
+                ClipKind::Pattern => self.set_sequencer_playing(false),
+            }
+            self.events.push(EngineEventKind::ClipStopped, key_code as u16, slot as u32, now);
+        }
+        self.clips.lanes[key_code as usize].playing = None;
+
+        let Some((slot, launched)) = clip.and_then(|slot| lane.clips[slot as usize].map(|clip| (slot, clip))) else {
+            return;
+        };
+        match launched.kind {
+            ClipKind::Loop => {
+                if !self.sounds[launched.index as usize].loaded {
+                    return;
+                }
+                let mapping = KeyMapping {
+                    sound_index: launched.index as usize,
+                    mode: PlaybackMode::Loop,
+                    has_sound: true,
+                    slice: None,
+                    ..self.key_mappings[key_code as usize]
+                };
+                self.trigger_voice(&mapping, key_code as u16, VoiceSource::Clip);
+            }
+            ClipKind::Pattern => {
+                // One playhead: the pattern clip of another lane gives way
+                for (other, lane) in self.clips.lanes.iter_mut().enumerate() {
+                    if let Some((other_slot, Clip { kind: ClipKind::Pattern, .. })) = lane.playing {
+                        self.events.push(EngineEventKind::ClipStopped, other as u16, other_slot as u32, now);
+                        lane.playing = None;
+                    }
+                }
+                self.sequencer.selected = launched.index as usize;
+                self.sequencer.song_mode = false;
+                self.set_sequencer_playing(true);
+            }
+        }
+        self.clips.lanes[key_code as usize].playing = Some((slot, launched));
+        self.events.push(EngineEventKind::ClipLaunched, key_code as u16, slot as u32, now);
+    }
+
+    /// Queue a lane change for the next launch boundary, or make it now
+    fn queue_clip(&mut self, key_code: u8, clip: Option<u8>) {
+        let samples_per_boundary = self.samples_per_bar() * self.clips.quantize_bars as u64;
+        if samples_per_boundary == 0 {
+            self.clips.lanes[key_code as usize].queued = None;
+            self.clips.update_next_change();
+            self.switch_clip(key_code, clip);
+            return;
+        }
+        let at = self.global_sample_position.div_ceil(samples_per_boundary) * samples_per_boundary;
+        self.clips.lanes[key_code as usize].queued = Some((clip, at));
+        self.clips.update_next_change();
+    }
+
+    /// Forget queued launches and what the lanes play (the clock was reset)
+    pub(crate) fn reset_clips(&mut self) {
+        for lane in self.clips.lanes.iter_mut() {
+            lane.playing = None;
+            lane.queued = None;
+        }
+        self.clips.next_change = None;
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Put a clip in one of a key's lane slots (0-7)
+    ///
+    /// `index` is the pattern (0-15) or sound slot the clip plays. A clip
+    /// replaced while playing keeps playing until the lane changes. Returns
+    /// false for an unknown slot, pattern or sound.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_clip(&mut self, key_code: u8, slot: u8, kind: ClipKind, index: u8) -> bool {
+        let known = match kind {
+            ClipKind::Pattern => (index as usize) < MAX_PATTERNS,
+            ClipKind::Loop => (index as usize) < self.sounds.len(),
+        };
+        let Some(clip) = self.clips.lanes[key_code as usize].clips.get_mut(slot as usize) else {
+            return false;
+        };
+        if !known {
+            return false;
+        }
+        *clip = Some(Clip { kind, index });
+        true
+    }
+
+    /// Empty a lane slot, stopping its clip straight away if it plays
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_clip(&mut self, key_code: u8, slot: u8) {
+        if (slot as usize) >= MAX_CLIPS_PER_LANE {
+            return;
+        }
+        if self.clips.lanes[key_code as usize].playing.is_some_and(|(playing, _)| playing == slot) {
+            self.switch_clip(key_code, None);
+        }
+        let lane = &mut self.clips.lanes[key_code as usize];
+        lane.clips[slot as usize] = None;
+        if lane.queued.is_some_and(|(clip, _)| clip == Some(slot)) {
+            lane.queued = None;
+            self.clips.update_next_change();
+        }
+    }
+
+    /// Launch a clip at the next launch boundary, replacing the lane's
+    /// current clip; returns false if the slot is empty
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn launch_clip(&mut self, key_code: u8, slot: u8) -> bool {
+        if self.clips.lanes[key_code as usize].clips.get(slot as usize).is_none_or(Option::is_none) {
+            return false;
+        }
+        self.queue_clip(key_code, Some(slot));
+        true
+    }
+
+    /// Stop a lane at the next launch boundary
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stop_clip(&mut self, key_code: u8) {
+        self.queue_clip(key_code, None);
+    }
+
+    /// Set the launch boundary in bars (0 launches and stops straight away;
+    /// default 1)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_clip_quantize(&mut self, bars: u32) {
+        self.clips.quantize_bars = bars;
+    }
+
+    /// Slot of the clip a lane is playing, or -1
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_playing_clip(&self, key_code: u8) -> i32 {
+        self.clips.lanes[key_code as usize].playing.map_or(-1, |(slot, _)| slot as i32)
+    }
+
+    /// Slot of the clip a lane will launch at the next boundary; -1 if
+    /// nothing is queued and -2 if the lane is queued to stop
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_queued_clip(&self, key_code: u8) -> i32 {
+        match self.clips.lanes[key_code as usize].queued {
+            None => -1,
+            Some((None, _)) => -2,
+            Some((Some(slot), _)) => slot as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverlapMode;
+
+    #[test]
+    fn test_clips_launch_on_the_bar() {
+        // 120 BPM at 1 kHz: a bar is 2000 samples
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(3, &[0.5; 500]);
+        engine.set_key_mapping(66, 3, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        assert!(engine.set_step(66, 0, 127, 0, 1.0, 0.0));
+        assert!(engine.set_clip(65, 0, ClipKind::Loop, 3) && engine.set_clip(65, 1, ClipKind::Pattern, 0));
+        assert!(!engine.set_clip(65, 8, ClipKind::Loop, 3) && !engine.set_clip(65, 2, ClipKind::Pattern, 16));
+        assert!(!engine.launch_clip(65, 2), "empty slot");
+
+        engine.process(&mut [0.0; 2 * 100]);
+        assert!(engine.launch_clip(65, 0));
+        assert_eq!((engine.get_playing_clip(65), engine.get_queued_clip(65)), (-1, 0));
+        engine.process(&mut [0.0; 2 * 1900]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.get_playing_clip(65), engine.get_queued_clip(65)), (0, -1));
+        assert!(engine.voices.iter().any(|v| v.active && v.source == VoiceSource::Clip && v.sound_index == 3));
+
+        // The pattern clip takes over the lane on the next bar
+        assert!(engine.launch_clip(65, 1));
+        engine.process(&mut [0.0; 2 * 2000]);
+        assert_eq!(engine.get_playing_clip(65), 1);
+        assert!(engine.is_sequencer_playing());
+        assert!(engine.voices.iter().all(|v| !v.active || v.source == VoiceSource::Sequencer));
+
+        engine.set_clip_quantize(0);
+        engine.stop_clip(65);
+        assert!(engine.get_playing_clip(65) == -1 && !engine.is_sequencer_playing());
+    }
+}
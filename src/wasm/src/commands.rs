@@ -0,0 +1,170 @@
+//! Parameter command queue
+//!
+//! The parameter setters (master, group and key volumes, key pitch and the
+//! morph amount) don't write the engine state themselves: they queue a
+//! command, and `process()` applies every queued command at the start of
+//! the next block, in the order they were made. A change made of several
+//! setter calls therefore lands in one block, never part way through one.
+//!
+//! The queue is a fixed ring with one producer (the setters) and one
+//! consumer (the block boundary); neither side allocates or waits.
+//! Anything else that writes the same values or queues its own work for
+//! the block boundary (the other setters recorded for undo, undo itself,
+//! bank switches, notes, controllers) applies the waiting commands first,
+//! so changes never overtake each other. The parameter setters record
+//! their undo step from the queued values instead, leaving the queue for
+//! the block. Getters of queued parameters report the queued value; state
+//! exports hold what the last block played.
+
+use crate::cc::CcTarget;
+use crate::DspEngine;
+
+/// Commands that fit in the queue; a setter finding it full applies the
+/// waiting commands straight away
+const COMMAND_CAPACITY: usize = 256;
+
+/// A state change waiting for the next block
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Command {
+    /// See `set_parameter`
+    Parameter { target: CcTarget, index: u8, value: f32 },
+}
+
+pub(crate) struct CommandQueue {
+    slots: Box<[Command]>,
+    /// Oldest waiting command
+    head: usize,
+    len: usize,
+}
+
+impl CommandQueue {
+    pub(crate) fn new() -> Self {
+        let empty = Command::Parameter { target: CcTarget::MasterVolume, index: 0, value: 0.0 };
+        Self { slots: vec![empty; COMMAND_CAPACITY].into_boxed_slice(), head: 0, len: 0 }
+    }
+
+    /// Add a command; returns false if the queue is full
+    fn push(&mut self, command: Command) -> bool {
+        if self.len == self.slots.len() {
+            return false;
+        }
+        let tail = (self.head + self.len) % self.slots.len();
+        self.slots[tail] = command;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Command> {
+        if self.len == 0 {
+            return None;
+        }
+        let command = self.slots[self.head];
+        self.head = (self.head + 1) % self.slots.len();
+        self.len -= 1;
+        Some(command)
+    }
+
+    /// Waiting commands, oldest first
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = Command> + '_ {
+        (0..self.len).map(|i| self.slots[(self.head + i) % self.slots.len()])
+    }
+
+    /// Value of the newest queued change to a parameter
+    pub(crate) fn queued_parameter(&self, target: CcTarget, index: u8) -> Option<f32> {
+        self.iter().rev().find_map(|command| match command {
+            Command::Parameter { target: t, index: x, value } if t == target && x == index => Some(value),
+            _ => None,
+        })
+    }
+}
+
+impl DspEngine {
+    /// Apply every queued command in order (called at the start of each
+    /// block, and before anything that must see the commands land first)
+    #[inline]
+    pub(crate) fn apply_commands(&mut self) {
+        while let Some(command) = self.commands.pop() {
+            match command {
+                Command::Parameter { target, index, value } => self.set_parameter(target, index, value),
+            }
+        }
+    }
+
+    /// Queue a change for the next block
+    pub(crate) fn queue_command(&mut self, command: Command) {
+        if !self.commands.push(command) {
+            self.apply_commands();
+            self.commands.push(command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_setters_land_together_at_the_next_block() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 64]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 2, 1.0, 0, false);
+
+        engine.set_group_volume(2, 0.5);
+        engine.set_key_volume(65, 0.75);
+        engine.set_key_pitch(65, 5);
+        assert_eq!(engine.key_mappings[65].pitch_semitones, 0, "nothing lands before the block");
+        assert_eq!((engine.get_key_volume(65), engine.get_key_pitch(65), engine.get_group_volume(2)), (0.75, 5, 0.5));
+
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.commands.len, 0);
+        assert_eq!((engine.key_mappings[65].volume, engine.key_mappings[65].pitch_semitones), (0.75, 5));
+        assert_eq!(engine.mixer.volumes[2], 0.5);
+
+        // A note sees the queued changes
+        engine.set_key_volume(65, 0.5);
+        engine.note_on(65);
+        assert!(engine.voices.iter().any(|voice| voice.active && voice.volume == 0.5));
+
+        // Undo reverts the queued change
+        engine.set_group_volume(2, 0.1);
+        assert!(engine.undo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.mixer.volumes[2], 0.5);
+    }
+
+    #[test]
+    fn test_queued_setters_leave_each_other_queued() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 2, 1.0, 0, false);
+        engine.set_key_mapping(66, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 2, 1.0, 0, false);
+
+        engine.set_key_volume(65, 0.25);
+        engine.set_key_volume(66, 0.5);
+        engine.set_key_pitch(65, 3);
+        engine.set_group_volume(2, 0.75);
+        engine.set_master_volume(0.5);
+        assert_eq!(engine.commands.len, 5);
+        assert_eq!((engine.key_mappings[65].volume, engine.key_mappings[66].volume), (1.0, 1.0));
+        let others = (engine.key_mappings[65].pitch_semitones, engine.mixer.volumes[2], engine.master_volume);
+        assert_eq!(others, (0, 1.0, 1.0));
+
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.key_mappings[65].volume, engine.key_mappings[66].volume), (0.25, 0.5));
+        let others = (engine.key_mappings[65].pitch_semitones, engine.mixer.volumes[2], engine.master_volume);
+        assert_eq!(others, (3, 0.75, 0.5));
+
+        // Each step was taken as the queue would leave the configuration
+        assert!(engine.undo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.master_volume, engine.mixer.volumes[2]), (1.0, 0.75));
+        assert!(engine.undo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.mixer.volumes[2], engine.key_mappings[65].pitch_semitones), (1.0, 3));
+        assert!(engine.undo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.key_mappings[65].pitch_semitones, engine.key_mappings[66].volume), (0, 0.5));
+        assert!(engine.undo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.key_mappings[66].volume, engine.key_mappings[65].volume), (1.0, 0.25));
+    }
+}
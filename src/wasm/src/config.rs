@@ -0,0 +1,70 @@
+//! Engine construction settings
+//!
+//! Capacities are chosen once when the engine is created, so low-memory
+//! devices and power users can run the same binary with different limits.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::{DEFAULT_KEY_BANKS, DEFAULT_MAX_SAMPLE_SECONDS, DEFAULT_MAX_SOUNDS, DEFAULT_MAX_VOICES};
+
+/// Upper bound on the voice pool size
+const VOICE_LIMIT: usize = 256;
+
+/// Upper bound on the number of sound slots
+const SOUND_LIMIT: usize = 4096;
+
+/// Upper bound on the number of key mapping banks
+const BANK_LIMIT: usize = 64;
+
+/// Upper bound on a single sound's length (seconds)
+pub(crate) const SAMPLE_SECONDS_LIMIT: f32 = 3600.0;
+
+/// Capacities and sample rate for `DspEngine::with_config`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug)]
+pub struct DspEngineConfig {
+    /// Voices that can play at once
+    pub max_voices: usize,
+    /// Number of sound slots
+    pub max_sounds: usize,
+    /// Longest sound that can be loaded (seconds); longer audio is truncated
+    pub max_sample_seconds: f32,
+    /// Engine sample rate (typically 44100 or 48000)
+    pub sample_rate: f32,
+    /// Number of key mapping banks (see `set_active_bank`)
+    pub key_banks: usize,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngineConfig {
+    /// Default capacities (64 voices, 64 sounds, 10 seconds per sound, 4 banks)
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            max_voices: DEFAULT_MAX_VOICES,
+            max_sounds: DEFAULT_MAX_SOUNDS,
+            max_sample_seconds: DEFAULT_MAX_SAMPLE_SECONDS,
+            sample_rate,
+            key_banks: DEFAULT_KEY_BANKS,
+        }
+    }
+}
+
+impl DspEngineConfig {
+    /// Clamp every field into its supported range
+    pub(crate) fn sanitized(self) -> Self {
+        Self {
+            max_voices: self.max_voices.clamp(1, VOICE_LIMIT),
+            max_sounds: self.max_sounds.clamp(1, SOUND_LIMIT),
+            max_sample_seconds: self.max_sample_seconds.clamp(0.01, SAMPLE_SECONDS_LIMIT),
+            sample_rate: if self.sample_rate > 0.0 { self.sample_rate } else { 48000.0 },
+            key_banks: self.key_banks.clamp(1, BANK_LIMIT),
+        }
+    }
+
+    /// Longest sound in samples at the configured rate
+    pub(crate) fn max_sample_length(&self) -> usize {
+        (self.max_sample_seconds * self.sample_rate) as usize
+    }
+}
@@ -0,0 +1,206 @@
+//! Shared control block
+//!
+//! High-rate controls (a fader being dragged, a controller streaming
+//! values) sent as messages queue up behind each other and land late. The
+//! control block is the alternative: a few 32-bit slots in memory the host
+//! shares with the engine, each holding the bits of an `f32`. The host
+//! stores the latest value whenever it likes (`Atomics.store` on an
+//! `Int32Array` view, or `ControlBlock::set` natively) and the engine reads
+//! every slot at the start of each block, applying the ones that changed
+//! after any queued setters. Slots hold NaN until written; writing NaN
+//! hands the parameter back to the setters.
+//!
+//! | Slot | Drives                                                       |
+//! |------|--------------------------------------------------------------|
+//! | 0    | master volume (0.0 to 1.0)                                   |
+//! | 1    | morph amount, the crossfader between scenes A and B          |
+//! | 2-9  | macros 0-7, each bound to a parameter by `set_control_macro` |
+//!
+//! When the engine's memory is shared with the main thread (a threaded
+//! build) the main thread writes the slots at `get_control_block_ptr`
+//! directly. Otherwise the worklet copies its own SharedArrayBuffer into
+//! them before each `process()`, which still skips the message round trip.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::cc::CcTarget;
+use crate::DspEngine;
+
+const MASTER_SLOT: usize = 0;
+const MORPH_SLOT: usize = 1;
+const FIRST_MACRO_SLOT: usize = 2;
+
+/// Macros in the block
+const CONTROL_MACROS: usize = 8;
+
+/// Slots in the block
+pub const CONTROL_SLOTS: usize = FIRST_MACRO_SLOT + CONTROL_MACROS;
+
+/// Bits of the NaN an unwritten slot holds
+const UNSET_BITS: u32 = 0x7fc0_0000;
+
+/// Slots the host writes and the engine reads each block
+pub struct ControlBlock {
+    slots: [AtomicU32; CONTROL_SLOTS],
+}
+
+impl ControlBlock {
+    fn new() -> Self {
+        Self { slots: std::array::from_fn(|_| AtomicU32::new(UNSET_BITS)) }
+    }
+
+    /// Store a slot's value for the next block (out-of-range slots are
+    /// ignored)
+    pub fn set(&self, slot: usize, value: f32) {
+        if let Some(slot) = self.slots.get(slot) {
+            slot.store(value.to_bits(), Ordering::Release);
+        }
+    }
+
+    /// Value held by a slot (NaN if unwritten or out of range)
+    pub fn get(&self, slot: usize) -> f32 {
+        self.slots.get(slot).map_or(f32::NAN, |slot| f32::from_bits(slot.load(Ordering::Acquire)))
+    }
+}
+
+/// Parameter a macro slot drives, and the range its 0.0-1.0 maps onto
+#[derive(Clone, Copy)]
+struct ControlMacro {
+    target: CcTarget,
+    index: u8,
+    min: f32,
+    max: f32,
+}
+
+pub(crate) struct ControlPlane {
+    block: Arc<ControlBlock>,
+    /// Bits of each slot as last applied
+    applied: [u32; CONTROL_SLOTS],
+    macros: [Option<ControlMacro>; CONTROL_MACROS],
+}
+
+impl ControlPlane {
+    pub(crate) fn new() -> Self {
+        Self {
+            block: Arc::new(ControlBlock::new()),
+            applied: [UNSET_BITS; CONTROL_SLOTS],
+            macros: [None; CONTROL_MACROS],
+        }
+    }
+}
+
+impl DspEngine {
+    /// Apply the slots written since the last block (real-time safe)
+    pub(crate) fn apply_control_block(&mut self) {
+        for slot in 0..CONTROL_SLOTS {
+            let bits = self.control.block.slots[slot].load(Ordering::Acquire);
+            if bits == self.control.applied[slot] {
+                continue;
+            }
+            self.control.applied[slot] = bits;
+            let value = f32::from_bits(bits);
+            if value.is_nan() {
+                continue;
+            }
+            match slot {
+                MASTER_SLOT => self.set_parameter(CcTarget::MasterVolume, 0, value),
+                MORPH_SLOT => self.set_parameter(CcTarget::Morph, 0, value),
+                _ => {
+                    if let Some(bound) = self.control.macros[slot - FIRST_MACRO_SLOT] {
+                        let value = bound.min + (bound.max - bound.min) * value.clamp(0.0, 1.0);
+                        self.set_parameter(bound.target, bound.index, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The control block, to write from another thread
+    pub fn control_block(&self) -> Arc<ControlBlock> {
+        Arc::clone(&self.control.block)
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Address of the control block's first slot in the engine's memory
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_control_block_ptr(&self) -> *const u32 {
+        // AtomicU32 has the same layout as u32
+        self.control.block.slots.as_ptr() as *const u32
+    }
+
+    /// Number of slots in the control block
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_control_block_len(&self) -> usize {
+        CONTROL_SLOTS
+    }
+
+    /// Bind a macro (0-7) to a parameter; its slot's 0.0-1.0 maps onto
+    /// `min`-`max`
+    ///
+    /// `target_index` picks the group or key for per-group and per-key
+    /// targets. The slot's current value is applied at the next block.
+    /// Returns false if the macro or range is invalid.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_control_macro(
+        &mut self,
+        macro_index: usize,
+        target: CcTarget,
+        target_index: u8,
+        min: f32,
+        max: f32,
+    ) -> bool {
+        if macro_index >= CONTROL_MACROS || !min.is_finite() || !max.is_finite() {
+            return false;
+        }
+        self.control.macros[macro_index] = Some(ControlMacro { target, index: target_index, min, max });
+        self.control.applied[FIRST_MACRO_SLOT + macro_index] = UNSET_BITS;
+        true
+    }
+
+    /// Unbind a macro; returns false if it was not bound
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_control_macro(&mut self, macro_index: usize) -> bool {
+        self.control.macros.get_mut(macro_index).and_then(Option::take).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CcTarget, DspEngine};
+
+    #[test]
+    fn test_control_block_drives_parameters() {
+        let mut engine = DspEngine::new(48000.0);
+        let block = engine.control_block();
+        assert!(engine.set_control_macro(0, CcTarget::GroupVolume, 3, 0.0, 0.5));
+        assert!(!engine.set_control_macro(8, CcTarget::GroupVolume, 3, 0.0, 1.0));
+
+        block.set(0, 0.5);
+        block.set(2, 0.5);
+        engine.process(&mut [0.0; 2 * 4]);
+        assert_eq!(engine.master_volume, 0.5);
+        assert_eq!(engine.get_group_volume(3), 0.25);
+
+        // Unchanged slots leave the setters alone
+        engine.set_master_volume(0.8);
+        engine.process(&mut [0.0; 2 * 4]);
+        assert_eq!(engine.master_volume, 0.8);
+
+        block.set(0, 0.3);
+        engine.set_master_volume(0.9);
+        engine.process(&mut [0.0; 2 * 4]);
+        assert_eq!(engine.master_volume, 0.3, "the block lands after queued setters");
+
+        block.set(0, f32::NAN);
+        engine.set_master_volume(0.6);
+        engine.process(&mut [0.0; 2 * 4]);
+        assert_eq!(engine.master_volume, 0.6);
+        assert!(engine.clear_control_macro(0) && !engine.clear_control_macro(0));
+    }
+}
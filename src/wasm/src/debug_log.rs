@@ -0,0 +1,135 @@
+//! Real-time-safe diagnostic log
+//!
+//! `console.log` is not available from the audio thread, so the engine
+//! records terse numeric codes into a fixed ring buffer instead. When the
+//! buffer is full the oldest entries are overwritten.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Number of entries retained by the log
+const LOG_CAPACITY: usize = 128;
+
+/// Number of u32 values written per entry by `drain_log`
+const LOG_STRIDE: usize = 3;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum LogCode {
+    /// A voice was stolen (arg = voice slot)
+    VoiceStolen = 0,
+    /// A sound slot index was out of range (arg = index)
+    BadSoundIndex = 1,
+    /// A voice referenced a sound that is no longer loaded (arg = sound index)
+    SoundNotLoaded = 2,
+    /// NaN or infinity appeared in the mix and was silenced (arg = frame)
+    NonFiniteSample = 3,
+    /// An encoded file could not be decoded (arg = decoder error code)
+    DecodeFailed = 4,
+}
+
+impl LogCode {
+    fn description(self) -> &'static str {
+        match self {
+            LogCode::VoiceStolen => "voice stolen",
+            LogCode::BadSoundIndex => "sound index out of range",
+            LogCode::SoundNotLoaded => "voice referenced an unloaded sound",
+            LogCode::NonFiniteSample => "non-finite sample silenced",
+            LogCode::DecodeFailed => "audio file could not be decoded",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LogEntry {
+    code: LogCode,
+    arg: u32,
+    /// Engine sample position (wrapping)
+    time: u32,
+}
+
+pub(crate) struct DebugLog {
+    entries: [LogEntry; LOG_CAPACITY],
+    /// Index of the oldest entry
+    head: usize,
+    /// Number of retained entries
+    len: usize,
+}
+
+impl DebugLog {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: [LogEntry { code: LogCode::VoiceStolen, arg: 0, time: 0 }; LOG_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Record a diagnostic (real-time safe, overwrites the oldest when full)
+    #[inline]
+    pub(crate) fn push(&mut self, code: LogCode, arg: u32, time: u64) {
+        let index = (self.head + self.len) % LOG_CAPACITY;
+        self.entries[index] = LogEntry { code, arg, time: time as u32 };
+        if self.len == LOG_CAPACITY {
+            self.head = (self.head + 1) % LOG_CAPACITY;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<LogEntry> {
+        if self.len == 0 {
+            return None;
+        }
+        let entry = self.entries[self.head];
+        self.head = (self.head + 1) % LOG_CAPACITY;
+        self.len -= 1;
+        Some(entry)
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Move logged diagnostics into `out`, oldest first
+    ///
+    /// Each entry occupies 3 consecutive values: `[code, arg, time]` where
+    /// `code` is a `LogCode`. Returns the number of entries written.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn drain_log(&mut self, out: &mut [u32]) -> u32 {
+        let mut written = 0;
+        for record in out.chunks_exact_mut(LOG_STRIDE) {
+            let Some(entry) = self.debug_log.pop() else {
+                break;
+            };
+            record[0] = entry.code as u32;
+            record[1] = entry.arg;
+            record[2] = entry.time;
+            written += 1;
+        }
+        written
+    }
+
+    /// Human-readable description of a log code (for the host's console)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn describe_log_code(code: LogCode) -> String {
+        code.description().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_overwrites_oldest() {
+        let mut log = DebugLog::new();
+        for i in 0..LOG_CAPACITY + 2 {
+            log.push(LogCode::BadSoundIndex, i as u32, 0);
+        }
+        assert_eq!(log.len, LOG_CAPACITY);
+        assert_eq!(log.pop().unwrap().arg, 2);
+    }
+}
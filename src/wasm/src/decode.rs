@@ -0,0 +1,569 @@
+//! Encoded audio decoding
+//!
+//! Parses audio files handed over as raw bytes so the host does not need
+//! to round-trip through `decodeAudioData`. Decoding allocates and must
+//! never be called from `process()`.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::debug_log::LogCode;
+use crate::errors::ErrorCode;
+use crate::resample::is_supported_rate;
+use crate::DspEngine;
+
+/// Why a file could not be decoded
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub(crate) enum DecodeError {
+    /// The data ended before a complete header or chunk
+    Truncated = 0,
+    /// The container signature was not recognized
+    UnknownContainer = 1,
+    /// The sample encoding or bit depth is not supported
+    UnsupportedEncoding = 2,
+    /// The file had no audio data
+    NoAudio = 3,
+}
+
+/// Decoded PCM, interleaved, as f32 in -1.0..1.0
+pub(crate) struct DecodedAudio {
+    pub(crate) samples: Vec<f32>,
+    pub(crate) channels: usize,
+    pub(crate) sample_rate: u32,
+}
+
+impl DecodedAudio {
+    /// Average all channels into a single mono signal
+    pub(crate) fn to_mono(&self) -> Vec<f32> {
+        if self.channels == 1 {
+            return self.samples.clone();
+        }
+        let scale = 1.0 / self.channels as f32;
+        self.samples
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() * scale)
+            .collect()
+    }
+
+    /// Left and right channels of stereo audio (None for any other layout)
+    pub(crate) fn to_stereo(&self) -> Option<(Vec<f32>, Vec<f32>)> {
+        if self.channels != 2 {
+            return None;
+        }
+        Some(self.samples.chunks_exact(2).map(|frame| (frame[0], frame[1])).unzip())
+    }
+}
+
+// ============================================================================
+// BYTE HELPERS
+// ============================================================================
+
+fn read_u16_le(bytes: &[u8], at: usize) -> Result<u16, DecodeError> {
+    let b = bytes.get(at..at + 2).ok_or(DecodeError::Truncated)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32_le(bytes: &[u8], at: usize) -> Result<u32, DecodeError> {
+    let b = bytes.get(at..at + 4).ok_or(DecodeError::Truncated)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// ============================================================================
+// WAV
+// ============================================================================
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Decode a RIFF/WAVE file (integer PCM 8/16/24/32-bit or float 32/64-bit)
+pub(crate) fn decode_wav(bytes: &[u8]) -> Result<DecodedAudio, DecodeError> {
+    if bytes.len() < 12 {
+        return Err(DecodeError::Truncated);
+    }
+    if &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(DecodeError::UnknownContainer);
+    }
+
+    let mut format: Option<(u16, usize, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+
+    // Walk the chunk list
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = read_u32_le(bytes, offset + 4)? as usize;
+        let body_start = offset + 8;
+        // Tolerate a data chunk whose declared size runs past the end
+        let body_end = body_start.saturating_add(size).min(bytes.len());
+        let body = &bytes[body_start..body_end];
+
+        match id {
+            b"fmt " => {
+                let mut tag = read_u16_le(body, 0)?;
+                let channels = read_u16_le(body, 2)? as usize;
+                let sample_rate = read_u32_le(body, 4)?;
+                let bits = read_u16_le(body, 14)?;
+                if tag == WAVE_FORMAT_EXTENSIBLE {
+                    // The real format tag is the first two bytes of the subformat GUID
+                    tag = read_u16_le(body, 24)?;
+                }
+                format = Some((tag, channels, sample_rate, bits));
+            }
+            b"data" => data = Some(body),
+            _ => {}
+        }
+
+        // Chunks are padded to even sizes
+        offset = body_start.saturating_add(size).saturating_add(size & 1);
+    }
+
+    let (tag, channels, sample_rate, bits) = format.ok_or(DecodeError::Truncated)?;
+    let data = data.ok_or(DecodeError::NoAudio)?;
+    if channels == 0 || !is_supported_rate(sample_rate as f32) {
+        return Err(DecodeError::UnsupportedEncoding);
+    }
+
+    let samples: Vec<f32> = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => data.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+        (WAVE_FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        _ => return Err(DecodeError::UnsupportedEncoding),
+    };
+
+    // Drop a trailing partial frame
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return Err(DecodeError::NoAudio);
+    }
+    let mut samples = samples;
+    samples.truncate(frames * channels);
+
+    Ok(DecodedAudio { samples, channels, sample_rate })
+}
+
+// ============================================================================
+// AIFF / AIFF-C
+// ============================================================================
+
+#[cfg(feature = "aiff")]
+fn read_u16_be(bytes: &[u8], at: usize) -> Result<u16, DecodeError> {
+    let b = bytes.get(at..at + 2).ok_or(DecodeError::Truncated)?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+
+#[cfg(feature = "aiff")]
+fn read_u32_be(bytes: &[u8], at: usize) -> Result<u32, DecodeError> {
+    let b = bytes.get(at..at + 4).ok_or(DecodeError::Truncated)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Parse the 80-bit IEEE extended float AIFF uses for its sample rate
+#[cfg(feature = "aiff")]
+fn read_extended(bytes: &[u8], at: usize) -> Result<f64, DecodeError> {
+    let b = bytes.get(at..at + 10).ok_or(DecodeError::Truncated)?;
+    let sign = if b[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (((b[0] & 0x7F) as i32) << 8) | b[1] as i32;
+    let mantissa = u64::from_be_bytes([b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9]]);
+    if exponent == 0 && mantissa == 0 {
+        return Ok(0.0);
+    }
+    Ok(sign * mantissa as f64 * 2.0_f64.powi(exponent - 16383 - 63))
+}
+
+/// Decode an AIFF or uncompressed AIFF-C file
+/// (big-endian 8/16/24/32-bit PCM, `sowt` little-endian PCM, `fl32` float)
+#[cfg(feature = "aiff")]
+pub(crate) fn decode_aiff(bytes: &[u8]) -> Result<DecodedAudio, DecodeError> {
+    if bytes.len() < 12 {
+        return Err(DecodeError::Truncated);
+    }
+    if &bytes[0..4] != b"FORM" || (&bytes[8..12] != b"AIFF" && &bytes[8..12] != b"AIFC") {
+        return Err(DecodeError::UnknownContainer);
+    }
+
+    let mut common: Option<(usize, u16, f64, [u8; 4])> = None;
+    let mut data: Option<&[u8]> = None;
+
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = read_u32_be(bytes, offset + 4)? as usize;
+        let body_start = offset + 8;
+        let body_end = body_start.saturating_add(size).min(bytes.len());
+        let body = &bytes[body_start..body_end];
+
+        match id {
+            b"COMM" => {
+                let channels = read_u16_be(body, 0)? as usize;
+                let bits = read_u16_be(body, 6)?;
+                let sample_rate = read_extended(body, 8)?;
+                // AIFF-C appends a compression type; plain AIFF is big-endian PCM
+                let compression = body.get(18..22).map_or(*b"NONE", |c| [c[0], c[1], c[2], c[3]]);
+                common = Some((channels, bits, sample_rate, compression));
+            }
+            b"SSND" => {
+                let data_offset = read_u32_be(body, 0)? as usize;
+                data = Some(body.get(8 + data_offset..).ok_or(DecodeError::Truncated)?);
+            }
+            _ => {}
+        }
+
+        offset = body_start.saturating_add(size).saturating_add(size & 1);
+    }
+
+    let (channels, bits, sample_rate, compression) = common.ok_or(DecodeError::Truncated)?;
+    let data = data.ok_or(DecodeError::NoAudio)?;
+    if channels == 0 || !is_supported_rate(sample_rate as f32) {
+        return Err(DecodeError::UnsupportedEncoding);
+    }
+
+    let mut samples: Vec<f32> = match (&compression, bits) {
+        (b"NONE" | b"twos", 8) => data.iter().map(|&b| b as i8 as f32 / 128.0).collect(),
+        (b"NONE" | b"twos", 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        (b"NONE" | b"twos", 24) => data
+            .chunks_exact(3)
+            .map(|b| (i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        (b"NONE" | b"twos", 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (b"sowt", 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        (b"fl32" | b"FL32", _) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        _ => return Err(DecodeError::UnsupportedEncoding),
+    };
+
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return Err(DecodeError::NoAudio);
+    }
+    samples.truncate(frames * channels);
+
+    Ok(DecodedAudio { samples, channels, sample_rate: sample_rate.round() as u32 })
+}
+
+// ============================================================================
+// FLAC
+// ============================================================================
+
+/// Decode a native FLAC stream
+#[cfg(feature = "flac")]
+pub(crate) fn decode_flac(bytes: &[u8]) -> Result<DecodedAudio, DecodeError> {
+    if bytes.len() < 4 {
+        return Err(DecodeError::Truncated);
+    }
+    if &bytes[0..4] != b"fLaC" {
+        return Err(DecodeError::UnknownContainer);
+    }
+
+    let mut reader = claxon::FlacReader::new(bytes).map_err(|_| DecodeError::UnsupportedEncoding)?;
+    let info = reader.streaminfo();
+    if !is_supported_rate(info.sample_rate as f32) {
+        return Err(DecodeError::UnsupportedEncoding);
+    }
+    let channels = info.channels as usize;
+    let scale = 1.0 / (1_u64 << (info.bits_per_sample - 1)) as f32;
+
+    let samples = reader
+        .samples()
+        .map(|sample| sample.map(|s| s as f32 * scale))
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| DecodeError::Truncated)?;
+    if samples.len() < channels || channels == 0 {
+        return Err(DecodeError::NoAudio);
+    }
+
+    Ok(DecodedAudio { samples, channels, sample_rate: info.sample_rate })
+}
+
+// ============================================================================
+// COMPRESSED FORMATS (MP3, Ogg Vorbis)
+// ============================================================================
+
+/// Decode MP3 or Ogg Vorbis through symphonia
+///
+/// Opus is not covered: symphonia has no Opus decoder yet.
+#[cfg(feature = "compressed")]
+pub(crate) fn decode_compressed(bytes: &[u8]) -> Result<DecodedAudio, DecodeError> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let source = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes.to_vec())), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&Hint::new(), source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|_| DecodeError::UnknownContainer)?;
+    let mut format = probed.format;
+
+    let track = format.default_track().ok_or(DecodeError::NoAudio)?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|_| DecodeError::UnsupportedEncoding)?;
+
+    let mut samples = Vec::new();
+    let mut channels = 0;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+
+    // Runs until end of stream (or a broken tail), keeping what was decoded
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(buffer) => {
+                let spec = *buffer.spec();
+                channels = spec.channels.count();
+                sample_rate = spec.rate;
+                let mut interleaved = SampleBuffer::<f32>::new(buffer.capacity() as u64, spec);
+                interleaved.copy_interleaved_ref(buffer);
+                samples.extend_from_slice(interleaved.samples());
+            }
+            // Skip corrupt frames, as players do
+            Err(Error::DecodeError(_)) => continue,
+            Err(_) => break,
+        }
+    }
+
+    if channels == 0 || samples.len() < channels || sample_rate == 0 {
+        return Err(DecodeError::NoAudio);
+    }
+    if !is_supported_rate(sample_rate as f32) {
+        return Err(DecodeError::UnsupportedEncoding);
+    }
+    Ok(DecodedAudio { samples, channels, sample_rate })
+}
+
+// ============================================================================
+// FORMAT SNIFFING
+// ============================================================================
+
+/// Decode any supported format, chosen by the file's signature
+pub(crate) fn decode_any(bytes: &[u8]) -> Result<DecodedAudio, DecodeError> {
+    match bytes.get(0..4) {
+        Some(b"RIFF") => decode_wav(bytes),
+        #[cfg(feature = "aiff")]
+        Some(b"FORM") => decode_aiff(bytes),
+        #[cfg(feature = "flac")]
+        Some(b"fLaC") => decode_flac(bytes),
+        // Compressed streams have no single signature; let the prober decide
+        #[cfg(feature = "compressed")]
+        Some(_) => decode_compressed(bytes),
+        #[cfg(not(feature = "compressed"))]
+        Some(_) => Err(DecodeError::UnknownContainer),
+        None => Err(DecodeError::Truncated),
+    }
+}
+
+// ============================================================================
+// ENGINE API
+// ============================================================================
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Decode a WAV file and load it into a sound slot
+    ///
+    /// Supports 8/16/24/32-bit integer and 32/64-bit float PCM with any
+    /// channel count (stereo kept, other layouts mixed down to mono).
+    /// Returns the same codes as `load_sound`, or `DecodeFailed` if the file
+    /// could not be decoded.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound_wav(&mut self, sound_index: usize, bytes: &[u8]) -> ErrorCode {
+        self.load_decoded(sound_index, decode_wav(bytes))
+    }
+
+    /// Decode a file of any supported format and load it into a sound slot
+    ///
+    /// WAV is always available; AIFF, FLAC and MP3/Ogg Vorbis require the
+    /// `aiff`, `flac` and `compressed` cargo features. The format is
+    /// detected from the file header.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound_encoded(&mut self, sound_index: usize, bytes: &[u8]) -> ErrorCode {
+        self.load_decoded(sound_index, decode_any(bytes))
+    }
+
+    /// Decode an AIFF/AIFF-C file and load it into a sound slot
+    #[cfg(feature = "aiff")]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound_aiff(&mut self, sound_index: usize, bytes: &[u8]) -> ErrorCode {
+        self.load_decoded(sound_index, decode_aiff(bytes))
+    }
+
+    /// Decode a FLAC file and load it into a sound slot
+    #[cfg(feature = "flac")]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound_flac(&mut self, sound_index: usize, bytes: &[u8]) -> ErrorCode {
+        self.load_decoded(sound_index, decode_flac(bytes))
+    }
+
+    /// Decode an MP3 or Ogg Vorbis file and load it into a sound slot
+    #[cfg(feature = "compressed")]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound_compressed(&mut self, sound_index: usize, bytes: &[u8]) -> ErrorCode {
+        self.load_decoded(sound_index, decode_compressed(bytes))
+    }
+
+    /// Sample rate the sound's source file was recorded at
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_source_sample_rate(&self, sound_index: usize) -> f32 {
+        self.sounds.get(sound_index).map_or(0.0, |sound| sound.source_sample_rate)
+    }
+}
+
+impl DspEngine {
+    /// Load the result of a decoder into a slot, logging failures
+    pub(crate) fn load_decoded(&mut self, sound_index: usize, decoded: Result<DecodedAudio, DecodeError>) -> ErrorCode {
+        if sound_index >= self.sounds.len() {
+            return self.bad_sound_index(sound_index);
+        }
+        match decoded {
+            Ok(audio) => {
+                // Stereo files keep both channels; other layouts are mixed to mono
+                let rate = audio.sample_rate as f32;
+                match audio.to_stereo() {
+                    Some((left, right)) => self.store_channels(sound_index, &left, Some(&right), rate),
+                    None => self.store_sound(sound_index, &audio.to_mono(), rate),
+                }
+            }
+            Err(error) => {
+                self.debug_log.push(LogCode::DecodeFailed, error as u32, self.global_sample_position);
+                self.report_error(ErrorCode::DecodeFailed, error as u32)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a minimal WAV file around raw sample bytes
+    fn wav_bytes(tag: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        wav_bytes_at(44100, tag, channels, bits, data)
+    }
+
+    fn wav_bytes_at(rate: u32, tag: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&rate.to_le_bytes());
+        bytes.extend_from_slice(&(rate * channels as u32 * bits as u32 / 8).to_le_bytes());
+        bytes.extend_from_slice(&(channels * bits / 8).to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_decode_wav_formats() {
+        let pcm16: Vec<u8> = [16384i16, -32768].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let audio = decode_wav(&wav_bytes(WAVE_FORMAT_PCM, 1, 16, &pcm16)).unwrap();
+        assert_eq!(audio.sample_rate, 44100);
+        assert_eq!(audio.samples, vec![0.5, -1.0]);
+
+        // 24-bit stereo frame (0.5, -0.5) downmixes to 0.0
+        let pcm24 = [0x00, 0x00, 0x40, 0x00, 0x00, 0xC0];
+        let audio = decode_wav(&wav_bytes(WAVE_FORMAT_PCM, 2, 24, &pcm24)).unwrap();
+        assert_eq!(audio.samples, vec![0.5, -0.5]);
+        assert_eq!(audio.to_mono(), vec![0.0]);
+        assert_eq!(audio.to_stereo(), Some((vec![0.5], vec![-0.5])));
+
+        let float: Vec<u8> = 0.25f32.to_le_bytes().to_vec();
+        let audio = decode_wav(&wav_bytes(WAVE_FORMAT_IEEE_FLOAT, 1, 32, &float)).unwrap();
+        assert_eq!(audio.samples, vec![0.25]);
+    }
+
+    #[cfg(feature = "aiff")]
+    #[test]
+    fn test_decode_aiff() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"FORM");
+        bytes.extend_from_slice(&46u32.to_be_bytes());
+        bytes.extend_from_slice(b"AIFFCOMM");
+        bytes.extend_from_slice(&18u32.to_be_bytes());
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&2u32.to_be_bytes());
+        bytes.extend_from_slice(&16u16.to_be_bytes());
+        // 44100 as 80-bit extended
+        bytes.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"SSND");
+        bytes.extend_from_slice(&12u32.to_be_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&16384i16.to_be_bytes());
+        bytes.extend_from_slice(&(-16384i16).to_be_bytes());
+
+        let audio = decode_any(&bytes).unwrap();
+        assert_eq!(audio.sample_rate, 44100);
+        assert_eq!(audio.samples, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_decode_wav_errors() {
+        assert_eq!(decode_wav(b"RIFF").err(), Some(DecodeError::Truncated));
+        assert_eq!(decode_wav(b"OggS00000000").err(), Some(DecodeError::UnknownContainer));
+        let bytes = wav_bytes(WAVE_FORMAT_PCM, 1, 12, &[0, 0]);
+        assert_eq!(decode_wav(&bytes).err(), Some(DecodeError::UnsupportedEncoding));
+        // A header rate that would make conversion blow up
+        let bytes = wav_bytes_at(10, WAVE_FORMAT_PCM, 1, 16, &[0, 0]);
+        assert_eq!(decode_wav(&bytes).err(), Some(DecodeError::UnsupportedEncoding));
+    }
+
+    #[test]
+    fn test_decode_loads_report_error_codes() {
+        let mut engine = DspEngine::new(44100.0);
+        let bytes = wav_bytes(WAVE_FORMAT_PCM, 1, 16, &[0, 64, 0, 64]);
+        assert_eq!(engine.load_sound_wav(0, &bytes), ErrorCode::None);
+        assert_eq!(engine.load_sound_wav(999, &bytes), ErrorCode::BadSoundIndex);
+        assert_eq!(engine.load_sound_encoded(1, b"RIFF"), ErrorCode::DecodeFailed);
+        assert_eq!(engine.get_last_error_arg(), DecodeError::Truncated as u32);
+        assert!(!engine.sounds[1].loaded);
+    }
+
+    #[test]
+    fn test_stereo_file_loads_both_channels() {
+        let frames: Vec<u8> = [16384i16, -8192].repeat(4).iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut engine = DspEngine::new(44100.0);
+        assert_eq!(engine.load_sound_wav(0, &wav_bytes(WAVE_FORMAT_PCM, 2, 16, &frames)), ErrorCode::None);
+        assert!(engine.is_sound_stereo(0));
+        assert_eq!(engine.loaded_channels(0), Some((&[0.5; 4][..], Some(&[-0.25; 4][..]))));
+    }
+}
@@ -0,0 +1,168 @@
+//! Multiple input devices
+//!
+//! The key code API (`note_on`/`note_off`) is device 0 and always plays the
+//! active bank. Further devices (1-15), such as a second keyboard or a pad
+//! controller, send their keys through `device_note_on`/`device_note_off`
+//! and can each be assigned a bank of their own, so two performers can play
+//! different mapping sets on one engine. Their voices are released only by
+//! their own device, even when key codes coincide.
+//!
+//! MIDI channels can likewise be assigned a bank: notes that match no note
+//! range play the key mapping with the same number in that bank.
+//!
+//! Assignments are saved with the state, so a rig comes back the way it
+//! was set up as long as the devices are plugged in in the same order.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::release::DEFAULT_RELEASE_VELOCITY;
+use crate::{DspEngine, VoiceSource};
+
+/// Input devices addressable, including the key code API (device 0)
+const MAX_DEVICES: usize = 16;
+
+pub(crate) struct InputDevices {
+    /// Bank each device plays (None = the active bank)
+    pub(crate) device_banks: [Option<usize>; MAX_DEVICES],
+    /// Bank unmatched notes on each MIDI channel play (None = ignored)
+    pub(crate) channel_banks: [Option<usize>; 16],
+}
+
+impl InputDevices {
+    pub(crate) const fn new() -> Self {
+        Self { device_banks: [None; MAX_DEVICES], channel_banks: [None; 16] }
+    }
+
+    /// Bank assigned to a MIDI channel
+    #[inline]
+    pub(crate) fn channel_bank(&self, channel: u8) -> Option<usize> {
+        self.channel_banks.get(channel as usize).copied().flatten()
+    }
+}
+
+impl DspEngine {
+    /// Bank index from the API (-1 = none); Err if out of range
+    fn bank_argument(&self, bank: i32) -> Result<Option<usize>, ()> {
+        match usize::try_from(bank) {
+            Ok(bank) if bank < self.key_banks.len() => Ok(Some(bank)),
+            Ok(_) => Err(()),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Assign a bank to an input device (1-15); -1 follows the active bank
+    ///
+    /// Returns false for device 0 (the key code API, which always plays the
+    /// active bank), an unknown device or an out-of-range bank.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_device_bank(&mut self, device: u8, bank: i32) -> bool {
+        if device == 0 || device as usize >= MAX_DEVICES {
+            return false;
+        }
+        let Ok(bank) = self.bank_argument(bank) else {
+            return false;
+        };
+        self.devices.device_banks[device as usize] = bank;
+        true
+    }
+
+    /// Bank an input device plays (-1 = the active bank)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_device_bank(&self, device: u8) -> i32 {
+        let bank = self.devices.device_banks.get(device as usize).copied().flatten();
+        bank.map_or(-1, |bank| bank as i32)
+    }
+
+    /// Assign a bank to a MIDI channel's unmatched notes; -1 removes it
+    ///
+    /// Returns false for an invalid channel or an out-of-range bank.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_midi_channel_bank(&mut self, channel: u8, bank: i32) -> bool {
+        if channel > 15 {
+            return false;
+        }
+        let Ok(bank) = self.bank_argument(bank) else {
+            return false;
+        };
+        self.devices.channel_banks[channel as usize] = bank;
+        true
+    }
+
+    /// Bank a MIDI channel's unmatched notes play (-1 = none)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_midi_channel_bank(&self, channel: u8) -> i32 {
+        self.devices.channel_bank(channel).map_or(-1, |bank| bank as i32)
+    }
+
+    /// Key down on an input device
+    ///
+    /// Device 0 is the same as `note_on`.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn device_note_on(&mut self, device: u8, key_code: u8) {
+        if device == 0 {
+            self.note_on(key_code);
+            return;
+        }
+        let Some(&bank) = self.devices.device_banks.get(device as usize) else {
+            return;
+        };
+        let mapping = match bank {
+            Some(bank) => self.bank(bank)[key_code as usize],
+            None => self.key_mappings[key_code as usize],
+        };
+        if !mapping.has_sound || !self.sounds[mapping.sound_index].loaded {
+            return;
+        }
+        self.trigger_voice(&mapping, key_code as u16, VoiceSource::Device(device));
+    }
+
+    /// Key up on an input device
+    ///
+    /// Device 0 is the same as `note_off`.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn device_note_off(&mut self, device: u8, key_code: u8) {
+        if device == 0 {
+            self.note_off(key_code);
+            return;
+        }
+        self.release_voices(VoiceSource::Device(device), key_code as u16, DEFAULT_RELEASE_VELOCITY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_devices_play_their_own_banks() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 4800]);
+        engine.load_sound(1, &[0.25; 4800]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.bank_mut(1)[65] = engine.key_mappings[65];
+        engine.bank_mut(1)[65].sound_index = 1;
+
+        assert!(!engine.set_device_bank(0, 1), "the key code API follows the active bank");
+        assert!(engine.set_device_bank(1, 1));
+        assert_eq!(engine.get_device_bank(1), 1);
+        engine.note_on(65);
+        engine.device_note_on(1, 65);
+        assert_eq!((engine.voices[0].sound_index, engine.voices[1].sound_index), (0, 1));
+
+        engine.note_off(65);
+        assert!(!engine.voices[0].active && engine.voices[1].active, "releases stay on their device");
+        engine.device_note_off(1, 65);
+        assert_eq!(engine.get_active_voice_count(), 0);
+
+        // MIDI channel 2 plays bank 1 for notes outside every range
+        assert!(engine.set_midi_channel_bank(2, 1));
+        engine.midi_note_on(3, 65, 127);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        engine.midi_note_on(2, 65, 127);
+        assert_eq!(engine.voices[0].sound_index, 1);
+    }
+}
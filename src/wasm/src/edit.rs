@@ -0,0 +1,445 @@
+//! Offline sound editing
+//!
+//! Operations that derive new audio from sounds already in the bank, or edit
+//! it in place, so the host does not have to round-trip sample data through
+//! JS. These allocate and must never be called from inside `process()`.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::metadata::SoundMetadata;
+use crate::preprocess::db_to_gain;
+use crate::{DspEngine, Voice};
+
+/// Left (or mono) audio of a sound and its right channel if stereo
+pub(crate) type Channels<'a> = (&'a [f32], Option<&'a [f32]>);
+
+/// Combine the left channels of two sounds and, if either is stereo, their
+/// right channels (a mono sound stands in with its only channel)
+fn combine_channels(
+    first: Channels,
+    second: Channels,
+    combine: impl Fn(&[f32], &[f32]) -> Vec<f32>,
+) -> (Vec<f32>, Option<Vec<f32>>) {
+    let left = combine(first.0, second.0);
+    let right = (first.1.is_some() || second.1.is_some())
+        .then(|| combine(first.1.unwrap_or(first.0), second.1.unwrap_or(second.0)));
+    (left, right)
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Store a reversed copy of `src_slot` in `dst_slot`
+    ///
+    /// `src_slot` and `dst_slot` may be the same to reverse in place.
+    /// Returns false if either slot is invalid or the source is empty.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn create_reversed_copy(&mut self, src_slot: usize, dst_slot: usize) -> bool {
+        let Some((left, right)) = self.loaded_channels(src_slot) else {
+            return false;
+        };
+        if dst_slot >= self.sounds.len() {
+            self.bad_sound_index(dst_slot);
+            return false;
+        }
+        let reverse = |channel: &[f32]| channel.iter().rev().copied().collect::<Vec<f32>>();
+        self.install_derived_sound(src_slot, dst_slot, reverse(left), right.map(reverse));
+        true
+    }
+
+    /// Copy `src_slot` into `dst_slot`, including its analysis, gain and slices
+    ///
+    /// Returns false if either slot is invalid or the source is empty.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn copy_sound(&mut self, src_slot: usize, dst_slot: usize) -> bool {
+        if self.loaded_samples(src_slot).is_none() {
+            return false;
+        }
+        if dst_slot >= self.sounds.len() {
+            self.bad_sound_index(dst_slot);
+            return false;
+        }
+        if src_slot != dst_slot {
+            let copy = self.sounds[src_slot].clone();
+            self.vacate_slot(dst_slot);
+            self.sounds[dst_slot] = copy;
+            self.mark_audio_dirty(dst_slot);
+        }
+        true
+    }
+
+    /// Join `second_slot` onto the end of `first_slot`, storing the result in `dst_slot`
+    ///
+    /// The result is truncated to the engine's maximum sound length.
+    /// Returns false if any slot is invalid or a source is empty.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn concatenate_sounds(&mut self, first_slot: usize, second_slot: usize, dst_slot: usize) -> bool {
+        let (Some(first), Some(second)) = (self.loaded_channels(first_slot), self.loaded_channels(second_slot)) else {
+            return false;
+        };
+        if dst_slot >= self.sounds.len() {
+            self.bad_sound_index(dst_slot);
+            return false;
+        }
+        let max_length = self.max_sample_length;
+        let (left, right) = combine_channels(first, second, |first, second| {
+            first.iter().chain(second).copied().take(max_length).collect()
+        });
+        self.install_derived_sound(first_slot, dst_slot, left, right);
+        true
+    }
+
+    /// Mix `first_slot` and `second_slot` on top of each other into `dst_slot`
+    ///
+    /// Both start together; the result is as long as the longer source.
+    /// Returns false if any slot is invalid or a source is empty.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn layer_sounds(&mut self, first_slot: usize, second_slot: usize, dst_slot: usize) -> bool {
+        let (Some(first), Some(second)) = (self.loaded_channels(first_slot), self.loaded_channels(second_slot)) else {
+            return false;
+        };
+        if dst_slot >= self.sounds.len() {
+            self.bad_sound_index(dst_slot);
+            return false;
+        }
+        let (left, right) = combine_channels(first, second, |first, second| {
+            let mut layered = vec![0.0; first.len().max(second.len())];
+            for (i, sample) in layered.iter_mut().enumerate() {
+                *sample = first.get(i).copied().unwrap_or(0.0) + second.get(i).copied().unwrap_or(0.0);
+            }
+            layered
+        });
+        self.install_derived_sound(first_slot, dst_slot, left, right);
+        true
+    }
+
+    /// Pad `src_slot` with silence up to the next bar boundary (4/4 at the session BPM)
+    ///
+    /// Sounds already an exact number of bars long are copied unchanged.
+    /// Returns false if either slot is invalid or the source is empty.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn pad_sound_to_bar(&mut self, src_slot: usize, dst_slot: usize) -> bool {
+        let Some((left, right)) = self.loaded_channels(src_slot) else {
+            return false;
+        };
+        if dst_slot >= self.sounds.len() {
+            self.bad_sound_index(dst_slot);
+            return false;
+        }
+        let samples_per_bar = ((self.sample_rate * 60.0 / self.bpm) * 4.0).round() as usize;
+        let bars = left.len().div_ceil(samples_per_bar.max(1));
+        let length = (bars * samples_per_bar).min(self.max_sample_length).max(left.len());
+        let pad = |channel: &[f32]| {
+            let mut padded = channel.to_vec();
+            padded.resize(length, 0.0);
+            padded
+        };
+        self.install_derived_sound(src_slot, dst_slot, pad(left), right.map(pad));
+        true
+    }
+
+    /// Render a key through its per-key settings into the first empty slot
+    ///
+    /// The bounce bakes in the key's slice, pitch, volume and the sound's
+    /// playback gain, so it plays back identically at default settings.
+    /// Loop keys are rendered for one pass. Returns the slot used, or -1 if
+    /// the key has no sound or no slot is free.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn bounce_key(&mut self, key_code: u8) -> i32 {
+        let mapping = self.key_mappings[key_code as usize];
+        if !mapping.has_sound || self.loaded_samples(mapping.sound_index).is_none() {
+            return -1;
+        }
+        let Some(dst_slot) = self.sounds.iter().position(|sound| !sound.loaded) else {
+            return -1;
+        };
+        let sound = &self.sounds[mapping.sound_index];
+        let Some(region) = sound.key_region(mapping.slice) else {
+            return -1;
+        };
+
+        let mut voice = Voice::new();
+        voice.start(&mapping, key_code as u16, region, self.sample_rate);
+        let (_, region_length) = voice.region(sound);
+        let frames = ((region_length as f64 / voice.pitch as f64).ceil() as usize).min(self.max_sample_length);
+
+        let mut left = Vec::with_capacity(frames);
+        let mut right = Vec::with_capacity(if sound.is_stereo() { frames } else { 0 });
+        while (voice.position as usize) < region_length && left.len() < frames {
+            let (left_level, right_level) = voice.frame_at(sound);
+            left.push(left_level * sound.gain * voice.volume);
+            if sound.is_stereo() {
+                right.push(right_level * sound.gain * voice.volume);
+            }
+            voice.position += voice.pitch as f64;
+        }
+
+        let right = (!right.is_empty()).then_some(right);
+        self.install_derived_sound(mapping.sound_index, dst_slot, left, right);
+        // Gain is already baked into the audio
+        self.sounds[dst_slot].gain = 1.0;
+        dst_slot as i32
+    }
+
+    /// Fade the first `length` samples of a sound in from silence
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn fade_in_sound(&mut self, sound_index: usize, length: u32) -> bool {
+        self.edit_samples(sound_index, |samples| {
+            let length = (length as usize).min(samples.len());
+            for (i, sample) in samples[..length].iter_mut().enumerate() {
+                *sample *= i as f32 / length as f32;
+            }
+        })
+    }
+
+    /// Fade the last `length` samples of a sound out to silence
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn fade_out_sound(&mut self, sound_index: usize, length: u32) -> bool {
+        self.edit_samples(sound_index, |samples| {
+            let length = (length as usize).min(samples.len());
+            let start = samples.len() - length;
+            for (i, sample) in samples[start..].iter_mut().enumerate() {
+                *sample *= 1.0 - (i + 1) as f32 / length as f32;
+            }
+        })
+    }
+
+    /// Scale a sound's stored audio by `gain_db`
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn apply_sound_gain(&mut self, sound_index: usize, gain_db: f32) -> bool {
+        let gain = db_to_gain(gain_db.clamp(-96.0, 48.0));
+        self.edit_samples(sound_index, |samples| {
+            for sample in samples.iter_mut() {
+                *sample *= gain;
+            }
+        })
+    }
+
+    /// Remove samples `start..end` from a sound, joining what remains
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn cut_sound_region(&mut self, sound_index: usize, start: u32, end: u32) -> bool {
+        self.edit_samples(sound_index, |samples| {
+            let end = (end as usize).min(samples.len());
+            let start = (start as usize).min(end);
+            samples.drain(start..end);
+        })
+    }
+
+    /// Keep only samples `start..end` of a sound
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn trim_sound(&mut self, sound_index: usize, start: u32, end: u32) -> bool {
+        self.edit_samples(sound_index, |samples| {
+            let end = (end as usize).min(samples.len());
+            let start = (start as usize).min(end);
+            samples.truncate(end);
+            samples.drain(..start);
+        })
+    }
+}
+
+impl DspEngine {
+    /// Left (or mono) audio of a loaded sound (None if the slot is invalid or empty)
+    pub(crate) fn loaded_samples(&self, sound_index: usize) -> Option<&[f32]> {
+        self.loaded_channels(sound_index).map(|(left, _)| left)
+    }
+
+    /// Both channels of a loaded sound (None if the slot is invalid or empty)
+    pub(crate) fn loaded_channels(&self, sound_index: usize) -> Option<Channels<'_>> {
+        let sound = self.sounds.get(sound_index)?;
+        if !sound.loaded || sound.length == 0 {
+            return None;
+        }
+        let right = sound.is_stereo().then(|| &sound.right[..sound.length]);
+        Some((&sound.samples[..sound.length], right))
+    }
+
+    /// Apply a destructive edit to a loaded sound and re-analyze it
+    ///
+    /// The edit is made on a copy that replaces the slot, so voices playing
+    /// the sound fade out from the original. Slices are dropped if the edit
+    /// changes the length, since their chop points no longer line up.
+    /// Returns false if the sound is not loaded.
+    fn edit_samples(&mut self, sound_index: usize, edit: impl Fn(&mut Vec<f32>)) -> bool {
+        if self.loaded_samples(sound_index).is_none() {
+            return false;
+        }
+        let mut sound = self.sounds[sound_index].clone();
+        self.vacate_slot(sound_index);
+        sound.samples.truncate(sound.length);
+        edit(&mut sound.samples);
+        sound.samples.shrink_to_fit();
+        if sound.is_stereo() {
+            sound.right.truncate(sound.length);
+            edit(&mut sound.right);
+            sound.right.shrink_to_fit();
+        }
+        if sound.samples.len() != sound.length {
+            sound.length = sound.samples.len();
+            sound.slices.clear();
+        }
+        sound.loaded = sound.length > 0;
+        self.sounds[sound_index] = sound;
+
+        self.analyze_sound(sound_index);
+        self.apply_load_normalization(sound_index);
+        true
+    }
+
+    /// Store audio derived from `src_slot` into `dst_slot` and re-analyze it
+    ///
+    /// `right` holds the right channel of stereo audio; the channels are
+    /// cut to the shorter one's length. Load-time provenance (source rate,
+    /// baked-in normalization gain) is inherited from the source; trim
+    /// offsets no longer apply. The destination keeps its own metadata.
+    pub(crate) fn install_derived_sound(
+        &mut self,
+        src_slot: usize,
+        dst_slot: usize,
+        mut left: Vec<f32>,
+        right: Option<Vec<f32>>,
+    ) {
+        let source_sample_rate = self.sounds[src_slot].source_sample_rate;
+        let peak_normalize_gain = self.sounds[src_slot].peak_normalize_gain;
+
+        self.vacate_slot(dst_slot);
+        let sound = &mut self.sounds[dst_slot];
+        // Metadata belongs to the slot, not the audio
+        let metadata = std::mem::replace(&mut sound.metadata, SoundMetadata::new());
+        sound.clear();
+        sound.metadata = metadata;
+        if let Some(mut right) = right {
+            left.truncate(right.len());
+            right.truncate(left.len());
+            sound.right = right;
+        }
+        sound.length = left.len();
+        sound.samples = left;
+        sound.loaded = true;
+        sound.source_sample_rate = source_sample_rate;
+        sound.peak_normalize_gain = peak_normalize_gain;
+
+        self.analyze_sound(dst_slot);
+        self.apply_load_normalization(dst_slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, StatePart};
+
+    #[test]
+    fn test_reversed_copy() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.1, 0.2, 0.3]);
+        assert!(engine.create_reversed_copy(0, 1));
+        assert_eq!(engine.loaded_samples(1), Some(&[0.3, 0.2, 0.1][..]));
+        assert_eq!(engine.loaded_samples(0), Some(&[0.1, 0.2, 0.3][..]));
+
+        assert!(!engine.create_reversed_copy(5, 2), "empty source");
+        assert!(!engine.create_reversed_copy(0, 1000), "bad destination");
+    }
+
+    #[test]
+    fn test_copy_sound_is_independent() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.1, -0.2, 0.3]);
+        assert!(engine.copy_sound(0, 4));
+        assert_eq!(engine.get_sound_peak(4), engine.get_sound_peak(0));
+
+        engine.set_peak_normalization(true, 0.0);
+        engine.load_sound(4, &[0.1, -0.2, 0.3]);
+        assert_eq!(engine.loaded_samples(0), Some(&[0.1, -0.2, 0.3][..]), "original untouched");
+
+        // Voices on the destination fade out, staged audio does not land on
+        // top and the copy counts as changed for incremental export
+        use crate::{OverlapMode, PlaybackMode};
+        engine.set_peak_normalization(false, 0.0);
+        engine.load_sound(5, &[0.125; 1000]);
+        engine.set_key_mapping(65, 5, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 10]);
+        engine.stage_sound(5, &[0.75; 100]);
+        engine.export_state_changes(true);
+        assert!(engine.copy_sound(0, 5));
+        assert_ne!(engine.get_dirty_state_parts() & StatePart::Sounds as u32, 0);
+        let mut output = [0.0; 2 * 10];
+        engine.process(&mut output);
+        assert!(output[0] > 0.1 && output[0] <= 0.125, "no jump to the copy");
+        assert_eq!(engine.loaded_samples(5), Some(&[0.1, -0.2, 0.3][..]));
+    }
+
+    #[test]
+    fn test_join_layer_and_pad() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.1, 0.2]);
+        engine.load_sound(1, &[0.3]);
+
+        assert!(engine.concatenate_sounds(0, 1, 2));
+        assert_eq!(engine.loaded_samples(2), Some(&[0.1, 0.2, 0.3][..]));
+        assert!(engine.layer_sounds(0, 1, 3));
+        assert_eq!(engine.loaded_samples(3), Some(&[0.4, 0.2][..]));
+
+        // 120 BPM at 1 kHz: one bar is 2000 samples
+        engine.set_bpm(120.0);
+        assert!(engine.pad_sound_to_bar(2, 4));
+        assert_eq!(engine.get_sound_length_samples(4), 2000);
+    }
+
+    #[test]
+    fn test_bounce_key() {
+        use crate::{OverlapMode, PlaybackMode};
+
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.1, 0.2, 0.3, 0.4, 0.5, 0.6]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 0.5, 12, false);
+
+        assert_eq!(engine.bounce_key(65), 1);
+        assert_eq!(engine.loaded_samples(1), Some(&[0.05, 0.15, 0.25][..]));
+        assert_eq!(engine.bounce_key(66), -1);
+    }
+
+    #[test]
+    fn test_derived_sounds_stay_stereo() {
+        use crate::{OverlapMode, PlaybackMode};
+
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound_stereo(0, &[0.25, 0.5], &[-0.25, -0.5]);
+        engine.load_sound(1, &[0.5]);
+
+        assert!(engine.create_reversed_copy(0, 2));
+        assert_eq!(engine.loaded_channels(2), Some((&[0.5, 0.25][..], Some(&[-0.5, -0.25][..]))));
+        // The mono sound plays on both sides of the result
+        assert!(engine.concatenate_sounds(0, 1, 3));
+        assert_eq!(engine.loaded_channels(3), Some((&[0.25, 0.5, 0.5][..], Some(&[-0.25, -0.5, 0.5][..]))));
+        assert!(engine.layer_sounds(1, 0, 4));
+        assert_eq!(engine.loaded_channels(4), Some((&[0.75, 0.5][..], Some(&[0.25, -0.5][..]))));
+        engine.set_bpm(120.0);
+        assert!(engine.pad_sound_to_bar(0, 5));
+        assert!(engine.is_sound_stereo(5) && engine.get_sound_length_samples(5) == 2000);
+
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 0.5, 0, false);
+        assert_eq!(engine.bounce_key(65), 6);
+        assert_eq!(engine.loaded_channels(6), Some((&[0.125, 0.25][..], Some(&[-0.125, -0.25][..]))));
+
+        // Mono sources still give mono results
+        assert!(engine.create_reversed_copy(1, 7) && !engine.is_sound_stereo(7));
+    }
+
+    #[test]
+    fn test_destructive_edits() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[1.0; 8]);
+
+        assert!(engine.fade_in_sound(0, 4));
+        assert!(engine.fade_out_sound(0, 2));
+        assert_eq!(engine.loaded_samples(0), Some(&[0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 0.5, 0.0][..]));
+
+        assert!(engine.apply_sound_gain(0, -6.0206));
+        assert!(engine.cut_sound_region(0, 1, 4));
+        assert!(engine.trim_sound(0, 1, 4));
+        assert_eq!(engine.get_sound_length_samples(0), 3);
+        let edited = engine.loaded_samples(0).unwrap();
+        assert!((edited[0] - 0.5).abs() < 1e-4 && (edited[2] - 0.25).abs() < 1e-4);
+        assert!(!engine.trim_sound(7, 0, 1));
+    }
+}
@@ -0,0 +1,106 @@
+//! WAV encoding
+//!
+//! Turns recorded takes, loops and offline renders into WAV files the host
+//! can offer for download. Integer formats are dithered with triangular
+//! (TPDF) noise of one LSB, seeded the same way each time so an export is
+//! repeatable. Encoding allocates and must never be called from
+//! `process()`.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::synth::NoiseSource;
+use crate::DspEngine;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+
+/// Seed of the dither noise
+const DITHER_SEED: u32 = 0xD1;
+
+/// Encode interleaved samples as a WAV file; None for an unsupported bit
+/// depth (16 and 24-bit integer or 32-bit float)
+pub(crate) fn write_wav(samples: &[f32], channels: u16, sample_rate: u32, bit_depth: u8) -> Option<Vec<u8>> {
+    let (tag, bytes_per_sample) = match bit_depth {
+        16 => (WAVE_FORMAT_PCM, 2),
+        24 => (WAVE_FORMAT_PCM, 3),
+        32 => (WAVE_FORMAT_IEEE_FLOAT, 4),
+        _ => return None,
+    };
+    let channels = channels.max(1);
+    let data_len = (samples.len() * bytes_per_sample) as u32;
+    let block_align = channels * bytes_per_sample as u16;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&tag.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&(bit_depth as u16).to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+
+    let mut noise = NoiseSource::new(DITHER_SEED);
+    for &sample in samples {
+        let sample = if sample.is_finite() { sample.clamp(-1.0, 1.0) } else { 0.0 };
+        if tag == WAVE_FORMAT_IEEE_FLOAT {
+            wav.extend_from_slice(&sample.to_le_bytes());
+            continue;
+        }
+        let full_scale = (1i32 << (bit_depth - 1)) as f32;
+        let dither = (noise.next() + noise.next()) * 0.5;
+        let value = (sample * full_scale + dither).round().clamp(-full_scale, full_scale - 1.0) as i32;
+        wav.extend_from_slice(&value.to_le_bytes()[..bytes_per_sample]);
+    }
+    Some(wav)
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Encode a sound slot as a mono WAV file at the engine sample rate
+    ///
+    /// `bit_depth` is 16 or 24 (integer, dithered) or 32 (float). Returns
+    /// an empty array for an unloaded slot or an unsupported bit depth.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn encode_sound_wav(&self, sound_index: usize, bit_depth: u8) -> Vec<u8> {
+        let Some(samples) = self.loaded_samples(sound_index) else {
+            return Vec::new();
+        };
+        write_wav(samples, 1, self.sample_rate as u32, bit_depth).unwrap_or_default()
+    }
+
+    /// Encode interleaved audio, such as an offline render, as a WAV file
+    /// at the engine sample rate
+    ///
+    /// `bit_depth` is 16 or 24 (integer, dithered) or 32 (float). Returns
+    /// an empty array for an unsupported bit depth.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn encode_wav(&self, samples: &[f32], channels: u16, bit_depth: u8) -> Vec<u8> {
+        write_wav(samples, channels, self.sample_rate as u32, bit_depth).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode_wav;
+
+    #[test]
+    fn test_encoded_wav_decodes_back() {
+        let samples = [0.0, 0.5, -0.25, 1.0, -1.0, 0.125];
+        for bit_depth in [16, 24, 32] {
+            let audio = decode_wav(&write_wav(&samples, 2, 48000, bit_depth).unwrap()).unwrap();
+            assert_eq!((audio.channels, audio.sample_rate), (2, 48000));
+            let lsb = if bit_depth == 32 { 0.0 } else { 2.0 / (1u32 << (bit_depth - 1)) as f32 };
+            for (decoded, original) in audio.samples.iter().zip(samples) {
+                assert!((decoded - original).abs() <= lsb, "{bit_depth}-bit: {decoded} vs {original}");
+            }
+        }
+        assert!(write_wav(&samples, 1, 48000, 8).is_none());
+    }
+}
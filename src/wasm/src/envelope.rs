@@ -0,0 +1,153 @@
+//! Attack, decay and sustain
+//!
+//! A key's envelope shapes every voice it starts: the voice fades in over
+//! the attack time, falls to the sustain level over the decay time and
+//! holds there. The release stage (see `release`) fades out from whatever
+//! level the voice has reached. Single-shot voices play on after key-up,
+//! so theirs starts early enough to end with the sound instead of cutting
+//! it off. Keys default to no envelope: full level at once and no decay.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::history::ConfigChange;
+use crate::DspEngine;
+
+/// Longest attack or decay time in milliseconds
+const MAX_STAGE_MS: f32 = 10_000.0;
+
+/// An envelope stage's time clamped into 0-10000 ms
+fn stage_ms(ms: f32) -> f32 {
+    if ms.is_finite() { ms.clamp(0.0, MAX_STAGE_MS) } else { 0.0 }
+}
+
+/// A key's attack and decay times and sustain level
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct Envelope {
+    pub(crate) attack_ms: f32,
+    pub(crate) decay_ms: f32,
+    /// Level held after the decay (0.0 to 1.0)
+    pub(crate) sustain: f32,
+}
+
+impl Envelope {
+    pub(crate) const NONE: Self = Self { attack_ms: 0.0, decay_ms: 0.0, sustain: 1.0 };
+
+    /// Clamp times into 0-10000 ms and the sustain level into 0.0-1.0
+    pub(crate) fn clamped(attack_ms: f32, decay_ms: f32, sustain: f32) -> Self {
+        let sustain = if sustain.is_finite() { sustain.clamp(0.0, 1.0) } else { 1.0 };
+        Self { attack_ms: stage_ms(attack_ms), decay_ms: stage_ms(decay_ms), sustain }
+    }
+}
+
+/// A voice's progress through attack and decay
+#[derive(Clone, Copy)]
+pub(crate) struct EnvelopeState {
+    level: f32,
+    /// Level gained per sample while attacking (0 = attack over)
+    attack_step: f32,
+    /// Level lost per sample while decaying
+    decay_step: f32,
+    sustain: f32,
+}
+
+impl EnvelopeState {
+    /// Full level, no decay
+    pub(crate) const fn new() -> Self {
+        Self { level: 1.0, attack_step: 0.0, decay_step: 0.0, sustain: 1.0 }
+    }
+
+    pub(crate) fn start(envelope: &Envelope, sample_rate: f32) -> Self {
+        let samples = |ms: f32| ms * 0.001 * sample_rate;
+        let attack = samples(envelope.attack_ms);
+        let decay = samples(envelope.decay_ms).max(1.0);
+        Self {
+            level: if attack < 1.0 { 1.0 } else { 0.0 },
+            attack_step: if attack < 1.0 { 0.0 } else { 1.0 / attack },
+            decay_step: (1.0 - envelope.sustain) / decay,
+            sustain: envelope.sustain,
+        }
+    }
+
+    /// Level for the current sample, then advance to the next
+    #[inline(always)]
+    pub(crate) fn next(&mut self) -> f32 {
+        let level = self.level;
+        if self.attack_step > 0.0 {
+            self.level += self.attack_step;
+            if self.level >= 1.0 {
+                self.level = 1.0;
+                self.attack_step = 0.0;
+            }
+        } else if self.level > self.sustain {
+            self.level = (self.level - self.decay_step).max(self.sustain);
+        }
+        level
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set a key's envelope: attack, decay and release in milliseconds
+    /// (0-10000) and the sustain level (0.0-1.0)
+    ///
+    /// The release time is the one `set_key_release` sets.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_envelope(&mut self, key_code: u8, attack_ms: f32, decay_ms: f32, sustain: f32, release_ms: f32) {
+        self.record_change(ConfigChange::KeyMapping(key_code));
+        let mapping = &mut self.key_mappings[key_code as usize];
+        mapping.envelope = Envelope::clamped(attack_ms, decay_ms, sustain);
+        mapping.release_ms = stage_ms(release_ms);
+    }
+
+    /// Attack time of a key in milliseconds
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_attack(&self, key_code: u8) -> f32 {
+        self.key_mappings[key_code as usize].envelope.attack_ms
+    }
+
+    /// Decay time of a key in milliseconds
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_decay(&self, key_code: u8) -> f32 {
+        self.key_mappings[key_code as usize].envelope.decay_ms
+    }
+
+    /// Sustain level of a key
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_sustain(&self, key_code: u8) -> f32 {
+        self.key_mappings[key_code as usize].envelope.sustain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_envelope_shapes_single_shots() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.set_smoothing_time(0.0);
+        engine.load_sound(0, &[0.4; 200]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        // 10 ms attack, 20 ms decay to half level, 50 ms release
+        engine.set_key_envelope(65, 10.0, 20.0, 0.5, 50.0);
+        let envelope = (engine.get_key_attack(65), engine.get_key_decay(65), engine.get_key_sustain(65));
+        assert_eq!(envelope, (10.0, 20.0, 0.5));
+
+        engine.note_on(65);
+        engine.note_off(65);
+        let mut output = [0.0; 2 * 250];
+        engine.process(&mut output);
+        let frames: Vec<f32> = output.iter().step_by(2).copied().collect();
+        assert_eq!(frames[0], 0.0, "fades in");
+        assert!(frames[..10].windows(2).all(|pair| pair[1] > pair[0]));
+        assert!((frames[10] - 0.4).abs() < 1e-5);
+        assert!((frames[30] - 0.2).abs() < 1e-5 && frames[100] == frames[40], "sustains");
+
+        // The release ends with the sound instead of cutting it off
+        assert!(frames[150..200].windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(frames[199] > 0.0 && frames[199] < 0.01);
+        assert_eq!(frames[200], 0.0);
+        assert_eq!(engine.get_active_voice_count(), 0);
+    }
+}
@@ -0,0 +1,120 @@
+//! Errors reported to the host
+//!
+//! Calls that can't act on their input leave the engine as it was (or do
+//! what they can) instead of panicking, which used to leave the host
+//! debugging silence. Each rejection is now recorded as the engine's last
+//! error together with the value at fault, and the main loading and
+//! mapping calls also return the code directly. The debug log still gets
+//! its entries for the same failures.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::debug_log::LogCode;
+use crate::DspEngine;
+
+/// Why a call did nothing (or less than asked)
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The call succeeded
+    None = 0,
+    /// A sound slot index was out of range (arg = index)
+    BadSoundIndex = 1,
+    /// A key was mapped to a slot with no audio loaded yet (arg = index)
+    SoundNotLoaded = 2,
+    /// A key or trigger with no sound mapped was played (arg = trigger)
+    KeyNotMapped = 3,
+    /// An encoded file could not be decoded (arg = decoder error code)
+    DecodeFailed = 4,
+    /// Audio with no samples was loaded (arg = sound index)
+    EmptyAudio = 5,
+    /// Audio came with a sample rate that is not finite or outside
+    /// 1-384 kHz (arg = rate in Hz, saturated)
+    BadSampleRate = 6,
+}
+
+impl ErrorCode {
+    fn description(self) -> &'static str {
+        match self {
+            ErrorCode::None => "no error",
+            ErrorCode::BadSoundIndex => "sound index out of range",
+            ErrorCode::SoundNotLoaded => "key mapped to a sound slot with no audio",
+            ErrorCode::KeyNotMapped => "key played with no sound mapped",
+            ErrorCode::DecodeFailed => "audio file could not be decoded",
+            ErrorCode::EmptyAudio => "loaded audio has no samples",
+            ErrorCode::BadSampleRate => "source sample rate outside 1-384 kHz",
+        }
+    }
+}
+
+impl DspEngine {
+    /// Record a rejected call as the last error and return its code
+    #[inline]
+    pub(crate) fn report_error(&mut self, code: ErrorCode, arg: u32) -> ErrorCode {
+        self.last_error = (code, arg);
+        code
+    }
+
+    /// Log and report an out-of-range sound slot
+    pub(crate) fn bad_sound_index(&mut self, sound_index: usize) -> ErrorCode {
+        self.debug_log.push(LogCode::BadSoundIndex, sound_index as u32, self.global_sample_position);
+        self.report_error(ErrorCode::BadSoundIndex, sound_index as u32)
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Code of the most recent rejected call (`None` if there was none
+    /// since the last `clear_last_error`)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_last_error(&self) -> ErrorCode {
+        self.last_error.0
+    }
+
+    /// Value at fault in the most recent error (see `ErrorCode`)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_last_error_arg(&self) -> u32 {
+        self.last_error.1
+    }
+
+    /// Human-readable description of the most recent error
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_last_error_message(&self) -> String {
+        self.last_error.0.description().to_string()
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_last_error(&mut self) {
+        self.last_error = (ErrorCode::None, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_rejected_calls_report_why() {
+        let mut engine = DspEngine::new(48000.0);
+        assert_eq!(engine.get_last_error(), ErrorCode::None);
+        assert_eq!(engine.load_sound(0, &[0.5; 64]), ErrorCode::None);
+
+        assert_eq!(engine.load_sound(999, &[0.5; 64]), ErrorCode::BadSoundIndex);
+        assert_eq!((engine.get_last_error(), engine.get_last_error_arg()), (ErrorCode::BadSoundIndex, 999));
+        assert_eq!(engine.get_last_error_message(), "sound index out of range");
+
+        let mapped = engine.set_key_mapping(65, 3, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        assert_eq!(mapped, ErrorCode::SoundNotLoaded);
+        engine.note_on(66);
+        assert_eq!((engine.get_last_error(), engine.get_last_error_arg()), (ErrorCode::KeyNotMapped, 66));
+        assert_eq!(engine.load_sound(1, &[]), ErrorCode::EmptyAudio);
+
+        engine.clear_last_error();
+        assert_eq!(engine.get_last_error(), ErrorCode::None);
+        let mapped = engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        assert_eq!(mapped, ErrorCode::None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Engine → JS event queue
+//!
+//! A fixed-capacity ring buffer the audio path pushes notifications into.
+//! The host drains it periodically (e.g. once per animation frame) instead
+//! of polling individual getters.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Maximum number of undrained events kept by the queue
+const EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// Number of u32 values written per event by `drain_events`
+const EVENT_STRIDE: usize = 4;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum EngineEventKind {
+    /// A block took longer than its real-time budget (value = load in ‰)
+    Overload = 0,
+    /// note_on found no free voice (trigger = triggering key)
+    VoicePoolExhausted = 1,
+    /// A voice started playing (value = voice slot)
+    VoiceStarted = 2,
+    /// A voice reached the end of its sound (value = voice slot)
+    VoiceEnded = 3,
+    /// A voice was cut by a monophonic group (value = voice slot)
+    VoiceChoked = 4,
+    /// A voice was taken over by a new note (value = voice slot)
+    VoiceStolen = 5,
+    /// A voice was stopped by key release, panic or unload (value = voice slot)
+    VoiceStopped = 6,
+    /// Master recording began capturing at a bar (value = sound slot)
+    RecordingStarted = 7,
+    /// Master recording reached its stop bar or length limit (value = sound slot)
+    RecordingFinished = 8,
+    /// A scheduled punch started recording (value = punch target)
+    PunchIn = 9,
+    /// A scheduled punch stopped recording (value = punch target)
+    PunchOut = 10,
+    /// A clip started in its lane (trigger = key, value = clip slot)
+    ClipLaunched = 11,
+    /// A clip stopped in its lane (trigger = key, value = clip slot)
+    ClipStopped = 12,
+}
+
+#[derive(Clone, Copy)]
+struct EngineEvent {
+    kind: EngineEventKind,
+    /// Key code, extended trigger ID or MIDI note
+    trigger: u16,
+    value: u32,
+    /// Engine sample position when the event occurred (wrapping)
+    time: u32,
+}
+
+impl EngineEvent {
+    const fn new() -> Self {
+        Self {
+            kind: EngineEventKind::Overload,
+            trigger: 0,
+            value: 0,
+            time: 0,
+        }
+    }
+}
+
+/// Fixed-size FIFO; when full, new events are dropped and counted
+pub(crate) struct EventQueue {
+    events: [EngineEvent; EVENT_QUEUE_CAPACITY],
+    /// Index of the oldest event
+    head: usize,
+    /// Number of queued events
+    len: usize,
+    /// Events lost because the queue was full
+    dropped: u32,
+}
+
+impl EventQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: [const { EngineEvent::new() }; EVENT_QUEUE_CAPACITY],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Queue an event (real-time safe)
+    #[inline]
+    pub(crate) fn push(&mut self, kind: EngineEventKind, trigger: u16, value: u32, time: u64) {
+        if self.len == EVENT_QUEUE_CAPACITY {
+            self.dropped = self.dropped.saturating_add(1);
+            return;
+        }
+        let index = (self.head + self.len) % EVENT_QUEUE_CAPACITY;
+        self.events[index] = EngineEvent {
+            kind,
+            trigger,
+            value,
+            time: time as u32,
+        };
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<EngineEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head];
+        self.head = (self.head + 1) % EVENT_QUEUE_CAPACITY;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Move queued events into `out`, oldest first
+    ///
+    /// Each event occupies 4 consecutive values:
+    /// `[kind, trigger, value, time]` where `kind` is an `EngineEventKind`,
+    /// `trigger` the key code or extended trigger ID
+    /// and `time` is the (wrapping) engine sample position.
+    /// Returns the number of events written.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn drain_events(&mut self, out: &mut [u32]) -> u32 {
+        let mut written = 0;
+        for record in out.chunks_exact_mut(EVENT_STRIDE) {
+            let Some(event) = self.events.pop() else {
+                break;
+            };
+            record[0] = event.kind as u32;
+            record[1] = event.trigger as u32;
+            record[2] = event.value;
+            record[3] = event.time;
+            written += 1;
+        }
+        written
+    }
+
+    /// Number of events waiting to be drained
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_pending_event_count(&self) -> u32 {
+        self.events.len as u32
+    }
+
+    /// Number of events lost because the queue was full
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_dropped_event_count(&self) -> u32 {
+        self.events.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_drops_when_full() {
+        let mut queue = EventQueue::new();
+        for i in 0..EVENT_QUEUE_CAPACITY + 3 {
+            queue.push(EngineEventKind::VoicePoolExhausted, i as u16, 0, 0);
+        }
+        assert_eq!(queue.dropped, 3);
+        assert_eq!(queue.pop().unwrap().trigger, 0);
+        assert_eq!(queue.len, EVENT_QUEUE_CAPACITY - 1);
+    }
+
+    #[test]
+    fn test_voice_lifecycle_events() {
+        use crate::{OverlapMode, PlaybackMode, DEFAULT_MAX_VOICES};
+
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        engine.set_key_mapping(1, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        for _ in 0..=DEFAULT_MAX_VOICES {
+            engine.note_on(1);
+        }
+
+        let mut out = [0u32; (DEFAULT_MAX_VOICES + 3) * EVENT_STRIDE];
+        let count = engine.drain_events(&mut out) as usize;
+        let kinds: Vec<u32> = out[..count * EVENT_STRIDE].chunks(EVENT_STRIDE).map(|e| e[0]).collect();
+
+        assert_eq!(count, DEFAULT_MAX_VOICES + 3);
+        assert_eq!(kinds[DEFAULT_MAX_VOICES], EngineEventKind::VoicePoolExhausted as u32);
+        assert_eq!(kinds[DEFAULT_MAX_VOICES + 1], EngineEventKind::VoiceStolen as u32);
+        assert_eq!(out[(DEFAULT_MAX_VOICES + 1) * EVENT_STRIDE + 2], 0, "oldest voice (slot 0) is stolen");
+        assert_eq!(kinds[DEFAULT_MAX_VOICES + 2], EngineEventKind::VoiceStarted as u32);
+    }
+}
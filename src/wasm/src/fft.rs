@@ -0,0 +1,76 @@
+//! Minimal radix-2 FFT for offline analysis
+//!
+//! Only used outside the audio callback (load-time analysis, offline
+//! processing), so clarity wins over speed here.
+
+use std::f32::consts::PI;
+
+/// In-place complex FFT over separate real/imaginary buffers
+///
+/// Both slices must have the same power-of-two length.
+pub(crate) fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    // Butterflies
+    let mut size = 2;
+    while size <= n {
+        let angle = -2.0 * PI / size as f32;
+        let (w_im, w_re) = angle.sin_cos();
+        for start in (0..n).step_by(size) {
+            let (mut cur_re, mut cur_im) = (1.0_f32, 0.0_f32);
+            for k in 0..size / 2 {
+                let a = start + k;
+                let b = a + size / 2;
+                let t_re = re[b] * cur_re - im[b] * cur_im;
+                let t_im = re[b] * cur_im + im[b] * cur_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+                let next_re = cur_re * w_re - cur_im * w_im;
+                cur_im = cur_re * w_im + cur_im * w_re;
+                cur_re = next_re;
+            }
+        }
+        size <<= 1;
+    }
+}
+
+/// Hann window coefficient for index `i` of an `n`-point window
+#[inline]
+pub(crate) fn hann(i: usize, n: usize) -> f32 {
+    0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fft_finds_bin() {
+        let n = 64;
+        let mut re: Vec<f32> = (0..n).map(|i| (2.0 * PI * 5.0 * i as f32 / n as f32).cos()).collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+
+        let magnitude = |k: usize| (re[k] * re[k] + im[k] * im[k]).sqrt();
+        assert!((magnitude(5) - n as f32 / 2.0).abs() < 1e-3);
+        assert!(magnitude(6) < 1e-3);
+    }
+}
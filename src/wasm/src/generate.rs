@@ -0,0 +1,180 @@
+//! Pattern generators
+//!
+//! Quick ways to fill or vary the selected sequencer pattern without
+//! entering steps by hand. Random generators take a seed so a result can
+//! be repeated.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::sequencer::{Step, MAX_PATTERN_STEPS};
+use crate::synth::NoiseSource;
+use crate::DspEngine;
+
+/// Velocity of generated steps
+const GENERATED_VELOCITY: u8 = 100;
+
+/// Whether step `index` of an Euclidean rhythm is a pulse
+///
+/// Spreads `pulses` as evenly as possible over `steps` (Bresenham's line,
+/// which yields the same rhythms as Bjorklund's algorithm up to rotation)
+/// with the first pulse on step 0 before rotating.
+fn euclidean_pulse(index: u32, pulses: u32, steps: u32, rotation: u32) -> bool {
+    let index = (index + rotation) % steps;
+    index * pulses % steps < pulses
+}
+
+/// Uniform random number in 0.0..1.0
+fn unit(rng: &mut NoiseSource) -> f32 {
+    rng.next() * 0.5 + 0.5
+}
+
+/// Uniform random index below `n` (n > 0)
+fn below(rng: &mut NoiseSource, n: usize) -> usize {
+    ((unit(rng) * n as f32) as usize).min(n - 1)
+}
+
+impl DspEngine {
+    /// Replace a key's steps in the selected pattern with hits on `steps`
+    ///
+    /// Returns false (leaving the pattern as it was) if they don't fit.
+    fn replace_key_steps(&mut self, key_code: u8, steps: Vec<u8>) -> bool {
+        let pattern = self.sequencer.pattern_mut();
+        let others = pattern.steps.iter().filter(|s| s.key_code != key_code).count();
+        if others + steps.len() > MAX_PATTERN_STEPS {
+            return false;
+        }
+        pattern.steps.retain(|s| s.key_code != key_code);
+        for step in steps {
+            pattern.steps.push(Step::new(key_code, step, GENERATED_VELOCITY, 0, 1.0, 0.0));
+        }
+        self.sequencer.edited();
+        true
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Replace a key's steps in the selected pattern with an Euclidean
+    /// rhythm: `pulses` hits spread evenly over `steps` steps, rotated
+    /// `rotation` steps earlier and repeated to the end of the pattern
+    ///
+    /// Returns false (leaving the pattern as it was) if `steps` is 0,
+    /// `pulses` exceeds it or the pattern has no room.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn generate_euclidean(&mut self, key_code: u8, pulses: u8, steps: u8, rotation: u8) -> bool {
+        let (pulses, steps) = (pulses as u32, steps as u32);
+        if steps == 0 || pulses > steps {
+            return false;
+        }
+        let hits = (0..self.sequencer.pattern().length)
+            .filter(|&step| euclidean_pulse(step as u32, pulses, steps, rotation as u32))
+            .collect();
+        self.replace_key_steps(key_code, hits)
+    }
+
+    /// Replace a key's steps in the selected pattern with random hits, each
+    /// step of the pattern sounding with probability `density` (0-1)
+    ///
+    /// The same seed gives the same hits. Returns false if the pattern has
+    /// no room.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn randomize_pattern(&mut self, key_code: u8, density: f32, seed: u32) -> bool {
+        let density = if density.is_finite() { density.clamp(0.0, 1.0) } else { 0.0 };
+        let mut rng = NoiseSource::new(seed);
+        let hits = (0..self.sequencer.pattern().length).filter(|_| unit(&mut rng) < density).collect();
+        self.replace_key_steps(key_code, hits)
+    }
+
+    /// Vary the selected pattern slightly with `changes` random edits
+    ///
+    /// Each edit moves a step one step earlier or later, removes it, or
+    /// adds a hit for one of the pattern's keys on a free step. The result
+    /// replaces the pattern in one go. The same seed gives the same edits.
+    /// Returns false if the pattern has no steps to vary.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn mutate_pattern(&mut self, changes: u32, seed: u32) -> bool {
+        let pattern = self.sequencer.pattern();
+        if pattern.steps.is_empty() {
+            return false;
+        }
+        let length = pattern.length;
+        let mut steps = pattern.steps.clone();
+        let mut rng = NoiseSource::new(seed);
+        let is_free =
+            |steps: &[Step], key_code: u8, step: u8| !steps.iter().any(|s| s.key_code == key_code && s.step == step);
+        for _ in 0..changes {
+            if steps.is_empty() {
+                break;
+            }
+            let index = below(&mut rng, steps.len());
+            match below(&mut rng, 3) {
+                0 => {
+                    let Step { key_code, step, .. } = steps[index];
+                    let moved =
+                        if unit(&mut rng) < 0.5 { step.checked_sub(1).unwrap_or(length - 1) } else { (step + 1) % length };
+                    if is_free(&steps, key_code, moved) {
+                        steps[index].step = moved;
+                    }
+                }
+                1 => {
+                    steps.swap_remove(index);
+                }
+                _ => {
+                    let key_code = steps[index].key_code;
+                    let step = below(&mut rng, length as usize) as u8;
+                    if steps.len() < MAX_PATTERN_STEPS && is_free(&steps, key_code, step) {
+                        steps.push(Step::new(key_code, step, GENERATED_VELOCITY, 0, 1.0, 0.0));
+                    }
+                }
+            }
+        }
+        self.sequencer.pattern_mut().steps = steps;
+        self.sequencer.edited();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_euclidean_rhythms() {
+        let rhythm = |pulses, steps, rotation| -> String {
+            (0..steps).map(|i| if euclidean_pulse(i, pulses, steps, rotation) { 'x' } else { '.' }).collect()
+        };
+        assert_eq!(rhythm(3, 8, 0), "x..x..x.");
+        assert_eq!(rhythm(5, 8, 0), "x.x.xx.x");
+        assert_eq!(rhythm(3, 8, 1), "..x..x.x");
+        assert_eq!(rhythm(0, 4, 0), "....");
+
+        // A 3-in-8 tresillo repeated over a 16-step pattern
+        let mut engine = DspEngine::new(48000.0);
+        assert!(engine.set_step(66, 1, 127, 0, 1.0, 0.0));
+        assert!(engine.generate_euclidean(65, 3, 8, 0));
+        let steps: Vec<f32> = engine.get_pattern_packed().chunks(6).filter(|s| s[0] == 65.0).map(|s| s[1]).collect();
+        assert_eq!(steps, [0.0, 3.0, 6.0, 8.0, 11.0, 14.0]);
+        assert_eq!(engine.get_pattern_packed().len(), 7 * 6, "other keys are kept");
+        assert!(!engine.generate_euclidean(65, 9, 8, 0));
+    }
+
+    #[test]
+    fn test_randomize_and_mutate_repeat_per_seed() {
+        let mut engine = DspEngine::new(48000.0);
+        assert!(!engine.mutate_pattern(4, 1), "nothing to vary");
+        assert!(engine.randomize_pattern(65, 0.5, 7));
+        let random = engine.get_pattern_packed();
+        assert!((2..14).contains(&(random.len() / 6)));
+        assert!(engine.randomize_pattern(65, 0.5, 7));
+        assert_eq!(engine.get_pattern_packed(), random);
+
+        assert!(engine.mutate_pattern(3, 9));
+        let mutated = engine.get_pattern_packed();
+        assert_ne!(mutated, random);
+        assert!(mutated.chunks(6).all(|s| s[0] == 65.0 && s[1] < 16.0));
+        assert!(engine.randomize_pattern(65, 0.5, 7) && engine.mutate_pattern(3, 9));
+        assert_eq!(engine.get_pattern_packed(), mutated);
+        assert!(engine.randomize_pattern(65, 0.0, 7) && engine.get_pattern_packed().is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+//! Undo/redo for configuration changes
+//!
+//! Every key mapping and mixer setter records a snapshot of the
+//! configuration before it changes anything. Repeated changes to the same
+//! parameter (a volume slider being dragged) collapse into one step.
+//! Restored snapshots are applied at the start of the next `process()`
+//! block so a whole step lands at once.
+
+use std::collections::VecDeque;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::banks::KeyBank;
+use crate::cc::CcTarget;
+use crate::commands::Command;
+use crate::morph::Morph;
+use crate::{DspEngine, ModulationPreset};
+
+/// Most undo steps kept
+const MAX_HISTORY: usize = 32;
+
+/// Which setter produced a change (used to collapse repeated edits)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum ConfigChange {
+    KeyMapping(u8),
+    KeyMode(u8),
+    KeyModulation(u8),
+    KeyVolume(u8),
+    KeyPitch(u8),
+    KeyOverlap(u8),
+    KeySlice(u8),
+    MasterVolume,
+    GroupVolume(u8),
+    GroupMute(u8),
+    Metronome,
+    ModulationPreset,
+    /// Many values at once (imports); never collapsed
+    Bulk,
+}
+
+/// Everything undo/redo restores
+#[derive(Clone)]
+pub(crate) struct ConfigSnapshot {
+    /// Bank the mappings belong to
+    bank: usize,
+    key_mappings: KeyBank,
+    group_volumes: [f32; 256],
+    group_mutes: [bool; 256],
+    master_volume: f32,
+    metronome_enabled: bool,
+    metronome_volume: f32,
+    modulation_preset: ModulationPreset,
+}
+
+impl ConfigSnapshot {
+    /// Mirror of `DspEngine::set_parameter`
+    fn set_parameter(&mut self, target: CcTarget, index: u8, value: f32, morph: &Morph) {
+        let index = index as usize;
+        match target {
+            CcTarget::MasterVolume => self.master_volume = value.clamp(0.0, 1.0),
+            CcTarget::MetronomeVolume => self.metronome_volume = value.clamp(0.0, 1.0),
+            CcTarget::GroupVolume => self.group_volumes[index] = value.clamp(0.0, 1.0),
+            CcTarget::KeyVolume => self.key_mappings[index].volume = value.clamp(0.0, 1.0),
+            CcTarget::KeyPitch => self.key_mappings[index].pitch_semitones = value.round().clamp(-24.0, 24.0) as i8,
+            CcTarget::Morph => {
+                if let Some(blend) = morph.blend(value) {
+                    for (mapping, volume) in self.key_mappings.iter_mut().zip(blend.key_volumes) {
+                        mapping.volume = volume;
+                    }
+                    self.master_volume = blend.master_volume;
+                    self.metronome_volume = blend.metronome_volume;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct ConfigHistory {
+    undo: VecDeque<ConfigSnapshot>,
+    redo: Vec<ConfigSnapshot>,
+    last_change: Option<ConfigChange>,
+    /// Snapshot waiting for the next block boundary
+    pending: Option<ConfigSnapshot>,
+}
+
+impl ConfigHistory {
+    pub(crate) fn new() -> Self {
+        Self { undo: VecDeque::new(), redo: Vec::new(), last_change: None, pending: None }
+    }
+
+    /// Heap bytes held by stored steps
+    pub(crate) fn heap_bytes(&self) -> usize {
+        (self.undo.capacity() + self.redo.capacity()) * std::mem::size_of::<ConfigSnapshot>()
+    }
+}
+
+impl DspEngine {
+    fn config_snapshot(&self) -> ConfigSnapshot {
+        ConfigSnapshot {
+            bank: self.active_bank,
+            key_mappings: self.key_mappings,
+            group_volumes: self.mixer.volumes,
+            group_mutes: self.mixer.mutes,
+            master_volume: self.master_volume,
+            metronome_enabled: self.metronome_enabled,
+            metronome_volume: self.metronome_volume,
+            modulation_preset: self.modulation_preset,
+        }
+    }
+
+    /// Remember the configuration before a setter changes it
+    pub(crate) fn record_change(&mut self, change: ConfigChange) {
+        // Setters act on the configuration the user sees, including a pending
+        // undo, bank switch, bulk mapping or queued command
+        self.apply_pending_config();
+        self.apply_pending_bank();
+        self.apply_pending_mappings();
+        self.apply_commands();
+        self.push_undo_step(change, Self::config_snapshot);
+    }
+
+    /// Remember the configuration before a setter queues a command
+    ///
+    /// Commands already waiting stay queued for the block; the step holds
+    /// the configuration as they will leave it.
+    pub(crate) fn record_queued_change(&mut self, change: ConfigChange) {
+        self.apply_pending_config();
+        self.apply_pending_bank();
+        self.apply_pending_mappings();
+        self.push_undo_step(change, Self::queued_config_snapshot);
+    }
+
+    fn push_undo_step(&mut self, change: ConfigChange, snapshot: fn(&Self) -> ConfigSnapshot) {
+        let history = &mut self.history;
+        history.redo.clear();
+        if change != ConfigChange::Bulk && history.last_change == Some(change) {
+            return;
+        }
+        history.last_change = Some(change);
+        if history.undo.len() == MAX_HISTORY {
+            history.undo.pop_front();
+        }
+        let snapshot = snapshot(self);
+        self.history.undo.push_back(snapshot);
+    }
+
+    /// Configuration once the queued commands land
+    fn queued_config_snapshot(&self) -> ConfigSnapshot {
+        let mut snapshot = self.config_snapshot();
+        for command in self.commands.iter() {
+            let Command::Parameter { target, index, value } = command;
+            snapshot.set_parameter(target, index, value, &self.morph);
+        }
+        snapshot
+    }
+
+    /// Install a restored snapshot (called at the start of each block)
+    #[inline]
+    pub(crate) fn apply_pending_config(&mut self) {
+        let Some(snapshot) = self.history.pending.take() else {
+            return;
+        };
+        // The step may belong to a bank that has since been switched away
+        *self.bank_mut(snapshot.bank) = snapshot.key_mappings;
+        self.mixer.volumes = snapshot.group_volumes;
+        self.mixer.mutes = snapshot.group_mutes;
+        self.master_volume = snapshot.master_volume;
+        self.metronome_enabled = snapshot.metronome_enabled;
+        self.metronome_volume = snapshot.metronome_volume;
+        self.modulation_preset = snapshot.modulation_preset;
+    }
+
+    /// Configuration as it will be after any pending step lands
+    ///
+    /// When the pending step belongs to another bank, that bank is what the
+    /// step replaces.
+    fn effective_config(&self, bank: usize) -> ConfigSnapshot {
+        match &self.history.pending {
+            Some(pending) if pending.bank == bank => pending.clone(),
+            _ => ConfigSnapshot { bank, key_mappings: *self.bank(bank), ..self.config_snapshot() },
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Revert the last configuration change at the next block boundary
+    ///
+    /// Returns false if there is nothing to undo.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn undo(&mut self) -> bool {
+        // Pending bulk mappings and queued commands belong to the step being
+        // undone or redone over
+        self.apply_pending_mappings();
+        self.apply_commands();
+        let Some(snapshot) = self.history.undo.pop_back() else {
+            return false;
+        };
+        let current = self.effective_config(snapshot.bank);
+        self.history.redo.push(current);
+        self.history.pending = Some(snapshot);
+        self.history.last_change = None;
+        true
+    }
+
+    /// Re-apply the last undone change at the next block boundary
+    ///
+    /// Returns false if there is nothing to redo.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn redo(&mut self) -> bool {
+        // Pending bulk mappings and queued commands belong to the step being
+        // undone or redone over
+        self.apply_pending_mappings();
+        self.apply_commands();
+        let Some(snapshot) = self.history.redo.pop() else {
+            return false;
+        };
+        let current = self.effective_config(snapshot.bank);
+        self.history.undo.push_back(current);
+        self.history.pending = Some(snapshot);
+        self.history.last_change = None;
+        true
+    }
+
+    /// Number of steps `undo` can revert
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_undo_depth(&self) -> u32 {
+        self.history.undo.len() as u32
+    }
+
+    /// Number of steps `redo` can re-apply
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_redo_depth(&self) -> u32 {
+        self.history.redo.len() as u32
+    }
+
+    /// Forget all undo/redo steps
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_history(&mut self) {
+        self.apply_pending_config();
+        self.history = ConfigHistory::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_undo_redo_at_block_boundary() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 64]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        // A dragged slider is a single step
+        for volume in [0.9, 0.7, 0.5] {
+            engine.set_key_volume(65, volume);
+        }
+        engine.set_master_volume(0.3);
+        assert_eq!(engine.get_undo_depth(), 3);
+
+        assert!(engine.undo());
+        assert_eq!(engine.master_volume, 0.3, "applied at the next block");
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.master_volume, 1.0);
+
+        assert!(engine.undo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.key_mappings[65].volume, 1.0);
+
+        assert!(engine.redo() && engine.redo());
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.key_mappings[65].volume, engine.master_volume), (0.5, 0.3));
+        assert!(!engine.redo());
+
+        // Undoing the first mapping leaves the key unmapped again
+        while engine.undo() {}
+        engine.process(&mut [0.0; 2]);
+        assert!(!engine.key_mappings[65].has_sound);
+    }
+}
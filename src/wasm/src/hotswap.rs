@@ -0,0 +1,241 @@
+//! Glitch-free sound replacement
+//!
+//! New audio is prepared in a shadow `Sound` outside the audio callback and
+//! swapped into its slot at the start of the next `process()` block. Voices
+//! playing the slot can crossfade from the old audio to the new, which stays
+//! alive in a retired list until no voice reads from it anymore. Loading,
+//! editing or unloading the slot before the swap lands cancels it.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::{DspEngine, PlaybackMode, Sound, Voice};
+
+/// Fade applied to voices whose sound is unloaded or replaced (seconds)
+const RETIRE_FADE_SECONDS: f32 = 0.005;
+
+/// A prepared sound waiting to be swapped in at the next block
+pub(crate) struct SoundSwap {
+    sound_index: usize,
+    sound: Sound,
+}
+
+impl Voice {
+    /// Mix the left and right levels `new` with the retired audio this
+    /// voice is crossfading from
+    ///
+    /// Advances the crossfade by one sample.
+    #[inline]
+    pub(crate) fn blend_swap(&mut self, new: (f32, f32), old: &Sound) -> (f32, f32) {
+        let (_, region_length) = self.region(old);
+        let in_region = (self.position as usize) < region_length;
+        let (old_left, old_right) = if in_region { self.frame_at(old) } else { (0.0, 0.0) };
+        let weight = self.swap_fade_remaining as f32 / self.swap_fade_length as f32;
+        self.swap_fade_remaining -= 1;
+        let blend = |old_level: f32, new_level: f32| old_level * old.gain * weight + new_level * (1.0 - weight);
+        (blend(old_left, new.0), blend(old_right, new.1))
+    }
+
+    /// Next left and right samples of retired audio fading to silence
+    ///
+    /// Advances the position and the fade by one sample.
+    #[inline]
+    pub(crate) fn fade_out_retired(&mut self, old: &Sound) -> (f32, f32) {
+        let (_, region_length) = self.region(old);
+        if self.mode == PlaybackMode::Loop && region_length > 0 && self.position as usize >= region_length {
+            self.position -= region_length as f64;
+        }
+        let levels = self.blend_swap((0.0, 0.0), old);
+        self.position += self.pitch as f64;
+        levels
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Replace a sound without clicks
+    ///
+    /// The audio goes through the normal load pipeline into a shadow buffer
+    /// and is swapped in at the next block boundary. Voices already playing
+    /// the slot crossfade to the new audio (see `set_swap_crossfade`).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stage_sound(&mut self, sound_index: usize, samples: &[f32]) {
+        if sound_index >= self.sounds.len() {
+            self.bad_sound_index(sound_index);
+            return;
+        }
+        self.release_retired_sounds();
+
+        // Run the shared load path on a spare Sound, then move it aside
+        let current = std::mem::replace(&mut self.sounds[sound_index], Sound::new());
+        self.store_sound(sound_index, samples, self.sample_rate);
+        let mut staged = std::mem::replace(&mut self.sounds[sound_index], current);
+        staged.metadata = self.sounds[sound_index].metadata.clone();
+
+        self.pending_swaps.push(SoundSwap { sound_index, sound: staged });
+        // The swap in process() must not allocate
+        self.retired_sounds.reserve(self.pending_swaps.len());
+    }
+
+    /// Set the crossfade used when a staged sound replaces one that is playing
+    ///
+    /// 0 swaps instantly; voices then continue on the new audio.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_swap_crossfade(&mut self, ms: f32) {
+        self.swap_crossfade_samples = (ms.clamp(0.0, 500.0) * 0.001 * self.sample_rate) as u32;
+    }
+}
+
+impl DspEngine {
+    /// Swap staged sounds into their slots (called at the start of each block)
+    #[inline]
+    pub(crate) fn apply_pending_swaps(&mut self) {
+        for swap in self.pending_swaps.drain(..) {
+            let old = std::mem::replace(&mut self.sounds[swap.sound_index], swap.sound);
+            self.audio_dirty[swap.sound_index] = true;
+            let retired_index = self.retired_sounds.len();
+            for voice in self.voices.iter_mut() {
+                if voice.active && voice.sound_index == swap.sound_index && self.swap_crossfade_samples > 0 {
+                    voice.swap_source = retired_index;
+                    voice.swap_fade_length = self.swap_crossfade_samples;
+                    voice.swap_fade_remaining = self.swap_crossfade_samples;
+                }
+            }
+            // Capacity was reserved by stage_sound, so this never allocates
+            self.retired_sounds.push(old);
+        }
+    }
+
+    /// Make way for new audio in a slot (every path that replaces, edits or
+    /// clears a slot's audio outside a swap calls this first)
+    ///
+    /// Swaps still staged for the slot are dropped, so older staged audio
+    /// cannot land on top at the next block, and voices playing it fade out
+    /// from a retired copy.
+    pub(crate) fn vacate_slot(&mut self, sound_index: usize) {
+        self.pending_swaps.retain(|swap| swap.sound_index != sound_index);
+        self.retire_playing_sound(sound_index);
+    }
+
+    /// Move a slot's audio to the retired list if voices are playing it
+    ///
+    /// Those voices fade out over a few milliseconds from the retired copy,
+    /// leaving the slot free to be cleared or overwritten without a click.
+    fn retire_playing_sound(&mut self, sound_index: usize) {
+        let playing = |voice: &Voice| voice.active && voice.sound_index == sound_index && !voice.swap_fade_out;
+        if !self.sounds[sound_index].loaded || !self.voices.iter().any(playing) {
+            return;
+        }
+        self.release_retired_sounds();
+
+        let fade = ((RETIRE_FADE_SECONDS * self.sample_rate) as u32).max(1);
+        let retired_index = self.retired_sounds.len();
+        for voice in self.voices.iter_mut().filter(|voice| playing(voice)) {
+            voice.swap_source = retired_index;
+            voice.swap_fade_length = fade;
+            voice.swap_fade_remaining = fade;
+            voice.swap_fade_out = true;
+        }
+        let old = std::mem::replace(&mut self.sounds[sound_index], Sound::new());
+        self.sounds[sound_index].metadata = old.metadata.clone();
+        self.retired_sounds.push(old);
+        // Keep room for staged swaps that still have to land in process()
+        self.retired_sounds.reserve(self.pending_swaps.len());
+    }
+
+    /// Free replaced audio once no voice is crossfading from it
+    fn release_retired_sounds(&mut self) {
+        if self.voices.iter().all(|voice| !voice.active || voice.swap_fade_remaining == 0) {
+            self.retired_sounds.clear();
+        }
+    }
+
+    /// Heap bytes held by staged and retired sounds
+    pub(crate) fn swap_heap_bytes(&self) -> usize {
+        self.pending_swaps.iter().map(|swap| swap.sound.heap_bytes()).sum::<usize>()
+            + self.retired_sounds.iter().map(Sound::heap_bytes).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_staged_swap_crossfades() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_swap_crossfade(10.0);
+        engine.note_on(65);
+
+        let mut output = [0.0; 2 * 10];
+        engine.process(&mut output);
+        engine.stage_sound(0, &[-0.5; 1000]);
+        assert_eq!(engine.get_sound_peak(0), 0.5, "old audio plays until the block boundary");
+
+        let mut output = [0.0; 2 * 20];
+        engine.process(&mut output);
+        let left: Vec<f32> = output.iter().step_by(2).copied().collect();
+        // Moves from +0.5 to -0.5 over 10 samples without jumping
+        assert!(left.windows(2).all(|w| (w[1] - w[0]).abs() < 0.15));
+        assert!(left[0] > 0.3 && left[19] < -0.3);
+    }
+
+    #[test]
+    fn test_later_load_wins_over_staged_swap() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 100]);
+        engine.stage_sound(0, &[0.25; 100]);
+        engine.load_sound(0, &[-0.5; 100]);
+        engine.stage_sound(1, &[0.75; 100]);
+        engine.unload_sound(1);
+
+        engine.process(&mut [0.0; 2 * 10]);
+        assert_eq!(engine.loaded_samples(0), Some(&[-0.5; 100][..]));
+        assert_eq!(engine.get_sound_length_samples(1), 0, "unloaded slots stay empty");
+    }
+
+    #[test]
+    fn test_derived_and_edited_audio_wins_over_staged_swap() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(1, &[0.25, 0.5]);
+        engine.load_sound(2, &[0.5; 100]);
+        engine.stage_sound(2, &[0.75; 100]);
+        assert!(engine.create_reversed_copy(1, 2));
+        engine.stage_sound(1, &[0.75; 100]);
+        assert!(engine.trim_sound(1, 0, 1));
+        engine.process(&mut [0.0; 2 * 10]);
+        assert_eq!(engine.loaded_samples(2), Some(&[0.5, 0.25][..]));
+        assert_eq!(engine.loaded_samples(1), Some(&[0.25][..]));
+
+        // Voices playing an edited sound fade out instead of jumping
+        engine.load_sound(3, &[0.25; 1000]);
+        engine.set_key_mapping(65, 3, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 10]);
+        assert!(engine.apply_sound_gain(3, 6.0206));
+        let mut output = [0.0; 2 * 10];
+        engine.process(&mut output);
+        assert!(output[0] > 0.2 && output[0] <= 0.25, "no jump to the louder audio");
+        assert_eq!(output[2 * 9], 0.0);
+    }
+
+    #[test]
+    fn test_unload_fades_playing_voices() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.note_on(65);
+
+        let mut output = [0.0; 2 * 10];
+        engine.process(&mut output);
+        engine.unload_sound(0);
+        assert_eq!(engine.get_sound_length_samples(0), 0, "slot is free immediately");
+
+        engine.process(&mut output);
+        assert!(output[0] > 0.3, "no jump to silence");
+        assert_eq!(output[2 * 9], 0.0);
+        assert!(!engine.is_key_playing(65));
+    }
+}
@@ -0,0 +1,104 @@
+//! Round-trip latency measurement
+//!
+//! The engine emits a single impulse on request. The host records its
+//! input starting at the same block, hands the recording back, and the
+//! engine finds how many samples later the impulse arrived.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Amplitude of the test impulse (written after master volume and clipping)
+const LATENCY_IMPULSE: f32 = 0.9;
+
+/// Recorded peaks quieter than this are treated as "impulse not heard"
+const LATENCY_NOISE_FLOOR: f32 = 0.01;
+
+pub(crate) struct LatencyProbe {
+    /// An impulse is waiting to be written by the next `process()` call
+    pending: bool,
+    /// Last measured round-trip delay in samples (-1 = none)
+    measured: i32,
+}
+
+impl LatencyProbe {
+    pub(crate) const fn new() -> Self {
+        Self {
+            pending: false,
+            measured: -1,
+        }
+    }
+
+    /// Write the impulse into the first frame of `output` if one is armed
+    #[inline]
+    pub(crate) fn emit(&mut self, output: &mut [f32]) {
+        if self.pending && output.len() >= 2 {
+            output[0] = LATENCY_IMPULSE;
+            output[1] = LATENCY_IMPULSE;
+            self.pending = false;
+        }
+    }
+}
+
+/// Find the arrival of the impulse in a recording
+///
+/// Returns the index of the first sample reaching half the recording's
+/// peak, or `None` if nothing rose above the noise floor.
+pub(crate) fn find_impulse(recorded: &[f32]) -> Option<usize> {
+    let peak = recorded.iter().fold(0.0_f32, |m, s| m.max(s.abs()));
+    if peak < LATENCY_NOISE_FLOOR {
+        return None;
+    }
+    recorded.iter().position(|s| s.abs() >= peak * 0.5)
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Emit a test impulse at the start of the next processed block
+    ///
+    /// Start recording the input from that same block, then pass the
+    /// recording to `measure_latency`.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn start_latency_test(&mut self) {
+        self.latency.pending = true;
+    }
+
+    /// Compute the round-trip delay from a recording aligned to the impulse
+    ///
+    /// Returns the delay in samples, or -1 if the impulse was not found.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn measure_latency(&mut self, recorded: &[f32]) -> i32 {
+        self.latency.measured = find_impulse(recorded).map_or(-1, |index| index as i32);
+        self.latency.measured
+    }
+
+    /// Last measured round-trip delay in samples (-1 if never measured)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_measured_latency_samples(&self) -> i32 {
+        self.latency.measured
+    }
+
+    /// Last measured round-trip delay in milliseconds (negative if unknown)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_measured_latency_ms(&self) -> f32 {
+        if self.latency.measured < 0 {
+            return -1.0;
+        }
+        self.latency.measured as f32 * 1000.0 / self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_impulse() {
+        let mut recorded = vec![0.002_f32; 1024];
+        recorded[300] = 0.4;
+        recorded[301] = -0.3;
+        assert_eq!(find_impulse(&recorded), Some(300));
+        assert_eq!(find_impulse(&[0.001; 64]), None);
+    }
+}
@@ -3,29 +3,140 @@
 //! Zero-allocation real-time audio processing for launchpad application.
 //! All audio processing happens here, never in JavaScript.
 
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
+mod aftertouch;
+mod analysis;
+mod bandlimit;
+mod banks;
+mod cc;
+mod clips;
+mod commands;
+mod config;
+mod control;
+mod debug_log;
+mod decode;
+mod devices;
+mod edit;
+mod encode;
+mod envelope;
+mod errors;
+mod events;
+mod fft;
+mod generate;
+mod history;
+mod hotswap;
+mod interpolation;
+mod latency;
+mod looper;
+mod metadata;
+mod mix;
+mod mixer;
+mod midi;
+mod midi_file;
+mod morph;
+#[cfg(feature = "native")]
+pub mod native;
+mod offline;
+mod pads;
+mod performance;
+mod preprocess;
+mod punch;
+mod recorder;
+mod release;
+mod resample;
+mod scope;
+mod sequencer;
+mod simd;
+mod slicing;
+mod smoothing;
+mod state;
+mod stereo;
+mod stretch;
+mod synth;
+mod triggers;
+mod telemetry;
+mod timed;
+mod upload;
+mod velocity;
+
+pub use cc::{CcCurve, CcTarget};
+pub use clips::ClipKind;
+pub use config::DspEngineConfig;
+pub use control::{ControlBlock, CONTROL_SLOTS};
+pub use debug_log::LogCode;
+pub use errors::ErrorCode;
+pub use events::EngineEventKind;
+pub use interpolation::InterpolationQuality;
+pub use looper::LooperState;
+pub use metadata::SoundCategory;
+pub use punch::PunchTarget;
+pub use recorder::RecorderState;
+pub use resample::ResampleQuality;
+pub use sequencer::RecordQuantize;
+pub use state::{StateImportResult, StateMigration, StatePart};
+pub use synth::{DrumKind, NoiseColor};
+pub use telemetry::MemoryStats;
+pub use velocity::VelocityCurve;
+use aftertouch::Aftertouch;
+use bandlimit::BandLimitKernel;
+use banks::KeyBank;
+use cc::CcMap;
+use clips::ClipLauncher;
+use commands::{Command, CommandQueue};
+use control::ControlPlane;
+use debug_log::DebugLog;
+use devices::InputDevices;
+use envelope::{Envelope, EnvelopeState};
+use events::EventQueue;
+use history::{ConfigChange, ConfigHistory};
+use hotswap::SoundSwap;
+use interpolation::SincTable;
+use latency::LatencyProbe;
+use looper::InputLooper;
+use metadata::SoundMetadata;
+use mixer::GroupMixer;
+use midi::MidiInput;
+use midi_file::MidiSequence;
+use morph::Morph;
+use pads::PadStyle;
+use performance::{Performed, PerformanceRecorder};
+use preprocess::LoadOptions;
+use punch::Punch;
+use recorder::MasterRecorder;
+use scope::Scope;
+use sequencer::Sequencer;
+use smoothing::Smoothing;
+use telemetry::{CpuMeter, HealthCounters, VoiceStats};
+use timed::TimedInput;
+use upload::SoundUpload;
+use velocity::VelocityResponse;
+
 // ============================================================================
 // CONSTANTS - Fixed at compile time for zero runtime overhead
 // ============================================================================
 
-/// Maximum number of simultaneous voices (keys that can play at once)
-const MAX_VOICES: usize = 64;
+/// Default number of simultaneous voices (keys that can play at once)
+const DEFAULT_MAX_VOICES: usize = 64;
+
+/// Default maximum sample length per sound in seconds
+const DEFAULT_MAX_SAMPLE_SECONDS: f32 = 10.0;
 
-/// Maximum sample length per sound (10 seconds at 48kHz)
-const MAX_SAMPLE_LENGTH: usize = 480000;
+/// Default number of sounds that can be loaded
+const DEFAULT_MAX_SOUNDS: usize = 64;
 
-/// Maximum number of sounds that can be loaded
-const MAX_SOUNDS: usize = 64;
+/// Default number of key mapping banks
+const DEFAULT_KEY_BANKS: usize = 4;
 
-/// Audio processing block size (matches AudioWorklet quantum)
-const BLOCK_SIZE: usize = 128;
+/// Number of f32 values written per voice by `get_voice_states`
+const VOICE_STATE_STRIDE: usize = 5;
 
 // ============================================================================
 // PLAYBACK MODES
 // ============================================================================
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum PlaybackMode {
@@ -35,7 +146,7 @@ pub enum PlaybackMode {
     Loop = 1,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum OverlapMode {
@@ -49,6 +160,21 @@ pub enum OverlapMode {
 // VOICE - Represents a single playing sound instance
 // ============================================================================
 
+/// Input that started a voice, used to match its release
+#[derive(Clone, Copy, PartialEq)]
+enum VoiceSource {
+    /// The key code / trigger ID API
+    Key,
+    /// A key on another input device
+    Device(u8),
+    /// A MIDI note as (channel, note)
+    Midi(u8, u8),
+    /// A sequencer step
+    Sequencer,
+    /// A loop clip launched in a key's lane
+    Clip,
+}
+
 #[derive(Clone, Copy)]
 struct Voice {
     /// Index into sounds array
@@ -65,10 +191,45 @@ struct Voice {
     mode: PlaybackMode,
     /// Overlap group ID (voices in same group interact based on OverlapMode)
     group_id: u8,
-    /// Key code that triggered this voice (for release detection)
-    key_code: u8,
+    /// Key code or extended trigger ID that started this voice (for
+    /// release detection); the note number for MIDI voices
+    trigger: u16,
+    /// Input that triggered this voice
+    source: VoiceSource,
     /// Whether modulation is applied to this voice
     modulation_enabled: bool,
+    /// Monotonic trigger serial (higher = started more recently)
+    serial: u64,
+    /// First sample of the played region (0 for the whole sound)
+    region_start: usize,
+    /// End of the played region (clamped to the sound length)
+    region_end: usize,
+    /// Index into the retired sounds this voice is crossfading from
+    swap_source: usize,
+    /// Length of the current swap crossfade in samples
+    swap_fade_length: u32,
+    /// Samples left in the swap crossfade (0 = not crossfading)
+    swap_fade_remaining: u32,
+    /// Fade the retired audio to silence instead of crossfading to the slot's new audio
+    swap_fade_out: bool,
+    /// Gain from per-note pressure (1.0 = none)
+    pressure_gain: f32,
+    /// Playback rate multiplier from per-note pitch bend (1.0 = none)
+    note_bend: f32,
+    /// Semitones of aftertouch vibrato at full pressure
+    aftertouch_depth: f32,
+    /// Playback rate multiplier from aftertouch vibrato (1.0 = none)
+    vibrato: f32,
+    /// Release time of the voice's key in milliseconds
+    release_ms: f32,
+    /// Gain of the release stage (1.0 until released)
+    release_gain: f32,
+    /// Gain lost per sample while releasing (0 = held)
+    release_step: f32,
+    /// Smoothed volume times group channel gain
+    gain: f32,
+    /// Attack and decay progress
+    envelope: EnvelopeState,
 }
 
 impl Voice {
@@ -81,33 +242,201 @@ impl Voice {
             pitch: 1.0,
             mode: PlaybackMode::SingleShot,
             group_id: 0,
-            key_code: 0,
+            trigger: 0,
+            source: VoiceSource::Key,
             modulation_enabled: false,
+            serial: 0,
+            region_start: 0,
+            region_end: usize::MAX,
+            swap_source: 0,
+            swap_fade_length: 0,
+            swap_fade_remaining: 0,
+            swap_fade_out: false,
+            pressure_gain: 1.0,
+            note_bend: 1.0,
+            aftertouch_depth: 0.0,
+            vibrato: 1.0,
+            release_ms: 0.0,
+            release_gain: 1.0,
+            release_step: 0.0,
+            gain: smoothing::UNSET,
+            envelope: EnvelopeState::new(),
+        }
+    }
+
+    /// Start playing `mapping` from the beginning of `region`
+    fn start(&mut self, mapping: &KeyMapping, trigger: u16, region: (usize, usize), sample_rate: f32) {
+        // Convert semitones to pitch multiplier: 2^(semitones/12)
+        let pitch = 2.0_f32.powf(mapping.pitch_semitones as f32 / 12.0);
+
+        self.sound_index = mapping.sound_index;
+        self.position = 0.0;
+        self.active = true;
+        self.volume = mapping.volume;
+        self.pitch = pitch;
+        self.mode = mapping.mode;
+        self.group_id = mapping.group_id;
+        self.trigger = trigger;
+        self.source = VoiceSource::Key;
+        self.modulation_enabled = mapping.modulation_enabled;
+        (self.region_start, self.region_end) = region;
+        self.swap_fade_remaining = 0;
+        self.swap_fade_out = false;
+        self.pressure_gain = 1.0;
+        self.note_bend = 1.0;
+        self.aftertouch_depth = mapping.aftertouch_depth;
+        self.vibrato = 1.0;
+        self.release_ms = mapping.release_ms;
+        self.release_gain = 1.0;
+        self.release_step = 0.0;
+        self.gain = smoothing::UNSET;
+        self.envelope = EnvelopeState::start(&mapping.envelope, sample_rate);
+    }
+
+    /// Whether this voice was started by key or extended trigger `trigger`
+    #[inline]
+    fn is_trigger(&self, trigger: u16) -> bool {
+        self.trigger == trigger && self.source == VoiceSource::Key
+    }
+
+    /// Left and right samples of `sound` at the current position (linear
+    /// interpolation); both are the same for a mono sound
+    ///
+    /// The position must lie inside the voice's region.
+    #[inline]
+    fn frame_at(&self, sound: &Sound) -> (f32, f32) {
+        let left = self.channel_at(sound, &sound.samples);
+        if sound.is_stereo() {
+            (left, self.channel_at(sound, &sound.right))
+        } else {
+            (left, left)
         }
     }
+
+    /// Sample of one of `sound`'s channels at the current position
+    #[inline]
+    fn channel_at(&self, sound: &Sound, channel: &[f32]) -> f32 {
+        let (region_start, region_length) = self.region(sound);
+        let pos_floor = self.position as usize;
+        let pos_frac = self.position - pos_floor as f64;
+
+        let s1 = channel[region_start + pos_floor];
+        let s2 = if pos_floor + 1 < region_length {
+            channel[region_start + pos_floor + 1]
+        } else {
+            s1
+        };
+        s1 + (s2 - s1) * pos_frac as f32
+    }
+
+    /// Start and length of the region this voice plays within `sound`
+    #[inline]
+    fn region(&self, sound: &Sound) -> (usize, usize) {
+        let end = self.region_end.min(sound.length);
+        let start = self.region_start.min(end);
+        (start, end - start)
+    }
 }
 
 // ============================================================================
 // SOUND - Pre-loaded audio data
 // ============================================================================
 
+#[derive(Clone)]
 struct Sound {
-    /// Mono audio samples (interleaved stereo converted to mono on load)
-    samples: [f32; MAX_SAMPLE_LENGTH],
+    /// Mono audio samples (interleaved stereo converted to mono on load),
+    /// or the left channel of a stereo sound, allocated to the sound's exact
+    /// length at load time
+    samples: Vec<f32>,
+    /// Right channel of a stereo sound, as long as `samples` (empty if mono)
+    right: Vec<f32>,
     /// Actual length of audio data
     length: usize,
     /// Whether this slot contains valid audio
     loaded: bool,
+    /// Detected transient positions in samples (empty unless analyzed)
+    onsets: Vec<usize>,
+    /// Slice start points in samples, ascending (empty if not sliced)
+    slices: Vec<usize>,
+    /// Estimated fundamental frequency in Hz (None if unpitched)
+    detected_frequency: Option<f32>,
+    /// Samples stripped from the start by auto-trim
+    trim_start: usize,
+    /// Samples stripped from the end by auto-trim
+    trim_end: usize,
+    /// Integrated loudness in LUFS (None if silent)
+    loudness: Option<f32>,
+    /// Playback gain set by loudness normalization
+    gain: f32,
+    /// Gain baked into `samples` by peak normalization (1.0 = untouched)
+    peak_normalize_gain: f32,
+    /// Absolute sample peak (linear)
+    peak: f32,
+    /// RMS level (linear)
+    rms: f32,
+    /// Spectral centroid in Hz
+    spectral_centroid: f32,
+    /// Sample rate of the source audio (the engine rate for raw f32 loads)
+    source_sample_rate: f32,
+    /// Name, tempo, root note and category
+    metadata: SoundMetadata,
 }
 
 impl Sound {
     const fn new() -> Self {
         Self {
-            samples: [0.0; MAX_SAMPLE_LENGTH],
+            samples: Vec::new(),
+            right: Vec::new(),
             length: 0,
             loaded: false,
+            onsets: Vec::new(),
+            slices: Vec::new(),
+            detected_frequency: None,
+            trim_start: 0,
+            trim_end: 0,
+            loudness: None,
+            gain: 1.0,
+            peak_normalize_gain: 1.0,
+            peak: 0.0,
+            rms: 0.0,
+            spectral_centroid: 0.0,
+            source_sample_rate: 0.0,
+            metadata: SoundMetadata::new(),
         }
     }
+
+    /// Heap memory owned by this slot in bytes
+    fn heap_bytes(&self) -> usize {
+        (self.samples.capacity() + self.right.capacity()) * std::mem::size_of::<f32>()
+            + (self.onsets.capacity() + self.slices.capacity()) * std::mem::size_of::<usize>()
+    }
+
+    /// Whether the sound has separate left and right channels
+    #[inline]
+    fn is_stereo(&self) -> bool {
+        !self.right.is_empty()
+    }
+
+    /// Mark the slot empty, releasing its audio and metadata
+    fn clear(&mut self) {
+        self.samples = Vec::new();
+        self.right = Vec::new();
+        self.length = 0;
+        self.loaded = false;
+        self.onsets.clear();
+        self.slices.clear();
+        self.detected_frequency = None;
+        self.trim_start = 0;
+        self.trim_end = 0;
+        self.loudness = None;
+        self.gain = 1.0;
+        self.peak_normalize_gain = 1.0;
+        self.peak = 0.0;
+        self.rms = 0.0;
+        self.spectral_centroid = 0.0;
+        self.source_sample_rate = 0.0;
+        self.metadata = SoundMetadata::new();
+    }
 }
 
 // ============================================================================
@@ -132,6 +461,16 @@ struct KeyMapping {
     modulation_enabled: bool,
     /// Whether a sound is assigned to this key
     has_sound: bool,
+    /// Slice of the sound played by this key (None = whole sound)
+    slice: Option<usize>,
+    /// How note velocity scales the volume
+    velocity: VelocityResponse,
+    /// Semitones of aftertouch vibrato at full pressure (0 = none)
+    aftertouch_depth: f32,
+    /// Fade-out time of voices once released, in milliseconds
+    release_ms: f32,
+    /// Attack, decay and sustain of the voices started
+    envelope: Envelope,
 }
 
 impl KeyMapping {
@@ -145,6 +484,11 @@ impl KeyMapping {
             pitch_semitones: 0,
             modulation_enabled: false,
             has_sound: false,
+            slice: None,
+            velocity: VelocityResponse::LINEAR,
+            aftertouch_depth: 0.0,
+            release_ms: 0.0,
+            envelope: Envelope::NONE,
         }
     }
 }
@@ -153,7 +497,7 @@ impl KeyMapping {
 // MODULATION - Amplitude modulation for sidechain-like effects
 // ============================================================================
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum ModulationPreset {
@@ -171,12 +515,14 @@ pub enum ModulationPreset {
 // DSP ENGINE - Main audio processing state
 // ============================================================================
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct DspEngine {
     /// All loaded sounds
-    sounds: Box<[Sound; MAX_SOUNDS]>,
+    sounds: Box<[Sound]>,
     /// Active voices (playing sounds)
-    voices: [Voice; MAX_VOICES],
+    voices: Box<[Voice]>,
+    /// Longest sound that can be stored, in samples
+    max_sample_length: usize,
     /// Key mappings (256 possible key codes)
     key_mappings: [KeyMapping; 256],
     /// Sample rate (typically 44100 or 48000)
@@ -191,21 +537,127 @@ pub struct DspEngine {
     metronome_volume: f32,
     /// Current modulation preset
     modulation_preset: ModulationPreset,
+    /// How deep the modulation envelope ducks (0.0 = none, 1.0 = full)
+    modulation_depth: f32,
     /// Master volume
     master_volume: f32,
+    /// Serial assigned to the next triggered voice
+    next_voice_serial: u64,
+    /// Run transient detection when sounds are loaded
+    onset_detection_enabled: bool,
+    /// Transient detection sensitivity (0.0 to 1.0)
+    onset_sensitivity: f32,
+    /// Process-time load measurement
+    cpu_meter: CpuMeter,
+    /// Overload and voice-exhaustion counters
+    health: HealthCounters,
+    /// Polyphony usage statistics
+    voice_stats: VoiceStats,
+    /// Outgoing notifications for the host
+    events: EventQueue,
+    /// Transforms applied to incoming audio
+    load_options: LoadOptions,
+    /// Round-trip latency test state
+    latency: LatencyProbe,
+    /// Rolling copy of the master output
+    scope: Scope,
+    /// Diagnostic codes from the audio path
+    debug_log: DebugLog,
+    /// Most recent rejected call and the value at fault
+    last_error: (ErrorCode, u32),
+    /// Parameters the host writes through shared memory
+    control: ControlPlane,
+    /// Chunked upload in progress, if any
+    upload: Option<SoundUpload>,
+    /// Master output capture into a sound slot
+    recorder: MasterRecorder,
+    /// Sounds waiting to be swapped in at the next block
+    pending_swaps: Vec<SoundSwap>,
+    /// Replaced sounds kept alive for voices crossfading away from them
+    retired_sounds: Vec<Sound>,
+    /// Crossfade applied to playing voices when a staged sound is swapped in
+    swap_crossfade_samples: u32,
+    /// How voices read their sounds between samples
+    interpolation: InterpolationQuality,
+    /// Filter phases for sinc interpolation
+    sinc_table: SincTable,
+    /// Band-limit voices played well above their original pitch
+    band_limit: bool,
+    band_limit_kernel: BandLimitKernel,
+    /// Undo/redo steps for key mapping and mixer changes
+    history: ConfigHistory,
+    /// Stored key mapping banks (the active one is in `key_mappings`)
+    key_banks: Box<[KeyBank]>,
+    active_bank: usize,
+    /// Bank to switch to at the next block
+    pending_bank: Option<usize>,
+    /// Scenes and amount for volume morphing
+    morph: Morph,
+    /// Key mappings from `set_key_mappings_packed` waiting for the next block
+    pending_mappings: Vec<(u8, KeyMapping)>,
+    /// How the last imported state blob was upgraded (`StateMigration` flags)
+    state_migrations: u32,
+    /// Slots whose audio changed since the last incremental export
+    audio_dirty: Box<[bool]>,
+    /// Checksum of each tracked state section at the last incremental export
+    saved_sections: Vec<([u8; 4], u32)>,
+    /// Group channels and mixer scenes
+    mixer: GroupMixer,
+    /// Color and label of each key's pad (UI only)
+    pad_styles: Box<[PadStyle]>,
+    /// MIDI note ranges mapped to sounds
+    midi: MidiInput,
+    /// Controllers bound to parameters
+    cc_map: CcMap,
+    /// Loaded standard MIDI file and its playback position
+    midi_file: MidiSequence,
+    /// Mappings of extended trigger IDs (256 and up), sorted by ID
+    trigger_mappings: Vec<(u16, KeyMapping)>,
+    /// Banks played by extra input devices and MIDI channels
+    devices: InputDevices,
+    /// Channel pressure and the vibrato it drives
+    aftertouch: Aftertouch,
+    /// Timestamped key events waiting for their sample
+    timed_input: TimedInput,
+    /// Step patterns, song and playhead
+    sequencer: Sequencer,
+    /// Recorded input take and its playback
+    performance: PerformanceRecorder,
+    /// Live input loop and its overdub layer
+    looper: InputLooper,
+    /// Scheduled punch-in and punch-out
+    punch: Punch,
+    /// Clip lanes of every key
+    clips: ClipLauncher,
+    /// Parameter changes waiting for the next block
+    commands: CommandQueue,
+    /// Smoothed master and voice gains
+    smoothing: Smoothing,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl DspEngine {
-    /// Create a new DSP engine
-    #[wasm_bindgen(constructor)]
+    /// Create a new DSP engine with default capacities
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
     pub fn new(sample_rate: f32) -> Self {
-        // Pre-allocate all memory upfront - no allocation during audio processing
-        let sounds = Box::new([const { Sound::new() }; MAX_SOUNDS]);
+        Self::with_config(&DspEngineConfig::new(sample_rate))
+    }
+
+    /// Create a DSP engine with custom voice, sound and length limits
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn with_config(config: &DspEngineConfig) -> Self {
+        let config = config.sanitized();
+        let sample_rate = config.sample_rate;
+
+        // Pool sizes are fixed here; sound buffers are allocated per load and
+        // process() never allocates
+        let sounds = (0..config.max_sounds).map(|_| Sound::new()).collect();
+        let voices = vec![Voice::new(); config.max_voices].into_boxed_slice();
         
         Self {
             sounds,
-            voices: [const { Voice::new() }; MAX_VOICES],
+            voices,
+            max_sample_length: config.max_sample_length(),
             key_mappings: [const { KeyMapping::new() }; 256],
             sample_rate,
             bpm: 120.0,
@@ -213,7 +665,55 @@ impl DspEngine {
             metronome_enabled: false,
             metronome_volume: 0.5,
             modulation_preset: ModulationPreset::None,
+            modulation_depth: 1.0,
             master_volume: 1.0,
+            next_voice_serial: 0,
+            onset_detection_enabled: false,
+            onset_sensitivity: 0.5,
+            cpu_meter: CpuMeter::new(),
+            health: HealthCounters::new(),
+            voice_stats: VoiceStats::new(),
+            events: EventQueue::new(),
+            load_options: LoadOptions::new(),
+            latency: LatencyProbe::new(),
+            scope: Scope::new(),
+            debug_log: DebugLog::new(),
+            last_error: (ErrorCode::None, 0),
+            control: ControlPlane::new(),
+            upload: None,
+            recorder: MasterRecorder::new(),
+            pending_swaps: Vec::new(),
+            retired_sounds: Vec::new(),
+            swap_crossfade_samples: (0.01 * sample_rate) as u32, // 10 ms
+            interpolation: InterpolationQuality::Linear,
+            sinc_table: SincTable::new(),
+            band_limit: true,
+            band_limit_kernel: BandLimitKernel::new(),
+            history: ConfigHistory::new(),
+            key_banks: vec![[const { KeyMapping::new() }; 256]; config.key_banks].into_boxed_slice(),
+            active_bank: 0,
+            pending_bank: None,
+            morph: Morph::new(),
+            pending_mappings: Vec::new(),
+            state_migrations: 0,
+            audio_dirty: vec![false; config.max_sounds].into_boxed_slice(),
+            saved_sections: Vec::new(),
+            mixer: GroupMixer::new(),
+            pad_styles: vec![PadStyle::new(); 256].into_boxed_slice(),
+            midi: MidiInput::new(),
+            cc_map: CcMap::new(),
+            midi_file: MidiSequence::new(),
+            trigger_mappings: Vec::new(),
+            devices: InputDevices::new(),
+            aftertouch: Aftertouch::new(),
+            timed_input: TimedInput::new(),
+            sequencer: Sequencer::new(),
+            performance: PerformanceRecorder::new(),
+            looper: InputLooper::new(),
+            punch: Punch::new(),
+            clips: ClipLauncher::new(),
+            commands: CommandQueue::new(),
+            smoothing: Smoothing::new(sample_rate),
         }
     }
 
@@ -222,33 +722,33 @@ impl DspEngine {
     /// # Arguments
     /// * `sound_index` - Which slot to load into (0-63)
     /// * `samples` - Audio samples (mono f32)
-    #[wasm_bindgen]
-    pub fn load_sound(&mut self, sound_index: usize, samples: &[f32]) {
-        if sound_index >= MAX_SOUNDS {
-            return;
-        }
-
-        let len = samples.len().min(MAX_SAMPLE_LENGTH);
-        let sound = &mut self.sounds[sound_index];
-        
-        // Copy samples into pre-allocated buffer
-        sound.samples[..len].copy_from_slice(&samples[..len]);
-        sound.length = len;
-        sound.loaded = true;
+    ///
+    /// Returns `BadSoundIndex` or `EmptyAudio` if the load failed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound(&mut self, sound_index: usize, samples: &[f32]) -> ErrorCode {
+        self.store_sound(sound_index, samples, self.sample_rate)
     }
 
     /// Unload a sound from a slot
-    #[wasm_bindgen]
-    pub fn unload_sound(&mut self, sound_index: usize) {
-        if sound_index >= MAX_SOUNDS {
-            return;
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn unload_sound(&mut self, sound_index: usize) -> ErrorCode {
+        if sound_index >= self.sounds.len() {
+            return self.bad_sound_index(sound_index);
         }
-        self.sounds[sound_index].loaded = false;
-        self.sounds[sound_index].length = 0;
+        // Voices still playing the sound fade out from a retired copy
+        self.vacate_slot(sound_index);
+        self.sounds[sound_index].clear();
+        self.mark_audio_dirty(sound_index);
+        ErrorCode::None
     }
 
     /// Map a key to a sound with settings
-    #[wasm_bindgen]
+    ///
+    /// The mapping is stored even if it fails: `BadSoundIndex` for a slot
+    /// out of range, `SoundNotLoaded` for one with no audio yet (the key
+    /// plays once audio is loaded and the key is mapped again).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    #[allow(clippy::too_many_arguments)]
     pub fn set_key_mapping(
         &mut self,
         key_code: u8,
@@ -259,7 +759,8 @@ impl DspEngine {
         volume: f32,
         pitch_semitones: i8,
         modulation_enabled: bool,
-    ) {
+    ) -> ErrorCode {
+        self.record_change(ConfigChange::KeyMapping(key_code));
         let mapping = &mut self.key_mappings[key_code as usize];
         mapping.sound_index = sound_index;
         mapping.mode = mode;
@@ -268,134 +769,206 @@ impl DspEngine {
         mapping.volume = volume.clamp(0.0, 1.0);
         mapping.pitch_semitones = pitch_semitones.clamp(-24, 24);
         mapping.modulation_enabled = modulation_enabled;
-        mapping.has_sound = sound_index < MAX_SOUNDS && self.sounds[sound_index].loaded;
+        mapping.slice = None;
+        mapping.has_sound = sound_index < self.sounds.len() && self.sounds[sound_index].loaded;
+        if sound_index >= self.sounds.len() {
+            self.bad_sound_index(sound_index)
+        } else if !self.key_mappings[key_code as usize].has_sound {
+            self.report_error(ErrorCode::SoundNotLoaded, sound_index as u32)
+        } else {
+            ErrorCode::None
+        }
     }
 
     /// Update just the playback mode for a key
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_key_mode(&mut self, key_code: u8, mode: PlaybackMode) {
+        self.record_change(ConfigChange::KeyMode(key_code));
         self.key_mappings[key_code as usize].mode = mode;
     }
 
     /// Update just the modulation setting for a key
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_key_modulation(&mut self, key_code: u8, enabled: bool) {
+        self.record_change(ConfigChange::KeyModulation(key_code));
         self.key_mappings[key_code as usize].modulation_enabled = enabled;
     }
 
     /// Update volume for a key
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_key_volume(&mut self, key_code: u8, volume: f32) {
-        self.key_mappings[key_code as usize].volume = volume.clamp(0.0, 1.0);
+        self.record_queued_change(ConfigChange::KeyVolume(key_code));
+        let value = volume.clamp(0.0, 1.0);
+        self.record_performance(Performed::Parameter { target: CcTarget::KeyVolume, index: key_code, value });
+        self.queue_command(Command::Parameter { target: CcTarget::KeyVolume, index: key_code, value });
     }
 
     /// Update pitch for a key (in semitones)
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_key_pitch(&mut self, key_code: u8, semitones: i8) {
-        self.key_mappings[key_code as usize].pitch_semitones = semitones.clamp(-24, 24);
+        self.record_queued_change(ConfigChange::KeyPitch(key_code));
+        let semitones = semitones.clamp(-24, 24);
+        let value = semitones as f32;
+        self.record_performance(Performed::Parameter { target: CcTarget::KeyPitch, index: key_code, value });
+        self.queue_command(Command::Parameter { target: CcTarget::KeyPitch, index: key_code, value });
     }
 
     /// Set overlap mode and group for a key
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_key_overlap(&mut self, key_code: u8, mode: OverlapMode, group_id: u8) {
+        self.record_change(ConfigChange::KeyOverlap(key_code));
         self.key_mappings[key_code as usize].overlap_mode = mode;
         self.key_mappings[key_code as usize].group_id = group_id;
     }
 
+    /// Copy a key's complete mapping onto another key
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn copy_key_mapping(&mut self, src_key: u8, dst_key: u8) {
+        self.record_change(ConfigChange::KeyMapping(dst_key));
+        self.key_mappings[dst_key as usize] = self.key_mappings[src_key as usize];
+    }
+
+    /// Exchange the complete mappings of two keys
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn swap_key_mappings(&mut self, key_a: u8, key_b: u8) {
+        self.record_change(ConfigChange::Bulk);
+        self.key_mappings.swap(key_a as usize, key_b as usize);
+    }
+
     /// Trigger a sound (key down)
-    #[wasm_bindgen]
+    ///
+    /// If every voice is busy, the oldest voice is stolen.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn note_on(&mut self, key_code: u8) {
-        let mapping = &self.key_mappings[key_code as usize];
-        
-        if !mapping.has_sound {
+        self.trigger_on(key_code as u16);
+    }
+
+    /// Start a voice for a mapped trigger
+    ///
+    /// MIDI voices report the note number as their trigger, device voices
+    /// their key code.
+    fn trigger_voice(&mut self, mapping: &KeyMapping, trigger: u16, source: VoiceSource) {
+        let Some(region) = self.sounds[mapping.sound_index].key_region(mapping.slice) else {
             return;
-        }
+        };
+
+        let now = self.global_sample_position;
 
         // Handle monophonic mode - stop other voices in same group
         if mapping.overlap_mode == OverlapMode::Monophonic {
-            for voice in &mut self.voices {
+            for (slot, voice) in self.voices.iter_mut().enumerate() {
                 if voice.active && voice.group_id == mapping.group_id {
                     voice.active = false;
+                    self.events.push(EngineEventKind::VoiceChoked, voice.trigger, slot as u32, now);
                 }
             }
         }
 
-        // Find free voice slot
-        let voice_slot = self.voices.iter_mut().find(|v| !v.active);
-        
-        if let Some(voice) = voice_slot {
-            // Convert semitones to pitch multiplier: 2^(semitones/12)
-            let pitch = 2.0_f32.powf(mapping.pitch_semitones as f32 / 12.0);
-            
-            voice.sound_index = mapping.sound_index;
-            voice.position = 0.0;
-            voice.active = true;
-            voice.volume = mapping.volume;
-            voice.pitch = pitch;
-            voice.mode = mapping.mode;
-            voice.group_id = mapping.group_id;
-            voice.key_code = key_code;
-            voice.modulation_enabled = mapping.modulation_enabled;
+        // Find free voice slot, stealing the oldest voice if none is free
+        let slot = match self.voices.iter().position(|v| !v.active) {
+            Some(slot) => slot,
+            None => {
+                self.health.voice_pool_exhausted = self.health.voice_pool_exhausted.saturating_add(1);
+                self.events.push(EngineEventKind::VoicePoolExhausted, trigger, 0, now);
+
+                let Some(oldest) = (0..self.voices.len()).min_by_key(|&i| self.voices[i].serial) else {
+                    return;
+                };
+                self.events.push(EngineEventKind::VoiceStolen, self.voices[oldest].trigger, oldest as u32, now);
+                self.voice_stats.steals = self.voice_stats.steals.saturating_add(1);
+                self.debug_log.push(LogCode::VoiceStolen, oldest as u32, now);
+                oldest
+            }
+        };
+
+        let voice = &mut self.voices[slot];
+        voice.start(mapping, trigger, region, self.sample_rate);
+        voice.source = source;
+        voice.serial = self.next_voice_serial;
+        self.next_voice_serial += 1;
+        self.events.push(EngineEventKind::VoiceStarted, trigger, slot as u32, now);
+
+        let active = self.get_active_voice_count();
+        let stats = &mut self.voice_stats;
+        if let Some(count) = stats.key_triggers.get_mut(trigger as usize).filter(|_| source == VoiceSource::Key) {
+            *count = count.saturating_add(1);
         }
+        stats.peak_voices = stats.peak_voices.max(active);
     }
 
     /// Release a sound (key up)
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn note_off(&mut self, key_code: u8) {
-        // For SingleShot mode, sound continues playing after key release
-        // For Loop mode, sound stops on key release
-        for voice in &mut self.voices {
-            if voice.active && voice.key_code == key_code {
-                if voice.mode == PlaybackMode::Loop {
-                    voice.active = false;
-                }
-            }
-        }
+        self.trigger_off(key_code as u16);
     }
 
     /// Stop all sounds immediately
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn panic(&mut self) {
-        for voice in &mut self.voices {
-            voice.active = false;
-        }
+        self.stop_voices();
+        // Waiting events were timed against the clock being reset
+        self.timed_input.clear();
+        self.sequencer.reset_playhead();
+        self.halt_performance();
+        self.clear_punch();
+        self.reset_clips();
         self.global_sample_position = 0;
     }
 
     /// Set global BPM
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_bpm(&mut self, bpm: f32) {
         self.bpm = bpm.clamp(20.0, 300.0);
     }
 
     /// Get current BPM
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_bpm(&self) -> f32 {
         self.bpm
     }
 
     /// Enable/disable metronome
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_metronome(&mut self, enabled: bool, volume: f32) {
+        self.record_change(ConfigChange::Metronome);
         self.metronome_enabled = enabled;
         self.metronome_volume = volume.clamp(0.0, 1.0);
     }
 
     /// Set modulation preset
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_modulation_preset(&mut self, preset: ModulationPreset) {
+        self.record_change(ConfigChange::ModulationPreset);
         self.modulation_preset = preset;
     }
 
+    /// Set how deep modulation ducks voices (0.0 = not at all, 1.0 = full)
+    ///
+    /// The MIDI mod wheel (CC 1) drives this unless the controller is bound
+    /// to something else.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_modulation_depth(&mut self, depth: f32) {
+        self.modulation_depth = depth.clamp(0.0, 1.0);
+    }
+
+    /// Current modulation depth
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_modulation_depth(&self) -> f32 {
+        self.modulation_depth
+    }
+
     /// Set master volume
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn set_master_volume(&mut self, volume: f32) {
-        self.master_volume = volume.clamp(0.0, 1.0);
+        self.record_queued_change(ConfigChange::MasterVolume);
+        let value = volume.clamp(0.0, 1.0);
+        self.record_performance(Performed::Parameter { target: CcTarget::MasterVolume, index: 0, value });
+        self.queue_command(Command::Parameter { target: CcTarget::MasterVolume, index: 0, value });
     }
 
-    /// Calculate modulation amount based on current position and preset
+    /// Calculate modulation amount at sample `position` from the preset
     /// Returns a multiplier between 0.0 and 1.0
-    fn calculate_modulation(&self) -> f32 {
+    fn calculate_modulation(&self, position: u64) -> f32 {
         if self.modulation_preset == ModulationPreset::None {
             return 1.0;
         }
@@ -416,19 +989,20 @@ impl DspEngine {
         }
 
         // Position within current cycle (0.0 to 1.0)
-        let cycle_pos = (self.global_sample_position % samples_per_cycle) as f32 
+        let cycle_pos = (position % samples_per_cycle) as f32 
             / samples_per_cycle as f32;
 
         // Sidechain envelope: quick attack, exponential release
         // Duck at start of cycle, recover quickly
-        if cycle_pos < 0.1 {
+        let envelope = if cycle_pos < 0.1 {
             // Attack phase: duck down
             0.1 + (cycle_pos / 0.1) * 0.3
         } else {
             // Release phase: recover to full
             let release_pos = (cycle_pos - 0.1) / 0.9;
             0.4 + release_pos.powf(0.5) * 0.6
-        }
+        };
+        1.0 - self.modulation_depth * (1.0 - envelope)
     }
 
     /// Generate metronome click if appropriate
@@ -470,160 +1044,263 @@ impl DspEngine {
     /// 
     /// # Arguments
     /// * `output` - Mutable slice to write audio output (stereo interleaved)
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn process(&mut self, output: &mut [f32]) {
-        // Clear output buffer
-        output.fill(0.0);
-
-        let samples_per_beat = (self.sample_rate * 60.0 / self.bpm) as u64;
-        
-        // Process each sample
-        for frame in 0..(output.len() / 2) {
-            let mut sample = 0.0_f32;
-            
-            // Get modulation amount for this sample
-            let modulation = self.calculate_modulation();
-
-            // Mix all active voices
-            for voice in &mut self.voices {
-                if !voice.active {
-                    continue;
-                }
-
-                let sound = &self.sounds[voice.sound_index];
-                if !sound.loaded {
-                    voice.active = false;
-                    continue;
-                }
+        self.render(&[], output);
+    }
 
-                // Get sample at current position (linear interpolation)
-                let pos_floor = voice.position as usize;
-                let pos_frac = voice.position - pos_floor as f64;
-                
-                if pos_floor >= sound.length {
-                    if voice.mode == PlaybackMode::Loop {
-                        // Loop back to start
-                        voice.position = voice.position - sound.length as f64;
-                        continue;
-                    } else {
-                        // Single shot: deactivate when done
-                        voice.active = false;
-                        continue;
-                    }
-                }
-                
-                // BPM-sync for loop mode: quantize to 1/8 beat
-                if voice.mode == PlaybackMode::Loop {
-                    let samples_per_beat = (self.sample_rate * 60.0 / self.bpm) as u64;
-                    let samples_per_eighth = samples_per_beat / 2; // 1/8 note
-                    let sound_duration = sound.length as f64 / voice.pitch as f64;
-                    
-                    // Calculate how many 1/8 notes this sound should occupy
-                    let eighth_notes = (sound_duration / samples_per_eighth as f64).round() as u64;
-                    let target_length = eighth_notes * samples_per_eighth;
-                    
-                    // If we're past the target length, loop back
-                    if target_length > 0 && voice.position >= target_length as f64 {
-                        voice.position = voice.position % target_length as f64;
-                        continue;
-                    }
-                }
+    /// Process a block with live audio input for the looper
+    ///
+    /// `input` is mono, one sample per output frame; missing frames are
+    /// silence. The input is recorded, not played.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn process_with_input(&mut self, input: &[f32], output: &mut [f32]) {
+        self.render(input, output);
+    }
 
-                // Linear interpolation between samples
-                let s1 = sound.samples[pos_floor];
-                let s2 = if pos_floor + 1 < sound.length {
-                    sound.samples[pos_floor + 1]
-                } else {
-                    s1
-                };
-                let interpolated = s1 + (s2 - s1) * pos_frac as f32;
+    /// Get number of active voices (for UI feedback)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_active_voice_count(&self) -> u32 {
+        self.voices.iter().filter(|v| v.active).count() as u32
+    }
 
-                // Apply volume and optional modulation
-                let voice_mod = if voice.modulation_enabled { modulation } else { 1.0 };
-                sample += interpolated * voice.volume * voice_mod;
+    /// Check if a specific key is currently playing
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_key_playing(&self, key_code: u8) -> bool {
+        self.is_trigger_playing(key_code as u16)
+    }
 
-                // Advance position by pitch factor
-                voice.position += voice.pitch as f64;
+    /// Get the playhead of the most recently triggered voice for a key
+    ///
+    /// Returns the position normalized to the sound length (0.0 to 1.0),
+    /// or -1.0 if the key has no active voice.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_playhead(&self, key_code: u8) -> f32 {
+        match self.latest_voice_for_key(key_code) {
+            Some(voice) => {
+                let (_, length) = voice.region(&self.sounds[voice.sound_index]);
+                if length == 0 {
+                    return 0.0;
+                }
+                (voice.position / length as f64).clamp(0.0, 1.0) as f32
             }
+            None => -1.0,
+        }
+    }
 
-            // Add metronome
-            sample += self.generate_metronome_sample();
-
-            // Apply master volume
-            sample *= self.master_volume;
+    /// Get the playhead of the most recently triggered voice for a key
+    /// in samples, or -1.0 if the key has no active voice
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_playhead_samples(&self, key_code: u8) -> f64 {
+        self.latest_voice_for_key(key_code)
+            .map_or(-1.0, |voice| voice.position)
+    }
 
-            // Soft clipping to prevent harsh distortion
-            sample = soft_clip(sample);
+    /// Fill `out` with the state of every voice slot in one call
+    ///
+    /// Each voice occupies 5 consecutive values:
+    /// `[trigger, sound_index, position (samples), volume, active (0/1)]`.
+    /// Returns the number of voices written (limited by `out.len() / 5`).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_voice_states(&self, out: &mut [f32]) -> u32 {
+        let mut written = 0;
+        for (voice, record) in self.voices.iter().zip(out.chunks_exact_mut(VOICE_STATE_STRIDE)) {
+            record[0] = voice.trigger as f32;
+            record[1] = voice.sound_index as f32;
+            record[2] = voice.position as f32;
+            record[3] = voice.volume;
+            record[4] = if voice.active { 1.0 } else { 0.0 };
+            written += 1;
+        }
+        written
+    }
 
-            // Write to stereo output
-            output[frame * 2] = sample;
-            output[frame * 2 + 1] = sample;
+    /// Find the most recently triggered active voice for a key
+    fn latest_voice_for_key(&self, key_code: u8) -> Option<&Voice> {
+        self.voices
+            .iter()
+            .filter(|v| v.active && v.is_trigger(key_code as u16))
+            .max_by_key(|v| v.serial)
+    }
 
-            // Advance global position
-            self.global_sample_position += 1;
+    /// Length of a sound in samples (0 if not loaded)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_length_samples(&self, sound_index: usize) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
         }
+        self.sounds[sound_index].length as u32
     }
 
-    /// Get number of active voices (for UI feedback)
-    #[wasm_bindgen]
-    pub fn get_active_voice_count(&self) -> u32 {
-        self.voices.iter().filter(|v| v.active).count() as u32
+    /// Length of a sound in seconds at the engine sample rate
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_length_seconds(&self, sound_index: usize) -> f32 {
+        self.get_sound_length_samples(sound_index) as f32 / self.sample_rate
     }
 
-    /// Check if a specific key is currently playing
-    #[wasm_bindgen]
-    pub fn is_key_playing(&self, key_code: u8) -> bool {
-        self.voices.iter().any(|v| v.active && v.key_code == key_code)
+    /// Length of a sound in beats at the given BPM
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_length_beats(&self, sound_index: usize, bpm: f32) -> f32 {
+        if bpm <= 0.0 {
+            return 0.0;
+        }
+        self.get_sound_length_seconds(sound_index) * bpm / 60.0
     }
 
     /// Reset timing (call when starting/stopping transport)
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn reset_timing(&mut self) {
         self.global_sample_position = 0;
     }
 
     /// Get key mapping info (for serialization)
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_mode(&self, key_code: u8) -> PlaybackMode {
         self.key_mappings[key_code as usize].mode
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_volume(&self, key_code: u8) -> f32 {
-        self.key_mappings[key_code as usize].volume
+        let queued = self.commands.queued_parameter(CcTarget::KeyVolume, key_code);
+        queued.unwrap_or(self.key_mappings[key_code as usize].volume)
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_pitch(&self, key_code: u8) -> i8 {
-        self.key_mappings[key_code as usize].pitch_semitones
+        let queued = self.commands.queued_parameter(CcTarget::KeyPitch, key_code);
+        queued.map_or(self.key_mappings[key_code as usize].pitch_semitones, |semitones| semitones as i8)
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_modulation(&self, key_code: u8) -> bool {
         self.key_mappings[key_code as usize].modulation_enabled
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_has_sound(&self, key_code: u8) -> bool {
         self.key_mappings[key_code as usize].has_sound
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_sound_index(&self, key_code: u8) -> usize {
         self.key_mappings[key_code as usize].sound_index
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_overlap_mode(&self, key_code: u8) -> OverlapMode {
         self.key_mappings[key_code as usize].overlap_mode
     }
 
-    #[wasm_bindgen]
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
     pub fn get_key_group_id(&self, key_code: u8) -> u8 {
         self.key_mappings[key_code as usize].group_id
     }
 }
 
+impl DspEngine {
+    /// Shared load path: convert rate, trim, store, analyze
+    ///
+    /// Runs outside the audio callback; `samples` are mono at `source_rate`.
+    fn store_sound(&mut self, sound_index: usize, samples: &[f32], source_rate: f32) -> ErrorCode {
+        self.store_channels(sound_index, samples, None, source_rate)
+    }
+
+    /// `store_sound` for mono (`right` = None) or stereo audio
+    ///
+    /// Stereo channels are cut to the shorter one's length and trimmed
+    /// together. A `source_rate` of 0 means the engine rate; any other rate
+    /// must be the engine rate or a supported one.
+    fn store_channels(
+        &mut self,
+        sound_index: usize,
+        left: &[f32],
+        right: Option<&[f32]>,
+        source_rate: f32,
+    ) -> ErrorCode {
+        if sound_index >= self.sounds.len() {
+            return self.bad_sound_index(sound_index);
+        }
+
+        let (engine_rate, quality) = (self.sample_rate, self.load_options.resample_quality);
+        if source_rate != 0.0 && source_rate != engine_rate && !resample::is_supported_rate(source_rate) {
+            return self.report_error(ErrorCode::BadSampleRate, source_rate as u32);
+        }
+
+        // Play at the right pitch/speed regardless of the source rate. Only
+        // the source samples that can fit the maximum length are converted.
+        let resampling = source_rate > 0.0 && source_rate != engine_rate;
+        let needed = resample::input_needed(self.max_sample_length, source_rate, engine_rate);
+        let convert = |samples: &[f32]| {
+            resample::resample(&samples[..samples.len().min(needed)], source_rate, engine_rate, quality)
+        };
+        let (converted_left, converted_right);
+        let left = if resampling {
+            converted_left = convert(left);
+            &converted_left[..]
+        } else {
+            left
+        };
+        let right = match right {
+            Some(right) if resampling => {
+                converted_right = convert(right);
+                Some(&converted_right[..])
+            }
+            right => right,
+        };
+        let channel_len = right.map_or(left.len(), |right| left.len().min(right.len()));
+        let left = &left[..channel_len];
+        let right = right.map(|right| &right[..channel_len]);
+
+        // Optionally strip dead air before storing
+        let threshold = self.load_options.trim_threshold;
+        let (start, end) = match right {
+            _ if !self.load_options.auto_trim => (0, channel_len),
+            Some(right) => preprocess::find_stereo_trim_bounds(left, right, threshold),
+            None => preprocess::find_trim_bounds(left, threshold),
+        };
+        let trim_end = channel_len - end;
+
+        let len = (end - start).min(self.max_sample_length);
+        // Staged audio is older than this; voices playing the old audio fade
+        // out instead of jumping to the new
+        self.vacate_slot(sound_index);
+        let sound = &mut self.sounds[sound_index];
+        
+        // Allocate exactly what this sound needs (the old buffers are freed
+        // here, outside the audio callback)
+        sound.samples = left[start..start + len].to_vec();
+        sound.right = right.map_or_else(Vec::new, |right| right[start..start + len].to_vec());
+        sound.length = len;
+        sound.loaded = true;
+        sound.trim_start = start;
+        sound.trim_end = trim_end;
+        sound.slices.clear();
+        sound.source_sample_rate = if source_rate > 0.0 { source_rate } else { self.sample_rate };
+
+        self.apply_peak_normalization(sound_index);
+        self.analyze_sound(sound_index);
+        self.apply_load_normalization(sound_index);
+        if len == 0 {
+            return self.report_error(ErrorCode::EmptyAudio, sound_index as u32);
+        }
+        ErrorCode::None
+    }
+
+    /// Silence every voice at once, reporting each one stopped
+    pub(crate) fn stop_voices(&mut self) {
+        for (slot, voice) in self.voices.iter_mut().enumerate() {
+            if voice.active {
+                self.events.push(EngineEventKind::VoiceStopped, voice.trigger, slot as u32, self.global_sample_position);
+            }
+            voice.active = false;
+        }
+    }
+}
+
+/// Largest magnitude soft_clip can output (the curve's asymptote rounds to
+/// exactly 1.0 in f32 for large inputs, so it is capped just below)
+const SOFT_CLIP_CEILING: f32 = 1.0 - f32::EPSILON;
+
 /// Soft clipping function to prevent harsh digital distortion
 /// Uses tanh-like curve for natural saturation
 #[inline(always)]
@@ -631,9 +1308,9 @@ fn soft_clip(x: f32) -> f32 {
     if x.abs() < 0.5 {
         x
     } else if x > 0.0 {
-        0.5 + (1.0 - (-2.0 * (x - 0.5)).exp()) * 0.5
+        (0.5 + (1.0 - (-2.0 * (x - 0.5)).exp()) * 0.5).min(SOFT_CLIP_CEILING)
     } else {
-        -0.5 - (1.0 - (2.0 * (x + 0.5)).exp()) * 0.5
+        (-0.5 - (1.0 - (2.0 * (x + 0.5)).exp()) * 0.5).max(-SOFT_CLIP_CEILING)
     }
 }
 
@@ -651,6 +1328,65 @@ mod tests {
         assert_eq!(engine.get_bpm(), 120.0);
     }
 
+    #[test]
+    fn test_key_playhead() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        assert_eq!(engine.get_key_playhead(65), -1.0);
+
+        engine.note_on(65);
+        let mut output = [0.0; 256];
+        engine.process(&mut output);
+        assert!((engine.get_key_playhead(65) - 0.128).abs() < 1e-6);
+        assert_eq!(engine.get_key_playhead_samples(65), 128.0);
+    }
+
+    #[test]
+    fn test_voice_states() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(3, &[0.5; 1000]);
+        engine.set_key_mapping(70, 3, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 0.5, 0, false);
+        engine.note_on(70);
+
+        let mut states = [0.0; DEFAULT_MAX_VOICES * VOICE_STATE_STRIDE];
+        assert_eq!(engine.get_voice_states(&mut states), DEFAULT_MAX_VOICES as u32);
+        assert_eq!(&states[..5], &[70.0, 3.0, 0.0, 0.5, 1.0]);
+        assert_eq!(states[9], 0.0);
+    }
+
+    #[test]
+    fn test_engine_config() {
+        let mut config = DspEngineConfig::new(1000.0);
+        config.max_voices = 4;
+        config.max_sounds = 2;
+        config.max_sample_seconds = 1.0;
+        let mut engine = DspEngine::with_config(&config);
+
+        engine.load_sound(1, &[0.5; 5000]);
+        assert_eq!(engine.get_sound_length_samples(1), 1000);
+        engine.load_sound(2, &[0.5; 10]);
+        assert_eq!(engine.get_sound_length_samples(2), 0);
+
+        let mut states = [0.0; 8 * VOICE_STATE_STRIDE];
+        assert_eq!(engine.get_voice_states(&mut states), 4);
+    }
+
+    #[test]
+    fn test_copy_and_swap_key_mappings() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 10]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 2, 0.5, 3, true);
+
+        engine.copy_key_mapping(65, 66);
+        assert!(engine.get_key_has_sound(66));
+        assert_eq!(engine.key_mappings[66].pitch_semitones, 3);
+
+        engine.set_key_volume(66, 0.25);
+        engine.swap_key_mappings(65, 66);
+        assert_eq!((engine.key_mappings[65].volume, engine.key_mappings[66].volume), (0.25, 0.5));
+    }
+
     #[test]
     fn test_soft_clip() {
         assert_eq!(soft_clip(0.0), 0.0);
@@ -0,0 +1,288 @@
+//! Live input looper
+//!
+//! Records the live input passed to `process_with_input` into a sound slot
+//! as a loop of whole bars, starting on the next bar. Once stored and
+//! mapped to a key, further passes can be overdubbed: the input is summed
+//! into the slot's audio in place, at the loop position the transport is
+//! on, so the layer lands where it was played. The last overdub layer is
+//! kept separately and can be undone.
+//!
+//! Buffers are allocated when recording or overdubbing is armed, never in
+//! `process()`. The input is not monitored; the host hears it directly.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::events::EngineEventKind;
+use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum LooperState {
+    /// No loop
+    Idle = 0,
+    /// Armed, waiting for the next bar to start
+    WaitingForBar = 1,
+    /// Recording the first pass
+    Recording = 2,
+    /// First pass complete, waiting for `finish_input_loop`
+    Finished = 3,
+    /// Loop stored in its slot and ready to overdub
+    Ready = 4,
+    /// Overdub armed, waiting for the next bar to start
+    OverdubWaiting = 5,
+    /// Summing the input into the loop
+    Overdubbing = 6,
+}
+
+pub(crate) struct InputLooper {
+    state: LooperState,
+    /// Slot the loop is stored in
+    sound_index: usize,
+    /// Key the loop is mapped to when stored
+    key_code: Option<u8>,
+    /// First pass (capacity reserved when armed)
+    buffer: Vec<f32>,
+    /// Loop length in samples (whole bars)
+    length: usize,
+    /// Engine sample the loop started on
+    start: u64,
+    /// Input summed in by the last overdub, per loop sample
+    layer: Vec<f32>,
+}
+
+impl InputLooper {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: LooperState::Idle,
+            sound_index: 0,
+            key_code: None,
+            buffer: Vec::new(),
+            length: 0,
+            start: 0,
+            layer: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_active(&self) -> bool {
+        !matches!(self.state, LooperState::Idle | LooperState::Finished | LooperState::Ready)
+    }
+
+    /// Heap bytes held by the first pass and the overdub layer
+    pub(crate) fn heap_bytes(&self) -> usize {
+        (self.buffer.capacity() + self.layer.capacity()) * std::mem::size_of::<f32>()
+    }
+}
+
+impl DspEngine {
+    /// Feed one input sample to the looper (real-time safe)
+    ///
+    /// `on_bar` is true for the first sample of a bar.
+    #[inline]
+    pub(crate) fn feed_looper(&mut self, input: f32, on_bar: bool) {
+        let now = self.global_sample_position;
+        let looper = &mut self.looper;
+        let input = if input.is_finite() { input } else { 0.0 };
+        match looper.state {
+            LooperState::WaitingForBar | LooperState::Recording => {
+                if looper.state == LooperState::WaitingForBar {
+                    if !on_bar {
+                        return;
+                    }
+                    looper.state = LooperState::Recording;
+                    looper.start = now;
+                    self.events.push(EngineEventKind::RecordingStarted, 0, looper.sound_index as u32, now);
+                }
+                looper.buffer.push(input);
+                if looper.buffer.len() >= looper.length {
+                    looper.state = LooperState::Finished;
+                    self.events.push(EngineEventKind::RecordingFinished, 0, looper.sound_index as u32, now);
+                }
+            }
+            LooperState::OverdubWaiting | LooperState::Overdubbing => {
+                if looper.state == LooperState::OverdubWaiting {
+                    if !on_bar {
+                        return;
+                    }
+                    looper.state = LooperState::Overdubbing;
+                }
+                let sound = &mut self.sounds[looper.sound_index];
+                // The slot was replaced or unloaded under the loop
+                if !sound.loaded || sound.length != looper.length {
+                    looper.state = LooperState::Idle;
+                    return;
+                }
+                let position = (now.saturating_sub(looper.start) % looper.length as u64) as usize;
+                let added = input * sound.peak_normalize_gain;
+                sound.samples[position] += added;
+                if sound.is_stereo() {
+                    sound.right[position] += added;
+                }
+                looper.layer[position] += added;
+            }
+            LooperState::Idle | LooperState::Finished | LooperState::Ready => {}
+        }
+    }
+
+    /// Start a new, empty overdub layer (allocates; call outside `process()`)
+    ///
+    /// Returns false if no loop is ready.
+    pub(crate) fn prepare_overdub(&mut self) -> bool {
+        if self.looper.state != LooperState::Ready {
+            return false;
+        }
+        self.looper.layer = vec![0.0; self.looper.length];
+        true
+    }
+
+    /// Start or stop overdubbing on the current sample, without waiting for
+    /// a bar (real-time safe; starting needs a prepared layer)
+    pub(crate) fn set_overdubbing(&mut self, overdubbing: bool) {
+        let looper = &mut self.looper;
+        match looper.state {
+            LooperState::Ready if overdubbing && looper.layer.len() == looper.length => {
+                looper.state = LooperState::Overdubbing;
+            }
+            LooperState::OverdubWaiting | LooperState::Overdubbing if !overdubbing => {
+                looper.state = LooperState::Ready;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Arm recording of a `bars`-long loop of the live input into
+    /// `sound_index`, starting at the next bar
+    ///
+    /// With `key_code` 0-255, `finish_input_loop` maps the loop to that key;
+    /// -1 leaves the mappings alone. Returns false for an unknown slot or
+    /// key, or a loop longer than the maximum sound length.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn arm_input_loop(&mut self, sound_index: usize, bars: u32, key_code: i32) -> bool {
+        let key_code = match key_code {
+            -1 => None,
+            key_code => match u8::try_from(key_code) {
+                Ok(key_code) => Some(key_code),
+                Err(_) => return false,
+            },
+        };
+        let length = self.samples_per_bar() as usize * bars as usize;
+        if sound_index >= self.sounds.len() || length == 0 || length > self.max_sample_length {
+            return false;
+        }
+        let looper = &mut self.looper;
+        looper.buffer = Vec::with_capacity(length);
+        looper.layer = Vec::new();
+        looper.length = length;
+        looper.sound_index = sound_index;
+        looper.key_code = key_code;
+        looper.state = LooperState::WaitingForBar;
+        true
+    }
+
+    /// Store a finished first pass in its slot and map it to its key
+    ///
+    /// Call after the looper's `RecordingFinished` event. Returns the slot
+    /// written, or -1 if no pass is finished.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn finish_input_loop(&mut self) -> i32 {
+        if self.looper.state != LooperState::Finished {
+            return -1;
+        }
+        let sound_index = self.looper.sound_index;
+        let take = std::mem::take(&mut self.looper.buffer);
+
+        // Trimming would break the bar alignment of the loop
+        let auto_trim = std::mem::replace(&mut self.load_options.auto_trim, false);
+        self.store_sound(sound_index, &take, self.sample_rate);
+        self.load_options.auto_trim = auto_trim;
+        if let Some(key_code) = self.looper.key_code {
+            self.set_key_mapping(key_code, sound_index, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        }
+        self.looper.state = LooperState::Ready;
+        sound_index as i32
+    }
+
+    /// Start overdubbing the live input onto the loop at the next bar
+    ///
+    /// The new layer replaces the one `undo_overdub` would remove. Returns
+    /// false if no loop is ready.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn start_overdub(&mut self) -> bool {
+        if !self.prepare_overdub() {
+            return false;
+        }
+        self.looper.state = LooperState::OverdubWaiting;
+        true
+    }
+
+    /// Stop overdubbing; the layer stays in the loop
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stop_overdub(&mut self) {
+        self.set_overdubbing(false);
+    }
+
+    /// Remove the last overdub layer from the loop
+    ///
+    /// Stops an overdub in progress first. Returns false if there is no
+    /// layer to remove.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn undo_overdub(&mut self) -> bool {
+        self.stop_overdub();
+        let looper = &mut self.looper;
+        let sound = &mut self.sounds[looper.sound_index];
+        if looper.state != LooperState::Ready || looper.layer.is_empty() || sound.length != looper.length {
+            return false;
+        }
+        for (sample, added) in sound.samples.iter_mut().zip(&looper.layer) {
+            *sample -= added;
+        }
+        for (sample, added) in sound.right.iter_mut().zip(&looper.layer) {
+            *sample -= added;
+        }
+        looper.layer = Vec::new();
+        true
+    }
+
+    /// Current looper state
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_looper_state(&self) -> LooperState {
+        self.looper.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overdub_sums_and_undoes() {
+        // 120 BPM at 1 kHz: one bar is 2000 samples
+        let mut engine = DspEngine::new(1000.0);
+        let input = vec![0.25; 2000];
+        let mut block = vec![0.0; 2 * 2000];
+        engine.process_with_input(&input[..500], &mut block[..2 * 500]);
+        assert!(engine.arm_input_loop(4, 1, 65));
+        engine.process_with_input(&input, &mut block);
+        assert_eq!(engine.get_looper_state(), LooperState::Recording);
+        engine.process_with_input(&input, &mut block);
+        assert_eq!(engine.get_looper_state(), LooperState::Finished);
+        assert_eq!(engine.finish_input_loop(), 4);
+        assert!(engine.key_mappings[65].has_sound && engine.get_sound_length_samples(4) == 2000);
+
+        // Overdub half a pass from the next bar, then take it back
+        assert!(engine.start_overdub());
+        engine.process_with_input(&input[..1500], &mut block[..2 * 1500]);
+        engine.process_with_input(&input[..1000], &mut block[..2 * 1000]);
+        engine.stop_overdub();
+        assert!(engine.loaded_samples(4).is_some_and(|loop_| loop_[..1000].iter().all(|&s| s == 0.5)));
+        assert!(engine.loaded_samples(4).is_some_and(|loop_| loop_[1000..].iter().all(|&s| s == 0.25)));
+        assert!(engine.undo_overdub());
+        assert!(engine.loaded_samples(4).is_some_and(|loop_| loop_.iter().all(|&s| s == 0.25)));
+        assert!(!engine.undo_overdub());
+    }
+}
@@ -0,0 +1,165 @@
+//! Per-sound metadata
+//!
+//! Descriptive information about each slot (name, tempo, root note,
+//! category). It belongs to the slot: reloading or replacing the audio
+//! keeps it, unloading clears it.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Longest stored sound name in bytes
+const MAX_NAME_BYTES: usize = 64;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum SoundCategory {
+    /// Uncategorized
+    Other = 0,
+    /// One-shot drum or percussion hit
+    Drum = 1,
+    /// Bass note or phrase
+    Bass = 2,
+    /// Pitched instrument
+    Melodic = 3,
+    /// Vocal phrase or chop
+    Vocal = 4,
+    /// Riser, impact, texture
+    Fx = 5,
+    /// Tempo-based loop
+    Loop = 6,
+}
+
+#[derive(Clone)]
+pub(crate) struct SoundMetadata {
+    /// Display name
+    pub(crate) name: String,
+    /// Tempo the audio was recorded at (None if unknown)
+    pub(crate) original_bpm: Option<f32>,
+    /// MIDI note the audio sounds at unpitched (None = use detected pitch)
+    pub(crate) root_note: Option<u8>,
+    /// Broad kind of sound, for grouping in the UI
+    pub(crate) category: SoundCategory,
+}
+
+impl SoundMetadata {
+    pub(crate) const fn new() -> Self {
+        Self {
+            name: String::new(),
+            original_bpm: None,
+            root_note: None,
+            category: SoundCategory::Other,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set a sound's display name (truncated to 64 bytes)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sound_name(&mut self, sound_index: usize, name: &str) {
+        let Some(sound) = self.sounds.get_mut(sound_index) else {
+            return;
+        };
+        let mut end = name.len().min(MAX_NAME_BYTES);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        sound.metadata.name = name[..end].to_string();
+    }
+
+    /// Display name of a sound (empty if unset)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_name(&self, sound_index: usize) -> String {
+        self.sounds.get(sound_index).map_or_else(String::new, |sound| sound.metadata.name.clone())
+    }
+
+    /// Set the tempo a sound was recorded at (0 = unknown)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sound_original_bpm(&mut self, sound_index: usize, bpm: f32) {
+        if let Some(sound) = self.sounds.get_mut(sound_index) {
+            sound.metadata.original_bpm = (bpm > 0.0).then(|| bpm.clamp(20.0, 300.0));
+        }
+    }
+
+    /// Tempo a sound was recorded at (0 if unknown)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_original_bpm(&self, sound_index: usize) -> f32 {
+        self.sounds.get(sound_index).and_then(|sound| sound.metadata.original_bpm).unwrap_or(0.0)
+    }
+
+    /// Set the MIDI root note of a sound (-1 = fall back to the detected pitch)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sound_root_note(&mut self, sound_index: usize, note: i32) {
+        if let Some(sound) = self.sounds.get_mut(sound_index) {
+            sound.metadata.root_note = u8::try_from(note).ok().filter(|&note| note <= 127);
+        }
+    }
+
+    /// MIDI root note of a sound: the one set explicitly, else the detected
+    /// note, else -1
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_root_note(&self, sound_index: usize) -> i32 {
+        match self.sounds.get(sound_index).and_then(|sound| sound.metadata.root_note) {
+            Some(note) => note as i32,
+            None => self.get_sound_detected_note(sound_index),
+        }
+    }
+
+    /// Set a sound's category
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sound_category(&mut self, sound_index: usize, category: SoundCategory) {
+        if let Some(sound) = self.sounds.get_mut(sound_index) {
+            sound.metadata.category = category;
+        }
+    }
+
+    /// Category of a sound
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_category(&self, sound_index: usize) -> SoundCategory {
+        self.sounds.get(sound_index).map_or(SoundCategory::Other, |sound| sound.metadata.category)
+    }
+
+    /// Time-stretch a sound in place from its original BPM to the session BPM
+    ///
+    /// Its original BPM is updated to the session BPM. Returns false if the
+    /// original BPM is unknown or the stretch fails.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn conform_sound_to_session_bpm(&mut self, sound_index: usize) -> bool {
+        let Some(original_bpm) = self.sounds.get(sound_index).and_then(|sound| sound.metadata.original_bpm) else {
+            return false;
+        };
+        if !self.stretch_sound_to_bpm(sound_index, original_bpm, sound_index) {
+            return false;
+        }
+        self.sounds[sound_index].metadata.original_bpm = Some(self.bpm);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_belongs_to_slot() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 100]);
+        engine.set_sound_name(0, "Amen break");
+        engine.set_sound_original_bpm(0, 136.0);
+        engine.set_sound_category(0, SoundCategory::Loop);
+        assert_eq!(engine.get_sound_root_note(0), -1);
+        engine.set_sound_root_note(0, 60);
+
+        engine.load_sound(0, &[0.25; 100]);
+        assert_eq!(engine.get_sound_name(0), "Amen break");
+        assert_eq!(engine.get_sound_original_bpm(0), 136.0);
+        assert_eq!(engine.get_sound_root_note(0), 60);
+
+        engine.unload_sound(0);
+        assert_eq!(engine.get_sound_name(0), "");
+        assert_eq!(engine.get_sound_category(0), SoundCategory::Other);
+    }
+}
@@ -0,0 +1,444 @@
+//! MIDI note input
+//!
+//! MIDI controllers play the engine through their own mapping table rather
+//! than through keyboard key codes: each entry maps a note range on one
+//! channel (or all of them) to a sound. Ranges with a root note play
+//! chromatically, others play every note at the sound's own pitch, as drum
+//! pads expect. Velocity scales the voice volume through the mapping's
+//! velocity curve.
+//!
+//! The pitch wheel bends every voice within a configurable range.
+//!
+//! Per-note expression (MPE-lite) reaches only the voices of one note:
+//! polyphonic pressure swells their volume and per-note pitch bend offsets
+//! their pitch, so expressive controllers can shape chords note by note.
+//!
+//! `midi_panic` silences everything MIDI may have left behind, and the
+//! channel mode messages All Sound Off (CC 120), Reset All Controllers
+//! (CC 121) and All Notes Off (CC 123) do the same for one channel.
+//!
+//! Program changes select key mapping banks (program N = bank N), either at
+//! the next block or at the next bar, so footswitches can move between
+//! sections of a set.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::performance::Performed;
+use crate::release::DEFAULT_RELEASE_VELOCITY;
+use crate::{DspEngine, KeyMapping, OverlapMode, PlaybackMode, VoiceSource};
+
+/// Most note ranges kept
+const MAX_NOTE_RANGES: usize = 64;
+
+/// A note range mapped to a sound
+#[derive(Clone, Copy)]
+pub(crate) struct MidiNoteRange {
+    /// Channel listened to (None = every channel)
+    pub(crate) channel: Option<u8>,
+    pub(crate) low_note: u8,
+    pub(crate) high_note: u8,
+    /// Note that plays the sound unpitched (None = every note does)
+    pub(crate) root_note: Option<u8>,
+    /// Settings voices start with; the pitch follows the note
+    pub(crate) mapping: KeyMapping,
+}
+
+impl MidiNoteRange {
+    fn matches(&self, channel: u8, note: u8) -> bool {
+        self.channel.is_none_or(|c| c == channel) && (self.low_note..=self.high_note).contains(&note)
+    }
+}
+
+pub(crate) struct MidiInput {
+    /// Checked in order; the first matching range plays
+    pub(crate) note_ranges: Vec<MidiNoteRange>,
+    /// Channel program changes are taken from (None = every channel)
+    program_channel: Option<u8>,
+    /// Hold program changes until the next bar
+    program_quantize: bool,
+    /// Bank waiting for the bar starting at the given sample position
+    queued_bank: Option<(usize, u64)>,
+    /// Pitch wheel position (-1.0 to 1.0)
+    pitch_bend: f32,
+    /// Semitones the wheel bends at full throw
+    bend_range: f32,
+    /// Semitones a per-note bend reaches at full throw
+    note_bend_range: f32,
+}
+
+impl MidiInput {
+    pub(crate) const fn new() -> Self {
+        Self {
+            note_ranges: Vec::new(),
+            program_channel: None,
+            program_quantize: false,
+            queued_bank: None,
+            pitch_bend: 0.0,
+            bend_range: 2.0,
+            note_bend_range: 48.0,
+        }
+    }
+
+    /// Playback rate multiplier of the current pitch wheel position
+    #[inline]
+    pub(crate) fn pitch_bend_ratio(&self) -> f32 {
+        2.0_f32.powf(self.pitch_bend * self.bend_range / 12.0)
+    }
+}
+
+impl DspEngine {
+    /// Return the pitch wheel, mod wheel and aftertouch to rest
+    pub(crate) fn reset_controllers(&mut self) {
+        self.midi.pitch_bend = 0.0;
+        self.set_modulation_depth(1.0);
+        self.aftertouch.reset();
+    }
+
+    /// Request a bar-quantized bank once its bar has begun (called at the
+    /// start of each block, before `apply_pending_bank`)
+    ///
+    /// Notes reach the engine between blocks, so switching at the first
+    /// block boundary at or after the bar keeps every later note on the new
+    /// bank and every earlier one on the old.
+    #[inline]
+    pub(crate) fn apply_queued_bank(&mut self) {
+        if let Some((bank, at)) = self.midi.queued_bank {
+            if self.global_sample_position >= at {
+                self.apply_commands();
+                self.pending_bank = Some(bank);
+                self.midi.queued_bank = None;
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Map a range of MIDI notes to a sound
+    ///
+    /// # Arguments
+    /// * `channel` - MIDI channel 0-15, or -1 for every channel
+    /// * `low_note`, `high_note` - Inclusive note range (0-127)
+    /// * `root_note` - Note that plays the sound unpitched, or -1 to play
+    ///   every note of the range at the sound's own pitch
+    ///
+    /// Ranges are matched in the order they were added. Returns the range
+    /// index, or -1 if an argument is out of range or 64 ranges exist.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_midi_note_range(
+        &mut self,
+        channel: i32,
+        low_note: u8,
+        high_note: u8,
+        sound_index: usize,
+        mode: PlaybackMode,
+        overlap_mode: OverlapMode,
+        group_id: u8,
+        volume: f32,
+        root_note: i32,
+    ) -> i32 {
+        let channel = match channel {
+            -1 => None,
+            0..=15 => Some(channel as u8),
+            _ => return -1,
+        };
+        let root_note = match root_note {
+            -1 => None,
+            0..=127 => Some(root_note as u8),
+            _ => return -1,
+        };
+        if low_note > high_note || high_note > 127 || self.midi.note_ranges.len() >= MAX_NOTE_RANGES {
+            return -1;
+        }
+        if sound_index >= self.sounds.len() {
+            self.bad_sound_index(sound_index);
+            return -1;
+        }
+
+        let mut mapping = KeyMapping::new();
+        mapping.sound_index = sound_index;
+        mapping.mode = mode;
+        mapping.overlap_mode = overlap_mode;
+        mapping.group_id = group_id;
+        mapping.volume = volume.clamp(0.0, 1.0);
+        mapping.has_sound = true;
+        self.midi.note_ranges.push(MidiNoteRange { channel, low_note, high_note, root_note, mapping });
+        (self.midi.note_ranges.len() - 1) as i32
+    }
+
+    /// Remove a note range; later ranges move down one index
+    ///
+    /// Returns false if the range does not exist.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn remove_midi_note_range(&mut self, index: usize) -> bool {
+        if index >= self.midi.note_ranges.len() {
+            return false;
+        }
+        self.midi.note_ranges.remove(index);
+        true
+    }
+
+    /// Remove every note range
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_midi_note_ranges(&mut self) {
+        self.midi.note_ranges.clear();
+    }
+
+    /// Number of note ranges
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_midi_note_range_count(&self) -> usize {
+        self.midi.note_ranges.len()
+    }
+
+    /// Handle a MIDI note-on (velocity 0 is a note-off)
+    ///
+    /// A note outside every range plays the key mapping with the same
+    /// number in its channel's bank (see `set_midi_channel_bank`), or is
+    /// ignored if the channel has none.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+        if channel > 15 || note > 127 {
+            return;
+        }
+        if velocity == 0 {
+            self.midi_note_off(channel, note);
+            return;
+        }
+        // The note plays with the settings made before it
+        self.apply_commands();
+        self.record_performance(Performed::NoteOn { channel, note, velocity });
+        let (mut mapping, root_note) = match self.midi.note_ranges.iter().find(|range| range.matches(channel, note)) {
+            Some(range) => (range.mapping, range.root_note),
+            None => match self.devices.channel_bank(channel) {
+                Some(bank) => (self.bank(bank)[note as usize], None),
+                None => return,
+            },
+        };
+        // Ranges restored from a larger engine may name a missing slot
+        if !mapping.has_sound || !self.sounds[mapping.sound_index].loaded {
+            return;
+        }
+        if let Some(root) = root_note {
+            mapping.pitch_semitones = (note as i8 - root as i8).clamp(-48, 48);
+        }
+        mapping.volume *= mapping.velocity.gain(velocity);
+        self.trigger_voice(&mapping, note as u16, VoiceSource::Midi(channel, note));
+    }
+
+    /// Choose the channel program changes are taken from (-1 = every channel)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_program_change_channel(&mut self, channel: i32) {
+        self.midi.program_channel = u8::try_from(channel).ok().filter(|&channel| channel <= 15);
+    }
+
+    /// Hold bank switches from program changes until the next bar (4/4 at
+    /// the session BPM) instead of the next block
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_program_change_quantize(&mut self, enabled: bool) {
+        self.midi.program_quantize = enabled;
+        if !enabled {
+            self.midi.queued_bank = None;
+        }
+    }
+
+    /// Handle a MIDI program change: program N selects key mapping bank N
+    ///
+    /// A later program change replaces one still waiting for its bar.
+    /// Returns false if the message was ignored (other channel, or no such
+    /// bank).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_program_change(&mut self, channel: u8, program: u8) -> bool {
+        if self.midi.program_channel.is_some_and(|listened| listened != channel) {
+            return false;
+        }
+        let bank = program as usize;
+        if bank >= self.key_banks.len() {
+            return false;
+        }
+        let samples_per_bar = self.samples_per_bar();
+        if self.midi.program_quantize && samples_per_bar > 0 {
+            let next_bar = self.global_sample_position.div_ceil(samples_per_bar) * samples_per_bar;
+            self.midi.queued_bank = Some((bank, next_bar));
+            true
+        } else {
+            self.midi.queued_bank = None;
+            self.set_active_bank(bank)
+        }
+    }
+
+    /// Handle a MIDI pitch bend (14-bit, 8192 = centre); bends every voice
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_pitch_bend(&mut self, channel: u8, value: u16) {
+        self.record_performance(Performed::PitchBend { channel, value });
+        self.midi.pitch_bend = ((value.min(16383) as f32 - 8192.0) / 8192.0).max(-1.0);
+    }
+
+    /// Set how many semitones the pitch wheel bends at full throw (0-24)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_pitch_bend_range(&mut self, semitones: f32) {
+        self.midi.bend_range = semitones.clamp(0.0, 24.0);
+    }
+
+    /// Current pitch bend in semitones
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_pitch_bend(&self) -> f32 {
+        self.midi.pitch_bend * self.midi.bend_range
+    }
+
+    /// Handle polyphonic key pressure for one note
+    ///
+    /// Pressure swells the note's voices from their own volume (0) up to
+    /// twice it (127).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_poly_pressure(&mut self, channel: u8, note: u8, pressure: u8) {
+        let gain = 1.0 + pressure.min(127) as f32 / 127.0;
+        for voice in self.voices.iter_mut().filter(|voice| voice.active && voice.source == VoiceSource::Midi(channel, note)) {
+            voice.pressure_gain = gain;
+        }
+    }
+
+    /// Bend the voices of one note (14-bit, 8192 = centre)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_note_bend(&mut self, channel: u8, note: u8, value: u16) {
+        let bend = ((value.min(16383) as f32 - 8192.0) / 8192.0).max(-1.0);
+        let ratio = 2.0_f32.powf(bend * self.midi.note_bend_range / 12.0);
+        for voice in self.voices.iter_mut().filter(|voice| voice.active && voice.source == VoiceSource::Midi(channel, note)) {
+            voice.note_bend = ratio;
+        }
+    }
+
+    /// Set how many semitones a per-note bend reaches at full throw (0-96)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_note_bend_range(&mut self, semitones: f32) {
+        self.midi.note_bend_range = semitones.clamp(0.0, 96.0);
+    }
+
+    /// Silence everything MIDI may have left sounding or pending
+    ///
+    /// Every voice fades out over a few milliseconds, MIDI file and
+    /// performance playback stop, a bank waiting for its bar and timestamped
+    /// key events still waiting are dropped, and the pitch wheel, mod wheel
+    /// and aftertouch return to rest. Unlike `panic`, the transport keeps its position.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_panic(&mut self) {
+        self.stop_midi_file();
+        self.stop_performance_playback();
+        self.midi.queued_bank = None;
+        self.timed_input.clear();
+        self.sequencer.reset_playhead();
+        self.fade_out_voices(|_| true);
+        self.reset_controllers();
+    }
+
+    /// Handle a MIDI note-off: loop voices started by the note are released
+    /// at the default release velocity
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_note_off(&mut self, channel: u8, note: u8) {
+        self.midi_note_off_velocity(channel, note, DEFAULT_RELEASE_VELOCITY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_midi_note_ranges() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 4800]);
+        engine.load_sound(1, &[0.25; 4800]);
+        assert_eq!(engine.add_midi_note_range(9, 36, 51, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, -1), 0);
+        assert_eq!(engine.add_midi_note_range(-1, 48, 72, 1, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 60), 1);
+        assert_eq!(engine.add_midi_note_range(16, 0, 1, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, -1), -1);
+
+        // Drum channel: every pad at the sound's pitch, volume from velocity
+        engine.midi_note_on(9, 40, 127);
+        assert_eq!((engine.voices[0].sound_index, engine.voices[0].pitch), (0, 1.0));
+        // Other channels fall through to the chromatic range
+        engine.midi_note_on(0, 72, 64);
+        let voice = engine.voices[1];
+        assert_eq!((voice.sound_index, voice.pitch), (1, 2.0));
+        assert!((voice.volume - 64.0 / 127.0).abs() < 1e-6);
+
+        // Keyboard key 72 is a different trigger
+        engine.note_off(72);
+        assert!(engine.voices[1].active);
+        engine.midi_note_on(0, 72, 0);
+        assert!(!engine.voices[1].active);
+    }
+
+    #[test]
+    fn test_pitch_wheel_bends_voices() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 4800]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_pitch_bend_range(12.0);
+        engine.midi_pitch_bend(0, 16383);
+        assert!((engine.get_pitch_bend() - 12.0).abs() < 0.01);
+        engine.midi_pitch_bend(0, 0);
+        assert_eq!(engine.get_pitch_bend(), -12.0);
+
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 100]);
+        assert_eq!(engine.voices[0].position, 50.0, "an octave down plays at half speed");
+    }
+
+    #[test]
+    fn test_per_note_expression() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 4800]);
+        engine.add_midi_note_range(-1, 0, 127, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 60);
+        engine.midi_note_on(0, 60, 127);
+        engine.midi_note_on(0, 64, 127);
+
+        engine.midi_poly_pressure(0, 64, 127);
+        engine.set_note_bend_range(12.0);
+        engine.midi_note_bend(0, 60, 0);
+        assert_eq!((engine.voices[0].pressure_gain, engine.voices[0].note_bend), (1.0, 0.5));
+        assert_eq!((engine.voices[1].pressure_gain, engine.voices[1].note_bend), (2.0, 1.0));
+    }
+
+    #[test]
+    fn test_program_change_waits_for_bar() {
+        // 120 BPM at 1 kHz: one bar is 2000 samples
+        let mut engine = DspEngine::new(1000.0);
+        engine.set_program_change_channel(3);
+        engine.set_program_change_quantize(true);
+        engine.process(&mut [0.0; 2 * 500]);
+
+        assert!(!engine.midi_program_change(0, 1), "other channel");
+        assert!(!engine.midi_program_change(3, 99), "no such bank");
+        assert!(engine.midi_program_change(3, 1));
+        engine.process(&mut [0.0; 2 * 1000]);
+        assert_eq!(engine.get_active_bank(), 0);
+        engine.process(&mut [0.0; 2 * 500]);
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.get_active_bank(), 1);
+    }
+
+    #[test]
+    fn test_midi_panic_fades_everything() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.add_midi_note_range(-1, 0, 127, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, -1);
+        engine.note_on(65);
+        engine.midi_note_on(2, 60, 127);
+        engine.midi_pitch_bend(0, 0);
+
+        // All Sound Off only reaches its own channel
+        engine.control_change(1, 120, 0);
+        engine.process(&mut [0.0; 2 * 10]);
+        assert_eq!(engine.get_active_voice_count(), 2);
+
+        engine.midi_panic();
+        assert_eq!(engine.get_pitch_bend(), 0.0);
+        engine.process(&mut [0.0; 2 * 2]);
+        assert_eq!(engine.get_active_voice_count(), 2, "voices fade rather than cut");
+        engine.process(&mut [0.0; 2 * 10]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        assert!(engine.global_sample_position > 0);
+    }
+}
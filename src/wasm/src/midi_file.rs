@@ -0,0 +1,351 @@
+//! Standard MIDI file playback
+//!
+//! `load_midi_file` parses a type 0 or type 1 SMF into one time-ordered
+//! list of note events, measured in beats so playback follows the session
+//! BPM rather than the tempo stored in the file. While playing, events are
+//! fired sample-accurately from `process()` through the MIDI note input, so
+//! the file plays whatever the note ranges map its notes to.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Fractional bits of the fixed-point playback position
+const TICK_FRACTION_BITS: u32 = 32;
+
+/// Whole ticks as a fixed-point position
+#[inline]
+fn ticks_fixed(ticks: u32) -> u64 {
+    (ticks as u64) << TICK_FRACTION_BITS
+}
+
+/// A note-on (velocity > 0) or note-off (velocity 0) at a tick
+#[derive(Clone, Copy)]
+struct SequenceEvent {
+    tick: u32,
+    channel: u8,
+    note: u8,
+    velocity: u8,
+}
+
+pub(crate) struct MidiSequence {
+    /// Sorted by tick; note-offs before note-ons on the same tick
+    events: Vec<SequenceEvent>,
+    /// Ticks per quarter note
+    ticks_per_beat: u32,
+    /// End of the longest track in ticks
+    length_ticks: u32,
+    playing: bool,
+    looping: bool,
+    /// Playback position in ticks, fixed point with `TICK_FRACTION_BITS`
+    /// fractional bits so long playback doesn't drift
+    position: u64,
+    /// First event not yet fired
+    next_event: usize,
+}
+
+impl MidiSequence {
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            ticks_per_beat: 480,
+            length_ticks: 0,
+            playing: false,
+            looping: false,
+            position: 0,
+            next_event: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Whether playback fires a note at the current position
+    #[inline]
+    pub(crate) fn is_due(&self) -> bool {
+        self.playing && self.events.get(self.next_event).is_some_and(|event| ticks_fixed(event.tick) <= self.position)
+    }
+}
+
+/// Reads the chunks and events of an SMF
+struct SmfReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SmfReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Variable-length quantity (at most 4 bytes)
+    fn vlq(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Next chunk as (type, body)
+    fn chunk(&mut self) -> Option<([u8; 4], &'a [u8])> {
+        let tag = self.take(4)?;
+        let len = self.u32()? as usize;
+        Some(([tag[0], tag[1], tag[2], tag[3]], self.take(len)?))
+    }
+}
+
+/// Append the note events of one track; returns the track's end tick
+fn parse_track(body: &[u8], events: &mut Vec<SequenceEvent>) -> Option<u32> {
+    let mut r = SmfReader { bytes: body, pos: 0 };
+    let mut tick = 0u32;
+    let mut running_status = None;
+    while r.pos < body.len() {
+        tick = tick.saturating_add(r.vlq()?);
+        let mut status = r.u8()?;
+        if status < 0x80 {
+            // Running status: the byte just read is the first data byte
+            r.pos -= 1;
+            status = running_status?;
+        }
+        match status {
+            0xFF => {
+                let kind = r.u8()?;
+                let len = r.vlq()? as usize;
+                r.take(len)?;
+                if kind == 0x2F {
+                    break; // End of track
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = r.vlq()? as usize;
+                r.take(len)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                match status >> 4 {
+                    0x8 | 0x9 => {
+                        let note = r.u8()? & 0x7F;
+                        let velocity = r.u8()? & 0x7F;
+                        let velocity = if status >> 4 == 0x8 { 0 } else { velocity };
+                        events.push(SequenceEvent { tick, channel, note, velocity });
+                    }
+                    0xC | 0xD => {
+                        r.u8()?;
+                    }
+                    _ => {
+                        r.take(2)?;
+                    }
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(tick)
+}
+
+/// Parse an SMF into a sequence (None if malformed or SMPTE-timed)
+fn parse_smf(bytes: &[u8]) -> Option<MidiSequence> {
+    let mut r = SmfReader { bytes, pos: 0 };
+    let (tag, header) = r.chunk()?;
+    if &tag != b"MThd" || header.len() < 6 {
+        return None;
+    }
+    let mut h = SmfReader { bytes: header, pos: 0 };
+    let format = h.u16()?;
+    let track_count = h.u16()?;
+    let division = h.u16()?;
+    if format > 1 || division == 0 || division & 0x8000 != 0 {
+        return None;
+    }
+
+    let mut sequence = MidiSequence::new();
+    sequence.ticks_per_beat = division as u32;
+    let mut tracks = 0;
+    while tracks < track_count {
+        let (tag, body) = r.chunk()?;
+        // Unknown chunk types are skipped, as the format requires
+        if &tag == b"MTrk" {
+            let end = parse_track(body, &mut sequence.events)?;
+            sequence.length_ticks = sequence.length_ticks.max(end);
+            tracks += 1;
+        }
+    }
+    sequence.events.sort_by_key(|event| (event.tick, event.velocity > 0));
+    Some(sequence)
+}
+
+impl DspEngine {
+    /// Fire the file's events due at the current sample (called once per
+    /// frame from `process()` while playing)
+    ///
+    /// `ticks_per_sample` converts the session BPM into file ticks.
+    #[inline]
+    pub(crate) fn advance_midi_file(&mut self, ticks_per_sample: u64) {
+        loop {
+            let sequence = &self.midi_file;
+            let Some(&event) = sequence.events.get(sequence.next_event) else {
+                break;
+            };
+            if ticks_fixed(event.tick) > sequence.position {
+                break;
+            }
+            self.midi_file.next_event += 1;
+            self.midi_note_on(event.channel, event.note, event.velocity);
+        }
+
+        let sequence = &mut self.midi_file;
+        sequence.position += ticks_per_sample;
+        let length = ticks_fixed(sequence.length_ticks);
+        if sequence.position >= length && sequence.next_event == sequence.events.len() {
+            if sequence.looping && sequence.length_ticks > 0 {
+                sequence.position -= length;
+                sequence.next_event = 0;
+            } else {
+                sequence.playing = false;
+            }
+        }
+    }
+
+    /// Return playback to the start of the file (playing or not)
+    pub(crate) fn rewind_midi_file(&mut self) {
+        self.midi_file.position = 0;
+        self.midi_file.next_event = 0;
+    }
+
+    /// File ticks per output sample at the session BPM, fixed point
+    #[inline]
+    pub(crate) fn midi_file_ticks_per_sample(&self) -> u64 {
+        let ticks = self.midi_file.ticks_per_beat as f64 * self.bpm as f64 / (60.0 * self.sample_rate as f64);
+        (ticks * ticks_fixed(1) as f64).round() as u64
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Load a type 0 or type 1 standard MIDI file for playback
+    ///
+    /// Replaces any loaded file and stops playback. Returns the number of
+    /// note-on events, or -1 if the file was rejected (malformed, or timed
+    /// in SMPTE frames).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_midi_file(&mut self, bytes: &[u8]) -> i32 {
+        let Some(sequence) = parse_smf(bytes) else {
+            return -1;
+        };
+        self.stop_midi_file();
+        self.midi_file = sequence;
+        self.midi_file.events.iter().filter(|event| event.velocity > 0).count() as i32
+    }
+
+    /// Start the loaded file from its beginning
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn play_midi_file(&mut self, looping: bool) {
+        let sequence = &mut self.midi_file;
+        sequence.position = 0;
+        sequence.next_event = 0;
+        sequence.looping = looping;
+        sequence.playing = !sequence.events.is_empty();
+    }
+
+    /// Stop the file, releasing every note it may be holding
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stop_midi_file(&mut self) {
+        if !self.midi_file.playing {
+            return;
+        }
+        self.midi_file.playing = false;
+        for i in 0..self.midi_file.events.len() {
+            let event = self.midi_file.events[i];
+            if event.velocity > 0 {
+                self.midi_note_off(event.channel, event.note);
+            }
+        }
+    }
+
+    /// Whether the loaded file is playing
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_midi_file_playing(&self) -> bool {
+        self.midi_file.playing
+    }
+
+    /// Playback position in beats
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_midi_file_position_beats(&self) -> f64 {
+        self.midi_file.position as f64 / ticks_fixed(self.midi_file.ticks_per_beat) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode, VoiceSource};
+
+    /// Type 0 file, 96 ticks per beat: note 60 for a beat, then note 62
+    /// for a beat using running status
+    fn two_note_file() -> Vec<u8> {
+        let track = [
+            0x00, 0x90, 60, 100, // note on
+            0x60, 0x80, 60, 0, // note off after one beat
+            0x00, 0x90, 62, 100, // note on
+            0x60, 62, 0, // running status note on, velocity 0
+            0x00, 0xFF, 0x2F, 0x00, // end of track
+        ];
+        let mut file = b"MThd".to_vec();
+        file.extend_from_slice(&6u32.to_be_bytes());
+        file.extend_from_slice(&[0, 0, 0, 1, 0, 96]);
+        file.extend_from_slice(b"MTrk");
+        file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        file.extend_from_slice(&track);
+        file
+    }
+
+    #[test]
+    fn test_midi_file_plays_through_note_ranges() {
+        // 120 BPM at 1 kHz: one beat is 500 samples
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.add_midi_note_range(-1, 0, 127, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 60);
+        assert_eq!(engine.load_midi_file(&two_note_file()), 2);
+        assert_eq!(engine.load_midi_file(b"MThd"), -1);
+
+        let playing = |engine: &DspEngine| -> Vec<u8> {
+            let active = engine.voices.iter().filter(|voice| voice.active);
+            let midi_note = |voice: &crate::Voice| match voice.source {
+                VoiceSource::Midi(_, note) => Some(note),
+                _ => None,
+            };
+            active.filter_map(midi_note).collect()
+        };
+        engine.play_midi_file(false);
+        engine.process(&mut [0.0; 2 * 250]);
+        assert_eq!(playing(&engine), [60]);
+        engine.process(&mut [0.0; 2 * 500]);
+        assert_eq!(playing(&engine), [62], "first note released after one beat");
+
+        engine.process(&mut [0.0; 2 * 500]);
+        assert!(playing(&engine).is_empty());
+        assert!(!engine.is_midi_file_playing());
+    }
+}
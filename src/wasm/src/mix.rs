@@ -0,0 +1,455 @@
+//! Block rendering
+//!
+//! `process()` renders in chunks of up to `MIX_CHUNK` frames. Everything
+//! that can start, stop or change a voice mid-block (MIDI file notes,
+//! punches, clip launches, timestamped keys, sequencer steps and
+//! performance playback) is still checked on every frame, but the voices
+//! are only mixed up to the frame where one of those is due. Each voice
+//! then runs its own loop over the whole segment, reading its sound with
+//! the kernels in `simd` wherever no loop point or region end falls in
+//! between. Whatever follows the voice mix (metronome, master gain,
+//! clipping, recording) runs per frame as before.
+//!
+//! Segments end on the exact sample of the next input event, so voices
+//! start and stop on the same samples as when the engine ran one frame at
+//! a time.
+
+use crate::bandlimit::{mix_band_limited, BAND_LIMIT_MIN_STEP};
+use crate::debug_log::LogCode;
+use crate::events::EngineEventKind;
+use crate::interpolation::{mix_interpolated, InterpolationQuality};
+use crate::{soft_clip, DspEngine, PlaybackMode, Voice};
+
+/// Most frames mixed in one go (sizes the stack buffers)
+const MIX_CHUNK: usize = 128;
+
+/// Settings that hold for a whole `process()` block
+struct Block {
+    samples_per_beat: u64,
+    samples_per_bar: u64,
+    samples_per_step: f64,
+    midi_file_ticks: u64,
+    pitch_bend: f32,
+    /// The group bus being recorded, if any
+    recorded_group: Option<u8>,
+    /// A NaN/inf was already reported this block
+    non_finite_logged: bool,
+}
+
+/// Frames from `position` in steps of `step` before reaching `limit`, at
+/// most `max`
+#[inline]
+fn frames_before(position: f64, step: f64, limit: f64, max: usize) -> usize {
+    if position >= limit {
+        return 0;
+    }
+    if step <= 0.0 {
+        return max;
+    }
+    let mut frames = ((limit - position) / step).ceil().min(max as f64) as usize;
+    // Rounding may put the last position on the limit
+    while frames > 0 && position + (frames - 1) as f64 * step >= limit {
+        frames -= 1;
+    }
+    frames
+}
+
+impl DspEngine {
+    pub(crate) fn render(&mut self, input: &[f32], output: &mut [f32]) {
+        // Clear output buffer
+        output.fill(0.0);
+        self.cpu_meter.record_block(output.len() / 2);
+        self.apply_pending_swaps();
+        self.apply_pending_config();
+        self.apply_queued_bank();
+        self.apply_pending_bank();
+        self.apply_pending_mappings();
+        self.apply_commands();
+        self.apply_control_block();
+        self.update_vibrato(output.len() / 2);
+
+        let mut block = Block {
+            samples_per_beat: (self.sample_rate * 60.0 / self.bpm) as u64,
+            samples_per_bar: self.samples_per_bar(),
+            samples_per_step: self.samples_per_step(),
+            midi_file_ticks: self.midi_file_ticks_per_sample(),
+            pitch_bend: self.midi.pitch_bend_ratio(),
+            recorded_group: self.recorder.group(),
+            non_finite_logged: false,
+        };
+
+        let frames = output.len() / 2;
+        let mut start = 0;
+        while start < frames {
+            let end = (start + MIX_CHUNK).min(frames);
+            self.render_chunk(&mut block, input, output, start, end);
+            start = end;
+        }
+
+        // Latency test impulse goes out untouched by volume and clipping
+        self.latency.emit(output);
+    }
+
+    /// Render frames `start..end` (at most `MIX_CHUNK`) of the block
+    fn render_chunk(&mut self, block: &mut Block, input: &[f32], output: &mut [f32], start: usize, end: usize) {
+        let first_sample = self.global_sample_position;
+        let mut mix = [[0.0_f32; 2]; MIX_CHUNK];
+        let mut bus = [0.0_f32; MIX_CHUNK];
+        // Frames before `mixed` are mixed and written out
+        let mut mixed = start;
+
+        for frame in start..end {
+            let now = first_sample + (frame - start) as u64;
+            if frame > mixed && self.input_due(now, block) {
+                self.global_sample_position = first_sample + (mixed - start) as u64;
+                let segment = mixed - start..frame - start;
+                self.mix_voices(block, &mut mix[segment.clone()], &mut bus[segment.clone()]);
+                self.finish_frames(block, input, output, mixed, &mix[segment.clone()], &bus[segment]);
+                mixed = frame;
+            }
+            self.global_sample_position = now;
+
+            // Notes from a playing MIDI file start on their exact sample
+            if self.midi_file.is_playing() {
+                self.advance_midi_file(block.midi_file_ticks);
+            }
+            // So do punches, clip launches, timestamped key events and
+            // sequencer steps
+            if self.punch.is_pending() {
+                self.advance_punch();
+            }
+            if self.clips.is_pending() {
+                self.advance_clips();
+            }
+            if !self.timed_input.is_empty() {
+                self.fire_timed_events();
+            }
+            if self.sequencer.is_active() {
+                self.advance_sequencer(block.samples_per_step);
+            }
+            if self.performance.is_playing() {
+                self.advance_performance();
+            }
+        }
+
+        self.global_sample_position = first_sample + (mixed - start) as u64;
+        let segment = mixed - start..end - start;
+        self.mix_voices(block, &mut mix[segment.clone()], &mut bus[segment.clone()]);
+        self.finish_frames(block, input, output, mixed, &mix[segment.clone()], &bus[segment]);
+    }
+
+    /// Whether any input acts on the voices at sample `now`
+    #[inline]
+    fn input_due(&self, now: u64, block: &Block) -> bool {
+        self.midi_file.is_due()
+            || self.punch.is_due(now)
+            || self.clips.is_due(now)
+            || self.timed_input.is_due(now)
+            || self.performance.is_due(now)
+            || (self.sequencer.is_active() && self.sequencer.is_due(now, block.samples_per_step))
+    }
+
+    /// Add every active voice to the stereo `mix` (and the recorded group's
+    /// voices, mixed to mono, to `bus`) for the frames starting at the
+    /// current sample
+    fn mix_voices(&mut self, block: &Block, mix: &mut [[f32; 2]], bus: &mut [f32]) {
+        let frames = mix.len();
+        let now = self.global_sample_position;
+
+        // Sidechain modulation and group crossfades for each frame
+        let mut modulation = [1.0_f32; MIX_CHUNK];
+        for (offset, modulation) in modulation[..frames].iter_mut().enumerate() {
+            *modulation = self.calculate_modulation(now + offset as u64);
+        }
+        let mut fade_progress = [1.0_f32; MIX_CHUNK];
+        self.mixer.fade_progress_ahead(&mut fade_progress[..frames]);
+        let mut levels = [0.0_f32; MIX_CHUNK];
+        let mut levels_right = [0.0_f32; MIX_CHUNK];
+
+        let Self {
+            voices,
+            sounds,
+            retired_sounds,
+            mixer,
+            events,
+            debug_log,
+            interpolation,
+            sinc_table,
+            band_limit,
+            band_limit_kernel,
+            smoothing,
+            sample_rate,
+            ..
+        } = self;
+        for (slot, voice) in voices.iter_mut().enumerate() {
+            if !voice.active {
+                continue;
+            }
+            let recorded = block.recorded_group == Some(voice.group_id);
+            // Apply volume and group channel (smoothed), pressure, envelope,
+            // release and optional modulation
+            let gain = |voice: &mut Voice, frame: usize| {
+                let target = voice.volume * mixer.group_gain_at(voice.group_id, fade_progress[frame]);
+                voice.gain = smoothing.follow(voice.gain, target);
+                let voice_mod = if voice.modulation_enabled { modulation[frame] } else { 1.0 };
+                let envelope = voice.envelope.next();
+                voice.gain * voice.pressure_gain * envelope * voice.release_gain * voice_mod
+            };
+
+            // Sound was unloaded or replaced: play out the old audio
+            if voice.swap_fade_out {
+                for frame in 0..frames {
+                    let (left, right) = voice.fade_out_retired(&retired_sounds[voice.swap_source]);
+                    let gain = gain(voice, frame);
+                    mix[frame][0] += left * gain;
+                    mix[frame][1] += right * gain;
+                    if recorded {
+                        bus[frame] += (left + right) * 0.5 * gain;
+                    }
+                    if voice.swap_fade_remaining == 0 {
+                        voice.active = false;
+                        events.push(EngineEventKind::VoiceStopped, voice.trigger, slot as u32, now + frame as u64);
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let sound = &sounds[voice.sound_index];
+            if !sound.loaded {
+                voice.active = false;
+                debug_log.push(LogCode::SoundNotLoaded, voice.sound_index as u32, now);
+                events.push(EngineEventKind::VoiceStopped, voice.trigger, slot as u32, now);
+                continue;
+            }
+
+            let (region_start, region_length) = voice.region(sound);
+            let stereo = sound.is_stereo();
+            // Position advance: pitch factor, per-note bend, vibrato and the pitch wheel
+            let step = (voice.pitch * voice.note_bend * voice.vibrato * block.pitch_bend) as f64;
+            let band_limited = *band_limit && step > BAND_LIMIT_MIN_STEP;
+            // Linear reads need the sample after the position; the others
+            // repeat the last sample
+            let read_ahead = if *interpolation == InterpolationQuality::Linear && !band_limited { 1 } else { 0 };
+
+            // BPM-sync for loop mode: quantize to 1/8 beat
+            let mut sync_length = f64::INFINITY;
+            if voice.mode == PlaybackMode::Loop {
+                let samples_per_eighth = block.samples_per_beat / 2;
+                let sound_duration = region_length as f64 / voice.pitch as f64;
+                // How many 1/8 notes this sound should occupy
+                let eighth_notes = (sound_duration / samples_per_eighth as f64).round() as u64;
+                let target_length = eighth_notes * samples_per_eighth;
+                if target_length > 0 {
+                    sync_length = target_length as f64;
+                }
+            }
+
+            // Single shots release in time to end with the sound
+            let release_samples = voice.release_ms * 0.001 * *sample_rate;
+            let mut tail_at = f64::INFINITY;
+            if voice.mode == PlaybackMode::SingleShot && release_samples >= 1.0 && voice.release_step == 0.0 {
+                tail_at = region_length as f64 - release_samples as f64 * step;
+            }
+
+            let mut frame = 0;
+            while frame < frames {
+                if voice.position >= tail_at {
+                    // One frame more than remain, so the sound ends before
+                    // the release does
+                    let remaining = ((region_length as f64 - voice.position) / step).ceil() + 1.0;
+                    voice.release_step = voice.release_gain / remaining as f32;
+                    tail_at = f64::INFINITY;
+                }
+                if voice.position as usize >= region_length {
+                    if voice.mode == PlaybackMode::Loop && region_length > 0 {
+                        // Loop back to start, silent for this frame
+                        voice.position -= region_length as f64;
+                        frame += 1;
+                        continue;
+                    }
+                    // Single shot: deactivate when done
+                    voice.active = false;
+                    events.push(EngineEventKind::VoiceEnded, voice.trigger, slot as u32, now + frame as u64);
+                    break;
+                }
+                if voice.position >= sync_length {
+                    voice.position %= sync_length;
+                    frame += 1;
+                    continue;
+                }
+
+                // Read a run of frames in one go up to the end of the region
+                // or the sync point; crossfades and a linear read of the
+                // last sample go one frame at a time
+                let limit = ((region_length - read_ahead) as f64).min(sync_length).min(tail_at);
+                let run = match voice.swap_fade_remaining {
+                    0 => frames_before(voice.position, step, limit, frames - frame),
+                    _ => 0,
+                };
+                let run = if run == 0 {
+                    let (left, right) = voice.frame_at(sound);
+                    let mut level = (left * sound.gain, right * sound.gain);
+                    if voice.swap_fade_remaining > 0 {
+                        level = voice.blend_swap(level, &retired_sounds[voice.swap_source]);
+                    }
+                    (levels[frame], levels_right[frame]) = level;
+                    voice.position += step;
+                    1
+                } else {
+                    let position = voice.position;
+                    let read = |channel: &[f32], levels: &mut [f32]| {
+                        levels.fill(0.0);
+                        let region = &channel[region_start..region_start + region_length];
+                        if band_limited {
+                            mix_band_limited(band_limit_kernel, region, position, step, sound.gain, levels);
+                        } else {
+                            mix_interpolated(*interpolation, sinc_table, region, position, step, sound.gain, levels);
+                        }
+                    };
+                    read(&sound.samples, &mut levels[frame..frame + run]);
+                    if stereo {
+                        read(&sound.right, &mut levels_right[frame..frame + run]);
+                    }
+                    voice.position += run as f64 * step;
+                    run
+                };
+
+                for frame in frame..frame + run {
+                    let gain = gain(voice, frame);
+                    let left = levels[frame] * gain;
+                    let right = if stereo { levels_right[frame] * gain } else { left };
+                    mix[frame][0] += left;
+                    mix[frame][1] += right;
+                    if recorded {
+                        bus[frame] += (left + right) * 0.5;
+                    }
+                    if voice.release_step > 0.0 {
+                        voice.release_gain -= voice.release_step;
+                        if voice.release_gain <= 0.0 {
+                            voice.active = false;
+                            events.push(EngineEventKind::VoiceStopped, voice.trigger, slot as u32, now + frame as u64);
+                            break;
+                        }
+                    }
+                }
+                if !voice.active {
+                    break;
+                }
+                frame += run;
+            }
+        }
+    }
+
+    /// Finish mixed frames from `first` on: metronome, master gain,
+    /// clipping, metering, recording and output
+    fn finish_frames(
+        &mut self,
+        block: &mut Block,
+        input: &[f32],
+        output: &mut [f32],
+        first: usize,
+        mix: &[[f32; 2]],
+        bus: &[f32],
+    ) {
+        for (offset, (&[left, right], &bus_sample)) in mix.iter().zip(bus).enumerate() {
+            let frame = first + offset;
+
+            // Add metronome
+            let click = self.generate_metronome_sample();
+            let mut samples = [left + click, right + click];
+
+            // Apply master volume
+            let master = self.mixer.master_gain(self.master_volume);
+            self.smoothing.master = self.smoothing.follow(self.smoothing.master, master);
+            self.mixer.advance();
+
+            for sample in samples.iter_mut() {
+                *sample *= self.smoothing.master;
+                // Never let a NaN/inf reach the speakers; report once per block
+                if !sample.is_finite() {
+                    if !block.non_finite_logged {
+                        self.debug_log.push(LogCode::NonFiniteSample, frame as u32, self.global_sample_position);
+                        block.non_finite_logged = true;
+                    }
+                    *sample = 0.0;
+                }
+
+                // Soft clipping to prevent harsh distortion
+                *sample = soft_clip(*sample);
+            }
+            let [left, right] = samples;
+            // Metering and recording take the mono mix
+            let sample = (left + right) * 0.5;
+
+            self.scope.push(sample);
+
+            let on_bar = block.samples_per_bar > 0 && self.global_sample_position.is_multiple_of(block.samples_per_bar);
+            let recorded = match block.recorded_group {
+                Some(_) if bus_sample.is_finite() => bus_sample,
+                Some(_) => 0.0,
+                None => sample,
+            };
+            if let Some(kind) = self.recorder.push(recorded, on_bar) {
+                self.events.push(kind, 0, self.recorder.sound_index() as u32, self.global_sample_position);
+            }
+            if self.looper.is_active() {
+                self.feed_looper(input.get(frame).copied().unwrap_or(0.0), on_bar);
+            }
+
+            // Write to stereo output
+            output[frame * 2] = left;
+            output[frame * 2 + 1] = right;
+
+            // Advance global position
+            self.global_sample_position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ModulationPreset, OverlapMode};
+
+    #[test]
+    fn test_blocks_match_frame_by_frame_rendering() {
+        // 120 BPM at 1 kHz: one step is 125 samples
+        let sound: Vec<f32> = (0..700).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        let mut engines = [DspEngine::new(1000.0), DspEngine::new(1000.0)];
+        for engine in engines.iter_mut() {
+            engine.load_sound(0, &sound);
+            engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 0.8, 7, true);
+            engine.set_key_mapping(66, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 1, 1.0, 0, false);
+            engine.set_modulation_preset(ModulationPreset::EighthSidechain);
+            assert!(engine.set_step(65, 0, 127, 0, 1.0, 0.3));
+            assert!(engine.set_step(66, 3, 100, -5, 1.0, -0.1));
+            assert!(engine.set_step(66, 9, 100, 0, 1.0, 0.0));
+            engine.set_sequencer_playing(true);
+        }
+
+        let mut frame_by_frame = Vec::new();
+        for _ in 0..3000 {
+            let mut frame = [0.0; 2];
+            engines[0].process(&mut frame);
+            frame_by_frame.push(frame[0]);
+        }
+        let mut blocks = vec![0.0; 2 * 3000];
+        for block in blocks.chunks_mut(2 * 500) {
+            engines[1].process(block);
+        }
+        assert!(frame_by_frame.iter().any(|&sample| sample != 0.0));
+        for (frame, (&expected, &rendered)) in frame_by_frame.iter().zip(blocks.iter().step_by(2)).enumerate() {
+            assert!((expected - rendered).abs() < 1e-5, "frame {frame}: {expected} vs {rendered}");
+        }
+    }
+
+    #[test]
+    fn test_frames_before_stops_short_of_the_limit() {
+        assert_eq!(frames_before(0.0, 1.0, 10.0, 64), 10);
+        assert_eq!(frames_before(0.5, 1.5, 10.0, 64), 7);
+        assert_eq!(frames_before(9.0, 0.5, 10.0, 64), 2);
+        assert_eq!(frames_before(10.0, 1.0, 10.0, 64), 0);
+        assert_eq!(frames_before(0.0, 0.1, 10.0, 8), 8);
+    }
+}
@@ -0,0 +1,247 @@
+//! Group mixer and mixer scenes
+//!
+//! Every voice plays through the channel of its key's group (0-255), which
+//! has a volume and a mute. Named scenes capture the group channels and
+//! the master volume and can be recalled instantly or with a timed
+//! crossfade, independent of key mappings and presets.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::cc::CcTarget;
+use crate::commands::Command;
+use crate::history::ConfigChange;
+use crate::performance::Performed;
+use crate::DspEngine;
+
+/// Longest stored scene name in bytes
+const MAX_SCENE_NAME_BYTES: usize = 64;
+
+/// Most scenes kept
+const MAX_SCENES: usize = 64;
+
+/// A stored mixer setting
+#[derive(Clone)]
+pub(crate) struct MixerScene {
+    pub(crate) name: String,
+    pub(crate) group_volumes: [f32; 256],
+    pub(crate) group_mutes: [bool; 256],
+    pub(crate) master_volume: f32,
+}
+
+pub(crate) struct GroupMixer {
+    pub(crate) volumes: [f32; 256],
+    pub(crate) mutes: [bool; 256],
+    pub(crate) scenes: Vec<MixerScene>,
+    /// Group gains and master volume a crossfade started from
+    fade_from: [f32; 256],
+    fade_from_master: f32,
+    /// 0.0 at the start of a crossfade, 1.0 once it has finished
+    fade_progress: f32,
+    /// Progress added per sample
+    fade_step: f32,
+}
+
+impl GroupMixer {
+    pub(crate) const fn new() -> Self {
+        Self {
+            volumes: [1.0; 256],
+            mutes: [false; 256],
+            scenes: Vec::new(),
+            fade_from: [1.0; 256],
+            fade_from_master: 1.0,
+            fade_progress: 1.0,
+            fade_step: 0.0,
+        }
+    }
+
+    /// Gain a group's channel is heading to
+    #[inline]
+    fn target_gain(&self, group: u8) -> f32 {
+        if self.mutes[group as usize] { 0.0 } else { self.volumes[group as usize] }
+    }
+
+    /// Current gain of a group's channel, following any crossfade
+    #[inline]
+    pub(crate) fn group_gain(&self, group: u8) -> f32 {
+        let from = self.fade_from[group as usize];
+        from + (self.target_gain(group) - from) * self.fade_progress
+    }
+
+    /// Current master gain, following any crossfade towards `master_volume`
+    #[inline]
+    pub(crate) fn master_gain(&self, master_volume: f32) -> f32 {
+        self.fade_from_master + (master_volume - self.fade_from_master) * self.fade_progress
+    }
+
+    /// Gain of a group's channel at crossfade `progress` (see
+    /// `fade_progress_ahead`)
+    #[inline]
+    pub(crate) fn group_gain_at(&self, group: u8, progress: f32) -> f32 {
+        let from = self.fade_from[group as usize];
+        from + (self.target_gain(group) - from) * progress
+    }
+
+    /// Crossfade progress of each of the next `progress.len()` samples, as
+    /// `advance` will step it
+    #[inline]
+    pub(crate) fn fade_progress_ahead(&self, progress: &mut [f32]) {
+        let mut current = self.fade_progress;
+        for progress in progress.iter_mut() {
+            *progress = current;
+            if current < 1.0 {
+                current = (current + self.fade_step).min(1.0);
+            }
+        }
+    }
+
+    /// Advance a running crossfade by one sample
+    #[inline]
+    pub(crate) fn advance(&mut self) {
+        if self.fade_progress < 1.0 {
+            self.fade_progress = (self.fade_progress + self.fade_step).min(1.0);
+        }
+    }
+
+    /// Freeze the current gains as the start of a crossfade lasting `samples`
+    fn start_fade(&mut self, master_volume: f32, samples: u32) {
+        self.fade_from_master = self.master_gain(master_volume);
+        self.fade_from = std::array::from_fn(|group| self.group_gain(group as u8));
+        if samples == 0 {
+            self.fade_progress = 1.0;
+        } else {
+            self.fade_progress = 0.0;
+            self.fade_step = 1.0 / samples as f32;
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set the volume of a group's channel (0.0 to 1.0)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_group_volume(&mut self, group_id: u8, volume: f32) {
+        self.record_queued_change(ConfigChange::GroupVolume(group_id));
+        let value = volume.clamp(0.0, 1.0);
+        self.record_performance(Performed::Parameter { target: CcTarget::GroupVolume, index: group_id, value });
+        self.queue_command(Command::Parameter { target: CcTarget::GroupVolume, index: group_id, value });
+    }
+
+    /// Volume of a group's channel
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_group_volume(&self, group_id: u8) -> f32 {
+        let queued = self.commands.queued_parameter(CcTarget::GroupVolume, group_id);
+        queued.unwrap_or(self.mixer.volumes[group_id as usize])
+    }
+
+    /// Mute or unmute a group's channel
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_group_mute(&mut self, group_id: u8, muted: bool) {
+        self.record_change(ConfigChange::GroupMute(group_id));
+        self.mixer.mutes[group_id as usize] = muted;
+    }
+
+    /// Whether a group's channel is muted
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_group_mute(&self, group_id: u8) -> bool {
+        self.mixer.mutes[group_id as usize]
+    }
+
+    /// Store the group channels and master volume as a named scene
+    ///
+    /// A scene with the same name is replaced. Returns false if the scene
+    /// limit (64) is reached.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn store_mixer_scene(&mut self, name: &str) -> bool {
+        let mut end = name.len().min(MAX_SCENE_NAME_BYTES);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        let scene = MixerScene {
+            name: name[..end].to_string(),
+            group_volumes: self.mixer.volumes,
+            group_mutes: self.mixer.mutes,
+            master_volume: self.master_volume,
+        };
+        let scenes = &mut self.mixer.scenes;
+        match scenes.iter().position(|stored| stored.name == scene.name) {
+            Some(index) => scenes[index] = scene,
+            None if scenes.len() < MAX_SCENES => scenes.push(scene),
+            None => return false,
+        }
+        true
+    }
+
+    /// Recall a named scene, crossfading to it over `fade_ms` (0 = instantly)
+    ///
+    /// Returns false if no scene has that name.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn recall_mixer_scene(&mut self, name: &str, fade_ms: f32) -> bool {
+        let Some(index) = self.mixer.scenes.iter().position(|scene| scene.name == name) else {
+            return false;
+        };
+        self.record_change(ConfigChange::Bulk);
+        let fade_samples = (fade_ms.clamp(0.0, 60_000.0) * 0.001 * self.sample_rate) as u32;
+        self.mixer.start_fade(self.master_volume, fade_samples);
+
+        let scene = &self.mixer.scenes[index];
+        let (volumes, mutes, master) = (scene.group_volumes, scene.group_mutes, scene.master_volume);
+        self.mixer.volumes = volumes;
+        self.mixer.mutes = mutes;
+        self.master_volume = master;
+        true
+    }
+
+    /// Delete a named scene; returns false if no scene has that name
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn delete_mixer_scene(&mut self, name: &str) -> bool {
+        let count = self.mixer.scenes.len();
+        self.mixer.scenes.retain(|scene| scene.name != name);
+        self.mixer.scenes.len() != count
+    }
+
+    /// Number of stored mixer scenes
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_mixer_scene_count(&self) -> usize {
+        self.mixer.scenes.len()
+    }
+
+    /// Name of the scene at `index` (empty if out of range)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_mixer_scene_name(&self, index: usize) -> String {
+        self.mixer.scenes.get(index).map_or_else(String::new, |scene| scene.name.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_mixer_scene_crossfade() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 3, 1.0, 0, false);
+        // Only the scene crossfade shapes the gain
+        engine.set_smoothing_time(0.0);
+        assert!(engine.store_mixer_scene("full"));
+        engine.set_group_mute(3, true);
+        assert!(engine.store_mixer_scene("muted"));
+        assert!(engine.recall_mixer_scene("full", 0.0));
+        assert!(!engine.recall_mixer_scene("missing", 0.0));
+
+        engine.note_on(65);
+        let mut output = [0.0; 2 * 4];
+        engine.process(&mut output);
+        assert_eq!(output[0], 0.5);
+
+        // Fades to silence over 10 samples
+        assert!(engine.recall_mixer_scene("muted", 10.0));
+        let mut output = [0.0; 2 * 12];
+        engine.process(&mut output);
+        let left: Vec<f32> = output.iter().step_by(2).copied().collect();
+        assert!(left.windows(2).all(|w| w[1] <= w[0]));
+        assert!(left[1] > 0.3 && left[11] == 0.0);
+        assert!(engine.get_group_mute(3));
+    }
+}
@@ -0,0 +1,147 @@
+//! Scene morphing
+//!
+//! Two scenes (A and B) capture the continuous mix parameters: every key's
+//! volume plus the master and metronome levels. A single morph amount
+//! crossfades the live settings between them, so a performer can glide from
+//! one mix to another with one control. Discrete settings (sounds, modes,
+//! groups) are not morphed.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::cc::CcTarget;
+use crate::commands::Command;
+use crate::performance::Performed;
+use crate::{DspEngine, VoiceSource};
+
+/// Continuous parameters captured by a scene
+#[derive(Clone, Copy)]
+pub(crate) struct MorphScene {
+    pub(crate) key_volumes: [f32; 256],
+    pub(crate) master_volume: f32,
+    pub(crate) metronome_volume: f32,
+}
+
+pub(crate) struct Morph {
+    /// Scene A and scene B
+    pub(crate) scenes: [Option<MorphScene>; 2],
+    /// 0.0 = scene A, 1.0 = scene B
+    pub(crate) amount: f32,
+}
+
+impl Morph {
+    pub(crate) const fn new() -> Self {
+        Self { scenes: [None, None], amount: 0.0 }
+    }
+
+    /// Settings at `amount` between scene A and scene B, once both are stored
+    pub(crate) fn blend(&self, amount: f32) -> Option<MorphScene> {
+        let [Some(a), Some(b)] = self.scenes else {
+            return None;
+        };
+        let t = amount.clamp(0.0, 1.0);
+        Some(MorphScene {
+            key_volumes: std::array::from_fn(|key| lerp(a.key_volumes[key], b.key_volumes[key], t)),
+            master_volume: lerp(a.master_volume, b.master_volume, t),
+            metronome_volume: lerp(a.metronome_volume, b.metronome_volume, t),
+        })
+    }
+}
+
+#[inline]
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+impl DspEngine {
+    /// Crossfade the live volumes to `amount` (real-time safe)
+    pub(crate) fn blend_morph(&mut self, amount: f32) {
+        let Some(blend) = self.morph.blend(amount) else {
+            return;
+        };
+        self.morph.amount = amount.clamp(0.0, 1.0);
+
+        for (mapping, volume) in self.key_mappings.iter_mut().zip(blend.key_volumes) {
+            mapping.volume = volume;
+        }
+        for voice in self.voices.iter_mut().filter(|voice| voice.active && voice.source == VoiceSource::Key) {
+            if let Some(mapping) = self.key_mappings.get(voice.trigger as usize) {
+                voice.volume = mapping.volume;
+            }
+        }
+        self.master_volume = blend.master_volume;
+        self.metronome_volume = blend.metronome_volume;
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Capture the current volumes as morph scene A (0) or B (1)
+    ///
+    /// Returns false if `scene` is not 0 or 1.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn store_morph_scene(&mut self, scene: u8) -> bool {
+        self.apply_commands();
+        let Some(slot) = self.morph.scenes.get_mut(scene as usize) else {
+            return false;
+        };
+        *slot = Some(MorphScene {
+            key_volumes: std::array::from_fn(|key| self.key_mappings[key].volume),
+            master_volume: self.master_volume,
+            metronome_volume: self.metronome_volume,
+        });
+        true
+    }
+
+    /// Forget both morph scenes
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_morph_scenes(&mut self) {
+        self.morph = Morph::new();
+    }
+
+    /// Blend the live volumes between scene A (0.0) and scene B (1.0)
+    ///
+    /// Voices already playing follow the new key volumes. Does nothing
+    /// until both scenes are stored.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_morph(&mut self, amount: f32) {
+        let value = amount.clamp(0.0, 1.0);
+        self.record_performance(Performed::Parameter { target: CcTarget::Morph, index: 0, value });
+        self.queue_command(Command::Parameter { target: CcTarget::Morph, index: 0, value });
+    }
+
+    /// Current morph amount
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_morph(&self) -> f32 {
+        match self.morph.scenes {
+            [Some(_), Some(_)] => self.commands.queued_parameter(CcTarget::Morph, 0).unwrap_or(self.morph.amount),
+            _ => self.morph.amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_morph_between_scenes() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 48000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_master_volume(0.2);
+        assert!(engine.store_morph_scene(0));
+        engine.set_key_volume(65, 0.0);
+        engine.set_master_volume(1.0);
+        assert!(engine.store_morph_scene(1));
+        assert!(!engine.store_morph_scene(2));
+
+        engine.note_on(65);
+        engine.set_morph(0.25);
+        assert_eq!(engine.get_morph(), 0.25);
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.key_mappings[65].volume, 0.75);
+        assert_eq!(engine.master_volume, 0.4);
+        assert_eq!(engine.voices[0].volume, 0.75, "held voices follow");
+    }
+}
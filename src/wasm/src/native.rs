@@ -0,0 +1,201 @@
+//! Native backend
+//!
+//! With the `native` feature the engine can be driven from a desktop
+//! program, for development, benchmarks and integration tests without a
+//! browser. `DeviceOutput` adapts the engine's stereo output to a device
+//! buffer of any channel count; with the `cpal` feature `AudioRunner` plays
+//! an engine through the default output device.
+
+use crate::DspEngine;
+
+/// Frames rendered per pass when adapting to a device's channel count
+const SCRATCH_FRAMES: usize = 1024;
+
+/// Renders the engine into interleaved device buffers
+///
+/// Owns the stereo scratch buffer so filling a buffer never allocates.
+pub struct DeviceOutput {
+    scratch: Vec<f32>,
+}
+
+impl DeviceOutput {
+    pub fn new() -> Self {
+        Self { scratch: vec![0.0; SCRATCH_FRAMES * 2] }
+    }
+
+    /// Render into `output`, interleaved with `channels` channels
+    ///
+    /// Mono devices get the average of left and right; devices with more
+    /// than two channels get left and right on the first two and silence on
+    /// the rest.
+    pub fn fill(&mut self, engine: &mut DspEngine, output: &mut [f32], channels: usize) {
+        if channels == 2 {
+            engine.process(output);
+            return;
+        }
+        if channels == 0 {
+            output.fill(0.0);
+            return;
+        }
+        for block in output.chunks_mut(SCRATCH_FRAMES * channels) {
+            let frames = block.len() / channels;
+            let stereo = &mut self.scratch[..frames * 2];
+            engine.process(stereo);
+            for (frame, pair) in block.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+                if channels == 1 {
+                    frame[0] = (pair[0] + pair[1]) * 0.5;
+                } else {
+                    frame[..2].copy_from_slice(pair);
+                    frame[2..].fill(0.0);
+                }
+            }
+        }
+    }
+}
+
+impl Default for DeviceOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "cpal")]
+pub use runner::{AudioRunner, RunnerError};
+
+#[cfg(feature = "cpal")]
+mod runner {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{SampleFormat, Stream};
+
+    use super::DeviceOutput;
+    use crate::{DspEngine, DspEngineConfig};
+
+    /// Why the output device could not be started
+    #[derive(Debug)]
+    pub enum RunnerError {
+        /// The host has no default output device
+        NoDevice,
+        /// The device offers no 32-bit float output
+        UnsupportedFormat,
+        /// The device or stream reported an error
+        Device(String),
+    }
+
+    impl fmt::Display for RunnerError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                RunnerError::NoDevice => write!(f, "no output device"),
+                RunnerError::UnsupportedFormat => write!(f, "output device has no f32 format"),
+                RunnerError::Device(message) => write!(f, "output device error: {message}"),
+            }
+        }
+    }
+
+    impl std::error::Error for RunnerError {}
+
+    fn device_error(error: impl fmt::Display) -> RunnerError {
+        RunnerError::Device(error.to_string())
+    }
+
+    /// An engine playing through the default output device
+    ///
+    /// The audio callback and the caller share the engine behind a mutex.
+    /// The callback never waits for it: a block that finds the engine
+    /// locked (say, while a sound is loading) is played as silence, so keep
+    /// the lock for short calls.
+    pub struct AudioRunner {
+        engine: Arc<Mutex<DspEngine>>,
+        sample_rate: f32,
+        // Playback stops when the stream is dropped
+        _stream: Stream,
+    }
+
+    impl AudioRunner {
+        /// Start a default engine at the device's sample rate
+        pub fn start() -> Result<Self, RunnerError> {
+            Self::start_with(DspEngine::new)
+        }
+
+        /// Start an engine built from `config` (its sample rate is replaced
+        /// by the device's)
+        pub fn start_with_config(config: &DspEngineConfig) -> Result<Self, RunnerError> {
+            Self::start_with(|sample_rate| DspEngine::with_config(&DspEngineConfig { sample_rate, ..*config }))
+        }
+
+        fn start_with(build: impl FnOnce(f32) -> DspEngine) -> Result<Self, RunnerError> {
+            let device = cpal::default_host().default_output_device().ok_or(RunnerError::NoDevice)?;
+            let mut supported = device.default_output_config().map_err(device_error)?;
+            if supported.sample_format() != SampleFormat::F32 {
+                supported = device
+                    .supported_output_configs()
+                    .map_err(device_error)?
+                    .find(|range| range.sample_format() == SampleFormat::F32)
+                    .ok_or(RunnerError::UnsupportedFormat)?
+                    .with_max_sample_rate();
+            }
+            let config = supported.config();
+            let sample_rate = config.sample_rate.0 as f32;
+            let channels = config.channels as usize;
+
+            let engine = Arc::new(Mutex::new(build(sample_rate)));
+            let shared = Arc::clone(&engine);
+            let mut output = DeviceOutput::new();
+            let stream = device
+                .build_output_stream(
+                    &config,
+                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| match shared.try_lock() {
+                        Ok(mut engine) => output.fill(&mut engine, data, channels),
+                        Err(_) => data.fill(0.0),
+                    },
+                    |error| eprintln!("qeyloop-dsp: output stream error: {error}"),
+                    None,
+                )
+                .map_err(device_error)?;
+            stream.play().map_err(device_error)?;
+            Ok(Self { engine, sample_rate, _stream: stream })
+        }
+
+        /// The playing engine; lock it to map keys, load sounds or trigger
+        pub fn engine(&self) -> &Arc<Mutex<DspEngine>> {
+            &self.engine
+        }
+
+        /// Sample rate of the output device
+        pub fn sample_rate(&self) -> f32 {
+            self.sample_rate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_device_output_adapts_channels() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.25; 48000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.note_on(65);
+        let mut output = DeviceOutput::new();
+
+        let mut stereo = [0.0; 2 * 8];
+        output.fill(&mut engine, &mut stereo, 2);
+        let level = stereo[0];
+        assert!(level > 0.0);
+
+        let mut mono = [0.0; 3000];
+        output.fill(&mut engine, &mut mono, 1);
+        assert!(mono.iter().all(|&sample| sample == level));
+
+        let mut surround = [0.0; 6 * 500];
+        output.fill(&mut engine, &mut surround, 6);
+        for frame in surround.chunks_exact(6) {
+            assert_eq!(frame, [level, level, 0.0, 0.0, 0.0, 0.0]);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Offline rendering
+//!
+//! Runs the engine as fast as it can compute instead of in real time, so an
+//! arrangement can be exported without recording a live pass. A render
+//! starts from a clean transport and reproduces the same audio every time:
+//! sequencer, song and step probabilities included. Scheduled punches, clip
+//! launches, the performance take, timed input, the master recorder and the
+//! input looper sit the render out and are left as they were. Render on a second engine loaded with the session
+//! to keep the live one playing.
+//!
+//! The deterministic renders are for regression tests: a session rendered
+//! with the same seed gives bit-identical output, so DSP changes can be
+//! checked against a stored hash (or a golden buffer, for null tests).
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::clips::ClipLauncher;
+use crate::looper::InputLooper;
+use crate::performance::PerformanceRecorder;
+use crate::punch::Punch;
+use crate::recorder::MasterRecorder;
+use crate::timed::TimedInput;
+use crate::{smoothing, DspEngine};
+
+/// Frames per block, one Web Audio render quantum as in live playback
+const RENDER_BLOCK_FRAMES: usize = 128;
+
+/// Most blocks `render_deterministic` returns (about 3 minutes at 48 kHz)
+const MAX_DETERMINISTIC_BLOCKS: u32 = 65_536;
+
+/// FNV-1a 64-bit offset basis and prime
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl DspEngine {
+    /// Run `render` from the starting point of a render, then put the clock
+    /// back where it was
+    ///
+    /// Voices stop, the transport and MIDI file go back to the top, random
+    /// choices restart from the seed and the master gain settles. Punches,
+    /// clip launches, the performance take, timed input, the master
+    /// recorder and the input looper are timed against the live clock, so
+    /// they are set aside until it is restored.
+    fn render_from_top<T>(&mut self, render: impl FnOnce(&mut Self) -> T) -> T {
+        let clock = self.global_sample_position;
+        let punch = std::mem::replace(&mut self.punch, Punch::new());
+        let clips = std::mem::replace(&mut self.clips, ClipLauncher::new());
+        let performance = std::mem::replace(&mut self.performance, PerformanceRecorder::new());
+        let timed_input = std::mem::replace(&mut self.timed_input, TimedInput::new());
+        let recorder = std::mem::replace(&mut self.recorder, MasterRecorder::new());
+        let looper = std::mem::replace(&mut self.looper, InputLooper::new());
+
+        self.stop_voices();
+        self.sequencer.reset_playhead();
+        self.global_sample_position = 0;
+        self.sequencer.reseed();
+        self.rewind_midi_file();
+        self.smoothing.master = smoothing::UNSET;
+        let rendered = render(self);
+
+        self.stop_voices();
+        self.sequencer.reset_playhead();
+        self.global_sample_position = clock;
+        self.punch = punch;
+        self.clips = clips;
+        self.performance = performance;
+        self.timed_input = timed_input;
+        self.recorder = recorder;
+        self.looper = looper;
+        rendered
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Render `bars` bars (4/4 at the session BPM) into `out` (stereo
+    /// interleaved) from the top of the transport
+    ///
+    /// Stops every voice before and after. Renders as many frames as fit in
+    /// `out` and returns the number of frames rendered.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn render_offline(&mut self, bars: u32, out: &mut [f32]) -> u32 {
+        self.render_from_top(|engine| {
+            let frames = (engine.samples_per_bar() * bars as u64).min(out.len() as u64 / 2) as usize;
+            for block in out[..frames * 2].chunks_mut(RENDER_BLOCK_FRAMES * 2) {
+                engine.process(block);
+            }
+            frames as u32
+        })
+    }
+
+    /// Set the seed of the engine's random choices (step probabilities)
+    ///
+    /// The choices restart from the seed now and at the start of every
+    /// render. The default seed is 0x5EC0.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_random_seed(&mut self, seed: u32) {
+        self.sequencer.seed = seed;
+        self.sequencer.reseed();
+    }
+
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_random_seed(&self) -> u32 {
+        self.sequencer.seed
+    }
+
+    /// Render `blocks` blocks of 128 frames (stereo interleaved) from the
+    /// top of the transport, the same every time for the same session and
+    /// seed
+    ///
+    /// Renders at most 65536 blocks.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn render_deterministic(&mut self, blocks: u32) -> Vec<f32> {
+        self.render_from_top(|engine| {
+            let mut out = vec![0.0; blocks.min(MAX_DETERMINISTIC_BLOCKS) as usize * RENDER_BLOCK_FRAMES * 2];
+            for block in out.chunks_mut(RENDER_BLOCK_FRAMES * 2) {
+                engine.process(block);
+            }
+            out
+        })
+    }
+
+    /// FNV-1a hash of the bits of what `render_deterministic` would return
+    /// for `blocks` blocks (any number, without holding the audio)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn render_deterministic_hash(&mut self, blocks: u32) -> u64 {
+        self.render_from_top(|engine| {
+            let mut block = [0.0; RENDER_BLOCK_FRAMES * 2];
+            let mut hash = FNV_OFFSET;
+            for _ in 0..blocks {
+                engine.process(&mut block);
+                for byte in block.iter().flat_map(|sample| sample.to_bits().to_le_bytes()) {
+                    hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+                }
+            }
+            hash
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ClipKind, DspEngine, LooperState, OverlapMode, PlaybackMode, PunchTarget, RecorderState};
+
+    #[test]
+    fn test_render_is_repeatable() {
+        // 120 BPM at 1 kHz: a bar is 2000 samples
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 100]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        for step in 0..16 {
+            assert!(engine.set_step(65, step, 127, 0, 0.5, 0.0));
+        }
+        engine.set_sequencer_playing(true);
+        engine.process(&mut [0.0; 2 * 300]);
+
+        let mut first = vec![0.0; 2 * 5000];
+        assert_eq!(engine.render_offline(2, &mut first), 4000);
+        assert!(first[4000 * 2..].iter().all(|&s| s == 0.0), "only two bars");
+        let mut second = vec![0.0; 2 * 4000];
+        engine.render_offline(2, &mut second);
+        assert_eq!(first[..4000 * 2], second[..]);
+        assert!(first.iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn test_deterministic_render_follows_the_seed() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 100]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        for step in 0..16 {
+            assert!(engine.set_step(65, step, 127, 0, 0.5, 0.0));
+        }
+        engine.set_sequencer_playing(true);
+        engine.set_master_volume(0.5);
+
+        let golden = engine.render_deterministic_hash(40);
+        engine.process(&mut [0.0; 2 * 300]);
+        assert_eq!(engine.render_deterministic_hash(40), golden);
+        let buffer = engine.render_deterministic(40);
+        assert_eq!(buffer.len(), 40 * 128 * 2);
+        assert!(buffer.iter().any(|&s| s != 0.0));
+
+        engine.set_random_seed(12345);
+        assert_eq!(engine.get_random_seed(), 12345);
+        assert_ne!(engine.render_deterministic_hash(40), golden);
+        engine.set_random_seed(0x5EC0);
+        assert_eq!(engine.render_deterministic_hash(40), golden);
+    }
+
+    #[test]
+    fn test_render_leaves_live_schedules_alone() {
+        // A bar is 2000 samples; the render covers the punch and clip launch
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 100]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.process(&mut [0.0; 2 * 300]);
+        assert!(engine.set_punch(PunchTarget::Sequencer, 1, 0, 2, 0));
+        assert!(engine.set_clip(66, 0, ClipKind::Loop, 0) && engine.launch_clip(66, 0));
+        engine.start_performance_recording();
+        engine.note_on_timestamped(65, 0.3);
+        assert!(engine.arm_master_recording(3) && engine.arm_input_loop(4, 1, -1));
+
+        // The render starts on a bar, where an armed take would start
+        engine.render_deterministic_hash(40);
+        assert_eq!(engine.get_engine_time(), 0.3);
+        assert!(engine.is_punch_pending() && engine.is_performance_recording());
+        assert_eq!(engine.get_master_recording_state(), RecorderState::WaitingForBar);
+        assert_eq!(engine.get_looper_state(), LooperState::WaitingForBar);
+        assert_eq!((engine.get_queued_clip(66), engine.get_playing_clip(66)), (0, -1));
+        assert_eq!(engine.get_active_voice_count(), 0);
+        engine.process(&mut [0.0; 2 * 150]);
+        assert_eq!(engine.get_active_voice_count(), 1, "the timed key still plays");
+    }
+}
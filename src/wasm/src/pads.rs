@@ -0,0 +1,69 @@
+//! Per-key pad appearance
+//!
+//! A color index and a short label for each key, kept for the UI only: the
+//! DSP never reads them. They travel with exported state and kits so a pad
+//! layout looks the same wherever it is loaded.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Longest stored pad label in bytes
+const MAX_LABEL_BYTES: usize = 32;
+
+/// Appearance of one key's pad
+#[derive(Clone, PartialEq)]
+pub(crate) struct PadStyle {
+    /// Opaque palette index chosen by the UI (0 = default)
+    pub(crate) color: u8,
+    /// Short display label (empty = none)
+    pub(crate) label: String,
+}
+
+impl PadStyle {
+    pub(crate) const fn new() -> Self {
+        Self { color: 0, label: String::new() }
+    }
+
+    /// Whether the pad still looks the way a fresh engine shows it
+    pub(crate) fn is_default(&self) -> bool {
+        self.color == 0 && self.label.is_empty()
+    }
+}
+
+/// `label` cut to at most `MAX_LABEL_BYTES` on a character boundary
+pub(crate) fn truncate_label(label: &str) -> &str {
+    let mut end = label.len().min(MAX_LABEL_BYTES);
+    while !label.is_char_boundary(end) {
+        end -= 1;
+    }
+    &label[..end]
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set the palette index of a key's pad (0 = default)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_color(&mut self, key_code: u8, color: u8) {
+        self.pad_styles[key_code as usize].color = color;
+    }
+
+    /// Palette index of a key's pad
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_color(&self, key_code: u8) -> u8 {
+        self.pad_styles[key_code as usize].color
+    }
+
+    /// Set a key's pad label (truncated to 32 bytes)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_label(&mut self, key_code: u8, label: &str) {
+        self.pad_styles[key_code as usize].label = truncate_label(label).to_string();
+    }
+
+    /// Label of a key's pad (empty if unset)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_label(&self, key_code: u8) -> String {
+        self.pad_styles[key_code as usize].label.clone()
+    }
+}
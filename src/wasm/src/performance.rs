@@ -0,0 +1,406 @@
+//! Performance recorder
+//!
+//! Records the input played into the engine (key triggers, MIDI notes,
+//! controllers, pitch bend and channel pressure) with the sample it arrived
+//! on, and plays the take back through the same entry points, so a jam can
+//! be heard again straight away with whatever sounds are mapped now. The
+//! take can also be exported as a standard MIDI file for a DAW.
+//!
+//! Mix moves made from the host (key volume and pitch, group and master
+//! volume, morph) are recorded too and play back as automation. Like CC
+//! moves they bypass the undo history when replayed.
+//!
+//! The event buffer is reserved when recording starts, never in
+//! `process()`; input beyond its capacity is not recorded.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::cc::CcTarget;
+use crate::DspEngine;
+
+/// Most events in one take
+pub(crate) const MAX_PERFORMANCE_EVENTS: usize = 32_768;
+
+/// Resolution of exported MIDI files
+const EXPORT_TICKS_PER_BEAT: u32 = 480;
+
+/// Beats per bar the export is aligned to (4/4)
+const BEATS_PER_BAR: u64 = 4;
+
+/// An input event as it reached the engine
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Performed {
+    TriggerOn(u16),
+    TriggerOff(u16),
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    Control { channel: u8, controller: u8, value: u8 },
+    PitchBend { channel: u8, value: u16 },
+    Pressure { channel: u8, pressure: u8 },
+    /// A parameter set from the host
+    Parameter { target: CcTarget, index: u8, value: f32 },
+}
+
+impl Performed {
+    /// Whether the event ends a note
+    #[inline]
+    fn is_release(&self) -> bool {
+        matches!(self, Self::TriggerOff(_) | Self::NoteOff { .. })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct PerformedEvent {
+    /// Samples from the start of the take
+    pub(crate) at: u64,
+    pub(crate) event: Performed,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TakeState {
+    Idle,
+    Recording,
+    Playing,
+}
+
+pub(crate) struct PerformanceRecorder {
+    state: TakeState,
+    /// Sorted by time (capacity reserved when recording starts)
+    pub(crate) events: Vec<PerformedEvent>,
+    /// Engine sample the take started on
+    pub(crate) start: u64,
+    /// Engine sample playback started on
+    played_from: u64,
+    /// Length of the take in samples
+    pub(crate) length: u64,
+    /// First event not yet played back
+    next_event: usize,
+}
+
+impl PerformanceRecorder {
+    pub(crate) const fn new() -> Self {
+        Self { state: TakeState::Idle, events: Vec::new(), start: 0, played_from: 0, length: 0, next_event: 0 }
+    }
+
+    #[inline]
+    pub(crate) fn is_playing(&self) -> bool {
+        self.state == TakeState::Playing
+    }
+
+    /// Whether playback fires an event at sample `now`
+    #[inline]
+    pub(crate) fn is_due(&self, now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.played_from);
+        self.is_playing() && self.events.get(self.next_event).is_some_and(|recorded| recorded.at <= elapsed)
+    }
+
+    /// Heap bytes held by the event buffer
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.events.capacity() * std::mem::size_of::<PerformedEvent>()
+    }
+}
+
+impl DspEngine {
+    /// Add an input event to the take being recorded (real-time safe)
+    #[inline]
+    pub(crate) fn record_performance(&mut self, event: Performed) {
+        let take = &mut self.performance;
+        if take.state == TakeState::Recording && take.events.len() < take.events.capacity() {
+            let at = self.global_sample_position.saturating_sub(take.start);
+            take.events.push(PerformedEvent { at, event });
+        }
+    }
+
+    /// Play the take's events due at the current sample (called once per
+    /// frame from `process()` while playing back)
+    #[inline]
+    pub(crate) fn advance_performance(&mut self) {
+        let elapsed = self.global_sample_position.saturating_sub(self.performance.played_from);
+        while let Some(&recorded) = self.performance.events.get(self.performance.next_event) {
+            if recorded.at > elapsed {
+                break;
+            }
+            self.performance.next_event += 1;
+            self.replay(recorded.event);
+        }
+        if elapsed >= self.performance.length && self.performance.next_event == self.performance.events.len() {
+            self.performance.state = TakeState::Idle;
+        }
+    }
+
+    fn replay(&mut self, event: Performed) {
+        match event {
+            Performed::TriggerOn(trigger) => self.trigger_on(trigger),
+            Performed::TriggerOff(trigger) => self.trigger_off(trigger),
+            Performed::NoteOn { channel, note, velocity } => self.midi_note_on(channel, note, velocity),
+            Performed::NoteOff { channel, note, velocity } => self.midi_note_off_velocity(channel, note, velocity),
+            Performed::Control { channel, controller, value } => self.control_change(channel, controller, value),
+            Performed::PitchBend { channel, value } => self.midi_pitch_bend(channel, value),
+            Performed::Pressure { channel, pressure } => self.midi_channel_pressure(channel, pressure),
+            Performed::Parameter { target, index, value } => self.set_parameter(target, index, value),
+        }
+    }
+
+    /// End recording or playback, e.g. because the clock was reset
+    pub(crate) fn halt_performance(&mut self) {
+        self.stop_performance_recording();
+        self.stop_performance_playback();
+    }
+}
+
+/// Append a variable-length quantity
+fn write_vlq(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0u8; 4];
+    let mut len = 0;
+    let mut rest = value & 0x0FFF_FFFF;
+    loop {
+        bytes[len] = (rest & 0x7F) as u8 | if len > 0 { 0x80 } else { 0 };
+        len += 1;
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    out.extend(bytes[..len].iter().rev());
+}
+
+/// MIDI message bytes of an event; None for triggers outside the note range
+/// and for parameter automation
+fn midi_message(event: Performed) -> Option<([u8; 3], usize)> {
+    // Input is recorded unchecked, so keep stray values inside their fields
+    let status = |kind: u8, channel: u8| kind | (channel & 0x0F);
+    let (message, len) = match event {
+        Performed::TriggerOn(trigger) => ([0x90, u8::try_from(trigger).ok().filter(|&n| n < 128)?, 127], 3),
+        Performed::TriggerOff(trigger) => ([0x80, u8::try_from(trigger).ok().filter(|&n| n < 128)?, 64], 3),
+        Performed::NoteOn { channel, note, velocity } => ([status(0x90, channel), note, velocity], 3),
+        Performed::NoteOff { channel, note, velocity } => ([status(0x80, channel), note, velocity], 3),
+        Performed::Control { channel, controller, value } => ([status(0xB0, channel), controller, value], 3),
+        Performed::PitchBend { channel, value } => ([status(0xE0, channel), value as u8, (value >> 7) as u8], 3),
+        Performed::Pressure { channel, pressure } => ([status(0xD0, channel), pressure, 0], 2),
+        Performed::Parameter { .. } => return None,
+    };
+    Some(([message[0], message[1] & 0x7F, message[2] & 0x7F], len))
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Start recording a new take, replacing the previous one
+    ///
+    /// Playback of the previous take stops.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn start_performance_recording(&mut self) {
+        self.stop_performance_playback();
+        let take = &mut self.performance;
+        take.events = Vec::with_capacity(MAX_PERFORMANCE_EVENTS);
+        take.start = self.global_sample_position;
+        take.length = 0;
+        take.state = TakeState::Recording;
+    }
+
+    /// Stop recording; the take ends at the current sample
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stop_performance_recording(&mut self) {
+        let take = &mut self.performance;
+        if take.state == TakeState::Recording {
+            take.length = self.global_sample_position.saturating_sub(take.start);
+            take.state = TakeState::Idle;
+        }
+    }
+
+    /// Whether a take is being recorded
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_performance_recording(&self) -> bool {
+        self.performance.state == TakeState::Recording
+    }
+
+    /// Play the take back from its start, firing each event on the sample
+    /// it was recorded at; returns false while recording
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn play_performance(&mut self) -> bool {
+        let take = &mut self.performance;
+        if take.state == TakeState::Recording {
+            return false;
+        }
+        take.played_from = self.global_sample_position;
+        take.next_event = 0;
+        take.state = TakeState::Playing;
+        true
+    }
+
+    /// Stop playback; notes still held in the take are released
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stop_performance_playback(&mut self) {
+        if !self.performance.is_playing() {
+            return;
+        }
+        self.performance.state = TakeState::Idle;
+        let pending = self.performance.next_event..self.performance.events.len();
+        for index in pending {
+            let event = self.performance.events[index].event;
+            if event.is_release() {
+                self.replay(event);
+            }
+        }
+    }
+
+    /// Whether the take is playing back
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_performance_playing(&self) -> bool {
+        self.performance.is_playing()
+    }
+
+    /// Events in the take
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_performance_event_count(&self) -> usize {
+        self.performance.events.len()
+    }
+
+    /// Length of the take in seconds
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_performance_length(&self) -> f64 {
+        self.performance.length as f64 / self.sample_rate as f64
+    }
+
+    /// Discard the take
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_performance(&mut self) {
+        self.halt_performance();
+        self.performance.events = Vec::new();
+        self.performance.length = 0;
+    }
+
+    /// Export the take as a type 0 standard MIDI file at the session BPM
+    ///
+    /// Times are counted from the bar the take started in, so the file
+    /// lines up with the bars of the session. `quantize` snaps events to
+    /// a grid of that many divisions per beat (0 = off, 2 = 1/8, 4 = 1/16).
+    /// Key triggers are written as notes on channel 1 with the key code as
+    /// the note number; triggers above 127 and parameter automation are
+    /// left out.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_performance_midi(&self, quantize: u8) -> Vec<u8> {
+        let take = &self.performance;
+        let samples_per_beat = self.sample_rate as f64 * 60.0 / self.bpm as f64;
+        let bar_offset = take.start % (samples_per_beat as u64 * BEATS_PER_BAR).max(1);
+        let grid = if quantize > 0 { (EXPORT_TICKS_PER_BEAT / quantize as u32).max(1) } else { 1 };
+
+        let mut timed: Vec<(u32, Performed)> = take
+            .events
+            .iter()
+            .map(|recorded| {
+                let beats = (bar_offset + recorded.at) as f64 / samples_per_beat;
+                let tick = (beats * EXPORT_TICKS_PER_BEAT as f64).round() as u32;
+                let tick = (tick + grid / 2) / grid * grid;
+                (tick, recorded.event)
+            })
+            .collect();
+        timed.sort_by_key(|&(tick, _)| tick);
+
+        let mut track = Vec::new();
+        // Tempo: microseconds per quarter note
+        let tempo = (60_000_000.0 / self.bpm as f64) as u32;
+        track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03]);
+        track.extend_from_slice(&tempo.to_be_bytes()[1..]);
+        let mut last_tick = 0;
+        for (tick, event) in timed {
+            let Some((message, len)) = midi_message(event) else {
+                continue;
+            };
+            write_vlq(&mut track, tick - last_tick);
+            track.extend_from_slice(&message[..len]);
+            last_tick = tick;
+        }
+        let end_beats = (bar_offset + take.length) as f64 / samples_per_beat;
+        let end_tick = ((end_beats * EXPORT_TICKS_PER_BEAT as f64).round() as u32).max(last_tick);
+        write_vlq(&mut track, end_tick - last_tick);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+        let mut file = Vec::with_capacity(22 + track.len());
+        file.extend_from_slice(b"MThd");
+        file.extend_from_slice(&6u32.to_be_bytes());
+        file.extend_from_slice(&[0, 0, 0, 1]);
+        file.extend_from_slice(&(EXPORT_TICKS_PER_BEAT as u16).to_be_bytes());
+        file.extend_from_slice(b"MTrk");
+        file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        file.extend_from_slice(&track);
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_take_plays_back_on_its_samples() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.process(&mut [0.0; 2 * 30]);
+
+        engine.start_performance_recording();
+        engine.process(&mut [0.0; 2 * 50]);
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 100]);
+        engine.note_off(65);
+        engine.process(&mut [0.0; 2 * 50]);
+        engine.stop_performance_recording();
+        assert_eq!(engine.get_performance_event_count(), 2);
+        assert_eq!(engine.get_performance_length(), 0.2);
+
+        assert!(engine.play_performance());
+        engine.process(&mut [0.0; 2 * 50]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.get_active_voice_count(), 1, "key down replayed 50 samples in");
+        engine.process(&mut [0.0; 2 * 100]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        assert_eq!(engine.get_performance_event_count(), 2, "playback is not recorded");
+        engine.process(&mut [0.0; 2 * 50]);
+        assert!(!engine.is_performance_playing());
+    }
+
+    #[test]
+    fn test_mix_moves_play_back_as_automation() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.set_key_volume(65, 0.8);
+        engine.start_performance_recording();
+        engine.process(&mut [0.0; 2 * 100]);
+        engine.set_key_volume(65, 0.2);
+        engine.set_group_volume(3, 0.5);
+        engine.process(&mut [0.0; 2 * 100]);
+        engine.stop_performance_recording();
+        assert_eq!(engine.get_performance_event_count(), 2);
+        engine.set_key_volume(65, 0.8);
+        engine.set_group_volume(3, 1.0);
+        assert!(engine.play_performance());
+        engine.process(&mut [0.0; 2 * 100]);
+        assert_eq!((engine.key_mappings[65].volume, engine.mixer.volumes[3]), (0.8, 1.0));
+        engine.process(&mut [0.0; 2]);
+        assert_eq!((engine.key_mappings[65].volume, engine.mixer.volumes[3]), (0.2, 0.5));
+        // Only the tempo and the end of the track (192 ticks in) are exported
+        assert_eq!(&engine.export_performance_midi(0)[22 + 7..], &[0x81, 0x40, 0xFF, 0x2F, 0x00]);
+    }
+
+    #[test]
+    fn test_export_quantizes_to_the_grid() {
+        // 120 BPM at 1 kHz: a beat is 500 samples, a 1/16 is 125
+        let mut engine = DspEngine::new(1000.0);
+        engine.start_performance_recording();
+        engine.process(&mut [0.0; 2 * 130]);
+        engine.midi_note_on(2, 60, 100);
+        engine.process(&mut [0.0; 2 * 120]);
+        engine.midi_note_off(2, 60);
+        engine.stop_performance_recording();
+
+        let file = engine.export_performance_midi(4);
+        assert_eq!(&file[..4], b"MThd");
+        let track = &file[22..];
+        // Tempo, then the note on a 1/16 (120 ticks) and its end on 1/8 (240)
+        assert_eq!(&track[..7], &[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]);
+        assert_eq!(&track[7..11], &[120, 0x92, 60, 100]);
+        assert_eq!(&track[11..15], &[120, 0x82, 60, 64]);
+        assert_eq!(&track[track.len() - 3..], &[0xFF, 0x2F, 0x00]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Load-time sample preparation
+//!
+//! Optional transforms applied to incoming audio before it is stored in a
+//! sound slot. Like analysis, this never runs inside `process()`.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::resample::ResampleQuality;
+use crate::DspEngine;
+
+/// Settings applied to every sound as it is loaded
+pub(crate) struct LoadOptions {
+    /// Strip leading/trailing silence
+    pub(crate) auto_trim: bool,
+    /// Linear amplitude below which audio counts as silence
+    pub(crate) trim_threshold: f32,
+    /// Set a playback gain so each sound hits the loudness target
+    pub(crate) loudness_normalize: bool,
+    /// Loudness target in LUFS
+    pub(crate) loudness_target: f32,
+    /// Scale stored audio so its peak hits `peak_target`
+    pub(crate) peak_normalize: bool,
+    /// Peak normalization target (linear)
+    pub(crate) peak_target: f32,
+    /// Converter used when the source rate differs from the engine rate
+    pub(crate) resample_quality: ResampleQuality,
+}
+
+impl LoadOptions {
+    pub(crate) const fn new() -> Self {
+        Self {
+            auto_trim: false,
+            trim_threshold: 0.001, // -60 dBFS
+            loudness_normalize: false,
+            loudness_target: -18.0,
+            peak_normalize: false,
+            peak_target: 0.891, // -1 dBFS
+            resample_quality: ResampleQuality::Sinc,
+        }
+    }
+}
+
+/// Largest boost loudness normalization may apply (dB), so near-silent
+/// sounds are not raised into a wall of noise
+const MAX_NORMALIZE_BOOST_DB: f32 = 24.0;
+
+/// Convert decibels to a linear gain factor
+#[inline]
+pub(crate) fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+/// Find the audible region of `samples`
+///
+/// Returns the `(start, end)` range between the first and last sample whose
+/// magnitude reaches `threshold`. Fully silent input is left untouched.
+pub(crate) fn find_trim_bounds(samples: &[f32], threshold: f32) -> (usize, usize) {
+    let audible = |s: &f32| s.abs() >= threshold;
+    match samples.iter().position(audible) {
+        Some(start) => {
+            let end = samples.iter().rposition(audible).map_or(samples.len(), |last| last + 1);
+            (start, end)
+        }
+        None => (0, samples.len()),
+    }
+}
+
+/// Find the audible region of a stereo pair of equal length
+///
+/// Like `find_trim_bounds`, counting a frame as audible if either channel
+/// is.
+pub(crate) fn find_stereo_trim_bounds(left: &[f32], right: &[f32], threshold: f32) -> (usize, usize) {
+    let audible = |&i: &usize| left[i].abs() >= threshold || right[i].abs() >= threshold;
+    match (0..left.len()).find(audible) {
+        Some(start) => {
+            let end = (0..left.len()).rfind(audible).map_or(left.len(), |last| last + 1);
+            (start, end)
+        }
+        None => (0, left.len()),
+    }
+}
+
+/// Scale `left` and `right` in place so their absolute peak equals `target`
+///
+/// `right` is empty for mono audio. Returns the applied gain (1.0 for
+/// silent input).
+pub(crate) fn peak_normalize(left: &mut [f32], right: &mut [f32], target: f32) -> f32 {
+    let peak = left.iter().chain(right.iter()).fold(0.0_f32, |max, s| max.max(s.abs()));
+    if peak <= 0.0 {
+        return 1.0;
+    }
+    let gain = target / peak;
+    for sample in left.iter_mut().chain(right.iter_mut()) {
+        *sample *= gain;
+    }
+    gain
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Enable or disable stripping of leading/trailing silence on load
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether subsequent loads are trimmed
+    /// * `threshold_db` - Level (dBFS) below which audio counts as silence
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_auto_trim(&mut self, enabled: bool, threshold_db: f32) {
+        self.load_options.auto_trim = enabled;
+        self.load_options.trim_threshold = db_to_gain(threshold_db.clamp(-120.0, 0.0));
+    }
+
+    /// Enable or disable loudness normalization of loaded sounds
+    ///
+    /// Normalization is non-destructive: the stored audio is untouched and
+    /// a per-sound playback gain brings it to `target_lufs`.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_loudness_normalization(&mut self, enabled: bool, target_lufs: f32) {
+        self.load_options.loudness_normalize = enabled;
+        self.load_options.loudness_target = target_lufs.clamp(-60.0, 0.0);
+    }
+
+    /// Enable or disable peak normalization of loaded sounds
+    ///
+    /// Unlike loudness normalization this rescales the stored audio. The
+    /// applied gain is kept so `undo_peak_normalization` can restore it.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_peak_normalization(&mut self, enabled: bool, target_db: f32) {
+        self.load_options.peak_normalize = enabled;
+        self.load_options.peak_target = db_to_gain(target_db.clamp(-60.0, 0.0));
+    }
+
+    /// Gain that peak normalization applied to a sound's audio (1.0 = none)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_peak_normalize_gain(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return 1.0;
+        }
+        self.sounds[sound_index].peak_normalize_gain
+    }
+
+    /// Restore a sound's audio to its level before peak normalization
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn undo_peak_normalization(&mut self, sound_index: usize) {
+        if sound_index >= self.sounds.len() || !self.sounds[sound_index].loaded {
+            return;
+        }
+        let sound = &mut self.sounds[sound_index];
+        if sound.peak_normalize_gain == 1.0 {
+            return;
+        }
+        let inverse = 1.0 / sound.peak_normalize_gain;
+        for sample in sound.samples.iter_mut().chain(sound.right.iter_mut()) {
+            *sample *= inverse;
+        }
+        sound.peak_normalize_gain = 1.0;
+
+        self.analyze_sound(sound_index);
+        self.apply_load_normalization(sound_index);
+    }
+
+    /// Measured integrated loudness of a sound in LUFS (-inf if silent)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_loudness(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return f32::NEG_INFINITY;
+        }
+        self.sounds[sound_index].loudness.unwrap_or(f32::NEG_INFINITY)
+    }
+
+    /// Playback gain applied to a sound by normalization (1.0 = none)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_gain(&self, sound_index: usize) -> f32 {
+        if sound_index >= self.sounds.len() {
+            return 1.0;
+        }
+        self.sounds[sound_index].gain
+    }
+
+    /// Number of samples removed from the start of a sound by auto-trim
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_trim_start(&self, sound_index: usize) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        self.sounds[sound_index].trim_start as u32
+    }
+
+    /// Number of samples removed from the end of a sound by auto-trim
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_trim_end(&self, sound_index: usize) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        self.sounds[sound_index].trim_end as u32
+    }
+}
+
+impl DspEngine {
+    /// Rescale a freshly stored sound to the peak target, if enabled
+    pub(crate) fn apply_peak_normalization(&mut self, sound_index: usize) {
+        let sound = &mut self.sounds[sound_index];
+        sound.peak_normalize_gain = if self.load_options.peak_normalize {
+            peak_normalize(&mut sound.samples, &mut sound.right, self.load_options.peak_target)
+        } else {
+            1.0
+        };
+    }
+
+    /// Set the playback gain of a freshly analyzed sound from the load options
+    pub(crate) fn apply_load_normalization(&mut self, sound_index: usize) {
+        let options = &self.load_options;
+        let sound = &mut self.sounds[sound_index];
+        sound.gain = match sound.loudness {
+            Some(loudness) if options.loudness_normalize => {
+                db_to_gain((options.loudness_target - loudness).min(MAX_NORMALIZE_BOOST_DB))
+            }
+            _ => 1.0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_trim_bounds() {
+        let samples = [0.0, 0.0005, 0.2, -0.5, 0.1, 0.0002, 0.0];
+        assert_eq!(find_trim_bounds(&samples, 0.001), (2, 5));
+        assert_eq!(find_trim_bounds(&[0.0; 4], 0.001), (0, 4));
+    }
+
+    #[test]
+    fn test_peak_normalize_undo() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.set_peak_normalization(true, -6.0);
+        engine.load_sound(0, &[0.1, -0.25, 0.05]);
+
+        let gain = engine.get_sound_peak_normalize_gain(0);
+        assert!((engine.get_sound_peak(0) - db_to_gain(-6.0)).abs() < 1e-5);
+        assert!((gain - db_to_gain(-6.0) / 0.25).abs() < 1e-5);
+
+        engine.undo_peak_normalization(0);
+        assert!((engine.get_sound_peak(0) - 0.25).abs() < 1e-6);
+        assert_eq!(engine.get_sound_peak_normalize_gain(0), 1.0);
+    }
+}
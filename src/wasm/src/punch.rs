@@ -0,0 +1,166 @@
+//! Punch-in/punch-out recording
+//!
+//! Schedules recording to start and stop at set bar and beat positions on
+//! the beat grid, so a take can be punched in hands-free while playing.
+//! The target is an overdub of the live input loop or live recording into
+//! the sequencer. Positions are converted to samples at the BPM in effect
+//! when the punch is set.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::events::EngineEventKind;
+use crate::DspEngine;
+
+/// Beats per bar of punch positions (4/4)
+const BEATS_PER_BAR: u32 = 4;
+
+/// What a punch records into
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum PunchTarget {
+    /// Overdub the live input onto the input loop
+    InputLoop = 0,
+    /// Record key presses into the sequencer
+    Sequencer = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PunchState {
+    Idle,
+    /// Waiting for the punch-in sample
+    Armed,
+    /// Recording until the punch-out sample
+    Punched,
+}
+
+pub(crate) struct Punch {
+    state: PunchState,
+    target: PunchTarget,
+    punch_in: u64,
+    punch_out: u64,
+}
+
+impl Punch {
+    pub(crate) const fn new() -> Self {
+        Self { state: PunchState::Idle, target: PunchTarget::InputLoop, punch_in: 0, punch_out: 0 }
+    }
+
+    #[inline]
+    pub(crate) fn is_pending(&self) -> bool {
+        self.state != PunchState::Idle
+    }
+
+    /// Whether the punch goes in or out at sample `now`
+    #[inline]
+    pub(crate) fn is_due(&self, now: u64) -> bool {
+        match self.state {
+            PunchState::Armed => now >= self.punch_in,
+            PunchState::Punched => now >= self.punch_out,
+            PunchState::Idle => false,
+        }
+    }
+}
+
+impl DspEngine {
+    /// Punch in or out on the current sample (called once per frame from
+    /// `process()` while a punch is pending)
+    #[inline]
+    pub(crate) fn advance_punch(&mut self) {
+        let now = self.global_sample_position;
+        let punch = &self.punch;
+        let (recording, kind) = match punch.state {
+            PunchState::Armed if now >= punch.punch_in => (true, EngineEventKind::PunchIn),
+            PunchState::Punched if now >= punch.punch_out => (false, EngineEventKind::PunchOut),
+            _ => return,
+        };
+        self.punch.state = if recording { PunchState::Punched } else { PunchState::Idle };
+        self.set_punch_recording(recording);
+        self.events.push(kind, 0, self.punch.target as u32, now);
+    }
+
+    fn set_punch_recording(&mut self, recording: bool) {
+        match self.punch.target {
+            PunchTarget::InputLoop => self.set_overdubbing(recording),
+            PunchTarget::Sequencer => self.sequencer.recording = recording,
+        }
+    }
+
+    /// Sample of a bar and beat on the beat grid
+    fn punch_position(&self, bar: u32, beat: u32) -> u64 {
+        let samples_per_bar = self.samples_per_bar();
+        samples_per_bar * bar as u64 + samples_per_bar * beat as u64 / BEATS_PER_BAR as u64
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Schedule recording into `target` from one bar and beat to another
+    ///
+    /// Bars count from the start of the transport and beats (0-3) within
+    /// the bar, both from 0. Replaces any pending punch. Returns false if
+    /// punch-out is not after punch-in, punch-in has already passed, or the
+    /// target is not ready (the input loop needs a stored loop).
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_punch(&mut self, target: PunchTarget, in_bar: u32, in_beat: u32, out_bar: u32, out_beat: u32) -> bool {
+        if in_beat >= BEATS_PER_BAR || out_beat >= BEATS_PER_BAR {
+            return false;
+        }
+        let punch_in = self.punch_position(in_bar, in_beat);
+        let punch_out = self.punch_position(out_bar, out_beat);
+        if punch_out <= punch_in || punch_in < self.global_sample_position {
+            return false;
+        }
+        self.clear_punch();
+        // Make room up front: punching in happens inside `process()`
+        match target {
+            PunchTarget::InputLoop if !self.prepare_overdub() => return false,
+            PunchTarget::InputLoop => {}
+            PunchTarget::Sequencer => self.sequencer.reserve_steps(),
+        }
+        self.punch = Punch { state: PunchState::Armed, target, punch_in, punch_out };
+        true
+    }
+
+    /// Cancel a pending punch; recording punched in stops now
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_punch(&mut self) {
+        if self.punch.state == PunchState::Punched {
+            self.set_punch_recording(false);
+        }
+        self.punch.state = PunchState::Idle;
+    }
+
+    /// Whether a punch is waiting to punch in or out
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_punch_pending(&self) -> bool {
+        self.punch.is_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_punch_records_the_sequencer_between_beats() {
+        // 120 BPM at 1 kHz: a bar is 2000 samples, a beat 500
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_sequencer_playing(true);
+        assert!(!engine.set_punch(PunchTarget::Sequencer, 0, 2, 0, 1), "out before in");
+        assert!(engine.set_punch(PunchTarget::Sequencer, 0, 1, 0, 3));
+
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 600]);
+        assert!(engine.is_sequencer_recording(), "punched in at beat 1");
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 1000]);
+        assert!(!engine.is_sequencer_recording() && !engine.is_punch_pending());
+        engine.note_on(65);
+        assert_eq!(engine.get_pattern_packed(), [65.0, 5.0, 127.0, 0.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Master output resampling
+//!
+//! Captures the master output, or one group's bus, into a sound slot while
+//! performing. Start and stop are quantized to bar boundaries and a take cut
+//! short by the length limit is rounded down to whole bars, so the take
+//! loops cleanly. It can be mapped to a key as a loop as soon as it is
+//! stored, for layering live loops. The capture buffer is allocated when
+//! recording is armed, never in `process()`.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::events::EngineEventKind;
+use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+/// Beats per bar used for quantizing start and stop (4/4)
+const BEATS_PER_BAR: f32 = 4.0;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum RecorderState {
+    /// Not recording
+    Idle = 0,
+    /// Armed, waiting for the next bar to start
+    WaitingForBar = 1,
+    /// Capturing the master output
+    Recording = 2,
+    /// Stop requested, capturing until the next bar
+    Stopping = 3,
+    /// Take complete, waiting for `finish_master_recording`
+    Finished = 4,
+}
+
+pub(crate) struct MasterRecorder {
+    state: RecorderState,
+    /// Destination sound slot
+    sound_index: usize,
+    /// Captured audio (capacity reserved when armed)
+    buffer: Vec<f32>,
+    /// Group whose bus is captured (None = the master output)
+    group: Option<u8>,
+    /// Key the finished take is mapped to as a loop
+    key_code: Option<u8>,
+}
+
+impl MasterRecorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: RecorderState::Idle,
+            sound_index: 0,
+            buffer: Vec::new(),
+            group: None,
+            key_code: None,
+        }
+    }
+
+    /// Group whose bus is captured, if not the master output
+    #[inline]
+    pub(crate) fn group(&self) -> Option<u8> {
+        self.group
+    }
+
+    /// Destination slot of the current take
+    #[inline]
+    pub(crate) fn sound_index(&self) -> usize {
+        self.sound_index
+    }
+
+    /// Heap bytes held by the capture buffer
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.buffer.capacity() * std::mem::size_of::<f32>()
+    }
+
+    /// Feed one master sample (real-time safe)
+    ///
+    /// `on_bar` is true for the first sample of a bar. Returns the event to
+    /// report when recording starts or finishes.
+    #[inline]
+    pub(crate) fn push(&mut self, sample: f32, on_bar: bool) -> Option<EngineEventKind> {
+        match self.state {
+            RecorderState::Idle | RecorderState::Finished => None,
+            RecorderState::WaitingForBar => {
+                if !on_bar {
+                    return None;
+                }
+                self.state = RecorderState::Recording;
+                self.buffer.push(sample);
+                Some(EngineEventKind::RecordingStarted)
+            }
+            RecorderState::Recording | RecorderState::Stopping => {
+                let stop_here = self.state == RecorderState::Stopping && on_bar;
+                // Never grow past the capacity reserved when armed
+                if stop_here || self.buffer.len() == self.buffer.capacity() {
+                    self.state = RecorderState::Finished;
+                    return Some(EngineEventKind::RecordingFinished);
+                }
+                self.buffer.push(sample);
+                None
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Arm recording of the master output into `sound_index`
+    ///
+    /// Capture starts at the next bar boundary and is limited to the maximum
+    /// sound length. Any unfinished take is discarded.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn arm_master_recording(&mut self, sound_index: usize) -> bool {
+        self.arm_loop_recording(sound_index, -1, -1)
+    }
+
+    /// Arm recording of a loop into `sound_index`
+    ///
+    /// `group` selects the group bus to capture (after its volume and mute,
+    /// before the master volume), or -1 for the master output. With
+    /// `key_code` 0-255, `finish_master_recording` maps the take to that key
+    /// as a loop; -1 leaves the mappings alone. Returns false for an unknown
+    /// slot, group or key.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn arm_loop_recording(&mut self, sound_index: usize, group: i32, key_code: i32) -> bool {
+        let group = match group {
+            -1 => None,
+            group => match u8::try_from(group) {
+                Ok(group) => Some(group),
+                Err(_) => return false,
+            },
+        };
+        let key_code = match key_code {
+            -1 => None,
+            key_code => match u8::try_from(key_code) {
+                Ok(key_code) => Some(key_code),
+                Err(_) => return false,
+            },
+        };
+        if sound_index >= self.sounds.len() {
+            return false;
+        }
+        let recorder = &mut self.recorder;
+        recorder.buffer = Vec::with_capacity(self.max_sample_length);
+        recorder.sound_index = sound_index;
+        recorder.group = group;
+        recorder.key_code = key_code;
+        recorder.state = RecorderState::WaitingForBar;
+        true
+    }
+
+    /// Stop recording at the next bar boundary
+    ///
+    /// If capture has not started yet, the recording is cancelled.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stop_master_recording(&mut self) {
+        match self.recorder.state {
+            RecorderState::WaitingForBar => self.recorder.state = RecorderState::Idle,
+            RecorderState::Recording => self.recorder.state = RecorderState::Stopping,
+            _ => {}
+        }
+    }
+
+    /// Current recorder state
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_master_recording_state(&self) -> RecorderState {
+        self.recorder.state
+    }
+
+    /// Store a finished take in its slot so it can be mapped and played
+    ///
+    /// Call after a `RecordingFinished` event. The take is rounded down to
+    /// whole bars (a take shorter than a bar is kept as it is) and mapped to
+    /// the key chosen when arming, if any. Returns the slot written, or -1
+    /// if no take is finished.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn finish_master_recording(&mut self) -> i32 {
+        if self.recorder.state != RecorderState::Finished {
+            return -1;
+        }
+        self.recorder.state = RecorderState::Idle;
+        let sound_index = self.recorder.sound_index;
+        let mut take = std::mem::take(&mut self.recorder.buffer);
+        let samples_per_bar = self.samples_per_bar() as usize;
+        if samples_per_bar > 0 && take.len() >= samples_per_bar {
+            take.truncate(take.len() / samples_per_bar * samples_per_bar);
+        }
+
+        // Trimming would break the bar alignment of the take
+        let auto_trim = std::mem::replace(&mut self.load_options.auto_trim, false);
+        self.store_sound(sound_index, &take, self.sample_rate);
+        self.load_options.auto_trim = auto_trim;
+        if let Some(key_code) = self.recorder.key_code {
+            self.set_key_mapping(key_code, sound_index, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        }
+        sound_index as i32
+    }
+}
+
+impl DspEngine {
+    /// Samples per bar at the current BPM
+    #[inline]
+    pub(crate) fn samples_per_bar(&self) -> u64 {
+        (self.sample_rate * 60.0 / self.bpm * BEATS_PER_BAR) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_master_quantized_to_bars() {
+        // 120 BPM at 1 kHz: one bar is 2000 samples
+        let mut engine = DspEngine::new(1000.0);
+        engine.set_bpm(120.0);
+        engine.load_sound(0, &[0.5; 8000]);
+        engine.set_key_mapping(65, 0, crate::PlaybackMode::SingleShot, crate::OverlapMode::Polyphonic, 0, 1.0, 0, false);
+
+        let mut output = vec![0.0; 2 * 500];
+        engine.process(&mut output);
+        assert!(engine.arm_master_recording(3));
+        engine.note_on(65);
+        engine.process(&mut output);
+        assert_eq!(engine.get_master_recording_state(), RecorderState::WaitingForBar);
+
+        // Bar 2 starts at 2000; stop requested mid-bar ends at 4000
+        let mut block = vec![0.0; 2 * 2000];
+        engine.process(&mut block);
+        assert_eq!(engine.get_master_recording_state(), RecorderState::Recording);
+        engine.stop_master_recording();
+        engine.process(&mut block);
+        assert_eq!(engine.get_master_recording_state(), RecorderState::Finished);
+
+        assert_eq!(engine.finish_master_recording(), 3);
+        assert_eq!(engine.get_sound_length_samples(3), 2000);
+    }
+
+    #[test]
+    fn test_loop_recording_of_a_group_bus() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 8000]);
+        engine.load_sound(1, &[0.25; 8000]);
+        engine.max_sample_length = 3000;
+        engine.set_key_mapping(65, 0, crate::PlaybackMode::Loop, crate::OverlapMode::Polyphonic, 2, 1.0, 0, false);
+        engine.set_key_mapping(66, 1, crate::PlaybackMode::Loop, crate::OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.note_on(65);
+        engine.note_on(66);
+
+        assert!(!engine.arm_loop_recording(3, 256, -1));
+        assert!(engine.arm_loop_recording(3, 2, 70));
+        let mut block = vec![0.0; 2 * 4000];
+        engine.process(&mut block);
+        // Cut off by the length limit at 3000 samples, then rounded to one bar
+        assert_eq!(engine.get_master_recording_state(), RecorderState::Finished);
+        assert_eq!(engine.finish_master_recording(), 3);
+        assert_eq!(engine.get_sound_length_samples(3), 2000);
+        assert!(engine.loaded_samples(3).is_some_and(|take| take.iter().all(|&s| s == 0.5)), "group 2 only");
+        let mapping = engine.key_mappings[70];
+        assert!(mapping.has_sound && mapping.sound_index == 3 && mapping.mode == crate::PlaybackMode::Loop);
+    }
+}
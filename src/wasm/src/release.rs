@@ -0,0 +1,108 @@
+//! Release stage and release velocity
+//!
+//! Releasing a loop voice fades it out over its key's release time instead
+//! of cutting it off (single shots fade out the same way at their end, see
+//! `envelope`). A MIDI note-off's release velocity scales that time: a
+//! quick lift (127) halves it, a slow one (0) doubles it, and the default
+//! of 64 leaves it as set. Keys default to no release, which stops voices
+//! on the spot as before.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::events::EngineEventKind;
+use crate::history::ConfigChange;
+use crate::performance::Performed;
+use crate::{DspEngine, PlaybackMode, Voice, VoiceSource};
+
+/// Longest release time in milliseconds
+const MAX_RELEASE_MS: f32 = 10_000.0;
+
+/// Release velocity assumed when a note-off carries none
+pub(crate) const DEFAULT_RELEASE_VELOCITY: u8 = 64;
+
+/// Fade used to silence voices without a click (panic, All Sound Off)
+const QUICK_FADE_MS: f32 = 5.0;
+
+impl DspEngine {
+    /// Release the loop voices started by `trigger` from `source`
+    pub(crate) fn release_voices(&mut self, source: VoiceSource, trigger: u16, velocity: u8) {
+        let scale = 2.0_f32.powf(1.0 - velocity.min(127) as f32 / DEFAULT_RELEASE_VELOCITY as f32);
+        for (slot, voice) in self.voices.iter_mut().enumerate() {
+            let matches = voice.source == source && voice.trigger == trigger;
+            if !voice.active || !matches || voice.mode != PlaybackMode::Loop || voice.release_step > 0.0 {
+                continue;
+            }
+            let release_samples = voice.release_ms * 0.001 * self.sample_rate * scale;
+            if release_samples < 1.0 {
+                voice.active = false;
+                self.events.push(EngineEventKind::VoiceStopped, trigger, slot as u32, self.global_sample_position);
+            } else {
+                voice.release_step = voice.release_gain / release_samples;
+            }
+        }
+    }
+
+    /// Fade out every voice `matches` accepts over `QUICK_FADE_MS`, whatever
+    /// its mode; voices already releasing faster keep their own pace
+    pub(crate) fn fade_out_voices(&mut self, matches: impl Fn(&Voice) -> bool) {
+        let fade_samples = (QUICK_FADE_MS * 0.001 * self.sample_rate).max(1.0);
+        for voice in self.voices.iter_mut().filter(|voice| voice.active && matches(voice)) {
+            voice.release_step = voice.release_step.max(voice.release_gain / fade_samples);
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set how long a key's loop voices take to fade out once released,
+    /// and its single shots before their end, in milliseconds (0-10000,
+    /// 0 = stop at once)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_release(&mut self, key_code: u8, ms: f32) {
+        self.record_change(ConfigChange::KeyMapping(key_code));
+        let ms = if ms.is_finite() { ms.clamp(0.0, MAX_RELEASE_MS) } else { 0.0 };
+        self.key_mappings[key_code as usize].release_ms = ms;
+    }
+
+    /// Release time of a key in milliseconds
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_release(&self, key_code: u8) -> f32 {
+        self.key_mappings[key_code as usize].release_ms
+    }
+
+    /// Handle a MIDI note-off carrying a release velocity
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn midi_note_off_velocity(&mut self, channel: u8, note: u8, velocity: u8) {
+        self.record_performance(Performed::NoteOff { channel, note, velocity });
+        self.release_voices(VoiceSource::Midi(channel, note), note as u16, velocity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_release_velocity_scales_release() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(60, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_key_release(60, 100.0);
+        engine.set_midi_channel_bank(0, 0);
+
+        // A slow lift doubles the 100 ms release
+        engine.midi_note_on(0, 60, 127);
+        engine.midi_note_off_velocity(0, 60, 0);
+        engine.process(&mut [0.0; 2 * 150]);
+        assert!(engine.voices[0].active && engine.voices[0].release_gain < 0.5);
+        engine.process(&mut [0.0; 2 * 60]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+
+        // A quick lift halves it
+        engine.midi_note_on(0, 60, 127);
+        engine.midi_note_off_velocity(0, 60, 127);
+        engine.process(&mut [0.0; 2 * 60]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+    }
+}
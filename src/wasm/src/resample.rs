@@ -0,0 +1,183 @@
+//! Offline sample-rate conversion
+//!
+//! Used when a sound's source rate differs from the engine rate so it plays
+//! at the right pitch and speed. Runs at load time only.
+
+use std::f64::consts::PI;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::errors::ErrorCode;
+use crate::simd::mix_linear;
+use crate::DspEngine;
+
+/// Lowest source rate accepted for conversion (Hz)
+pub(crate) const MIN_SOURCE_RATE: f32 = 1_000.0;
+
+/// Highest source rate accepted for conversion (Hz)
+pub(crate) const MAX_SOURCE_RATE: f32 = 384_000.0;
+
+/// Whether audio recorded at `rate` can be converted (false for NaN/inf)
+pub(crate) fn is_supported_rate(rate: f32) -> bool {
+    (MIN_SOURCE_RATE..=MAX_SOURCE_RATE).contains(&rate)
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum ResampleQuality {
+    /// Linear interpolation - fastest, audible aliasing/dulling
+    Linear = 0,
+    /// 16-tap windowed sinc - good default
+    Sinc = 1,
+    /// 64-tap windowed sinc - transparent, slowest
+    HighSinc = 2,
+}
+
+impl ResampleQuality {
+    /// Number of input samples on each side of the interpolation point
+    fn half_taps(self) -> usize {
+        match self {
+            ResampleQuality::Linear => 1,
+            ResampleQuality::Sinc => 8,
+            ResampleQuality::HighSinc => 32,
+        }
+    }
+}
+
+/// Normalized sinc: sin(pi x) / (pi x)
+#[inline]
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 { 1.0 } else { (PI * x).sin() / (PI * x) }
+}
+
+/// Blackman window over -1.0..=1.0
+#[inline]
+fn blackman(t: f64) -> f64 {
+    let x = (t + 1.0) * 0.5;
+    0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos()
+}
+
+/// Input samples that decide the first `output_len` samples of a
+/// conversion, so the rest can be dropped before converting
+pub(crate) fn input_needed(output_len: usize, from_rate: f32, to_rate: f32) -> usize {
+    let ratio = from_rate as f64 / to_rate as f64;
+    // The widest kernel, widened further when downsampling
+    let reach = ResampleQuality::HighSinc.half_taps() as f64 * ratio.max(1.0);
+    (output_len as f64 * ratio + reach).ceil() as usize + 1
+}
+
+/// Convert `input` from `from_rate` to `to_rate`
+///
+/// The sinc kernels are widened when downsampling so content above the new
+/// Nyquist frequency is filtered out rather than aliased.
+pub(crate) fn resample(input: &[f32], from_rate: f32, to_rate: f32, quality: ResampleQuality) -> Vec<f32> {
+    // NaN/inf rates would never finish widening the kernel
+    let usable = |rate: f32| rate.is_finite() && rate > 0.0;
+    if input.is_empty() || !usable(from_rate) || !usable(to_rate) || from_rate == to_rate {
+        return input.to_vec();
+    }
+
+    let ratio = from_rate as f64 / to_rate as f64;
+    let out_len = ((input.len() as f64) / ratio).round().max(1.0) as usize;
+    let sample = |i: isize| -> f64 {
+        if i < 0 || i as usize >= input.len() { 0.0 } else { input[i as usize] as f64 }
+    };
+
+    if quality == ResampleQuality::Linear {
+        // Every output but the last few reads two input samples in range
+        let mut inside = (((input.len() - 1) as f64 / ratio).ceil() as usize).min(out_len);
+        while inside > 0 && ((inside - 1) as f64 * ratio) as usize + 1 >= input.len() {
+            inside -= 1;
+        }
+        let mut output = vec![0.0; out_len];
+        mix_linear(input, 0.0, ratio, 1.0, &mut output[..inside]);
+        for (n, out) in output.iter_mut().enumerate().skip(inside) {
+            let pos = n as f64 * ratio;
+            let base = pos.floor() as isize;
+            let frac = pos - base as f64;
+            let next = if (base + 1) as usize >= input.len() { sample(base) } else { sample(base + 1) };
+            *out = (sample(base) + (next - sample(base)) * frac) as f32;
+        }
+        return output;
+    }
+
+    // Cutoff relative to the input Nyquist (lowered when downsampling)
+    let cutoff = (1.0 / ratio).min(1.0);
+    let half_taps = quality.half_taps() as f64 / cutoff;
+    let reach = half_taps.ceil() as isize;
+
+    (0..out_len)
+        .map(|n| {
+            let pos = n as f64 * ratio;
+            let center = pos.floor() as isize;
+            let mut acc = 0.0;
+            for i in (center - reach + 1)..=(center + reach) {
+                let distance = pos - i as f64;
+                if distance.abs() >= half_taps {
+                    continue;
+                }
+                acc += sample(i) * cutoff * sinc(distance * cutoff) * blackman(distance / half_taps);
+            }
+            acc as f32
+        })
+        .collect()
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Choose the converter used when a sound's rate differs from the engine's
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.load_options.resample_quality = quality;
+    }
+
+    /// Load mono samples recorded at `source_rate`, converting to the engine rate
+    ///
+    /// Returns `BadSampleRate` if the rate is not within 1-384 kHz.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_sound_with_rate(&mut self, sound_index: usize, samples: &[f32], source_rate: f32) -> ErrorCode {
+        self.store_sound(sound_index, samples, source_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample_preserves_tone() {
+        let tone = |rate: f32, len: usize| -> Vec<f32> {
+            (0..len).map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / rate).sin()).collect()
+        };
+        let input = tone(44100.0, 4410);
+        let expected = tone(48000.0, 4800);
+
+        for quality in [ResampleQuality::Linear, ResampleQuality::Sinc, ResampleQuality::HighSinc] {
+            let output = resample(&input, 44100.0, 48000.0, quality);
+            assert_eq!(output.len(), 4800);
+            // Compare away from the edges, where the kernel runs off the input
+            let error = output[200..4600]
+                .iter()
+                .zip(&expected[200..4600])
+                .fold(0.0_f32, |m, (a, b)| m.max((a - b).abs()));
+            assert!(error < 0.01, "{quality:?} error {error}");
+        }
+    }
+
+    #[test]
+    fn test_unusable_source_rates_are_rejected() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 100]);
+        for rate in [f32::INFINITY, f32::NAN, -44100.0, 10.0, 1_000_000.0] {
+            assert_eq!(engine.load_sound_with_rate(0, &[0.25; 2000], rate), ErrorCode::BadSampleRate, "{rate}");
+        }
+        assert_eq!(engine.get_sound_length_samples(0), 100, "the slot is unchanged");
+
+        // Only what fits the maximum length is converted
+        engine.set_max_sample_seconds(0.5);
+        assert_eq!(engine.load_sound_with_rate(0, &[0.25; 100_000], 1000.0), ErrorCode::None);
+        assert_eq!(engine.get_sound_length_samples(0), 24000);
+    }
+}
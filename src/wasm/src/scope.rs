@@ -0,0 +1,72 @@
+//! Oscilloscope snapshot buffer
+//!
+//! Keeps a rolling copy of the most recent master output so the UI can
+//! draw exactly what the engine produced.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Number of master samples retained for the scope
+const SCOPE_LENGTH: usize = 2048;
+
+pub(crate) struct Scope {
+    buffer: [f32; SCOPE_LENGTH],
+    /// Next write index
+    write: usize,
+}
+
+impl Scope {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buffer: [0.0; SCOPE_LENGTH],
+            write: 0,
+        }
+    }
+
+    /// Append one master sample (real-time safe)
+    #[inline(always)]
+    pub(crate) fn push(&mut self, sample: f32) {
+        self.buffer[self.write] = sample;
+        self.write = (self.write + 1) % SCOPE_LENGTH;
+    }
+
+    /// Copy the newest `out.len()` samples into `out`, oldest first
+    fn copy_latest(&self, out: &mut [f32]) -> usize {
+        let count = out.len().min(SCOPE_LENGTH);
+        let start = (self.write + SCOPE_LENGTH - count) % SCOPE_LENGTH;
+        for (i, dst) in out[..count].iter_mut().enumerate() {
+            *dst = self.buffer[(start + i) % SCOPE_LENGTH];
+        }
+        count
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Copy the most recent master output samples into `out`, oldest first
+    ///
+    /// Up to 2048 samples are retained. Returns the number written.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_scope(&self, out: &mut [f32]) -> u32 {
+        self.scope.copy_latest(out) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_returns_latest_in_order() {
+        let mut scope = Scope::new();
+        for i in 0..SCOPE_LENGTH + 10 {
+            scope.push(i as f32);
+        }
+        let mut out = [0.0; 4];
+        assert_eq!(scope.copy_latest(&mut out), 4);
+        let last = (SCOPE_LENGTH + 9) as f32;
+        assert_eq!(out, [last - 3.0, last - 2.0, last - 1.0, last]);
+    }
+}
@@ -0,0 +1,782 @@
+//! Step sequencer
+//!
+//! A pattern of 1/16-note steps plays keys on the beat grid the metronome
+//! uses, so patterns stay in time with loops and the click. Each step
+//! carries its own velocity (shaped by the key's velocity curve), a pitch
+//! offset from the key's own pitch, a probability of playing on each pass
+//! and a micro-timing nudge of up to half a step either way.
+//!
+//! Steps of loop keys are released when their step ends.
+//!
+//! There are 16 patterns. Outside song mode the selected pattern loops; in
+//! song mode the song (a list of patterns with repeat counts) plays from
+//! the top and loops as a whole. Either way the playhead is worked out from
+//! the beat grid, so the song stays in time when playback restarts.
+//!
+//! While recording, keys pressed during playback are added to the pattern
+//! under the playhead, snapped to the input quantization grid, and each
+//! pass overdubs the last. Patterns reserve room for their steps when
+//! recording is switched on, so recording never allocates in `process()`.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::release::DEFAULT_RELEASE_VELOCITY;
+use crate::synth::NoiseSource;
+use crate::{DspEngine, PlaybackMode, VoiceSource};
+
+/// Longest pattern in steps
+pub(crate) const MAX_PATTERN_LENGTH: u8 = 64;
+
+/// Most steps set in a pattern
+pub(crate) const MAX_PATTERN_STEPS: usize = 1024;
+
+/// Patterns available for editing and chaining
+pub(crate) const MAX_PATTERNS: usize = 16;
+
+/// Most entries in the song
+pub(crate) const MAX_SONG_ENTRIES: usize = 128;
+
+/// Default seed of the step probability generator
+const RNG_SEED: u32 = 0x5EC0;
+
+/// Most loop steps waiting for their release
+const MAX_GATES: usize = 256;
+
+/// Velocity of steps recorded from the keyboard
+const RECORDED_VELOCITY: u8 = 127;
+
+/// Grid live input is snapped to while recording
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum RecordQuantize {
+    /// Keep the exact timing as a step nudge
+    Off = 0,
+    /// Snap to the nearest 1/16 step
+    Sixteenth = 1,
+    /// Snap to the nearest 1/8 (every other step)
+    Eighth = 2,
+}
+
+/// Ticks per step of `set_step_offset` (480 per beat, as in MIDI files)
+const TICKS_PER_STEP: i32 = 120;
+
+/// Values of `get_pattern_packed` per step
+const PACKED_STEP_VALUES: usize = 6;
+
+/// A step that plays a key
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Step {
+    pub(crate) key_code: u8,
+    pub(crate) step: u8,
+    /// Note velocity (1-127)
+    pub(crate) velocity: u8,
+    /// Semitones added to the key's pitch
+    pub(crate) pitch: i8,
+    /// Chance of playing on each pass (0.0 to 1.0)
+    pub(crate) probability: f32,
+    /// Offset from the step in steps (-0.5 to 0.5)
+    pub(crate) nudge: f32,
+}
+
+impl Step {
+    /// A step with every setting brought into range
+    pub(crate) fn new(key_code: u8, step: u8, velocity: u8, pitch: i8, probability: f32, nudge: f32) -> Self {
+        let finite = |value: f32, default: f32| if value.is_finite() { value } else { default };
+        Self {
+            key_code,
+            step,
+            velocity: velocity.clamp(1, 127),
+            pitch: pitch.clamp(-24, 24),
+            probability: finite(probability, 1.0).clamp(0.0, 1.0),
+            nudge: finite(nudge, 0.0).clamp(-0.5, 0.5),
+        }
+    }
+
+    /// When the step plays, in steps from the pattern start
+    #[inline]
+    fn time(&self, length: u8, swing: f32) -> f64 {
+        (self.step as f64 + swing_delay(self.step, swing) + self.nudge as f64).rem_euclid(length as f64)
+    }
+}
+
+/// How late swing plays a step, in steps: every second step is delayed by
+/// up to half a step
+#[inline]
+fn swing_delay(step: u8, swing: f32) -> f64 {
+    if step % 2 == 1 {
+        swing as f64 * 0.5
+    } else {
+        0.0
+    }
+}
+
+pub(crate) struct Pattern {
+    /// Sorted by play time
+    pub(crate) steps: Vec<Step>,
+    /// Length in steps
+    pub(crate) length: u8,
+}
+
+impl Pattern {
+    pub(crate) const fn new() -> Self {
+        Self { steps: Vec::new(), length: 16 }
+    }
+
+    /// Restore play-time order after an edit
+    pub(crate) fn sort(&mut self, swing: f32) {
+        let length = self.length;
+        self.steps.sort_by(|a, b| a.time(length, swing).total_cmp(&b.time(length, swing)));
+    }
+
+    /// Add or replace a step in play-time order without growing past the
+    /// reserved capacity (real-time safe); returns false if there is no room
+    fn insert_in_place(&mut self, step: Step, swing: f32) -> bool {
+        if let Some(index) = self.steps.iter().position(|s| s.key_code == step.key_code && s.step == step.step) {
+            self.steps.remove(index);
+        } else if self.steps.len() >= self.steps.capacity().min(MAX_PATTERN_STEPS) {
+            return false;
+        }
+        let (length, time) = (self.length, step.time(self.length, swing));
+        let index = self.steps.partition_point(|s| s.time(length, swing) <= time);
+        self.steps.insert(index, step);
+        true
+    }
+}
+
+/// A song entry: a pattern played a number of times in a row
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct SongEntry {
+    pub(crate) pattern: u8,
+    /// Passes of the pattern (1-255)
+    pub(crate) repeats: u8,
+}
+
+/// Where the playhead is
+struct Playhead {
+    /// Song entry, if the song is playing
+    entry: Option<usize>,
+    /// Pass of the entry's pattern
+    repeat: u32,
+    pattern: usize,
+    /// Position in the pattern in steps
+    position: f64,
+}
+
+pub(crate) struct Sequencer {
+    pub(crate) patterns: Vec<Pattern>,
+    /// Pattern edited by the step API and played outside song mode
+    pub(crate) selected: usize,
+    pub(crate) song: Vec<SongEntry>,
+    pub(crate) song_mode: bool,
+    /// Delay of every second step (0.0 straight to 1.0, half a step)
+    pub(crate) swing: f32,
+    /// Length of the song in steps
+    song_steps: u64,
+    playing: bool,
+    /// Pattern played at the previous frame
+    last_pattern: usize,
+    /// First step not yet played on this pass
+    next_step: usize,
+    /// Position in the pattern at the previous frame, in steps
+    last_position: f64,
+    /// Find `next_step` again from the current position
+    resync: bool,
+    /// Loop steps to release as (sample position, key code)
+    gates: Vec<(u64, u8)>,
+    rng: NoiseSource,
+    /// Seed `rng` restarts from
+    pub(crate) seed: u32,
+    pub(crate) recording: bool,
+    record_quantize: RecordQuantize,
+    /// A step just recorded ahead of the playhead, as (key code, step,
+    /// last sample): the key already sounded, so it is not played again
+    recorded_ahead: Option<(u8, u8, u64)>,
+}
+
+impl Sequencer {
+    pub(crate) fn new() -> Self {
+        Self {
+            patterns: (0..MAX_PATTERNS).map(|_| Pattern::new()).collect(),
+            selected: 0,
+            song: Vec::with_capacity(MAX_SONG_ENTRIES),
+            song_mode: false,
+            swing: 0.0,
+            song_steps: 0,
+            playing: false,
+            last_pattern: 0,
+            next_step: 0,
+            last_position: 0.0,
+            resync: true,
+            gates: Vec::with_capacity(MAX_GATES),
+            rng: NoiseSource::new(RNG_SEED),
+            seed: RNG_SEED,
+            recording: false,
+            record_quantize: RecordQuantize::Sixteenth,
+            recorded_ahead: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_active(&self) -> bool {
+        self.playing || !self.gates.is_empty()
+    }
+
+    /// The selected pattern
+    #[inline]
+    pub(crate) fn pattern(&self) -> &Pattern {
+        &self.patterns[self.selected]
+    }
+
+    #[inline]
+    pub(crate) fn pattern_mut(&mut self) -> &mut Pattern {
+        &mut self.patterns[self.selected]
+    }
+
+    /// Make room for every step a pattern can hold, so recording can add
+    /// steps from `process()`
+    pub(crate) fn reserve_steps(&mut self) {
+        for pattern in self.patterns.iter_mut() {
+            pattern.steps.reserve(MAX_PATTERN_STEPS.saturating_sub(pattern.steps.len()));
+        }
+    }
+
+    fn reserve_for_recording(&mut self) {
+        if self.recording {
+            self.reserve_steps();
+        }
+    }
+
+    /// Note that patterns or the song changed under the playhead
+    pub(crate) fn edited(&mut self) {
+        for pattern in self.patterns.iter_mut() {
+            pattern.sort(self.swing);
+        }
+        self.reserve_for_recording();
+        let patterns = &self.patterns;
+        self.song_steps = self
+            .song
+            .iter()
+            .map(|entry| patterns[entry.pattern as usize].length as u64 * entry.repeats as u64)
+            .sum();
+        self.resync = true;
+    }
+
+    /// Locate the playhead `clock` steps after the start of the beat grid
+    fn locate(&self, clock: f64) -> Playhead {
+        if self.song_mode && self.song_steps > 0 {
+            let mut position = clock.rem_euclid(self.song_steps as f64);
+            for (index, entry) in self.song.iter().enumerate() {
+                let length = self.patterns[entry.pattern as usize].length as f64;
+                let entry_steps = length * entry.repeats as f64;
+                if position < entry_steps || index == self.song.len() - 1 {
+                    let repeat = ((position / length) as u32).min(entry.repeats as u32 - 1);
+                    position -= repeat as f64 * length;
+                    return Playhead { entry: Some(index), repeat, pattern: entry.pattern as usize, position };
+                }
+                position -= entry_steps;
+            }
+        }
+        let length = self.pattern().length as f64;
+        Playhead { entry: None, repeat: 0, pattern: self.selected, position: clock.rem_euclid(length) }
+    }
+
+    /// First step of `pattern` not yet played on this pass, with the
+    /// playhead at `position`
+    fn pending_step(&self, pattern: usize, position: f64) -> usize {
+        let (length, swing) = (self.patterns[pattern].length, self.swing);
+        if self.resync {
+            self.patterns[pattern].steps.partition_point(|step| step.time(length, swing) < position)
+        } else if pattern != self.last_pattern || position < self.last_position {
+            0
+        } else {
+            self.next_step
+        }
+    }
+
+    /// Whether a step plays or a loop step is released at sample `now`
+    #[inline]
+    pub(crate) fn is_due(&self, now: u64, samples_per_step: f64) -> bool {
+        if self.gates.first().is_some_and(|&(at, _)| at <= now) {
+            return true;
+        }
+        if !self.playing {
+            return false;
+        }
+        let Playhead { pattern, position, .. } = self.locate(now as f64 / samples_per_step);
+        let (steps, length) = (&self.patterns[pattern].steps, self.patterns[pattern].length);
+        steps.get(self.pending_step(pattern, position)).is_some_and(|step| step.time(length, self.swing) <= position)
+    }
+
+    /// Forget pending releases and find the playhead again (the clock jumped)
+    pub(crate) fn reset_playhead(&mut self) {
+        self.gates.clear();
+        self.resync = true;
+    }
+
+    /// Restart step probabilities from their seed, so a render repeats
+    pub(crate) fn reseed(&mut self) {
+        self.rng = NoiseSource::new(self.seed);
+    }
+}
+
+impl DspEngine {
+    /// The playhead at the current sample
+    fn playhead(&self) -> Playhead {
+        let samples_per_step = self.samples_per_step();
+        let clock = if samples_per_step > 0.0 { self.global_sample_position as f64 / samples_per_step } else { 0.0 };
+        self.sequencer.locate(clock)
+    }
+
+    /// Samples per sequencer step at the session BPM
+    #[inline]
+    pub(crate) fn samples_per_step(&self) -> f64 {
+        self.sample_rate as f64 * 60.0 / self.bpm as f64 / 4.0
+    }
+
+    /// Play the steps due at the current sample and release finished loop
+    /// steps (called once per frame from `process()` while active)
+    #[inline]
+    pub(crate) fn advance_sequencer(&mut self, samples_per_step: f64) {
+        let now = self.global_sample_position;
+        while let Some(&(at, key_code)) = self.sequencer.gates.first() {
+            if at > now {
+                break;
+            }
+            self.sequencer.gates.remove(0);
+            self.release_voices(VoiceSource::Sequencer, key_code as u16, DEFAULT_RELEASE_VELOCITY);
+        }
+        if !self.sequencer.playing {
+            return;
+        }
+
+        let sequencer = &mut self.sequencer;
+        let Playhead { pattern, position, .. } = sequencer.locate(now as f64 / samples_per_step);
+        let (length, swing) = (sequencer.patterns[pattern].length, sequencer.swing);
+        sequencer.next_step = sequencer.pending_step(pattern, position);
+        sequencer.resync = false;
+        sequencer.last_pattern = pattern;
+        sequencer.last_position = position;
+
+        while let Some(&step) = self.sequencer.patterns[pattern].steps.get(self.sequencer.next_step) {
+            if step.time(length, swing) > position {
+                break;
+            }
+            self.sequencer.next_step += 1;
+            if let Some((key_code, at_step, until)) = self.sequencer.recorded_ahead {
+                if now > until {
+                    self.sequencer.recorded_ahead = None;
+                } else if (key_code, at_step) == (step.key_code, step.step) {
+                    self.sequencer.recorded_ahead = None;
+                    continue;
+                }
+            }
+            self.play_step(step, samples_per_step);
+        }
+    }
+
+    /// Record a key pressed while the sequencer plays and records
+    pub(crate) fn record_step(&mut self, key_code: u8) {
+        if !self.sequencer.recording || !self.sequencer.playing {
+            return;
+        }
+        let samples_per_step = self.samples_per_step();
+        let Playhead { pattern, position, .. } = self.playhead();
+        let grid = match self.sequencer.record_quantize {
+            RecordQuantize::Off => None,
+            RecordQuantize::Sixteenth => Some(1.0),
+            RecordQuantize::Eighth => Some(2.0),
+        };
+        let swing = self.sequencer.swing;
+        let target = match grid {
+            Some(grid) => (position / grid).round() * grid,
+            None => position.round(),
+        };
+        let pattern_steps = &mut self.sequencer.patterns[pattern];
+        let step = (target as u64 % pattern_steps.length as u64) as u8;
+        // Unquantized takes keep their timing against the swung step
+        let nudge = match grid {
+            Some(_) => 0.0,
+            None => position - target - swing_delay(step, swing),
+        };
+        let recorded = Step::new(key_code, step, RECORDED_VELOCITY, 0, 1.0, nudge as f32);
+        if !pattern_steps.insert_in_place(recorded, swing) {
+            return;
+        }
+        // A step at or ahead of the playhead would sound a second time
+        let ahead = target + swing_delay(step, swing) + recorded.nudge as f64 - position;
+        if ahead >= 0.0 {
+            let until = self.global_sample_position + ((ahead + 0.5) * samples_per_step) as u64;
+            self.sequencer.recorded_ahead = Some((key_code, step, until));
+        }
+        self.sequencer.resync = true;
+    }
+
+    fn play_step(&mut self, step: Step, samples_per_step: f64) {
+        let mut mapping = self.key_mappings[step.key_code as usize];
+        if !mapping.has_sound || !self.sounds[mapping.sound_index].loaded {
+            return;
+        }
+        if step.probability < 1.0 && self.sequencer.rng.next() * 0.5 + 0.5 >= step.probability {
+            return;
+        }
+        mapping.volume *= mapping.velocity.gain(step.velocity);
+        mapping.pitch_semitones = mapping.pitch_semitones.saturating_add(step.pitch).clamp(-48, 48);
+        self.trigger_voice(&mapping, step.key_code as u16, VoiceSource::Sequencer);
+
+        let gates = &mut self.sequencer.gates;
+        if mapping.mode == PlaybackMode::Loop && gates.len() < MAX_GATES {
+            let at = self.global_sample_position + samples_per_step as u64;
+            let index = gates.partition_point(|&(gate, _)| gate <= at);
+            gates.insert(index, (at, step.key_code));
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set a step of the selected pattern to play a key
+    ///
+    /// `velocity` is 1-127, `pitch` is added to the key's pitch in
+    /// semitones, `probability` (0.0-1.0) is the chance the step plays on
+    /// each pass and `nudge` shifts it by up to half a step either way.
+    /// Returns false if the step lies beyond the pattern or the pattern is
+    /// full.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_step(
+        &mut self,
+        key_code: u8,
+        step: u8,
+        velocity: u8,
+        pitch: i8,
+        probability: f32,
+        nudge: f32,
+    ) -> bool {
+        let pattern = self.sequencer.pattern_mut();
+        if step >= pattern.length {
+            return false;
+        }
+        let set = Step::new(key_code, step, velocity, pitch, probability, nudge);
+        match pattern.steps.iter().position(|s| s.key_code == key_code && s.step == step) {
+            Some(index) => pattern.steps[index] = set,
+            None if pattern.steps.len() >= MAX_PATTERN_STEPS => return false,
+            None => pattern.steps.push(set),
+        }
+        self.sequencer.edited();
+        true
+    }
+
+    /// Clear a step; returns false if it was not set
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_step(&mut self, key_code: u8, step: u8) -> bool {
+        let steps = &mut self.sequencer.pattern_mut().steps;
+        let count = steps.len();
+        steps.retain(|s| s.key_code != key_code || s.step != step);
+        let cleared = steps.len() != count;
+        self.sequencer.edited();
+        cleared
+    }
+
+    /// Move a step of the selected pattern off the grid by `ticks` (120 to
+    /// a step, up to 60 either way)
+    ///
+    /// Returns false if the step is not set.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_step_offset(&mut self, key_code: u8, step: u8, ticks: i32) -> bool {
+        let steps = &mut self.sequencer.pattern_mut().steps;
+        let Some(set) = steps.iter_mut().find(|s| s.key_code == key_code && s.step == step) else {
+            return false;
+        };
+        let ticks = ticks.clamp(-TICKS_PER_STEP / 2, TICKS_PER_STEP / 2);
+        set.nudge = ticks as f32 / TICKS_PER_STEP as f32;
+        self.sequencer.edited();
+        true
+    }
+
+    /// Set the sequencer's swing, which delays every second step
+    ///
+    /// 0.0 plays straight, 1.0 delays by half a step (about 0.33 gives a
+    /// triplet feel). Only sequencer steps swing; keys and loops played
+    /// directly stay on the grid.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sequencer_swing(&mut self, amount: f32) {
+        self.sequencer.swing = if amount.is_finite() { amount.clamp(0.0, 1.0) } else { 0.0 };
+        self.sequencer.edited();
+    }
+
+    /// The sequencer's swing (0.0-1.0)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sequencer_swing(&self) -> f32 {
+        self.sequencer.swing
+    }
+
+    /// Clear every step of the selected pattern
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_pattern(&mut self) {
+        self.sequencer.pattern_mut().steps.clear();
+        self.sequencer.edited();
+    }
+
+    /// Set the selected pattern's length in steps (1-64); steps beyond it
+    /// are dropped
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_pattern_length(&mut self, steps: u8) {
+        let pattern = self.sequencer.pattern_mut();
+        pattern.length = steps.clamp(1, MAX_PATTERN_LENGTH);
+        let length = pattern.length;
+        pattern.steps.retain(|s| s.step < length);
+        self.sequencer.edited();
+    }
+
+    /// Selected pattern's length in steps
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_pattern_length(&self) -> u8 {
+        self.sequencer.pattern().length
+    }
+
+    /// Every set step of the selected pattern as 6 values each: key code, step, velocity, pitch,
+    /// probability and nudge, in play order
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_pattern_packed(&self) -> Vec<f32> {
+        let steps = &self.sequencer.pattern().steps;
+        let mut packed = Vec::with_capacity(steps.len() * PACKED_STEP_VALUES);
+        for s in steps {
+            packed.extend_from_slice(&[
+                s.key_code as f32,
+                s.step as f32,
+                s.velocity as f32,
+                s.pitch as f32,
+                s.probability,
+                s.nudge,
+            ]);
+        }
+        packed
+    }
+
+    /// Start or stop the sequencer
+    ///
+    /// It plays from wherever the beat grid is, so it starts in time with
+    /// the metronome and loops.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sequencer_playing(&mut self, playing: bool) {
+        self.sequencer.playing = playing;
+        self.sequencer.resync = true;
+    }
+
+    /// Whether the sequencer is playing
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_sequencer_playing(&self) -> bool {
+        self.sequencer.playing
+    }
+
+    /// Record keys pressed during playback into the pattern under the
+    /// playhead, overdubbing on each pass
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_sequencer_recording(&mut self, recording: bool) {
+        self.sequencer.recording = recording;
+        self.sequencer.reserve_for_recording();
+    }
+
+    /// Whether key presses are recorded into the sequencer
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_sequencer_recording(&self) -> bool {
+        self.sequencer.recording
+    }
+
+    /// Choose the grid recorded key presses are snapped to (default 1/16)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_record_quantize(&mut self, quantize: RecordQuantize) {
+        self.sequencer.record_quantize = quantize;
+    }
+
+    /// Select the pattern (0-15) the step API edits and that plays outside
+    /// song mode; returns false for an unknown pattern
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn select_pattern(&mut self, pattern: u8) -> bool {
+        if pattern as usize >= MAX_PATTERNS {
+            return false;
+        }
+        self.sequencer.selected = pattern as usize;
+        self.sequencer.resync = true;
+        true
+    }
+
+    /// Selected pattern
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_selected_pattern(&self) -> u8 {
+        self.sequencer.selected as u8
+    }
+
+    /// Append a pattern to the song, played `repeats` times (1-255)
+    ///
+    /// Returns false for an unknown pattern or when the song is full.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn add_song_entry(&mut self, pattern: u8, repeats: u8) -> bool {
+        let song = &mut self.sequencer.song;
+        if pattern as usize >= MAX_PATTERNS || song.len() >= MAX_SONG_ENTRIES {
+            return false;
+        }
+        song.push(SongEntry { pattern, repeats: repeats.max(1) });
+        self.sequencer.edited();
+        true
+    }
+
+    /// Remove every entry from the song
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_song(&mut self) {
+        self.sequencer.song.clear();
+        self.sequencer.edited();
+    }
+
+    /// The song as pattern and repeat count pairs
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_song_packed(&self) -> Vec<u8> {
+        self.sequencer.song.iter().flat_map(|entry| [entry.pattern, entry.repeats]).collect()
+    }
+
+    /// Play the song instead of the selected pattern
+    ///
+    /// An empty song leaves the selected pattern playing.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_song_mode(&mut self, enabled: bool) {
+        self.sequencer.song_mode = enabled;
+        self.sequencer.resync = true;
+    }
+
+    /// Whether song mode is on
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_song_mode(&self) -> bool {
+        self.sequencer.song_mode
+    }
+
+    /// Song entry under the playhead (-1 outside song mode)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_song_entry(&self) -> i32 {
+        self.playhead().entry.map_or(-1, |entry| entry as i32)
+    }
+
+    /// Pass of the current song entry's pattern, from 0
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_song_repeat(&self) -> u8 {
+        self.playhead().repeat as u8
+    }
+
+    /// Pattern under the playhead
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_playing_pattern(&self) -> u8 {
+        self.playhead().pattern as u8
+    }
+
+    /// Step under the playhead (counted even while stopped)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sequencer_step(&self) -> u8 {
+        self.playhead().position as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_steps_play_with_their_settings() {
+        // 120 BPM at 1 kHz: one step is 125 samples
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 2, false);
+        engine.set_pattern_length(4);
+        assert!(engine.set_step(65, 1, 127, 10, 1.0, -0.2));
+        assert!(engine.set_step(65, 3, 64, 0, 0.0, 0.0), "never plays");
+        assert!(!engine.set_step(65, 4, 127, 0, 1.0, 0.0), "beyond the pattern");
+
+        engine.set_sequencer_playing(true);
+        engine.process(&mut [0.0; 2 * 100]);
+        assert_eq!(engine.get_active_voice_count(), 0, "nudged to sample 100");
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.get_active_voice_count(), 1);
+        assert_eq!(engine.voices[0].pitch, 2.0, "key pitch plus the step's offset");
+
+        // The loop step is released one step later; step 3 never plays
+        engine.process(&mut [0.0; 2 * 399]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        assert_eq!(engine.get_sequencer_step(), 0);
+        assert_eq!(engine.get_pattern_packed().len(), 12);
+    }
+
+    #[test]
+    fn test_song_chains_patterns() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        for pattern in 0..2 {
+            assert!(engine.select_pattern(pattern));
+            engine.set_pattern_length(2);
+            assert!(engine.set_step(65, pattern, 127, 0, 1.0, 0.0));
+        }
+        assert!(engine.add_song_entry(1, 2) && engine.add_song_entry(0, 1));
+        assert!(!engine.add_song_entry(16, 1));
+        engine.set_song_mode(true);
+        engine.set_sequencer_playing(true);
+
+        // Pattern 1 twice (its step on 1 and 3), then pattern 0 (step 4)
+        engine.process(&mut [0.0; 2 * 375]);
+        assert_eq!((engine.get_song_entry(), engine.get_song_repeat(), engine.get_sequencer_step()), (0, 1, 1));
+        assert_eq!(engine.get_active_voice_count(), 1);
+        engine.process(&mut [0.0; 2 * 125]);
+        assert_eq!((engine.get_song_entry(), engine.get_playing_pattern()), (1, 0));
+        assert_eq!(engine.get_active_voice_count(), 2);
+        engine.process(&mut [0.0; 2]);
+        assert_eq!(engine.get_active_voice_count(), 3);
+
+        engine.set_song_mode(false);
+        assert_eq!((engine.get_song_entry(), engine.get_playing_pattern()), (-1, 1));
+    }
+
+    #[test]
+    fn test_live_recording_snaps_to_the_grid() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_pattern_length(4);
+        engine.set_sequencer_recording(true);
+        engine.set_sequencer_playing(true);
+
+        // Pressed just before step 2: recorded on it, and not played twice
+        engine.process(&mut [0.0; 2 * 240]);
+        engine.note_on(65);
+        assert_eq!(engine.get_pattern_packed(), [65.0, 2.0, 127.0, 0.0, 1.0, 0.0]);
+        engine.process(&mut [0.0; 2 * 100]);
+        assert_eq!(engine.get_active_voice_count(), 1);
+
+        // The next pass plays it; an unquantized press keeps its timing
+        engine.set_record_quantize(RecordQuantize::Off);
+        engine.process(&mut [0.0; 2 * 560]);
+        assert_eq!(engine.get_active_voice_count(), 2);
+        engine.note_on(65);
+        assert_eq!(&engine.get_pattern_packed()[6..], [65.0, 3.0, 127.0, 0.0, 1.0, 0.2]);
+    }
+
+    #[test]
+    fn test_swing_and_step_offsets() {
+        // One step is 125 samples; swing 0.4 delays odd steps by 25
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_sequencer_swing(0.4);
+        assert!(engine.set_step(65, 1, 127, 0, 1.0, 0.0) && engine.set_step(65, 2, 127, 0, 1.0, 0.0));
+        assert!(engine.set_step_offset(65, 2, -30) && !engine.set_step_offset(65, 3, 10));
+        assert_eq!(engine.get_pattern_packed()[11], -0.25);
+
+        engine.set_sequencer_playing(true);
+        engine.process(&mut [0.0; 2 * 150]);
+        assert_eq!(engine.get_active_voice_count(), 0);
+        engine.process(&mut [0.0; 2 * 2]);
+        assert_eq!(engine.get_active_voice_count(), 1, "odd step swung to sample 150");
+        engine.process(&mut [0.0; 2 * 66]);
+        assert_eq!(engine.get_active_voice_count(), 1);
+        engine.process(&mut [0.0; 2 * 2]);
+        assert_eq!(engine.get_active_voice_count(), 2, "even step 30 ticks early at sample 219");
+    }
+}
@@ -0,0 +1,106 @@
+//! SIMD mixing kernels
+//!
+//! Playback reads a sound at fractional positions and sums the linearly
+//! interpolated result into a buffer. With the `simd` feature on a wasm32
+//! build that enables the `simd128` target feature, the kernels interpolate
+//! and mix four frames at once with WebAssembly SIMD. Everywhere else they
+//! run the same arithmetic one frame at a time, so both paths give
+//! identical output.
+//!
+//! WebAssembly has no runtime feature detection inside a module: a host
+//! that also supports engines without SIMD ships both builds and picks one.
+
+/// Linear interpolation of `samples` at `position`; the sample after the
+/// position's floor must exist
+#[inline(always)]
+fn interpolate(samples: &[f32], position: f64) -> f32 {
+    let index = position as usize;
+    let frac = (position - index as f64) as f32;
+    let s1 = samples[index];
+    s1 + (samples[index + 1] - s1) * frac
+}
+
+/// Add `gain` times `samples`, interpolated at `start + i * step`, to
+/// `out[i]`
+///
+/// Every position read must have a following sample, that is
+/// `floor(start + i * step) + 1 < samples.len()` for each frame of `out`.
+#[cfg(not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128")))]
+#[inline]
+pub(crate) fn mix_linear(samples: &[f32], start: f64, step: f64, gain: f32, out: &mut [f32]) {
+    mix_linear_scalar(samples, start, step, gain, out, 0);
+}
+
+/// Add `gain` times `samples`, interpolated at `start + i * step`, to
+/// `out[i]`
+///
+/// Every position read must have a following sample, that is
+/// `floor(start + i * step) + 1 < samples.len()` for each frame of `out`.
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+#[inline]
+pub(crate) fn mix_linear(samples: &[f32], start: f64, step: f64, gain: f32, out: &mut [f32]) {
+    let quads = out.len() / 4 * 4;
+    let (head, tail) = out.split_at_mut(quads);
+    wasm::mix_linear_quads(samples, start, step, gain, head);
+    mix_linear_scalar(samples, start, step, gain, tail, quads);
+}
+
+/// One frame at a time, for frames `first..` of the run
+#[inline]
+fn mix_linear_scalar(samples: &[f32], start: f64, step: f64, gain: f32, out: &mut [f32], first: usize) {
+    for (i, out) in out.iter_mut().enumerate() {
+        *out += interpolate(samples, start + (first + i) as f64 * step) * gain;
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm {
+    use core::arch::wasm32::*;
+
+    /// Four frames at a time; `out.len()` is a multiple of four
+    #[inline]
+    pub(super) fn mix_linear_quads(samples: &[f32], start: f64, step: f64, gain: f32, out: &mut [f32]) {
+        let gain = f32x4_splat(gain);
+        for (quad, out) in out.chunks_exact_mut(4).enumerate() {
+            // Positions stay in f64 so long sounds keep their precision;
+            // WebAssembly SIMD has no gather, so the reads are scalar
+            let mut s1 = [0.0_f32; 4];
+            let mut s2 = [0.0_f32; 4];
+            let mut frac = [0.0_f32; 4];
+            for lane in 0..4 {
+                let position = start + (quad * 4 + lane) as f64 * step;
+                let index = position as usize;
+                s1[lane] = samples[index];
+                s2[lane] = samples[index + 1];
+                frac[lane] = (position - index as f64) as f32;
+            }
+            let s1 = f32x4(s1[0], s1[1], s1[2], s1[3]);
+            let s2 = f32x4(s2[0], s2[1], s2[2], s2[3]);
+            let frac = f32x4(frac[0], frac[1], frac[2], frac[3]);
+            let level = f32x4_add(s1, f32x4_mul(f32x4_sub(s2, s1), frac));
+            let mixed = f32x4_add(f32x4(out[0], out[1], out[2], out[3]), f32x4_mul(level, gain));
+            out[0] = f32x4_extract_lane::<0>(mixed);
+            out[1] = f32x4_extract_lane::<1>(mixed);
+            out[2] = f32x4_extract_lane::<2>(mixed);
+            out[3] = f32x4_extract_lane::<3>(mixed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_linear_interpolates_and_sums() {
+        let samples: Vec<f32> = (0..16).map(|i| (i * i) as f32).collect();
+        let mut out = [1.0_f32; 7];
+        mix_linear(&samples, 2.5, 1.5, 0.5, &mut out);
+        for (i, &mixed) in out.iter().enumerate() {
+            let position = 2.5 + i as f64 * 1.5;
+            let (index, frac) = (position as usize, position.fract() as f32);
+            let expected = samples[index] + (samples[index + 1] - samples[index]) * frac;
+            assert_eq!(mixed, 1.0 + expected * 0.5, "frame {i}");
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Sample slicing
+//!
+//! A sound can be chopped into slices (regions between ascending start
+//! points) and each slice mapped to its own key, so a breakbeat can be
+//! played one hit per key. Slices are resolved when a key is triggered, so
+//! chop points can change without remapping.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::analysis::detect_onsets;
+use crate::history::ConfigChange;
+use crate::{DspEngine, OverlapMode, PlaybackMode, Sound};
+
+/// Most slices a sound can hold (one per key code)
+const MAX_SLICES: usize = 256;
+
+/// Onsets closer than this to the start are folded into the first slice
+const SLICE_START_TOLERANCE: usize = 64;
+
+impl Sound {
+    /// Sample range `(start, end)` of slice `slice`, if it exists
+    pub(crate) fn slice_bounds(&self, slice: usize) -> Option<(usize, usize)> {
+        let start = *self.slices.get(slice)?;
+        let end = self.slices.get(slice + 1).copied().unwrap_or(self.length);
+        Some((start, end))
+    }
+
+    /// Region played by a key mapped to `slice` (None = whole sound)
+    ///
+    /// Returns None if the slice no longer exists.
+    pub(crate) fn key_region(&self, slice: Option<usize>) -> Option<(usize, usize)> {
+        match slice {
+            Some(slice) => self.slice_bounds(slice),
+            None => Some((0, usize::MAX)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Chop a sound at its transients and map the slices to consecutive keys
+    ///
+    /// # Arguments
+    /// * `sound_index` - Sound to slice
+    /// * `first_key` - Key code that receives the first slice
+    /// * `sensitivity` - Transient sensitivity (0.0 = strong hits only, 1.0 = every bump)
+    ///
+    /// Returns the number of slices created.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn auto_slice(&mut self, sound_index: usize, first_key: u8, sensitivity: f32) -> u32 {
+        if sound_index >= self.sounds.len() || !self.sounds[sound_index].loaded {
+            return 0;
+        }
+        let sound = &mut self.sounds[sound_index];
+        let mut onsets = Vec::new();
+        detect_onsets(&sound.samples[..sound.length], self.sample_rate, sensitivity, &mut onsets);
+
+        // Audio before the first transient still belongs to a slice
+        sound.slices.clear();
+        sound.slices.push(0);
+        sound.slices.extend(onsets.into_iter().filter(|&onset| onset > SLICE_START_TOLERANCE));
+        sound.slices.truncate(MAX_SLICES);
+        self.mark_audio_dirty(sound_index);
+
+        self.map_slices_to_keys(sound_index, first_key);
+        self.sounds[sound_index].slices.len() as u32
+    }
+
+    /// Split a sound into `divisions` equal slices and map them to keys
+    ///
+    /// Typical values are 4, 8, 16 or 32 for tempo-known loops. Returns the
+    /// number of slices created.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn slice_equal(&mut self, sound_index: usize, divisions: u32, first_key: u8) -> u32 {
+        if sound_index >= self.sounds.len() || !self.sounds[sound_index].loaded || divisions == 0 {
+            return 0;
+        }
+        let sound = &mut self.sounds[sound_index];
+        let divisions = (divisions as usize).min(MAX_SLICES).min(sound.length.max(1));
+        sound.slices.clear();
+        sound.slices.extend((0..divisions).map(|i| i * sound.length / divisions));
+        self.mark_audio_dirty(sound_index);
+
+        self.map_slices_to_keys(sound_index, first_key);
+        self.sounds[sound_index].slices.len() as u32
+    }
+
+    /// Map every slice of a sound to consecutive keys starting at `first_key`
+    ///
+    /// Keys are set to single-shot, polyphonic, full volume, no pitch shift.
+    /// Slices that would run past the last key code are left unmapped.
+    /// Returns the number of keys mapped.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn map_slices_to_keys(&mut self, sound_index: usize, first_key: u8) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        let count = self.sounds[sound_index].slices.len().min(256 - first_key as usize);
+        for slice in 0..count {
+            let key_code = first_key + slice as u8;
+            self.set_key_mapping(key_code, sound_index, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+            self.key_mappings[key_code as usize].slice = Some(slice);
+        }
+        count as u32
+    }
+
+    /// Number of slices in a sound (0 if not sliced)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_slice_count(&self, sound_index: usize) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        self.sounds[sound_index].slices.len() as u32
+    }
+
+    /// Copy a sound's slice start points (in samples) into `out`
+    ///
+    /// Returns the number of positions written.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_slices(&self, sound_index: usize, out: &mut [u32]) -> u32 {
+        if sound_index >= self.sounds.len() {
+            return 0;
+        }
+        let slices = &self.sounds[sound_index].slices;
+        let count = slices.len().min(out.len());
+        for (dst, &start) in out.iter_mut().zip(&slices[..count]) {
+            *dst = start as u32;
+        }
+        count as u32
+    }
+
+    /// Add a chop point at `position` (samples)
+    ///
+    /// Keys mapped to later slices follow them. Returns the new slice index,
+    /// or -1 if the position is out of range or already a chop point.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn add_slice(&mut self, sound_index: usize, position: u32) -> i32 {
+        if sound_index >= self.sounds.len() {
+            return -1;
+        }
+        let sound = &mut self.sounds[sound_index];
+        let position = position as usize;
+        if !sound.loaded || position >= sound.length || sound.slices.len() >= MAX_SLICES {
+            return -1;
+        }
+        let Err(index) = sound.slices.binary_search(&position) else {
+            return -1;
+        };
+        sound.slices.insert(index, position);
+        self.mark_audio_dirty(sound_index);
+        self.shift_key_slices(sound_index, index, 1);
+        index as i32
+    }
+
+    /// Move chop point `slice` to `position`, staying between its neighbours
+    ///
+    /// Returns false if the slice does not exist.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn move_slice(&mut self, sound_index: usize, slice: usize, position: u32) -> bool {
+        if sound_index >= self.sounds.len() {
+            return false;
+        }
+        let sound = &mut self.sounds[sound_index];
+        if slice >= sound.slices.len() {
+            return false;
+        }
+        let min = if slice == 0 { 0 } else { sound.slices[slice - 1] + 1 };
+        let max = sound.slices.get(slice + 1).map_or(sound.length, |&next| next).saturating_sub(1);
+        sound.slices[slice] = (position as usize).clamp(min, max.max(min));
+        self.mark_audio_dirty(sound_index);
+        true
+    }
+
+    /// Remove chop point `slice`, merging its audio into the previous slice
+    ///
+    /// Keys mapped to the removed slice are unassigned; keys mapped to later
+    /// slices follow them. Returns false if the slice does not exist.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn delete_slice(&mut self, sound_index: usize, slice: usize) -> bool {
+        if sound_index >= self.sounds.len() || slice >= self.sounds[sound_index].slices.len() {
+            return false;
+        }
+        self.sounds[sound_index].slices.remove(slice);
+        self.mark_audio_dirty(sound_index);
+        for mapping in self.key_mappings.iter_mut() {
+            if mapping.sound_index == sound_index && mapping.slice == Some(slice) {
+                mapping.slice = None;
+                mapping.has_sound = false;
+            }
+        }
+        self.shift_key_slices(sound_index, slice + 1, -1);
+        true
+    }
+
+    /// Make a key play one slice of its sound (-1 = the whole sound)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_slice(&mut self, key_code: u8, slice: i32) {
+        self.record_change(ConfigChange::KeySlice(key_code));
+        self.key_mappings[key_code as usize].slice = usize::try_from(slice).ok();
+    }
+
+    /// Slice played by a key, or -1 if it plays the whole sound
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_slice(&self, key_code: u8) -> i32 {
+        self.key_mappings[key_code as usize].slice.map_or(-1, |slice| slice as i32)
+    }
+}
+
+impl DspEngine {
+    /// Renumber key slices of `sound_index` at or after `from` by `delta`
+    fn shift_key_slices(&mut self, sound_index: usize, from: usize, delta: isize) {
+        for mapping in self.key_mappings.iter_mut() {
+            if mapping.sound_index != sound_index {
+                continue;
+            }
+            if let Some(slice) = mapping.slice.filter(|&slice| slice >= from) {
+                mapping.slice = Some(slice.saturating_add_signed(delta));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DspEngine;
+
+    #[test]
+    fn test_auto_slice_plays_regions() {
+        let sample_rate = 48000.0;
+        let hits = [0, 12000, 24000, 36000];
+        let mut samples = vec![0.0_f32; 48000];
+        for &hit in &hits {
+            for (i, s) in samples[hit..hit + 2400].iter_mut().enumerate() {
+                *s = (i as f32 * 0.3).sin() * (1.0 - i as f32 / 2400.0);
+            }
+        }
+
+        let mut engine = DspEngine::new(sample_rate);
+        engine.load_sound(0, &samples);
+        assert_eq!(engine.auto_slice(0, 60, 0.5), 4);
+        assert_eq!(engine.get_key_slice(62), 2);
+
+        // A slice key plays only its own region
+        engine.note_on(61);
+        let mut output = vec![0.0; 2 * 13000];
+        engine.process(&mut output);
+        assert!(!engine.is_key_playing(61));
+    }
+
+    #[test]
+    fn test_slice_equal() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        assert_eq!(engine.slice_equal(0, 8, 250), 8);
+        assert_eq!(engine.sounds[0].slice_bounds(3), Some((375, 500)));
+        assert_eq!(engine.get_key_slice(255), 5, "slices past key 255 stay unmapped");
+    }
+
+    #[test]
+    fn test_slice_editing_updates_keys() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 1000]);
+        engine.slice_equal(0, 4, 10);
+
+        assert_eq!(engine.add_slice(0, 100), 1);
+        assert_eq!(engine.get_key_slice(11), 2, "key follows its audio");
+        assert!(engine.move_slice(0, 2, 900));
+        let mut slices = [0; 8];
+        assert_eq!(engine.get_slices(0, &mut slices), 5);
+        assert_eq!(&slices[..5], &[0, 100, 499, 500, 750]);
+
+        assert!(engine.delete_slice(0, 2));
+        engine.note_on(11);
+        assert!(!engine.is_key_playing(11), "key of a deleted slice is unassigned");
+        assert_eq!(engine.get_key_slice(12), 2);
+    }
+}
@@ -0,0 +1,2449 @@
+//! Session state serialization
+//!
+//! A compact little-endian binary format: a header (magic, version) followed
+//! by tagged sections, each prefixed with its byte length. Readers skip
+//! sections they do not recognise, so new sections can be added without
+//! breaking older blobs.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::envelope::Envelope;
+use crate::history::ConfigChange;
+use crate::cc::{CcCurve, CcMapping, CcTarget};
+use crate::clips::{Clip, ClipKind, MAX_CLIPS_PER_LANE};
+use crate::devices::InputDevices;
+use crate::metadata::{SoundCategory, SoundMetadata};
+use crate::midi::MidiNoteRange;
+use crate::mixer::MixerScene;
+use crate::morph::{Morph, MorphScene};
+use crate::pads::{truncate_label, PadStyle};
+use crate::performance::{Performed, PerformedEvent, MAX_PERFORMANCE_EVENTS};
+use crate::resample::resample;
+use crate::sequencer::{Pattern, SongEntry, Step, MAX_PATTERNS, MAX_PATTERN_LENGTH, MAX_PATTERN_STEPS, MAX_SONG_ENTRIES};
+use crate::velocity::{VelocityCurve, VelocityResponse};
+use crate::{DspEngine, KeyMapping, ModulationPreset, OverlapMode, PlaybackMode, Sound};
+
+/// Identifies a qeyloop state blob
+const STATE_MAGIC: [u8; 4] = *b"QEYL";
+
+/// Current format version
+///
+/// * 1 - Initial layout
+/// * 2 - Trailing checksum section
+const STATE_VERSION: u16 = 2;
+
+/// First version whose blobs end with a checksum
+const CHECKSUM_VERSION: u16 = 2;
+
+/// Tempo, master volume, metronome and modulation
+const SECTION_GLOBALS: [u8; 4] = *b"GLOB";
+
+/// All 256 key mappings, then the velocity curves that are not linear, the
+/// aftertouch depths, release times, attack times, decay times and sustain
+/// drops (1 - sustain level) that are not zero
+const SECTION_KEYS: [u8; 4] = *b"KEYS";
+
+/// Every key mapping bank and which one is active, then each bank's
+/// velocity curves, aftertouch depths, release times, attack times, decay
+/// times and sustain drops (in that order)
+const SECTION_BANKS: [u8; 4] = *b"BNKS";
+
+/// Group channels and mixer scenes
+const SECTION_MIXER: [u8; 4] = *b"MIXR";
+
+/// Morph scenes A and B and the morph amount
+const SECTION_MORPH: [u8; 4] = *b"MRPH";
+
+/// Mappings of extended trigger IDs
+const SECTION_TRIGGERS: [u8; 4] = *b"TRIG";
+
+/// MIDI note ranges and CC bindings
+const SECTION_MIDI: [u8; 4] = *b"MIDI";
+
+/// Color and label of styled pads
+const SECTION_PADS: [u8; 4] = *b"PADS";
+
+/// Sequencer pattern length and steps
+const SECTION_PATTERN: [u8; 4] = *b"SEQN";
+
+/// Clip launch quantize and the clips of every lane that has any
+const SECTION_CLIPS: [u8; 4] = *b"CLIP";
+
+/// Bank of each input device and MIDI channel (0xFF = none)
+const SECTION_DEVICES: [u8; 4] = *b"DEVS";
+
+/// Recorded performance take: sample rate, start, length and events
+const SECTION_PERFORMANCE: [u8; 4] = *b"PERF";
+
+/// Per-slot metadata and playback gain
+const SECTION_SOUNDS: [u8; 4] = *b"SNDS";
+
+/// Audio and slices of loaded slots (optional)
+const SECTION_AUDIO: [u8; 4] = *b"AUDI";
+
+/// Right channels of the stereo slots in the preceding audio section (the
+/// audio section holds their left channels)
+const SECTION_AUDIO_RIGHT: [u8; 4] = *b"AUDR";
+
+/// A single key's mapping, velocity curve, aftertouch depth, release time
+/// and envelope (per-pad presets)
+const SECTION_KEY: [u8; 4] = *b"PAD1";
+
+/// Size of one record in `set_key_mappings_packed`: a key code followed by
+/// a serialized `KeyMapping`
+pub(crate) const PACKED_KEY_RECORD_BYTES: usize = 15;
+
+/// Name and content hash of each slot a kit refers to
+const SECTION_SOUND_REFS: [u8; 4] = *b"SREF";
+
+/// One sound's audio, slices and metadata (`export_sound`)
+const SECTION_SOUND: [u8; 4] = *b"SMP1";
+
+/// CRC-32 of everything before it; always the last section
+const SECTION_CHECKSUM: [u8; 4] = *b"CSUM";
+
+/// Size of the checksum section including its tag and length
+const CHECKSUM_SECTION_BYTES: usize = 12;
+
+/// Outcome of `import_state`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum StateImportResult {
+    /// The state was applied
+    Ok = 0,
+    /// Data ended before a complete value could be read
+    Truncated = 1,
+    /// The data is not a qeyloop state blob
+    BadMagic = 2,
+    /// The blob was written by a newer, incompatible version
+    UnsupportedVersion = 3,
+    /// A section holds a value out of range for its field
+    CorruptSection = 4,
+    /// A section the import needs is absent
+    MissingSection = 5,
+    /// The data was altered after it was written
+    ChecksumMismatch = 6,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum StateError {
+    /// Data ended before a complete value could be read
+    Truncated,
+    /// The blob does not start with the qeyloop magic
+    BadMagic,
+    /// The blob was written by a newer, incompatible version
+    UnsupportedVersion,
+    /// A value is out of range for its field
+    InvalidValue,
+    /// A section the import needs is absent
+    MissingSection,
+    /// The stored checksum does not match the data
+    ChecksumMismatch,
+}
+
+impl From<StateError> for StateImportResult {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::Truncated => Self::Truncated,
+            StateError::BadMagic => Self::BadMagic,
+            StateError::UnsupportedVersion => Self::UnsupportedVersion,
+            StateError::InvalidValue => Self::CorruptSection,
+            StateError::MissingSection => Self::MissingSection,
+            StateError::ChecksumMismatch => Self::ChecksumMismatch,
+        }
+    }
+}
+
+/// Parts of a state blob, combined as bit flags for `import_state_parts`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+pub enum StatePart {
+    /// Key mappings of every bank, the active bank, extended trigger
+    /// mappings, pad colors and labels and the banks of input devices and
+    /// MIDI channels
+    Mappings = 1,
+    /// Master volume, metronome, group channels, mixer and morph scenes
+    Mixer = 2,
+    /// Modulation preset
+    Modulation = 4,
+    /// BPM
+    Tempo = 8,
+    /// Sound metadata, gains and embedded audio
+    Sounds = 16,
+    /// MIDI note ranges and CC bindings
+    Midi = 32,
+    /// Sequencer patterns and song, clip lanes and the performance take
+    Sequencer = 64,
+}
+
+/// Upgrades applied while importing an older blob, combined as bit flags
+/// (see `get_state_migrations`)
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+pub enum StateMigration {
+    /// The blob predates checksums, so its integrity was not verified
+    Unverified = 1,
+    /// Some sections were shorter than the current layout; the missing
+    /// trailing fields took their defaults
+    DefaultedFields = 2,
+}
+
+/// Every `StatePart` flag
+const ALL_STATE_PARTS: u32 = 0x7F;
+
+/// CRC-32 (IEEE 802.3, as used by zip and PNG)
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(u32::MAX, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 })
+    })
+}
+
+// ============================================================================
+// WRITER / READER
+// ============================================================================
+
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Start a blob with the magic and current version
+    pub(crate) fn new() -> Self {
+        let mut writer = Self { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(&STATE_MAGIC);
+        writer.u16(STATE_VERSION);
+        writer
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Length-prefixed UTF-8 string
+    pub(crate) fn str(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Write a tagged section whose length is filled in afterwards
+    pub(crate) fn section(&mut self, tag: [u8; 4], body: impl FnOnce(&mut Self)) {
+        self.bytes.extend_from_slice(&tag);
+        let length_at = self.bytes.len();
+        self.u32(0);
+        body(self);
+        let length = (self.bytes.len() - length_at - 4) as u32;
+        self.bytes[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    /// Seal the blob with its checksum
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let checksum = crc32(&self.bytes);
+        self.section(SECTION_CHECKSUM, |w| w.u32(checksum));
+        self.bytes
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Set when `or_default` ran past the end of the data
+    defaulted: bool,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0, defaulted: false }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(count).filter(|&end| end <= self.bytes.len()).ok_or(StateError::Truncated)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.take(N)?.try_into().expect("take returns N bytes"))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, StateError> {
+        let value = self.array().map(f32::from_le_bytes)?;
+        if value.is_finite() { Ok(value) } else { Err(StateError::InvalidValue) }
+    }
+
+    pub(crate) fn str(&mut self) -> Result<String, StateError> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| StateError::InvalidValue)
+    }
+
+    /// Read a field that older writers may not have written yet
+    ///
+    /// Fields are only ever appended to a section, so running out of data
+    /// means the field is missing and `default` is used instead.
+    pub(crate) fn or_default<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, StateError>,
+        default: T,
+    ) -> Result<T, StateError> {
+        if self.is_empty() {
+            self.defaulted = true;
+            return Ok(default);
+        }
+        read(self)
+    }
+
+    /// True once every byte has been read
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    /// Next `(tag, body)` section, or None at the end of the data
+    pub(crate) fn section(&mut self) -> Result<Option<([u8; 4], StateReader<'a>)>, StateError> {
+        if self.pos == self.bytes.len() {
+            return Ok(None);
+        }
+        let tag = self.array()?;
+        let length = self.u32()? as usize;
+        Ok(Some((tag, StateReader::new(self.take(length)?))))
+    }
+
+    /// Check a blob's magic, version and checksum
+    ///
+    /// Returns a reader positioned at the first section (the checksum is
+    /// excluded) and the blob's version.
+    pub(crate) fn open(bytes: &'a [u8]) -> Result<(Self, u16), StateError> {
+        let mut reader = Self::new(bytes);
+        if reader.array::<4>()? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion);
+        }
+        if version >= CHECKSUM_VERSION {
+            let body_len = bytes.len().checked_sub(CHECKSUM_SECTION_BYTES).filter(|&len| len >= reader.pos);
+            let body_len = body_len.ok_or(StateError::Truncated)?;
+            let mut trailer = Self::new(&bytes[body_len..]);
+            if trailer.array::<4>()? != SECTION_CHECKSUM || trailer.u32()? != 4 {
+                return Err(StateError::Truncated);
+            }
+            if trailer.u32()? != crc32(&bytes[..body_len]) {
+                return Err(StateError::ChecksumMismatch);
+            }
+            reader.bytes = &bytes[..body_len];
+        }
+        Ok((reader, version))
+    }
+}
+
+fn playback_mode(value: u8) -> Result<PlaybackMode, StateError> {
+    match value {
+        0 => Ok(PlaybackMode::SingleShot),
+        1 => Ok(PlaybackMode::Loop),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn overlap_mode(value: u8) -> Result<OverlapMode, StateError> {
+    match value {
+        0 => Ok(OverlapMode::Polyphonic),
+        1 => Ok(OverlapMode::Monophonic),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn modulation_preset(value: u8) -> Result<ModulationPreset, StateError> {
+    match value {
+        0 => Ok(ModulationPreset::None),
+        1 => Ok(ModulationPreset::QuarterSidechain),
+        2 => Ok(ModulationPreset::EighthSidechain),
+        3 => Ok(ModulationPreset::SixteenthSidechain),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn cc_target(value: u8) -> Result<CcTarget, StateError> {
+    match value {
+        0 => Ok(CcTarget::MasterVolume),
+        1 => Ok(CcTarget::MetronomeVolume),
+        2 => Ok(CcTarget::GroupVolume),
+        3 => Ok(CcTarget::KeyVolume),
+        4 => Ok(CcTarget::KeyPitch),
+        5 => Ok(CcTarget::Morph),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn clip_kind(value: u8) -> Result<ClipKind, StateError> {
+    match value {
+        0 => Ok(ClipKind::Pattern),
+        1 => Ok(ClipKind::Loop),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn cc_curve(value: u8) -> Result<CcCurve, StateError> {
+    match value {
+        0 => Ok(CcCurve::Linear),
+        1 => Ok(CcCurve::Exponential),
+        2 => Ok(CcCurve::Logarithmic),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn velocity_curve(value: u8) -> Result<VelocityCurve, StateError> {
+    match value {
+        0 => Ok(VelocityCurve::Fixed),
+        1 => Ok(VelocityCurve::Linear),
+        2 => Ok(VelocityCurve::Soft),
+        3 => Ok(VelocityCurve::Hard),
+        4 => Ok(VelocityCurve::Custom),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+fn sound_category(value: u8) -> Result<SoundCategory, StateError> {
+    match value {
+        0 => Ok(SoundCategory::Other),
+        1 => Ok(SoundCategory::Drum),
+        2 => Ok(SoundCategory::Bass),
+        3 => Ok(SoundCategory::Melodic),
+        4 => Ok(SoundCategory::Vocal),
+        5 => Ok(SoundCategory::Fx),
+        6 => Ok(SoundCategory::Loop),
+        _ => Err(StateError::InvalidValue),
+    }
+}
+
+// ============================================================================
+// RECORDS
+// ============================================================================
+
+impl KeyMapping {
+    pub(crate) fn write(&self, w: &mut StateWriter) {
+        w.u8(self.has_sound as u8);
+        w.u16(self.sound_index as u16);
+        w.u8(self.mode as u8);
+        w.u8(self.overlap_mode as u8);
+        w.u8(self.group_id);
+        w.f32(self.volume);
+        w.u8(self.pitch_semitones as u8);
+        w.u8(self.modulation_enabled as u8);
+        w.u16(self.slice.map_or(u16::MAX, |slice| slice as u16));
+    }
+
+    pub(crate) fn read(r: &mut StateReader) -> Result<Self, StateError> {
+        let mut mapping = KeyMapping::new();
+        mapping.has_sound = r.u8()? != 0;
+        mapping.sound_index = r.u16()? as usize;
+        mapping.mode = playback_mode(r.u8()?)?;
+        mapping.overlap_mode = overlap_mode(r.u8()?)?;
+        mapping.group_id = r.u8()?;
+        mapping.volume = r.f32()?.clamp(0.0, 1.0);
+        mapping.pitch_semitones = (r.u8()? as i8).clamp(-24, 24);
+        mapping.modulation_enabled = r.u8()? != 0;
+        mapping.slice = match r.u16()? {
+            u16::MAX => None,
+            slice => Some(slice as usize),
+        };
+        Ok(mapping)
+    }
+}
+
+impl VelocityResponse {
+    pub(crate) fn write(&self, w: &mut StateWriter) {
+        w.u8(self.curve as u8);
+        w.f32(self.amount);
+    }
+
+    pub(crate) fn read(r: &mut StateReader) -> Result<Self, StateError> {
+        let curve = velocity_curve(r.u8()?)?;
+        Ok(Self { curve, amount: r.f32()?.clamp(-1.0, 1.0) })
+    }
+}
+
+/// Write the velocity curves of the keys in `mappings` that are not linear
+///
+/// Velocity curves postdate the mapping records, so they follow the
+/// mappings at the end of a section instead of being part of each record.
+fn write_velocity_curves(w: &mut StateWriter, mappings: &[KeyMapping]) {
+    let curved: Vec<usize> =
+        (0..mappings.len()).filter(|&key| mappings[key].velocity != VelocityResponse::LINEAR).collect();
+    w.u16(curved.len() as u16);
+    for &key in &curved {
+        w.u8(key as u8);
+        mappings[key].velocity.write(w);
+    }
+}
+
+/// Read curves written by `write_velocity_curves` into `mappings`
+fn read_velocity_curves(r: &mut StateReader, mappings: &mut [KeyMapping]) -> Result<(), StateError> {
+    for _ in 0..r.u16()? {
+        let key = r.u8()? as usize;
+        let velocity = VelocityResponse::read(r)?;
+        if let Some(mapping) = mappings.get_mut(key) {
+            mapping.velocity = velocity;
+        }
+    }
+    Ok(())
+}
+
+/// Write a sequencer pattern's length and steps
+fn write_pattern_steps(w: &mut StateWriter, pattern: &Pattern) {
+    w.u8(pattern.length);
+    w.u16(pattern.steps.len() as u16);
+    for step in &pattern.steps {
+        w.u8(step.key_code);
+        w.u8(step.step);
+        w.u8(step.velocity);
+        w.u8(step.pitch as u8);
+        w.f32(step.probability);
+        w.f32(step.nudge);
+    }
+}
+
+/// Read a pattern written by `write_pattern_steps`
+fn read_pattern(r: &mut StateReader) -> Result<Pattern, StateError> {
+    let mut pattern = Pattern::new();
+    pattern.length = r.u8()?.clamp(1, MAX_PATTERN_LENGTH);
+    for _ in 0..r.u16()? {
+        let (key_code, step, velocity, pitch) = (r.u8()?, r.u8()?, r.u8()?, r.u8()? as i8);
+        let step = Step::new(key_code, step, velocity, pitch, r.f32()?, r.f32()?);
+        if step.step < pattern.length && pattern.steps.len() < MAX_PATTERN_STEPS {
+            pattern.steps.push(step);
+        }
+    }
+    Ok(pattern)
+}
+
+/// Write one per-key value of the keys in `mappings` where it is not zero
+fn write_key_values(w: &mut StateWriter, mappings: &[KeyMapping], value: impl Fn(&KeyMapping) -> f32) {
+    let set: Vec<usize> = (0..mappings.len()).filter(|&key| value(&mappings[key]) != 0.0).collect();
+    w.u16(set.len() as u16);
+    for &key in &set {
+        w.u8(key as u8);
+        w.f32(value(&mappings[key]));
+    }
+}
+
+/// Read values written by `write_key_values` into `mappings`
+fn read_key_values(
+    r: &mut StateReader,
+    mappings: &mut [KeyMapping],
+    set: impl Fn(&mut KeyMapping, f32),
+) -> Result<(), StateError> {
+    for _ in 0..r.u16()? {
+        let key = r.u8()? as usize;
+        let value = r.f32()?;
+        if let Some(mapping) = mappings.get_mut(key) {
+            set(mapping, value);
+        }
+    }
+    Ok(())
+}
+
+fn aftertouch_depth(mapping: &KeyMapping) -> f32 {
+    mapping.aftertouch_depth
+}
+
+fn set_aftertouch_depth(mapping: &mut KeyMapping, depth: f32) {
+    mapping.aftertouch_depth = depth.clamp(0.0, 2.0);
+}
+
+fn release_ms(mapping: &KeyMapping) -> f32 {
+    mapping.release_ms
+}
+
+fn set_release_ms(mapping: &mut KeyMapping, ms: f32) {
+    mapping.release_ms = ms.clamp(0.0, 10_000.0);
+}
+
+fn attack_ms(mapping: &KeyMapping) -> f32 {
+    mapping.envelope.attack_ms
+}
+
+fn set_attack_ms(mapping: &mut KeyMapping, ms: f32) {
+    let envelope = mapping.envelope;
+    mapping.envelope = Envelope::clamped(ms, envelope.decay_ms, envelope.sustain);
+}
+
+fn decay_ms(mapping: &KeyMapping) -> f32 {
+    mapping.envelope.decay_ms
+}
+
+fn set_decay_ms(mapping: &mut KeyMapping, ms: f32) {
+    let envelope = mapping.envelope;
+    mapping.envelope = Envelope::clamped(envelope.attack_ms, ms, envelope.sustain);
+}
+
+/// Stored as the drop from full level so the default (no envelope) is zero
+fn sustain_drop(mapping: &KeyMapping) -> f32 {
+    1.0 - mapping.envelope.sustain
+}
+
+fn set_sustain_drop(mapping: &mut KeyMapping, drop: f32) {
+    let envelope = mapping.envelope;
+    mapping.envelope = Envelope::clamped(envelope.attack_ms, envelope.decay_ms, 1.0 - drop);
+}
+
+impl Performed {
+    /// Tag byte followed by the event's fields
+    pub(crate) fn write(&self, w: &mut StateWriter) {
+        match *self {
+            Self::TriggerOn(trigger) | Self::TriggerOff(trigger) => {
+                w.u8(if matches!(self, Self::TriggerOn(_)) { 0 } else { 1 });
+                w.u16(trigger);
+            }
+            Self::NoteOn { channel, note, velocity } | Self::NoteOff { channel, note, velocity } => {
+                w.u8(if matches!(self, Self::NoteOn { .. }) { 2 } else { 3 });
+                w.u8(channel);
+                w.u8(note);
+                w.u8(velocity);
+            }
+            Self::Control { channel, controller, value } => {
+                w.u8(4);
+                w.u8(channel);
+                w.u8(controller);
+                w.u8(value);
+            }
+            Self::PitchBend { channel, value } => {
+                w.u8(5);
+                w.u8(channel);
+                w.u16(value);
+            }
+            Self::Pressure { channel, pressure } => {
+                w.u8(6);
+                w.u8(channel);
+                w.u8(pressure);
+            }
+            Self::Parameter { target, index, value } => {
+                w.u8(7);
+                w.u8(target as u8);
+                w.u8(index);
+                w.f32(value);
+            }
+        }
+    }
+
+    pub(crate) fn read(r: &mut StateReader) -> Result<Self, StateError> {
+        Ok(match r.u8()? {
+            0 => Self::TriggerOn(r.u16()?),
+            1 => Self::TriggerOff(r.u16()?),
+            2 => Self::NoteOn { channel: r.u8()?, note: r.u8()?, velocity: r.u8()? },
+            3 => Self::NoteOff { channel: r.u8()?, note: r.u8()?, velocity: r.u8()? },
+            4 => Self::Control { channel: r.u8()?, controller: r.u8()?, value: r.u8()? },
+            5 => Self::PitchBend { channel: r.u8()?, value: r.u16()? },
+            6 => Self::Pressure { channel: r.u8()?, pressure: r.u8()? },
+            7 => Self::Parameter { target: cc_target(r.u8()?)?, index: r.u8()?, value: r.f32()? },
+            _ => return Err(StateError::InvalidValue),
+        })
+    }
+}
+
+impl SoundMetadata {
+    pub(crate) fn write(&self, w: &mut StateWriter) {
+        w.str(&self.name);
+        w.f32(self.original_bpm.unwrap_or(0.0));
+        w.u8(self.root_note.unwrap_or(u8::MAX));
+        w.u8(self.category as u8);
+    }
+
+    pub(crate) fn read(r: &mut StateReader) -> Result<Self, StateError> {
+        let name = r.str()?;
+        let bpm = r.f32()?;
+        let root_note = r.u8()?;
+        Ok(Self {
+            name,
+            original_bpm: (bpm > 0.0).then_some(bpm),
+            root_note: (root_note <= 127).then_some(root_note),
+            category: sound_category(r.u8()?)?,
+        })
+    }
+}
+
+/// Global settings carried by a state blob
+struct Globals {
+    bpm: f32,
+    master_volume: f32,
+    metronome_enabled: bool,
+    metronome_volume: f32,
+    modulation_preset: ModulationPreset,
+}
+
+/// Group channel volumes and mutes
+type Channels = ([f32; 256], [bool; 256]);
+
+fn write_channels(w: &mut StateWriter, volumes: &[f32; 256], mutes: &[bool; 256]) {
+    for (&volume, &muted) in volumes.iter().zip(mutes) {
+        w.f32(volume);
+        w.u8(muted as u8);
+    }
+}
+
+fn read_channels(r: &mut StateReader) -> Result<Channels, StateError> {
+    let mut channels = ([1.0; 256], [false; 256]);
+    for group in 0..256 {
+        channels.0[group] = r.f32()?.clamp(0.0, 1.0);
+        channels.1[group] = r.u8()? != 0;
+    }
+    Ok(channels)
+}
+
+/// Decode little-endian f32 samples, at most `max`, replacing NaN/inf with
+/// silence
+fn read_samples(raw: &[u8], max: usize) -> Vec<f32> {
+    raw.chunks_exact(4)
+        .take(max)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .map(|s| if s.is_finite() { s } else { 0.0 })
+        .collect()
+}
+
+/// Audio of one slot carried by a state blob
+struct SlotAudio {
+    sound_index: usize,
+    source_sample_rate: f32,
+    peak_normalize_gain: f32,
+    samples: Vec<f32>,
+    /// Right channel of stereo audio (empty if mono)
+    right: Vec<f32>,
+    slices: Vec<usize>,
+}
+
+/// Everything parsed from a blob, applied only once fully validated
+struct ParsedState {
+    /// `StateMigration` flags describing upgrades made while parsing
+    migrations: u32,
+    globals: Option<Globals>,
+    keys: Option<Vec<KeyMapping>>,
+    /// Active bank and the mappings of every bank
+    banks: Option<(usize, Vec<Vec<KeyMapping>>)>,
+    /// Group channels and stored mixer scenes
+    mixer: Option<(Channels, Vec<MixerScene>)>,
+    /// Morph scenes and amount
+    morph: Option<Morph>,
+    /// Extended trigger mappings
+    triggers: Option<Vec<(u16, KeyMapping)>>,
+    /// MIDI note ranges and CC bindings
+    midi: Option<(Vec<MidiNoteRange>, Vec<CcMapping>)>,
+    /// Styled pads by key code
+    pads: Option<Vec<(u8, PadStyle)>>,
+    /// Sequencer patterns and song
+    sequence: Option<ParsedSequence>,
+    /// Clip launch quantize and lanes
+    clips: Option<ParsedClips>,
+    /// Input device and MIDI channel banks
+    devices: Option<InputDevices>,
+    /// Performance take
+    performance: Option<ParsedTake>,
+    sounds: Vec<(usize, f32, SoundMetadata)>,
+    audio: Vec<SlotAudio>,
+    sound_refs: Vec<SoundRef>,
+}
+
+/// Sequencer state read from a blob
+struct ParsedSequence {
+    patterns: Vec<Pattern>,
+    selected: usize,
+    song: Vec<SongEntry>,
+    song_mode: bool,
+    swing: f32,
+}
+
+/// Clip launcher settings read from a blob
+struct ParsedClips {
+    quantize_bars: u32,
+    /// Clips of each lane that has any, by key code
+    lanes: Vec<(u8, [Option<Clip>; MAX_CLIPS_PER_LANE])>,
+}
+
+/// Performance take read from a blob, rescaled to the engine's sample rate
+struct ParsedTake {
+    start: u64,
+    length: u64,
+    events: Vec<PerformedEvent>,
+}
+
+/// A slot a kit was built against, identified independently of its index
+struct SoundRef {
+    sound_index: usize,
+    hash: u32,
+    name: String,
+}
+
+impl Sound {
+    /// FNV-1a hash of the channel count and sample data (left, then right),
+    /// for recognising the same audio in another slot
+    pub(crate) fn content_hash(&self) -> u32 {
+        let channels = if self.is_stereo() { 2u8 } else { 1 };
+        let right = if self.is_stereo() { &self.right[..self.length] } else { &[] };
+        let samples = self.samples[..self.length].iter().chain(right);
+        std::iter::once(channels)
+            .chain(samples.flat_map(|sample| sample.to_bits().to_le_bytes()))
+            .fold(0x811C_9DC5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+    }
+}
+
+impl DspEngine {
+    fn parse_state(&self, bytes: &[u8]) -> Result<ParsedState, StateError> {
+        let (mut reader, version) = StateReader::open(bytes)?;
+        let mut parsed = ParsedState {
+            migrations: if version < CHECKSUM_VERSION { StateMigration::Unverified as u32 } else { 0 },
+            globals: None,
+            keys: None,
+            banks: None,
+            mixer: None,
+            morph: None,
+            triggers: None,
+            midi: None,
+            pads: None,
+            sequence: None,
+            clips: None,
+            devices: None,
+            performance: None,
+            sounds: Vec::new(),
+            audio: Vec::new(),
+            sound_refs: Vec::new(),
+        };
+
+        while let Some((tag, mut r)) = reader.section()? {
+            match tag {
+                SECTION_GLOBALS => {
+                    parsed.globals = Some(Globals {
+                        bpm: r.or_default(StateReader::f32, 120.0)?.clamp(20.0, 300.0),
+                        master_volume: r.or_default(StateReader::f32, 1.0)?.clamp(0.0, 1.0),
+                        metronome_enabled: r.or_default(StateReader::u8, 0)? != 0,
+                        metronome_volume: r.or_default(StateReader::f32, 0.5)?.clamp(0.0, 1.0),
+                        modulation_preset: modulation_preset(r.or_default(StateReader::u8, 0)?)?,
+                    });
+                    if r.defaulted {
+                        parsed.migrations |= StateMigration::DefaultedFields as u32;
+                    }
+                }
+                SECTION_KEYS => {
+                    let count = (r.u16()? as usize).min(256);
+                    let mut keys = (0..count).map(|_| KeyMapping::read(&mut r)).collect::<Result<Vec<_>, _>>()?;
+                    r.or_default(|r| read_velocity_curves(r, &mut keys), ())?;
+                    r.or_default(|r| read_key_values(r, &mut keys, set_aftertouch_depth), ())?;
+                    for set in [set_release_ms, set_attack_ms, set_decay_ms, set_sustain_drop] {
+                        r.or_default(|r| read_key_values(r, &mut keys, set), ())?;
+                    }
+                    if r.defaulted {
+                        parsed.migrations |= StateMigration::DefaultedFields as u32;
+                    }
+                    parsed.keys = Some(keys);
+                }
+                SECTION_BANKS => {
+                    let count = r.u16()? as usize;
+                    let active = r.u16()? as usize;
+                    let mut banks = (0..count)
+                        .map(|_| (0..256).map(|_| KeyMapping::read(&mut r)).collect::<Result<Vec<_>, _>>())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    r.or_default(|r| banks.iter_mut().try_for_each(|bank| read_velocity_curves(r, bank)), ())?;
+                    for set in [set_aftertouch_depth, set_release_ms, set_attack_ms, set_decay_ms, set_sustain_drop] {
+                        r.or_default(|r| banks.iter_mut().try_for_each(|bank| read_key_values(r, bank, set)), ())?;
+                    }
+                    if r.defaulted {
+                        parsed.migrations |= StateMigration::DefaultedFields as u32;
+                    }
+                    // Banks beyond this engine's count are dropped
+                    if active < count.min(self.key_banks.len()) {
+                        parsed.banks = Some((active, banks));
+                    }
+                }
+                SECTION_SOUNDS => {
+                    for _ in 0..r.u16()? {
+                        let sound_index = r.u16()? as usize;
+                        let gain = r.f32()?;
+                        let metadata = SoundMetadata::read(&mut r)?;
+                        if sound_index < self.sounds.len() {
+                            parsed.sounds.push((sound_index, gain, metadata));
+                        }
+                    }
+                }
+                SECTION_AUDIO => {
+                    for _ in 0..r.u16()? {
+                        let sound_index = r.u16()? as usize;
+                        let source_sample_rate = r.f32()?;
+                        let peak_normalize_gain = r.f32()?;
+                        let length = r.u32()? as usize;
+                        let raw = r.take(length.checked_mul(4).ok_or(StateError::Truncated)?)?;
+                        let slice_count = r.u16()? as usize;
+                        let slices = (0..slice_count).map(|_| r.u32().map(|s| s as usize)).collect::<Result<Vec<_>, _>>()?;
+                        if sound_index >= self.sounds.len() {
+                            continue;
+                        }
+                        let samples = read_samples(raw, self.max_sample_length);
+                        let right = Vec::new();
+                        parsed.audio.push(SlotAudio {
+                            sound_index,
+                            source_sample_rate,
+                            peak_normalize_gain,
+                            samples,
+                            right,
+                            slices,
+                        });
+                    }
+                }
+                SECTION_AUDIO_RIGHT => {
+                    for _ in 0..r.u16()? {
+                        let sound_index = r.u16()? as usize;
+                        let length = r.u32()? as usize;
+                        let raw = r.take(length.checked_mul(4).ok_or(StateError::Truncated)?)?;
+                        if let Some(slot) = parsed.audio.iter_mut().rev().find(|slot| slot.sound_index == sound_index) {
+                            slot.right = read_samples(raw, self.max_sample_length);
+                        }
+                    }
+                }
+                SECTION_MIXER => {
+                    let channels = read_channels(&mut r)?;
+                    let scenes = (0..r.u16()?)
+                        .map(|_| {
+                            let name = r.str()?;
+                            let master_volume = r.f32()?.clamp(0.0, 1.0);
+                            let (group_volumes, group_mutes) = read_channels(&mut r)?;
+                            Ok(MixerScene { name, group_volumes, group_mutes, master_volume })
+                        })
+                        .collect::<Result<_, StateError>>()?;
+                    parsed.mixer = Some((channels, scenes));
+                }
+                SECTION_MORPH => {
+                    let mut morph = Morph::new();
+                    morph.amount = r.f32()?.clamp(0.0, 1.0);
+                    for scene in morph.scenes.iter_mut() {
+                        if r.u8()? == 0 {
+                            continue;
+                        }
+                        let mut key_volumes = [0.0; 256];
+                        for volume in key_volumes.iter_mut() {
+                            *volume = r.f32()?.clamp(0.0, 1.0);
+                        }
+                        let master_volume = r.f32()?.clamp(0.0, 1.0);
+                        let metronome_volume = r.f32()?.clamp(0.0, 1.0);
+                        *scene = Some(MorphScene { key_volumes, master_volume, metronome_volume });
+                    }
+                    parsed.morph = Some(morph);
+                }
+                SECTION_TRIGGERS => {
+                    let mut triggers = (0..r.u16()?)
+                        .map(|_| Ok((r.u16()?, KeyMapping::read(&mut r)?)))
+                        .collect::<Result<Vec<_>, StateError>>()?;
+                    triggers.retain(|&(trigger, _)| trigger > u8::MAX as u16);
+                    triggers.sort_by_key(|&(trigger, _)| trigger);
+                    triggers.dedup_by_key(|(trigger, _)| *trigger);
+                    parsed.triggers = Some(triggers);
+                }
+                SECTION_MIDI => {
+                    let note_ranges = (0..r.u16()?)
+                        .map(|_| {
+                            let channel = r.u8()?;
+                            let low_note = r.u8()?;
+                            let high_note = r.u8()?;
+                            let root_note = r.u8()?;
+                            let mapping = KeyMapping::read(&mut r)?;
+                            Ok(MidiNoteRange {
+                                channel: (channel <= 15).then_some(channel),
+                                low_note,
+                                high_note,
+                                root_note: (root_note <= 127).then_some(root_note),
+                                mapping,
+                            })
+                        })
+                        .collect::<Result<_, StateError>>()?;
+                    let cc_mappings = (0..r.u16()?)
+                        .map(|_| {
+                            Ok(CcMapping {
+                                channel: r.u8()?,
+                                controller: r.u8()?,
+                                target: cc_target(r.u8()?)?,
+                                target_index: r.u8()?,
+                                min: r.f32()?,
+                                max: r.f32()?,
+                                curve: cc_curve(r.u8()?)?,
+                            })
+                        })
+                        .collect::<Result<_, StateError>>()?;
+                    parsed.midi = Some((note_ranges, cc_mappings));
+                }
+                SECTION_PADS => {
+                    let pads = (0..r.u16()?)
+                        .map(|_| {
+                            let key_code = r.u8()?;
+                            let color = r.u8()?;
+                            let label = truncate_label(&r.str()?).to_string();
+                            Ok((key_code, PadStyle { color, label }))
+                        })
+                        .collect::<Result<_, StateError>>()?;
+                    parsed.pads = Some(pads);
+                }
+                SECTION_PATTERN => {
+                    let mut patterns = vec![read_pattern(&mut r)?];
+                    // Blobs from before pattern chaining hold a single pattern
+                    for index in 1..=r.or_default(StateReader::u8, 0)? as usize {
+                        let pattern = read_pattern(&mut r)?;
+                        if index < MAX_PATTERNS {
+                            patterns.push(pattern);
+                        }
+                    }
+                    let selected = (r.or_default(StateReader::u8, 0)? as usize).min(patterns.len() - 1);
+                    let mut song = Vec::new();
+                    for _ in 0..r.or_default(StateReader::u16, 0)? {
+                        let (pattern, repeats) = (r.u8()?, r.u8()?);
+                        if (pattern as usize) < patterns.len() && song.len() < MAX_SONG_ENTRIES {
+                            song.push(SongEntry { pattern, repeats: repeats.max(1) });
+                        }
+                    }
+                    let song_mode = r.or_default(StateReader::u8, 0)? != 0;
+                    let swing = r.or_default(StateReader::f32, 0.0)?;
+                    let swing = if swing.is_finite() { swing.clamp(0.0, 1.0) } else { 0.0 };
+                    parsed.sequence = Some(ParsedSequence { patterns, selected, song, song_mode, swing });
+                }
+                SECTION_CLIPS => {
+                    let quantize_bars = r.u32()?;
+                    let mut lanes = Vec::new();
+                    for _ in 0..r.u16()? {
+                        let key_code = r.u8()?;
+                        let mut clips = [None; MAX_CLIPS_PER_LANE];
+                        for clip in clips.iter_mut() {
+                            let (kind, index) = (r.u8()?, r.u8()?);
+                            if kind == u8::MAX {
+                                continue;
+                            }
+                            let kind = clip_kind(kind)?;
+                            // Same rule as set_clip
+                            let known = match kind {
+                                ClipKind::Pattern => (index as usize) < MAX_PATTERNS,
+                                ClipKind::Loop => (index as usize) < self.sounds.len(),
+                            };
+                            *clip = known.then_some(Clip { kind, index });
+                        }
+                        lanes.push((key_code, clips));
+                    }
+                    parsed.clips = Some(ParsedClips { quantize_bars, lanes });
+                }
+                SECTION_DEVICES => {
+                    let mut devices = InputDevices::new();
+                    let banks = self.key_banks.len();
+                    for bank in devices.device_banks.iter_mut().chain(devices.channel_banks.iter_mut()) {
+                        // Unknown or out-of-range banks fall back to none
+                        *bank = Some(r.u8()? as usize).filter(|&bank| bank < banks);
+                    }
+                    parsed.devices = Some(devices);
+                }
+                SECTION_PERFORMANCE => {
+                    let sample_rate = r.f32()?;
+                    if sample_rate <= 0.0 {
+                        return Err(StateError::InvalidValue);
+                    }
+                    let ratio = self.sample_rate as f64 / sample_rate as f64;
+                    let rescale = |samples: u64| (samples as f64 * ratio).round() as u64;
+                    let start = rescale(r.u64()?);
+                    let length = rescale(r.u64()?);
+                    let mut events = Vec::new();
+                    for _ in 0..r.u32()? {
+                        let at = rescale(r.u64()?);
+                        let event = Performed::read(&mut r)?;
+                        if events.len() < MAX_PERFORMANCE_EVENTS {
+                            events.push(PerformedEvent { at, event });
+                        }
+                    }
+                    events.sort_by_key(|recorded| recorded.at);
+                    parsed.performance = Some(ParsedTake { start, length, events });
+                }
+                SECTION_SOUND_REFS => {
+                    for _ in 0..r.u16()? {
+                        let sound_index = r.u16()? as usize;
+                        let hash = r.u32()?;
+                        let name = r.str()?;
+                        parsed.sound_refs.push(SoundRef { sound_index, hash, name });
+                    }
+                }
+                // Sections from newer writers are skipped
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Install mappings from `set_key_mappings_packed` (called at the start of each block)
+    #[inline]
+    pub(crate) fn apply_pending_mappings(&mut self) {
+        for (key_code, mapping) in self.pending_mappings.drain(..) {
+            self.key_mappings[key_code as usize] = mapping;
+        }
+    }
+
+    fn write_globals(&self, w: &mut StateWriter) {
+        w.section(SECTION_GLOBALS, |w| {
+            w.f32(self.bpm);
+            w.f32(self.master_volume);
+            w.u8(self.metronome_enabled as u8);
+            w.f32(self.metronome_volume);
+            w.u8(self.modulation_preset as u8);
+        });
+    }
+
+    fn write_mixer(&self, w: &mut StateWriter) {
+        w.section(SECTION_MIXER, |w| {
+            write_channels(w, &self.mixer.volumes, &self.mixer.mutes);
+            w.u16(self.mixer.scenes.len() as u16);
+            for scene in &self.mixer.scenes {
+                w.str(&scene.name);
+                w.f32(scene.master_volume);
+                write_channels(w, &scene.group_volumes, &scene.group_mutes);
+            }
+        });
+    }
+
+    fn write_morph(&self, w: &mut StateWriter) {
+        w.section(SECTION_MORPH, |w| {
+            w.f32(self.morph.amount);
+            for scene in &self.morph.scenes {
+                w.u8(scene.is_some() as u8);
+                if let Some(scene) = scene {
+                    for &volume in &scene.key_volumes {
+                        w.f32(volume);
+                    }
+                    w.f32(scene.master_volume);
+                    w.f32(scene.metronome_volume);
+                }
+            }
+        });
+    }
+
+    fn write_triggers(&self, w: &mut StateWriter) {
+        w.section(SECTION_TRIGGERS, |w| {
+            w.u16(self.trigger_mappings.len() as u16);
+            for (trigger, mapping) in &self.trigger_mappings {
+                w.u16(*trigger);
+                mapping.write(w);
+            }
+        });
+    }
+
+    fn write_midi(&self, w: &mut StateWriter) {
+        w.section(SECTION_MIDI, |w| {
+            w.u16(self.midi.note_ranges.len() as u16);
+            for range in &self.midi.note_ranges {
+                w.u8(range.channel.unwrap_or(u8::MAX));
+                w.u8(range.low_note);
+                w.u8(range.high_note);
+                w.u8(range.root_note.unwrap_or(u8::MAX));
+                range.mapping.write(w);
+            }
+            w.u16(self.cc_map.mappings.len() as u16);
+            for mapping in &self.cc_map.mappings {
+                w.u8(mapping.channel);
+                w.u8(mapping.controller);
+                w.u8(mapping.target as u8);
+                w.u8(mapping.target_index);
+                w.f32(mapping.min);
+                w.f32(mapping.max);
+                w.u8(mapping.curve as u8);
+            }
+        });
+    }
+
+    fn write_pattern(&self, w: &mut StateWriter) {
+        w.section(SECTION_PATTERN, |w| {
+            let sequencer = &self.sequencer;
+            write_pattern_steps(w, &sequencer.patterns[0]);
+            w.u8(sequencer.patterns.len() as u8 - 1);
+            for pattern in &sequencer.patterns[1..] {
+                write_pattern_steps(w, pattern);
+            }
+            w.u8(sequencer.selected as u8);
+            w.u16(sequencer.song.len() as u16);
+            for entry in &sequencer.song {
+                w.u8(entry.pattern);
+                w.u8(entry.repeats);
+            }
+            w.u8(sequencer.song_mode as u8);
+            w.f32(sequencer.swing);
+        });
+    }
+
+    fn write_clips(&self, w: &mut StateWriter) {
+        w.section(SECTION_CLIPS, |w| {
+            w.u32(self.clips.quantize_bars);
+            let used: Vec<usize> =
+                (0..256).filter(|&key| self.clips.lanes[key].clips.iter().any(Option::is_some)).collect();
+            w.u16(used.len() as u16);
+            for &key in &used {
+                w.u8(key as u8);
+                for clip in &self.clips.lanes[key].clips {
+                    match clip {
+                        Some(clip) => {
+                            w.u8(clip.kind as u8);
+                            w.u8(clip.index);
+                        }
+                        None => {
+                            w.u8(u8::MAX);
+                            w.u8(0);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn write_devices(&self, w: &mut StateWriter) {
+        w.section(SECTION_DEVICES, |w| {
+            for bank in self.devices.device_banks.iter().chain(&self.devices.channel_banks) {
+                w.u8(bank.map_or(u8::MAX, |bank| bank as u8));
+            }
+        });
+    }
+
+    fn write_performance(&self, w: &mut StateWriter) {
+        w.section(SECTION_PERFORMANCE, |w| {
+            let take = &self.performance;
+            w.f32(self.sample_rate);
+            w.u64(take.start);
+            w.u64(take.length);
+            w.u32(take.events.len() as u32);
+            for recorded in &take.events {
+                w.u64(recorded.at);
+                recorded.event.write(w);
+            }
+        });
+    }
+
+    fn write_pads(&self, w: &mut StateWriter) {
+        w.section(SECTION_PADS, |w| {
+            let styled: Vec<usize> = (0..256).filter(|&key| !self.pad_styles[key].is_default()).collect();
+            w.u16(styled.len() as u16);
+            for &key in &styled {
+                w.u8(key as u8);
+                w.u8(self.pad_styles[key].color);
+                w.str(&self.pad_styles[key].label);
+            }
+        });
+    }
+
+    fn write_keys(&self, w: &mut StateWriter) {
+        w.section(SECTION_KEYS, |w| {
+            w.u16(self.key_mappings.len() as u16);
+            for mapping in self.key_mappings.iter() {
+                mapping.write(w);
+            }
+            write_velocity_curves(w, &self.key_mappings);
+            write_key_values(w, &self.key_mappings, aftertouch_depth);
+            for value in [release_ms, attack_ms, decay_ms, sustain_drop] {
+                write_key_values(w, &self.key_mappings, value);
+            }
+        });
+    }
+
+    fn write_banks(&self, w: &mut StateWriter) {
+        w.section(SECTION_BANKS, |w| {
+            w.u16(self.key_banks.len() as u16);
+            w.u16(self.get_active_bank() as u16);
+            for bank in 0..self.key_banks.len() {
+                for mapping in self.bank(bank).iter() {
+                    mapping.write(w);
+                }
+            }
+            for bank in 0..self.key_banks.len() {
+                write_velocity_curves(w, self.bank(bank));
+            }
+            for value in [aftertouch_depth, release_ms, attack_ms, decay_ms, sustain_drop] {
+                for bank in 0..self.key_banks.len() {
+                    write_key_values(w, self.bank(bank), value);
+                }
+            }
+        });
+    }
+
+    fn write_sounds(&self, w: &mut StateWriter) {
+        w.section(SECTION_SOUNDS, |w| {
+            let described: Vec<usize> = (0..self.sounds.len())
+                .filter(|&i| self.sounds[i].loaded || !self.sounds[i].metadata.name.is_empty())
+                .collect();
+            w.u16(described.len() as u16);
+            for &i in &described {
+                w.u16(i as u16);
+                w.f32(self.sounds[i].gain);
+                self.sounds[i].metadata.write(w);
+            }
+        });
+    }
+
+    /// Audio of the given slots (an unloaded slot is written with no samples)
+    fn write_audio(&self, w: &mut StateWriter, slots: &[usize]) {
+        w.section(SECTION_AUDIO, |w| {
+            w.u16(slots.len() as u16);
+            for &i in slots {
+                let sound = &self.sounds[i];
+                w.u16(i as u16);
+                w.f32(sound.source_sample_rate);
+                w.f32(sound.peak_normalize_gain);
+                w.u32(sound.length as u32);
+                for &sample in &sound.samples[..sound.length] {
+                    w.f32(sample);
+                }
+                w.u16(sound.slices.len() as u16);
+                for &slice in &sound.slices {
+                    w.u32(slice as u32);
+                }
+            }
+        });
+
+        let stereo: Vec<usize> = slots.iter().copied().filter(|&i| self.sounds[i].is_stereo()).collect();
+        if stereo.is_empty() {
+            return;
+        }
+        w.section(SECTION_AUDIO_RIGHT, |w| {
+            w.u16(stereo.len() as u16);
+            for &i in &stereo {
+                let sound = &self.sounds[i];
+                w.u16(i as u16);
+                w.u32(sound.length as u32);
+                for &sample in &sound.right[..sound.length] {
+                    w.f32(sample);
+                }
+            }
+        });
+    }
+
+    /// Content hash of every slot, None for empty ones
+    fn slot_hashes(&self) -> Vec<Option<u32>> {
+        self.sounds.iter().map(|sound| sound.loaded.then(|| sound.content_hash())).collect()
+    }
+
+    /// Loaded slot holding the referenced audio: same content first, then same name
+    ///
+    /// `hashes` comes from `slot_hashes`, so a kit hashes each slot once.
+    fn resolve_sound_ref(&self, sound_ref: &SoundRef, hashes: &[Option<u32>]) -> Option<usize> {
+        hashes.iter().position(|&hash| hash == Some(sound_ref.hash)).or_else(|| {
+            let named = |i: usize| hashes[i].is_some() && self.sounds[i].metadata.name == sound_ref.name;
+            (0..hashes.len()).find(|&i| !sound_ref.name.is_empty() && named(i))
+        })
+    }
+
+    /// Replace a slot's audio and slices, keeping its metadata
+    fn install_slot_audio(&mut self, slot: SlotAudio) {
+        self.vacate_slot(slot.sound_index);
+        let sound = &mut self.sounds[slot.sound_index];
+        let metadata = std::mem::replace(&mut sound.metadata, SoundMetadata::new());
+        *sound = Sound::new();
+        sound.metadata = metadata;
+        sound.length = slot.samples.len();
+        sound.samples = slot.samples;
+        if slot.right.len() == sound.length {
+            sound.right = slot.right;
+        }
+        sound.loaded = sound.length > 0;
+        sound.source_sample_rate = slot.source_sample_rate;
+        sound.peak_normalize_gain = slot.peak_normalize_gain;
+        sound.slices = slot.slices.into_iter().filter(|&s| s < sound.length).collect();
+        self.analyze_sound(slot.sound_index);
+    }
+
+    /// Apply the `StatePart` flags in `parts` from a parsed blob
+    fn apply_state(&mut self, mut parsed: ParsedState, parts: u32) {
+        let wants = |part: StatePart| parts & part as u32 != 0;
+        if !wants(StatePart::Sounds) {
+            parsed.audio.clear();
+            parsed.sounds.clear();
+        }
+        if !wants(StatePart::Mappings) {
+            parsed.keys = None;
+            parsed.banks = None;
+            parsed.pads = None;
+            parsed.triggers = None;
+            parsed.devices = None;
+        }
+
+        self.record_change(ConfigChange::Bulk);
+        self.state_migrations = parsed.migrations;
+        if let Some(globals) = parsed.globals {
+            if wants(StatePart::Tempo) {
+                self.bpm = globals.bpm;
+            }
+            if wants(StatePart::Mixer) {
+                self.master_volume = globals.master_volume;
+                self.metronome_enabled = globals.metronome_enabled;
+                self.metronome_volume = globals.metronome_volume;
+            }
+            if wants(StatePart::Modulation) {
+                self.modulation_preset = globals.modulation_preset;
+            }
+        }
+
+        if let Some(((volumes, mutes), scenes)) = parsed.mixer.filter(|_| wants(StatePart::Mixer)) {
+            self.mixer.volumes = volumes;
+            self.mixer.mutes = mutes;
+            self.mixer.scenes = scenes;
+        }
+        // Restores the stored scenes; the live volumes already hold the morph result
+        if let Some(morph) = parsed.morph.filter(|_| wants(StatePart::Mixer)) {
+            self.morph = morph;
+        }
+
+        for slot in parsed.audio {
+            self.install_slot_audio(slot);
+        }
+
+        for (sound_index, gain, metadata) in parsed.sounds {
+            let sound = &mut self.sounds[sound_index];
+            sound.metadata = metadata;
+            if sound.loaded {
+                sound.gain = gain.clamp(0.0, 16.0);
+            }
+        }
+
+        if let Some((active, banks)) = parsed.banks {
+            for (bank, imported) in banks.into_iter().enumerate().take(self.key_banks.len()) {
+                let slots = self.sounds.len();
+                for (mapping, imported) in self.bank_mut(bank).iter_mut().zip(imported) {
+                    *mapping = imported;
+                    mapping.has_sound &= mapping.sound_index < slots;
+                }
+            }
+            self.pending_bank = Some(active);
+            self.apply_pending_bank();
+        }
+
+        if let Some(triggers) = parsed.triggers {
+            self.trigger_mappings = triggers;
+            for (_, mapping) in self.trigger_mappings.iter_mut() {
+                mapping.has_sound &= mapping.sound_index < self.sounds.len();
+            }
+        }
+
+        if let Some((note_ranges, cc_mappings)) = parsed.midi.filter(|_| wants(StatePart::Midi)) {
+            self.midi.note_ranges = note_ranges;
+            for range in self.midi.note_ranges.iter_mut() {
+                range.mapping.has_sound &= range.mapping.sound_index < self.sounds.len();
+            }
+            self.cc_map.mappings = cc_mappings;
+        }
+
+        if let Some(sequence) = parsed.sequence.filter(|_| wants(StatePart::Sequencer)) {
+            let sequencer = &mut self.sequencer;
+            let mut patterns = sequence.patterns.into_iter();
+            for pattern in sequencer.patterns.iter_mut() {
+                *pattern = patterns.next().unwrap_or_else(Pattern::new);
+            }
+            sequencer.selected = sequence.selected;
+            sequencer.song = sequence.song;
+            sequencer.song_mode = sequence.song_mode;
+            sequencer.swing = sequence.swing;
+            sequencer.edited();
+        }
+
+        // Like set_clip, a lane's playing clip carries on until the lane changes
+        if let Some(clips) = parsed.clips.filter(|_| wants(StatePart::Sequencer)) {
+            self.clips.quantize_bars = clips.quantize_bars;
+            for lane in self.clips.lanes.iter_mut() {
+                lane.clips = [None; MAX_CLIPS_PER_LANE];
+            }
+            for (key_code, clips) in clips.lanes {
+                self.clips.lanes[key_code as usize].clips = clips;
+            }
+        }
+
+        if let Some(take) = parsed.performance.filter(|_| wants(StatePart::Sequencer)) {
+            self.halt_performance();
+            self.performance.start = take.start;
+            self.performance.length = take.length;
+            self.performance.events = take.events;
+        }
+
+        if let Some(devices) = parsed.devices {
+            self.devices = devices;
+        }
+
+        // Blobs without the section predate pad styles and leave them as they are
+        if let Some(pads) = parsed.pads {
+            self.pad_styles.fill(PadStyle::new());
+            for (key_code, style) in pads {
+                self.pad_styles[key_code as usize] = style;
+            }
+        }
+
+        // The active bank's keys; also present in blobs from before banks existed
+        if let Some(keys) = parsed.keys {
+            for (mapping, imported) in self.key_mappings.iter_mut().zip(keys) {
+                *mapping = imported;
+                // Keys bound to a slot this engine lacks are left without a sound
+                mapping.has_sound &= mapping.sound_index < self.sounds.len();
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Serialize the session: key mappings, pad colors and labels, input
+    /// device and MIDI channel banks, tempo, modulation, master, metronome
+    /// and group mixer settings, mixer and morph scenes, MIDI note ranges
+    /// and CC bindings, sequencer patterns and song, clip lanes, the
+    /// performance take and per-sound metadata
+    ///
+    /// With `include_audio`, the sample data and slices of every loaded
+    /// sound are embedded too, making the blob fully self-contained.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_state(&self, include_audio: bool) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        self.write_globals(&mut w);
+        self.write_mixer(&mut w);
+        self.write_morph(&mut w);
+        self.write_keys(&mut w);
+        self.write_banks(&mut w);
+        self.write_triggers(&mut w);
+        self.write_pads(&mut w);
+        self.write_devices(&mut w);
+        self.write_midi(&mut w);
+        self.write_pattern(&mut w);
+        self.write_clips(&mut w);
+        self.write_performance(&mut w);
+
+        self.write_sounds(&mut w);
+        if include_audio {
+            let loaded: Vec<usize> = (0..self.sounds.len()).filter(|&i| self.sounds[i].loaded).collect();
+            self.write_audio(&mut w, &loaded);
+        }
+
+        w.finish()
+    }
+
+    /// Restore a blob written by `export_state`
+    ///
+    /// The blob's checksum is verified and every section parsed before
+    /// anything is changed, so a damaged blob leaves the engine untouched.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn import_state(&mut self, bytes: &[u8]) -> StateImportResult {
+        self.import_state_parts(bytes, ALL_STATE_PARTS)
+    }
+
+    /// Restore only some parts of a blob written by `export_state`
+    ///
+    /// `parts` combines `StatePart` flags; everything else in the engine is
+    /// left as it is. Lets a downloaded kit's mappings be applied without
+    /// touching the tempo or mix.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn import_state_parts(&mut self, bytes: &[u8], parts: u32) -> StateImportResult {
+        match self.parse_state(bytes) {
+            Ok(parsed) => {
+                self.apply_state(parsed, parts);
+                StateImportResult::Ok
+            }
+            Err(error) => error.into(),
+        }
+    }
+
+    /// `StateMigration` flags describing how the last imported blob was
+    /// upgraded to the current layout (0 if it was already current)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_state_migrations(&self) -> u32 {
+        self.state_migrations
+    }
+
+    /// Serialize a kit: every key mapping and pad style plus tempo and mixer
+    /// settings, no audio
+    ///
+    /// Each slot the mappings use is recorded by name and content hash so
+    /// `import_kit` can find the samples again wherever they are loaded.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_kit(&self) -> Vec<u8> {
+        let mut referenced: Vec<usize> =
+            self.key_mappings.iter().filter(|mapping| mapping.has_sound).map(|mapping| mapping.sound_index).collect();
+        referenced.sort_unstable();
+        referenced.dedup();
+
+        let mut w = StateWriter::new();
+        self.write_globals(&mut w);
+        self.write_keys(&mut w);
+        self.write_pads(&mut w);
+        self.write_mixer(&mut w);
+        w.section(SECTION_SOUND_REFS, |w| {
+            w.u16(referenced.len() as u16);
+            for &i in &referenced {
+                w.u16(i as u16);
+                w.u32(self.sounds[i].content_hash());
+                w.str(&self.sounds[i].metadata.name);
+            }
+        });
+        w.finish()
+    }
+
+    /// Load a kit from `export_kit`, re-binding its keys to the loaded slots
+    /// holding the same audio (matched by content, then by name)
+    ///
+    /// Keys whose sound cannot be found are left without a sound. Returns the
+    /// number of unresolved sounds, or -1 if the blob was rejected.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn import_kit(&mut self, bytes: &[u8]) -> i32 {
+        let Ok(mut parsed) = self.parse_state(bytes) else {
+            return -1;
+        };
+        let hashes = self.slot_hashes();
+        let bindings: Vec<(usize, Option<usize>)> = parsed
+            .sound_refs
+            .iter()
+            .map(|sound_ref| (sound_ref.sound_index, self.resolve_sound_ref(sound_ref, &hashes)))
+            .collect();
+        if let Some(keys) = parsed.keys.as_mut() {
+            for mapping in keys.iter_mut() {
+                if let Some(&(_, bound)) = bindings.iter().find(|(original, _)| *original == mapping.sound_index) {
+                    // Out of range leaves the key without a sound
+                    mapping.sound_index = bound.unwrap_or(self.sounds.len());
+                }
+            }
+        }
+        self.apply_state(parsed, ALL_STATE_PARTS);
+        bindings.iter().filter(|(_, bound)| bound.is_none()).count() as i32
+    }
+
+    /// Serialize one key's complete configuration as a preset blob
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_key(&self, key_code: u8) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.section(SECTION_KEY, |w| {
+            let mapping = &self.key_mappings[key_code as usize];
+            mapping.write(w);
+            mapping.velocity.write(w);
+            w.f32(mapping.aftertouch_depth);
+            w.f32(mapping.release_ms);
+            w.f32(mapping.envelope.attack_ms);
+            w.f32(mapping.envelope.decay_ms);
+            w.f32(mapping.envelope.sustain);
+        });
+        w.finish()
+    }
+
+    /// Replace many key mappings at once at the next block boundary
+    ///
+    /// `bytes` holds 15-byte records, each a key code followed by:
+    /// mapped flag (u8), sound index (u16), mode (u8), overlap mode (u8),
+    /// group (u8), volume (f32), pitch (i8), modulation (u8) and slice
+    /// (u16, 0xFFFF = whole sound), all little-endian. A zero mapped flag
+    /// clears the key. Either every record is applied or none is; returns
+    /// false if any record is malformed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_mappings_packed(&mut self, bytes: &[u8]) -> bool {
+        if !bytes.len().is_multiple_of(PACKED_KEY_RECORD_BYTES) {
+            return false;
+        }
+        let mut r = StateReader::new(bytes);
+        let mut records = Vec::with_capacity(bytes.len() / PACKED_KEY_RECORD_BYTES);
+        while !r.is_empty() {
+            let Ok(key_code) = r.u8() else {
+                return false;
+            };
+            let Ok(mut mapping) = KeyMapping::read(&mut r) else {
+                return false;
+            };
+            // Same rule as set_key_mapping
+            mapping.has_sound &= mapping.sound_index < self.sounds.len() && self.sounds[mapping.sound_index].loaded;
+            records.push((key_code, mapping));
+        }
+
+        self.record_change(ConfigChange::Bulk);
+        self.pending_mappings = records;
+        true
+    }
+
+    /// Apply a preset blob from `export_key` to a key (possibly a different one)
+    ///
+    /// Returns false if the blob was rejected; the key is then unchanged.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn import_key(&mut self, key_code: u8, bytes: &[u8]) -> bool {
+        let mapping = (|| {
+            let (mut reader, _) = StateReader::open(bytes)?;
+            while let Some((tag, mut r)) = reader.section()? {
+                if tag == SECTION_KEY {
+                    let mut mapping = KeyMapping::read(&mut r)?;
+                    mapping.velocity = r.or_default(VelocityResponse::read, VelocityResponse::LINEAR)?;
+                    set_aftertouch_depth(&mut mapping, r.or_default(StateReader::f32, 0.0)?);
+                    set_release_ms(&mut mapping, r.or_default(StateReader::f32, 0.0)?);
+                    let attack_ms = r.or_default(StateReader::f32, 0.0)?;
+                    let decay_ms = r.or_default(StateReader::f32, 0.0)?;
+                    let sustain = r.or_default(StateReader::f32, 1.0)?;
+                    mapping.envelope = Envelope::clamped(attack_ms, decay_ms, sustain);
+                    return Ok(mapping);
+                }
+            }
+            Err(StateError::MissingSection)
+        })();
+        let Ok(mut mapping) = mapping else {
+            return false;
+        };
+        mapping.has_sound &= mapping.sound_index < self.sounds.len();
+        self.record_change(ConfigChange::KeyMapping(key_code));
+        self.key_mappings[key_code as usize] = mapping;
+        true
+    }
+}
+
+// ============================================================================
+// SOUND EXPORT - One slot's audio for host-side storage
+// ============================================================================
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Serialize one sound: its audio with a header (sample rate, length,
+    /// channel count), slices, gain and metadata
+    ///
+    /// Lets the host keep decoded samples (e.g. in IndexedDB) and restore
+    /// them with `import_sound` without decoding the source file again.
+    /// Returns an empty vector if the slot is not loaded.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_sound(&self, sound_index: usize) -> Vec<u8> {
+        let Some(sound) = self.sounds.get(sound_index).filter(|sound| sound.loaded) else {
+            return Vec::new();
+        };
+        let mut w = StateWriter::new();
+        w.section(SECTION_SOUND, |w| {
+            w.f32(self.sample_rate);
+            w.u32(sound.length as u32);
+            w.u8(if sound.is_stereo() { 2 } else { 1 }); // channels
+            sound.metadata.write(w);
+            w.f32(sound.gain);
+            w.f32(sound.source_sample_rate);
+            w.f32(sound.peak_normalize_gain);
+            for frame in 0..sound.length {
+                w.f32(sound.samples[frame]);
+                if sound.is_stereo() {
+                    w.f32(sound.right[frame]);
+                }
+            }
+            w.u16(sound.slices.len() as u16);
+            for &slice in &sound.slices {
+                w.u32(slice as u32);
+            }
+        });
+        w.finish()
+    }
+
+    /// Load a blob from `export_sound` into a slot (possibly a different one)
+    ///
+    /// Audio written at another sample rate is resampled; stereo audio stays
+    /// stereo and audio with more channels is mixed down to mono. Returns false if the blob was rejected;
+    /// the slot is then unchanged.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn import_sound(&mut self, sound_index: usize, bytes: &[u8]) -> bool {
+        if sound_index >= self.sounds.len() {
+            return false;
+        }
+        let parsed = (|| {
+            let (mut reader, _) = StateReader::open(bytes)?;
+            while let Some((tag, mut r)) = reader.section()? {
+                if tag != SECTION_SOUND {
+                    continue;
+                }
+                let rate = r.f32()?;
+                let frames = r.u32()? as usize;
+                let channels = r.u8()? as usize;
+                if rate <= 0.0 || channels == 0 {
+                    return Err(StateError::InvalidValue);
+                }
+                let metadata = SoundMetadata::read(&mut r)?;
+                let gain = r.f32()?;
+                let source_sample_rate = r.f32()?;
+                let peak_normalize_gain = r.f32()?;
+                let count = frames.checked_mul(channels * 4).ok_or(StateError::Truncated)?;
+                let interleaved = read_samples(r.take(count)?, usize::MAX);
+                let (samples, right) = if channels == 2 {
+                    interleaved.chunks_exact(2).map(|frame| (frame[0], frame[1])).unzip()
+                } else {
+                    let mixed = |frame: &[f32]| frame.iter().sum::<f32>() / channels as f32;
+                    (interleaved.chunks_exact(channels).map(mixed).collect(), Vec::new())
+                };
+                let slices = (0..r.u16()?).map(|_| r.u32().map(|s| s as usize)).collect::<Result<Vec<_>, _>>()?;
+                let slot = SlotAudio { sound_index, source_sample_rate, peak_normalize_gain, samples, right, slices };
+                return Ok((rate, slot, metadata, gain));
+            }
+            Err(StateError::MissingSection)
+        })();
+        let Ok((rate, mut slot, metadata, gain)) = parsed else {
+            return false;
+        };
+
+        if rate != self.sample_rate {
+            let ratio = self.sample_rate as f64 / rate as f64;
+            slot.samples = resample(&slot.samples, rate, self.sample_rate, self.load_options.resample_quality);
+            if !slot.right.is_empty() {
+                slot.right = resample(&slot.right, rate, self.sample_rate, self.load_options.resample_quality);
+            }
+            for slice in slot.slices.iter_mut() {
+                *slice = (*slice as f64 * ratio).round() as usize;
+            }
+        }
+        slot.samples.truncate(self.max_sample_length);
+        slot.right.truncate(self.max_sample_length);
+        self.install_slot_audio(slot);
+        let sound = &mut self.sounds[sound_index];
+        sound.metadata = metadata;
+        sound.gain = gain.clamp(0.0, 16.0);
+        true
+    }
+}
+
+// ============================================================================
+// INCREMENTAL EXPORT - Only what changed since the last autosave
+// ============================================================================
+
+/// Sections compared by checksum for incremental export, with the parts
+/// each one carries
+const TRACKED_SECTIONS: [([u8; 4], u32); 13] = [
+    (SECTION_GLOBALS, StatePart::Tempo as u32 | StatePart::Mixer as u32 | StatePart::Modulation as u32),
+    (SECTION_MIXER, StatePart::Mixer as u32),
+    (SECTION_MORPH, StatePart::Mixer as u32),
+    (SECTION_KEYS, StatePart::Mappings as u32),
+    (SECTION_BANKS, StatePart::Mappings as u32),
+    (SECTION_TRIGGERS, StatePart::Mappings as u32),
+    (SECTION_PADS, StatePart::Mappings as u32),
+    (SECTION_DEVICES, StatePart::Mappings as u32),
+    (SECTION_SOUNDS, StatePart::Sounds as u32),
+    (SECTION_MIDI, StatePart::Midi as u32),
+    (SECTION_PATTERN, StatePart::Sequencer as u32),
+    (SECTION_CLIPS, StatePart::Sequencer as u32),
+    (SECTION_PERFORMANCE, StatePart::Sequencer as u32),
+];
+
+impl DspEngine {
+    /// Write one tracked section
+    fn write_tracked_section(&self, w: &mut StateWriter, tag: [u8; 4]) {
+        match tag {
+            SECTION_GLOBALS => self.write_globals(w),
+            SECTION_MIXER => self.write_mixer(w),
+            SECTION_MORPH => self.write_morph(w),
+            SECTION_KEYS => self.write_keys(w),
+            SECTION_BANKS => self.write_banks(w),
+            SECTION_TRIGGERS => self.write_triggers(w),
+            SECTION_PADS => self.write_pads(w),
+            SECTION_MIDI => self.write_midi(w),
+            SECTION_PATTERN => self.write_pattern(w),
+            SECTION_CLIPS => self.write_clips(w),
+            SECTION_DEVICES => self.write_devices(w),
+            SECTION_PERFORMANCE => self.write_performance(w),
+            _ => self.write_sounds(w),
+        }
+    }
+
+    /// Checksum of a tracked section when it was last exported
+    fn saved_section_crc(&self, tag: [u8; 4]) -> Option<u32> {
+        self.saved_sections.iter().find(|(saved, _)| *saved == tag).map(|&(_, crc)| crc)
+    }
+
+    /// Note that a slot's audio or slices changed since the last incremental export
+    pub(crate) fn mark_audio_dirty(&mut self, sound_index: usize) {
+        if let Some(dirty) = self.audio_dirty.get_mut(sound_index) {
+            *dirty = true;
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// `StatePart` flags for everything changed since the last
+    /// `export_state_changes`
+    ///
+    /// Tempo, mixer and modulation share a section and are reported together.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_dirty_state_parts(&self) -> u32 {
+        let mut parts = if self.audio_dirty.contains(&true) { StatePart::Sounds as u32 } else { 0 };
+        for (tag, section_parts) in TRACKED_SECTIONS {
+            let mut w = StateWriter { bytes: Vec::new() };
+            self.write_tracked_section(&mut w, tag);
+            if self.saved_section_crc(tag) != Some(crc32(&w.bytes)) {
+                parts |= section_parts;
+            }
+        }
+        parts
+    }
+
+    /// Serialize only the sections changed since the previous call
+    ///
+    /// The result is an ordinary state blob that `import_state` applies on
+    /// top of the last one; unchanged sections are simply absent. With
+    /// `include_audio`, only slots whose audio or slices changed are
+    /// embedded (an unloaded slot is sent empty). The first call exports
+    /// everything. `export_state` does not affect what counts as changed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_state_changes(&mut self, include_audio: bool) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for (tag, _) in TRACKED_SECTIONS {
+            let start = w.bytes.len();
+            self.write_tracked_section(&mut w, tag);
+            let crc = crc32(&w.bytes[start..]);
+            if self.saved_section_crc(tag) == Some(crc) {
+                w.bytes.truncate(start);
+            } else {
+                self.saved_sections.retain(|(saved, _)| *saved != tag);
+                self.saved_sections.push((tag, crc));
+            }
+        }
+
+        if include_audio {
+            let dirty: Vec<usize> = (0..self.sounds.len()).filter(|&i| self.audio_dirty[i]).collect();
+            if !dirty.is_empty() {
+                self.write_audio(&mut w, &dirty);
+            }
+            self.audio_dirty.fill(false);
+        }
+        w.finish()
+    }
+}
+
+// ============================================================================
+// JSON - Human-readable mirror of the binary format (serde feature)
+// ============================================================================
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonState {
+    version: u16,
+    bpm: f32,
+    master_volume: f32,
+    metronome_enabled: bool,
+    metronome_volume: f32,
+    modulation_preset: u8,
+    /// Keys with a sound assigned; all others are reset on import
+    keys: Vec<JsonKey>,
+    sounds: Vec<JsonSound>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonKey {
+    key_code: u8,
+    sound_index: usize,
+    mode: u8,
+    overlap_mode: u8,
+    group_id: u8,
+    volume: f32,
+    pitch_semitones: i8,
+    modulation_enabled: bool,
+    slice: Option<usize>,
+    /// Velocity curve and custom amount (absent = linear)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    velocity: Option<(u8, f32)>,
+    /// Aftertouch vibrato depth in semitones (absent = none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aftertouch_depth: Option<f32>,
+    /// Release time in milliseconds (absent = none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    release_ms: Option<f32>,
+    /// Attack and decay in milliseconds and sustain level (absent = none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    envelope: Option<(f32, f32, f32)>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonSound {
+    sound_index: usize,
+    name: String,
+    original_bpm: Option<f32>,
+    root_note: Option<u8>,
+    category: u8,
+    gain: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audio: Option<JsonAudio>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonAudio {
+    source_sample_rate: f32,
+    peak_normalize_gain: f32,
+    samples: Vec<f32>,
+    /// Right channel of a stereo sound (`samples` holds the left)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    right: Vec<f32>,
+    slices: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl DspEngine {
+    fn to_json_state(&self, include_audio: bool) -> JsonState {
+        let keys = (0..=255u8)
+            .filter(|&key| self.key_mappings[key as usize].has_sound)
+            .map(|key_code| {
+                let mapping = &self.key_mappings[key_code as usize];
+                JsonKey {
+                    key_code,
+                    sound_index: mapping.sound_index,
+                    mode: mapping.mode as u8,
+                    overlap_mode: mapping.overlap_mode as u8,
+                    group_id: mapping.group_id,
+                    volume: mapping.volume,
+                    pitch_semitones: mapping.pitch_semitones,
+                    modulation_enabled: mapping.modulation_enabled,
+                    slice: mapping.slice,
+                    velocity: (mapping.velocity != VelocityResponse::LINEAR)
+                        .then_some((mapping.velocity.curve as u8, mapping.velocity.amount)),
+                    aftertouch_depth: (mapping.aftertouch_depth > 0.0).then_some(mapping.aftertouch_depth),
+                    release_ms: (mapping.release_ms > 0.0).then_some(mapping.release_ms),
+                    envelope: (mapping.envelope != Envelope::NONE).then_some((
+                        mapping.envelope.attack_ms,
+                        mapping.envelope.decay_ms,
+                        mapping.envelope.sustain,
+                    )),
+                }
+            })
+            .collect();
+        let sounds = (0..self.sounds.len())
+            .filter(|&i| self.sounds[i].loaded || !self.sounds[i].metadata.name.is_empty())
+            .map(|sound_index| {
+                let sound = &self.sounds[sound_index];
+                JsonSound {
+                    sound_index,
+                    name: sound.metadata.name.clone(),
+                    original_bpm: sound.metadata.original_bpm,
+                    root_note: sound.metadata.root_note,
+                    category: sound.metadata.category as u8,
+                    gain: sound.gain,
+                    audio: (include_audio && sound.loaded).then(|| JsonAudio {
+                        source_sample_rate: sound.source_sample_rate,
+                        peak_normalize_gain: sound.peak_normalize_gain,
+                        samples: sound.samples[..sound.length].to_vec(),
+                        right: if sound.is_stereo() { sound.right[..sound.length].to_vec() } else { Vec::new() },
+                        slices: sound.slices.clone(),
+                    }),
+                }
+            })
+            .collect();
+        JsonState {
+            version: STATE_VERSION,
+            bpm: self.bpm,
+            master_volume: self.master_volume,
+            metronome_enabled: self.metronome_enabled,
+            metronome_volume: self.metronome_volume,
+            modulation_preset: self.modulation_preset as u8,
+            keys,
+            sounds,
+        }
+    }
+
+    /// Validate a JSON state into the same form the binary parser produces
+    fn parse_json_state(&self, state: JsonState) -> Result<ParsedState, StateError> {
+        if state.version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion);
+        }
+        let finite = |value: f32| if value.is_finite() { Ok(value) } else { Err(StateError::InvalidValue) };
+
+        let mut keys = vec![KeyMapping::new(); 256];
+        for key in state.keys {
+            keys[key.key_code as usize] = KeyMapping {
+                sound_index: key.sound_index,
+                mode: playback_mode(key.mode)?,
+                overlap_mode: overlap_mode(key.overlap_mode)?,
+                group_id: key.group_id,
+                volume: finite(key.volume)?.clamp(0.0, 1.0),
+                pitch_semitones: key.pitch_semitones.clamp(-24, 24),
+                modulation_enabled: key.modulation_enabled,
+                has_sound: true,
+                slice: key.slice,
+                velocity: match key.velocity {
+                    Some((curve, amount)) => {
+                        VelocityResponse { curve: velocity_curve(curve)?, amount: finite(amount)?.clamp(-1.0, 1.0) }
+                    }
+                    None => VelocityResponse::LINEAR,
+                },
+                aftertouch_depth: finite(key.aftertouch_depth.unwrap_or(0.0))?.clamp(0.0, 2.0),
+                release_ms: finite(key.release_ms.unwrap_or(0.0))?.clamp(0.0, 10_000.0),
+                envelope: match key.envelope {
+                    Some((attack_ms, decay_ms, sustain)) => {
+                        Envelope::clamped(finite(attack_ms)?, finite(decay_ms)?, finite(sustain)?)
+                    }
+                    None => Envelope::NONE,
+                },
+            };
+        }
+
+        let mut parsed = ParsedState {
+            migrations: 0,
+            globals: Some(Globals {
+                bpm: finite(state.bpm)?.clamp(20.0, 300.0),
+                master_volume: finite(state.master_volume)?.clamp(0.0, 1.0),
+                metronome_enabled: state.metronome_enabled,
+                metronome_volume: finite(state.metronome_volume)?.clamp(0.0, 1.0),
+                modulation_preset: modulation_preset(state.modulation_preset)?,
+            }),
+            keys: Some(keys),
+            sounds: Vec::new(),
+            audio: Vec::new(),
+            banks: None,
+            mixer: None,
+            morph: None,
+            triggers: None,
+            midi: None,
+            pads: None,
+            sequence: None,
+            clips: None,
+            devices: None,
+            performance: None,
+            sound_refs: Vec::new(),
+        };
+        for sound in state.sounds.into_iter().filter(|sound| sound.sound_index < self.sounds.len()) {
+            let metadata = SoundMetadata {
+                name: sound.name,
+                original_bpm: sound.original_bpm.filter(|bpm| bpm.is_finite() && *bpm > 0.0),
+                root_note: sound.root_note.filter(|&note| note <= 127),
+                category: sound_category(sound.category)?,
+            };
+            parsed.sounds.push((sound.sound_index, finite(sound.gain)?, metadata));
+            if let Some(mut audio) = sound.audio {
+                audio.samples.truncate(self.max_sample_length);
+                audio.right.truncate(self.max_sample_length);
+                if audio.samples.iter().chain(&audio.right).any(|s| !s.is_finite()) {
+                    return Err(StateError::InvalidValue);
+                }
+                parsed.audio.push(SlotAudio {
+                    sound_index: sound.sound_index,
+                    source_sample_rate: finite(audio.source_sample_rate)?,
+                    peak_normalize_gain: finite(audio.peak_normalize_gain)?,
+                    samples: audio.samples,
+                    right: audio.right,
+                    slices: audio.slices,
+                });
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Session state as pretty-printed JSON: globals, the active bank's key
+    /// mappings and per-sound metadata (no group mixer or other banks)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn export_state_json(&self, include_audio: bool) -> String {
+        serde_json::to_string_pretty(&self.to_json_state(include_audio)).unwrap_or_default()
+    }
+
+    /// Restore state from `export_state_json`
+    ///
+    /// Returns false if the JSON was rejected; the engine is then unchanged.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn import_state_json(&mut self, json: &str) -> bool {
+        let Ok(state) = serde_json::from_str::<JsonState>(json) else {
+            return false;
+        };
+        match self.parse_json_state(state) {
+            Ok(parsed) => {
+                self.apply_state(parsed, ALL_STATE_PARTS);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(2, &[0.1, 0.2, 0.3, 0.4]);
+        engine.set_sound_name(2, "snare");
+        engine.set_key_mapping(65, 2, PlaybackMode::Loop, OverlapMode::Monophonic, 3, 0.7, -5, true);
+        engine.set_key_velocity_curve(65, VelocityCurve::Custom, -0.5);
+        engine.set_bpm(97.0);
+        engine.set_modulation_preset(ModulationPreset::EighthSidechain);
+        engine.set_group_mute(3, true);
+        assert!(engine.store_mixer_scene("verse"));
+        assert!(engine.store_morph_scene(0) && engine.store_morph_scene(1));
+        engine.set_morph(0.5);
+        engine.set_trigger_mapping(300, 2, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.begin_cc_learn(CcTarget::GroupVolume, 3);
+        engine.control_change(0, 21, 127);
+        engine.set_pattern_length(8);
+        assert!(engine.set_step(65, 7, 90, -3, 0.5, 0.25));
+        assert!(engine.add_song_entry(0, 3) && engine.add_song_entry(4, 1));
+        engine.set_sequencer_swing(0.25);
+
+        let blob = engine.export_state(true);
+        let mut restored = DspEngine::new(48000.0);
+        assert_eq!(restored.import_state(&blob), StateImportResult::Ok);
+
+        assert_eq!(restored.get_bpm(), 97.0);
+        assert!(restored.modulation_preset == ModulationPreset::EighthSidechain);
+        assert_eq!(restored.get_sound_name(2), "snare");
+        assert!(restored.get_group_mute(3) && restored.get_mixer_scene_name(0) == "verse");
+        assert!(restored.get_morph() == 0.5 && restored.morph.scenes[1].is_some());
+        assert_eq!(restored.get_cc_mapping_count(), 1);
+        assert_eq!(restored.get_pattern_length(), 8);
+        assert_eq!(restored.get_pattern_packed(), [65.0, 7.0, 90.0, -3.0, 0.5, 0.25]);
+        assert_eq!(restored.get_song_packed(), [0, 3, 4, 1]);
+        assert_eq!(restored.get_sequencer_swing(), 0.25);
+        assert!(restored.trigger_mapping(300).is_some_and(|mapping| mapping.has_sound));
+        assert_eq!(restored.loaded_samples(2), Some(&[0.1, 0.2, 0.3, 0.4][..]));
+        let mapping = restored.key_mappings[65];
+        assert!(mapping.has_sound && mapping.mode == PlaybackMode::Loop && mapping.group_id == 3);
+        assert_eq!((mapping.volume, mapping.pitch_semitones), (0.7, -5));
+        assert!(mapping.velocity == VelocityResponse { curve: VelocityCurve::Custom, amount: -0.5 });
+        assert!(!restored.key_mappings[66].has_sound, "unmapped keys stay unmapped");
+
+        // Other banks travel with the state
+        engine.set_active_bank(2);
+        engine.set_key_mapping(66, 2, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        assert_eq!(restored.import_state(&engine.export_state(false)), StateImportResult::Ok);
+        assert_eq!(restored.get_active_bank(), 2);
+        assert!(restored.key_mappings[66].has_sound && !restored.key_mappings[65].has_sound);
+        assert_eq!(restored.bank(0)[65].sound_index, 2);
+
+        // Damaged blobs are rejected without touching the engine
+        assert_eq!(restored.import_state(&blob[..blob.len() - 3]), StateImportResult::Truncated);
+        assert_eq!(restored.import_state(b"QEY"), StateImportResult::Truncated);
+        assert_eq!(restored.import_state(b"RIFF...."), StateImportResult::BadMagic);
+        let mut corrupted = blob.clone();
+        corrupted[40] ^= 0x10;
+        assert_eq!(restored.import_state(&corrupted), StateImportResult::ChecksumMismatch);
+        assert_eq!(restored.get_bpm(), 97.0);
+    }
+
+    #[test]
+    fn test_clips_banks_and_take_round_trip() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 400]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        assert!(engine.set_clip(65, 3, ClipKind::Loop, 0) && engine.set_clip(66, 0, ClipKind::Pattern, 2));
+        engine.set_clip_quantize(2);
+        assert!(engine.set_device_bank(1, 2) && engine.set_midi_channel_bank(9, 1));
+        engine.start_performance_recording();
+        engine.process(&mut [0.0; 2 * 50]);
+        engine.note_on(65);
+        engine.process(&mut [0.0; 2 * 150]);
+        engine.stop_performance_recording();
+
+        // Restored at another rate, the take keeps its timing
+        let mut restored = DspEngine::new(2000.0);
+        restored.load_sound(0, &[0.5; 800]);
+        assert_eq!(restored.import_state(&engine.export_state(false)), StateImportResult::Ok);
+        assert!(restored.launch_clip(65, 3) && restored.launch_clip(66, 0) && !restored.launch_clip(65, 0));
+        assert_eq!(restored.clips.quantize_bars, 2);
+        assert_eq!((restored.get_device_bank(1), restored.get_device_bank(2)), (2, -1));
+        assert_eq!((restored.get_midi_channel_bank(9), restored.get_midi_channel_bank(0)), (1, -1));
+        assert_eq!((restored.get_performance_event_count(), restored.get_performance_length()), (1, 0.2));
+        assert_eq!(restored.performance.events[0].at, 100);
+
+        // Only the sequencer part carries clips and the take, only mappings the banks
+        let mut partial = DspEngine::new(1000.0);
+        let parts = StatePart::Sequencer as u32;
+        assert_eq!(partial.import_state_parts(&engine.export_state(false), parts), StateImportResult::Ok);
+        assert_eq!((partial.get_performance_event_count(), partial.get_device_bank(1)), (1, -1));
+    }
+
+    #[test]
+    fn test_old_blob_is_migrated() {
+        // Version 1: no checksum, globals section holding only the BPM
+        let mut blob = Vec::new();
+        blob.extend_from_slice(b"QEYL");
+        blob.extend_from_slice(&1u16.to_le_bytes());
+        blob.extend_from_slice(b"GLOB");
+        blob.extend_from_slice(&4u32.to_le_bytes());
+        blob.extend_from_slice(&100.0f32.to_le_bytes());
+
+        let mut engine = DspEngine::new(48000.0);
+        engine.set_master_volume(0.3);
+        assert_eq!(engine.import_state(&blob), StateImportResult::Ok);
+        assert_eq!((engine.get_bpm(), engine.master_volume), (100.0, 1.0));
+        let migrations = StateMigration::Unverified as u32 | StateMigration::DefaultedFields as u32;
+        assert_eq!(engine.get_state_migrations(), migrations);
+
+        assert_eq!(engine.import_state(&engine.export_state(false)), StateImportResult::Ok);
+        assert_eq!(engine.get_state_migrations(), 0);
+    }
+
+    #[test]
+    fn test_incremental_export() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 16]);
+        engine.load_sound(1, &[0.25; 16]);
+        let full = engine.export_state_changes(true);
+        assert_eq!(engine.get_dirty_state_parts(), 0);
+        assert!(engine.export_state_changes(true).len() < 32, "nothing changed");
+
+        engine.set_bpm(100.0);
+        engine.load_sound(1, &[-0.25; 8]);
+        assert_eq!(engine.get_dirty_state_parts() & StatePart::Tempo as u32, StatePart::Tempo as u32);
+        let diff = engine.export_state_changes(true);
+        assert!(diff.len() < full.len() / 2, "only slot 1 audio is re-sent");
+
+        let mut mirror = DspEngine::new(48000.0);
+        assert_eq!(mirror.import_state(&full), StateImportResult::Ok);
+        assert_eq!(mirror.import_state(&diff), StateImportResult::Ok);
+        assert_eq!(mirror.export_state(true), engine.export_state(true));
+    }
+
+    #[test]
+    fn test_import_selected_parts() {
+        let mut source = DspEngine::new(48000.0);
+        source.load_sound(0, &[0.5; 16]);
+        source.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        source.set_bpm(90.0);
+        source.set_master_volume(0.5);
+        let blob = source.export_state(false);
+
+        let mut engine = DspEngine::new(48000.0);
+        engine.set_bpm(128.0);
+        let parts = StatePart::Mappings as u32 | StatePart::Modulation as u32;
+        assert_eq!(engine.import_state_parts(&blob, parts), StateImportResult::Ok);
+        assert!(engine.key_mappings[65].has_sound);
+        assert_eq!((engine.get_bpm(), engine.master_volume), (128.0, 1.0));
+    }
+
+    #[test]
+    fn test_key_preset_copies_to_other_key() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(1, &[0.5; 64]);
+        engine.set_key_mapping(65, 1, PlaybackMode::Loop, OverlapMode::Polyphonic, 2, 0.5, 7, false);
+        engine.set_key_velocity_curve(65, VelocityCurve::Soft, 0.0);
+        engine.set_key_aftertouch_depth(65, 0.5);
+        engine.set_key_release(65, 250.0);
+
+        let preset = engine.export_key(65);
+        assert!(engine.import_key(66, &preset));
+        let mapping = engine.key_mappings[66];
+        assert!(mapping.has_sound && mapping.sound_index == 1 && mapping.pitch_semitones == 7);
+        assert_eq!(engine.get_key_velocity_curve(66), VelocityCurve::Soft);
+        assert_eq!((engine.get_key_aftertouch_depth(66), engine.get_key_release(66)), (0.5, 250.0));
+
+        // A full state blob carries no pad preset
+        assert!(!engine.import_key(67, &engine.export_state(false)));
+        assert!(!engine.key_mappings[67].has_sound);
+    }
+
+    #[test]
+    fn test_kit_rebinds_by_content() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 64]);
+        engine.load_sound(1, &[0.25; 64]);
+        engine.set_sound_name(1, "hat");
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_key_mapping(66, 1, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_key_color(66, 7);
+        engine.set_key_label(66, "Hat");
+        engine.set_group_volume(3, 0.5);
+        engine.process(&mut [0.0; 2]);
+        let kit = engine.export_kit();
+
+        // Same audio loaded into different slots; the hat only matches by name
+        let mut other = DspEngine::new(48000.0);
+        other.load_sound(5, &[0.5; 64]);
+        other.load_sound(9, &[0.3; 64]);
+        other.set_sound_name(9, "hat");
+        assert_eq!(other.import_kit(&kit), 0);
+        assert_eq!(other.key_mappings[65].sound_index, 5);
+        assert_eq!(other.key_mappings[66].sound_index, 9);
+        assert_eq!((other.get_key_color(66), other.get_key_label(66).as_str()), (7, "Hat"));
+        assert_eq!(other.get_group_volume(3), 0.5, "the kit carries the mixer");
+
+        let mut empty = DspEngine::new(48000.0);
+        assert_eq!(empty.import_kit(&kit), 2);
+        assert!(!empty.key_mappings[65].has_sound);
+
+        // Stereo audio only matches the same channels
+        other.load_sound_stereo(2, &[0.5; 64], &[0.5; 64]);
+        other.load_sound_stereo(3, &[0.5; 64], &[-0.5; 64]);
+        let hashes: Vec<u32> = [5, 2, 3].iter().map(|&i| other.sounds[i].content_hash()).collect();
+        assert!(hashes[0] != hashes[1] && hashes[1] != hashes[2] && hashes[0] != hashes[2]);
+    }
+
+    #[test]
+    fn test_sound_blob_round_trip() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(1, &[0.5; 1000]);
+        engine.set_sound_name(1, "pad");
+        engine.slice_equal(1, 4, 60);
+        let blob = engine.export_sound(1);
+        assert!(engine.export_sound(2).is_empty());
+
+        let mut restored = DspEngine::new(48000.0);
+        assert!(restored.import_sound(4, &blob));
+        assert_eq!(restored.get_sound_name(4), "pad");
+        assert_eq!(restored.loaded_samples(4), engine.loaded_samples(1));
+        assert_eq!(restored.get_sound_slice_count(4), 4);
+        assert!(!restored.import_sound(5, &blob[..blob.len() - 1]));
+
+        // Audio from an engine at another rate is resampled on the way in
+        let mut slower = DspEngine::new(24000.0);
+        assert!(slower.import_sound(0, &blob));
+        assert_eq!(slower.sounds[0].slice_bounds(1), Some((125, 250)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(3, &[0.1, -0.2, 0.3]);
+        engine.set_sound_name(3, "rim");
+        engine.set_key_mapping(70, 3, PlaybackMode::SingleShot, OverlapMode::Monophonic, 1, 0.8, 2, false);
+        engine.set_bpm(140.0);
+
+        let json = engine.export_state_json(true);
+        assert!(json.contains("\"name\": \"rim\""));
+        let mut restored = DspEngine::new(48000.0);
+        assert!(restored.import_state_json(&json));
+        assert_eq!(restored.export_state(true), engine.export_state(true));
+        assert!(!restored.import_state_json("{\"version\": 1}"));
+    }
+
+    #[test]
+    fn test_packed_mappings_apply_at_block() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(4, &[0.5; 64]);
+        let mut packed = Vec::new();
+        for (key, volume) in [(65u8, 0.5f32), (66, 0.25)] {
+            packed.push(key);
+            packed.extend_from_slice(&[1, 4, 0, 1, 0, 7]);
+            packed.extend_from_slice(&volume.to_le_bytes());
+            packed.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        }
+        assert_eq!(packed.len(), 2 * PACKED_KEY_RECORD_BYTES);
+        assert!(!engine.set_key_mappings_packed(&packed[..20]));
+
+        assert!(engine.set_key_mappings_packed(&packed));
+        assert!(!engine.get_key_has_sound(65), "applied at the next block");
+        engine.process(&mut [0.0; 2]);
+        let (a, b) = (engine.key_mappings[65], engine.key_mappings[66]);
+        assert!(a.has_sound && a.sound_index == 4 && a.mode == PlaybackMode::Loop && a.group_id == 7);
+        assert_eq!((a.volume, b.volume), (0.5, 0.25));
+    }
+}
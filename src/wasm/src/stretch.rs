@@ -0,0 +1,158 @@
+//! Offline time-stretching
+//!
+//! WSOLA (waveform-similarity overlap-add): the output is built from
+//! overlapping windowed grains of the input, each one nudged to the position
+//! that best continues the previous grain. Tempo changes without altering
+//! pitch. Runs outside the audio callback only.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::fft::hann;
+use crate::DspEngine;
+
+/// Grain length in seconds
+const GRAIN_SECONDS: f32 = 0.03;
+
+/// Stride used when comparing waveforms (trades accuracy for speed)
+const SIMILARITY_STRIDE: usize = 2;
+
+/// Slowest and fastest supported stretch factors (output / input length)
+const STRETCH_RANGE: (f64, f64) = (0.25, 4.0);
+
+/// Stretch `channels` (all the same length) so their length is multiplied
+/// by `factor`, keeping pitch
+///
+/// Every channel is cut into grains at the same positions, chosen by their
+/// combined similarity, so stereo audio stays in phase.
+pub(crate) fn time_stretch(channels: &[&[f32]], factor: f64, sample_rate: f32) -> Vec<Vec<f32>> {
+    let factor = factor.clamp(STRETCH_RANGE.0, STRETCH_RANGE.1);
+    let input_len = channels.iter().map(|channel| channel.len()).min().unwrap_or(0);
+    let out_len = (input_len as f64 * factor).round() as usize;
+    let grain = (((GRAIN_SECONDS * sample_rate) as usize) & !1).max(16);
+    let hop = grain / 2;
+    let tolerance = hop / 2;
+    if input_len < grain {
+        return channels.iter().map(|channel| channel.to_vec()).collect();
+    }
+
+    let sample = |channel: &[f32], i: usize| channel.get(i).copied().unwrap_or(0.0);
+    let window: Vec<f32> = (0..grain).map(|i| hann(i, grain)).collect();
+    let mut outputs = vec![vec![0.0_f32; out_len + grain]; channels.len()];
+    let mut weight = vec![0.0_f32; out_len + grain];
+    let mut previous: Option<usize> = None;
+
+    let mut out_pos = 0;
+    while out_pos < out_len {
+        let nominal = (out_pos as f64 / factor) as usize;
+
+        // Pick the grain start that best matches the natural continuation
+        // of the previous grain over the overlapping half
+        let start = match previous {
+            None => nominal,
+            Some(previous) => {
+                let target = previous + hop;
+                let lowest = nominal.saturating_sub(tolerance);
+                let highest = (nominal + tolerance).min(input_len.saturating_sub(1));
+                (lowest..=highest)
+                    .map(|candidate| {
+                        let similarity: f32 = channels
+                            .iter()
+                            .map(|&channel| {
+                                (0..hop)
+                                    .step_by(SIMILARITY_STRIDE)
+                                    .map(|i| sample(channel, target + i) * sample(channel, candidate + i))
+                                    .sum::<f32>()
+                            })
+                            .sum();
+                        (candidate, similarity)
+                    })
+                    .fold((nominal, f32::NEG_INFINITY), |best, next| if next.1 > best.1 { next } else { best })
+                    .0
+            }
+        };
+
+        for (i, &w) in window.iter().enumerate() {
+            for (output, &channel) in outputs.iter_mut().zip(channels) {
+                output[out_pos + i] += sample(channel, start + i) * w;
+            }
+            weight[out_pos + i] += w;
+        }
+        previous = Some(start);
+        out_pos += hop;
+    }
+
+    for output in outputs.iter_mut() {
+        output.truncate(out_len);
+        for (sample, &w) in output.iter_mut().zip(&weight) {
+            if w > 1.0e-3 {
+                *sample /= w;
+            }
+        }
+    }
+    outputs
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Conform a loop recorded at `source_bpm` to the session BPM without changing pitch
+    ///
+    /// The stretched audio is stored in `dst_slot` (which may equal
+    /// `sound_index`). Returns false if a slot is invalid, the source is
+    /// empty or the tempo ratio is outside 0.25x to 4x.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn stretch_sound_to_bpm(&mut self, sound_index: usize, source_bpm: f32, dst_slot: usize) -> bool {
+        let factor = source_bpm as f64 / self.bpm as f64;
+        if !(STRETCH_RANGE.0..=STRETCH_RANGE.1).contains(&factor) {
+            return false;
+        }
+        let Some((left, right)) = self.loaded_channels(sound_index) else {
+            return false;
+        };
+        if dst_slot >= self.sounds.len() {
+            self.bad_sound_index(dst_slot);
+            return false;
+        }
+        let channels: Vec<&[f32]> = std::iter::once(left).chain(right).collect();
+        let max_length = self.max_sample_length;
+        let mut stretched = time_stretch(&channels, factor, self.sample_rate).into_iter().map(|mut channel| {
+            channel.truncate(max_length);
+            channel
+        });
+        let left = stretched.next().unwrap_or_default();
+        self.install_derived_sound(sound_index, dst_slot, left, stretched.next());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stretch_keeps_pitch() {
+        let sample_rate = 48000.0;
+        let tone: Vec<f32> = (0..48000).map(|i| (i as f32 * 220.0 * std::f32::consts::TAU / sample_rate).sin()).collect();
+        let crossings = |samples: &[f32]| samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+
+        for factor in [0.8, 1.25] {
+            let stretched = time_stretch(&[&tone], factor, sample_rate).remove(0);
+            assert_eq!(stretched.len(), (48000.0 * factor) as usize);
+            // Same frequency: cycles scale with the new duration
+            let expected = 220.0 * factor;
+            let measured = crossings(&stretched) as f64;
+            assert!((measured - expected).abs() < expected * 0.03, "factor {factor}: {measured} cycles");
+        }
+    }
+
+    #[test]
+    fn test_stretch_keeps_stereo() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.set_bpm(100.0);
+        engine.load_sound_stereo(0, &[0.5; 400], &[-0.5; 400]);
+        assert!(engine.stretch_sound_to_bpm(0, 125.0, 1));
+        let (left, right) = engine.loaded_channels(1).unwrap();
+        assert_eq!(left.len(), 500);
+        assert!(left.iter().zip(right.unwrap()).all(|(left, right)| (left + right).abs() < 1e-6));
+    }
+}
@@ -0,0 +1,371 @@
+//! Built-in sound synthesis
+//!
+//! Renders simple drum sounds and test signals straight into sound slots so
+//! a fresh session has something playable before any files are loaded.
+//! Rendering happens once, outside the audio callback; playback is ordinary
+//! sample playback.
+
+use std::f32::consts::TAU;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::metadata::SoundCategory;
+use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+/// Peak level of rendered sounds
+const SYNTH_PEAK: f32 = 0.9;
+
+/// Amplitude below which an exponential decay counts as finished (-60 dB)
+const DECAY_FLOOR: f32 = 0.001;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum DrumKind {
+    /// Pitch-swept sine kick
+    Kick = 0,
+    /// Tuned body plus noise snare
+    Snare = 1,
+    /// Metallic square-cluster hi-hat
+    Hat = 2,
+    /// Multi-burst filtered-noise clap
+    Clap = 3,
+}
+
+/// Cheap deterministic white noise (xorshift32)
+pub(crate) struct NoiseSource {
+    state: u32,
+}
+
+impl NoiseSource {
+    pub(crate) const fn new(seed: u32) -> Self {
+        Self { state: if seed == 0 { 0x9E37_79B9 } else { seed } }
+    }
+
+    /// Next sample, uniform in -1.0..1.0
+    #[inline]
+    pub(crate) fn next(&mut self) -> f32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+/// Chamberlin state-variable filter (low/band/high outputs)
+struct StateVariableFilter {
+    f: f32,
+    damping: f32,
+    low: f32,
+    band: f32,
+}
+
+impl StateVariableFilter {
+    fn new(cutoff: f32, q: f32, sample_rate: f32) -> Self {
+        let cutoff = cutoff.clamp(20.0, sample_rate / 6.0);
+        Self {
+            f: 2.0 * (std::f32::consts::PI * cutoff / sample_rate).sin(),
+            damping: 1.0 / q.max(0.5),
+            low: 0.0,
+            band: 0.0,
+        }
+    }
+
+    /// Filter one sample, returning `(low, band, high)`
+    #[inline]
+    fn process(&mut self, x: f32) -> (f32, f32, f32) {
+        self.low += self.f * self.band;
+        let high = x - self.low - self.damping * self.band;
+        self.band += self.f * high;
+        (self.low, self.band, high)
+    }
+}
+
+/// Exponential decay envelope reaching -60 dB after `decay` seconds
+#[inline]
+fn decay_env(t: f32, decay: f32) -> f32 {
+    DECAY_FLOOR.powf(t / decay.max(0.001))
+}
+
+/// Scale `samples` so the peak sits at `SYNTH_PEAK`
+fn normalize(samples: &mut [f32]) {
+    let peak = samples.iter().fold(0.0_f32, |max, s| max.max(s.abs()));
+    if peak > 0.0 {
+        for sample in samples.iter_mut() {
+            *sample *= SYNTH_PEAK / peak;
+        }
+    }
+}
+
+/// Render a drum sound
+///
+/// * `pitch` - Tuning multiplier (1.0 = default tuning)
+/// * `decay` - Time (seconds) for the main envelope to fall by 60 dB
+/// * `tone` - Brightness/character (0.0 to 1.0)
+pub(crate) fn render_drum(kind: DrumKind, pitch: f32, decay: f32, tone: f32, sample_rate: f32) -> Vec<f32> {
+    let pitch = pitch.clamp(0.25, 4.0);
+    let decay = decay.clamp(0.01, 4.0);
+    let tone = tone.clamp(0.0, 1.0);
+    let length = ((decay + 0.01) * sample_rate) as usize;
+    let mut noise = NoiseSource::new(kind as u32 + 1);
+    let mut phase = 0.0_f32;
+
+    let mut samples: Vec<f32> = match kind {
+        DrumKind::Kick => {
+            let (start_hz, end_hz) = (160.0 * pitch, 48.0 * pitch);
+            (0..length)
+                .map(|i| {
+                    let t = i as f32 / sample_rate;
+                    let freq = end_hz + (start_hz - end_hz) * (-t / 0.035).exp();
+                    phase = (phase + freq / sample_rate).fract();
+                    // Tone drives the body into soft saturation and adds a click
+                    let body = ((phase * TAU).sin() * (1.0 + tone * 4.0)).tanh();
+                    let click = noise.next() * tone * decay_env(t, 0.004);
+                    (body + click) * decay_env(t, decay)
+                })
+                .collect()
+        }
+        DrumKind::Snare => {
+            let body_hz = 185.0 * pitch;
+            let mut filter = StateVariableFilter::new(1500.0 + tone * 6000.0, 0.7, sample_rate);
+            (0..length)
+                .map(|i| {
+                    let t = i as f32 / sample_rate;
+                    phase = (phase + body_hz / sample_rate).fract();
+                    let body = (phase * TAU).sin() * decay_env(t, decay * 0.4);
+                    let (_, _, snap) = filter.process(noise.next());
+                    body * (1.0 - tone * 0.5) + snap * decay_env(t, decay)
+                })
+                .collect()
+        }
+        DrumKind::Hat => {
+            // Classic 808 cymbal oscillator ratios
+            const RATIOS_HZ: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540.0, 800.0];
+            let mut phases = [0.0_f32; 6];
+            let mut filter = StateVariableFilter::new(7000.0 * pitch, 1.0, sample_rate);
+            (0..length)
+                .map(|i| {
+                    let t = i as f32 / sample_rate;
+                    let mut metal = 0.0;
+                    for (phase, &hz) in phases.iter_mut().zip(&RATIOS_HZ) {
+                        *phase = (*phase + hz * pitch / sample_rate).fract();
+                        metal += if *phase < 0.5 { 1.0 } else { -1.0 };
+                    }
+                    let source = metal / 6.0 * (1.0 - tone) + noise.next() * tone;
+                    let (_, _, high) = filter.process(source);
+                    high * decay_env(t, decay)
+                })
+                .collect()
+        }
+        DrumKind::Clap => {
+            const BURST_SPACING: f32 = 0.011;
+            let mut filter = StateVariableFilter::new((900.0 + tone * 1800.0) * pitch, 2.0, sample_rate);
+            (0..length)
+                .map(|i| {
+                    let t = i as f32 / sample_rate;
+                    // Three quick bursts followed by a longer tail
+                    let burst_t = t % BURST_SPACING;
+                    let bursts = if t < BURST_SPACING * 3.0 { decay_env(burst_t, 0.009) } else { 0.0 };
+                    let tail = if t >= BURST_SPACING * 2.0 { decay_env(t - BURST_SPACING * 2.0, decay) } else { 0.0 };
+                    let (_, band, _) = filter.process(noise.next());
+                    band * bursts.max(tail)
+                })
+                .collect()
+        }
+    };
+
+    normalize(&mut samples);
+    samples
+}
+
+// ============================================================================
+// TEST SIGNALS - Calibration tones and noise
+// ============================================================================
+
+/// Peak level of test signals (-6 dBFS)
+const TEST_SIGNAL_LEVEL: f32 = 0.5;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum NoiseColor {
+    /// Flat spectrum
+    White = 0,
+    /// -3 dB/octave
+    Pink = 1,
+    /// -6 dB/octave
+    Brown = 2,
+}
+
+/// Render `length` samples of colored noise peaking at `TEST_SIGNAL_LEVEL`
+pub(crate) fn render_noise(color: NoiseColor, length: usize) -> Vec<f32> {
+    let mut noise = NoiseSource::new(0x5EED);
+    let mut samples: Vec<f32> = match color {
+        NoiseColor::White => (0..length).map(|_| noise.next()).collect(),
+        NoiseColor::Pink => {
+            // Paul Kellet's economy pink filter
+            let (mut b0, mut b1, mut b2) = (0.0_f32, 0.0_f32, 0.0_f32);
+            (0..length)
+                .map(|_| {
+                    let white = noise.next();
+                    b0 = 0.99765 * b0 + white * 0.0990460;
+                    b1 = 0.96300 * b1 + white * 0.2965164;
+                    b2 = 0.57000 * b2 + white * 1.0526913;
+                    b0 + b1 + b2 + white * 0.1848
+                })
+                .collect()
+        }
+        NoiseColor::Brown => {
+            // Leaky integrator keeps the random walk from drifting off
+            let mut level = 0.0_f32;
+            (0..length)
+                .map(|_| {
+                    level = level * 0.998 + noise.next() * 0.05;
+                    level
+                })
+                .collect()
+        }
+    };
+    normalize(&mut samples);
+    for sample in samples.iter_mut() {
+        *sample *= TEST_SIGNAL_LEVEL / SYNTH_PEAK;
+    }
+    samples
+}
+
+/// Factory kit layout: key code, name, overlap mode and group for slots 0-4
+const FACTORY_KIT: [(u8, &str, OverlapMode, u8); 5] = [
+    (b'A', "Kick", OverlapMode::Polyphonic, 0),
+    (b'S', "Snare", OverlapMode::Polyphonic, 0),
+    (b'D', "Closed Hat", OverlapMode::Monophonic, 1),
+    (b'F', "Clap", OverlapMode::Polyphonic, 0),
+    (b'G', "Open Hat", OverlapMode::Monophonic, 1),
+];
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Synthesize a drum sound into a sound slot
+    ///
+    /// # Arguments
+    /// * `sound_index` - Slot to render into
+    /// * `kind` - Which drum to render
+    /// * `pitch` - Tuning multiplier (0.25 to 4.0, 1.0 = default)
+    /// * `decay` - Decay time in seconds (0.01 to 4.0)
+    /// * `tone` - Brightness/character (0.0 to 1.0)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn generate_drum(&mut self, sound_index: usize, kind: DrumKind, pitch: f32, decay: f32, tone: f32) {
+        let samples = render_drum(kind, pitch, decay, tone, self.sample_rate);
+        self.store_sound(sound_index, &samples, self.sample_rate);
+    }
+
+    /// Fill slots `first_sound..first_sound + 4` with a default kick, snare, hat and clap
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn generate_default_kit(&mut self, first_sound: usize) {
+        self.generate_drum(first_sound, DrumKind::Kick, 1.0, 0.5, 0.3);
+        self.generate_drum(first_sound + 1, DrumKind::Snare, 1.0, 0.25, 0.5);
+        self.generate_drum(first_sound + 2, DrumKind::Hat, 1.0, 0.08, 0.3);
+        self.generate_drum(first_sound + 3, DrumKind::Clap, 1.0, 0.3, 0.5);
+    }
+
+    /// Load the built-in demo kit: synthesized drums in slots 0-4, mapped to
+    /// the home row keys A S D F G
+    ///
+    /// The closed and open hats share a monophonic group so one chokes the
+    /// other. Existing sounds in those slots and mappings on those keys are
+    /// replaced. Does nothing if the engine has fewer than 5 sound slots.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn load_factory_kit(&mut self) {
+        if self.sounds.len() < FACTORY_KIT.len() {
+            return;
+        }
+        self.generate_default_kit(0);
+        self.generate_drum(4, DrumKind::Hat, 1.0, 0.45, 0.4);
+
+        for (sound_index, &(key_code, name, overlap_mode, group_id)) in FACTORY_KIT.iter().enumerate() {
+            self.set_sound_name(sound_index, name);
+            self.set_sound_category(sound_index, SoundCategory::Drum);
+            self.set_key_mapping(key_code, sound_index, PlaybackMode::SingleShot, overlap_mode, group_id, 1.0, 0, false);
+        }
+    }
+
+    /// Render a sine test tone at -6 dBFS into a sound slot
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn generate_test_tone(&mut self, sound_index: usize, frequency: f32, seconds: f32) {
+        let frequency = frequency.clamp(1.0, self.sample_rate / 2.0);
+        let length = self.test_signal_length(seconds);
+        let samples: Vec<f32> = (0..length)
+            .map(|i| (i as f32 * frequency / self.sample_rate * TAU).sin() * TEST_SIGNAL_LEVEL)
+            .collect();
+        self.store_sound(sound_index, &samples, self.sample_rate);
+    }
+
+    /// Render noise peaking at -6 dBFS into a sound slot
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn generate_noise(&mut self, sound_index: usize, color: NoiseColor, seconds: f32) {
+        let samples = render_noise(color, self.test_signal_length(seconds));
+        self.store_sound(sound_index, &samples, self.sample_rate);
+    }
+}
+
+impl DspEngine {
+    /// Length in samples of a generated signal, limited to the maximum sound length
+    fn test_signal_length(&self, seconds: f32) -> usize {
+        ((seconds.max(0.0) * self.sample_rate) as usize).min(self.max_sample_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_drums() {
+        for kind in [DrumKind::Kick, DrumKind::Snare, DrumKind::Hat, DrumKind::Clap] {
+            let samples = render_drum(kind, 1.0, 0.2, 0.5, 48000.0);
+            assert_eq!(samples.len(), (0.21 * 48000.0) as usize);
+            let peak = samples.iter().fold(0.0_f32, |max, s| max.max(s.abs()));
+            assert!((peak - SYNTH_PEAK).abs() < 1e-4, "{kind:?} peak {peak}");
+            assert!(samples.iter().all(|s| s.is_finite()));
+            // Decays to near silence
+            assert!(samples[samples.len() - 100..].iter().all(|s| s.abs() < 0.05), "{kind:?} tail");
+        }
+    }
+
+    #[test]
+    fn test_test_signals() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.generate_test_tone(0, 1000.0, 0.5);
+        assert_eq!(engine.get_sound_length_samples(0), 24000);
+        assert!((engine.get_sound_peak(0) - TEST_SIGNAL_LEVEL).abs() < 1e-3);
+
+        for color in [NoiseColor::White, NoiseColor::Pink, NoiseColor::Brown] {
+            let samples = render_noise(color, 48000);
+            let peak = samples.iter().fold(0.0_f32, |max, s| max.max(s.abs()));
+            assert!((peak - TEST_SIGNAL_LEVEL).abs() < 1e-4, "{color:?}");
+        }
+        // Redder noise concentrates energy lower in the spectrum
+        let centroid = |color| crate::analysis::measure_spectral_centroid(&render_noise(color, 48000), 48000.0);
+        assert!(centroid(NoiseColor::White) > centroid(NoiseColor::Pink));
+        assert!(centroid(NoiseColor::Pink) > centroid(NoiseColor::Brown));
+    }
+
+    #[test]
+    fn test_factory_kit_is_playable() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_factory_kit();
+        assert_eq!(engine.get_sound_name(4), "Open Hat");
+
+        engine.note_on(b'A');
+        let mut output = [0.0; 2 * 256];
+        engine.process(&mut output);
+        assert!(output.iter().any(|s| s.abs() > 0.1));
+
+        // Hats choke each other
+        engine.note_on(b'G');
+        engine.note_on(b'D');
+        assert!(!engine.is_key_playing(b'G'));
+    }
+}
@@ -0,0 +1,280 @@
+//! Engine telemetry
+//!
+//! Lightweight counters the host can poll to see how close the engine is
+//! to its real-time limits. Updating them never allocates.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::events::EngineEventKind;
+use crate::DspEngine;
+
+// ============================================================================
+// MEMORY STATS - Footprint of the engine and its sound bank
+// ============================================================================
+
+/// Snapshot of engine memory usage, returned by `get_memory_stats`
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy)]
+pub struct MemoryStats {
+    /// Bytes holding audio of loaded sounds
+    pub sound_bytes: u32,
+    /// Bytes reserved for sound buffers (loaded or not)
+    pub reserved_sound_bytes: u32,
+    /// Sound slots that are not loaded
+    pub free_sound_slots: u32,
+    /// Total engine footprint: engine state plus all heap buffers
+    pub total_bytes: u32,
+}
+
+// ============================================================================
+// CPU METER - Process time relative to the block's real-time budget
+// ============================================================================
+
+/// Smoothing factor for the running load average (per block)
+const LOAD_AVERAGE_COEFF: f64 = 0.05;
+
+/// Tracks how long `process()` takes compared to the audio it produces.
+///
+/// The engine has no clock of its own inside the worklet, so the host
+/// supplies timestamps around each `process()` call.
+pub(crate) struct CpuMeter {
+    /// Timestamp passed to `begin_block_timing` (ms), if a block is open
+    block_start_ms: Option<f64>,
+    /// Frames rendered by the most recent `process()` call
+    last_block_frames: usize,
+    /// Wall time of the last measured block (ms)
+    last_process_ms: f64,
+    /// Exponential moving average of the load (1.0 = whole budget used)
+    average_load: f64,
+    /// Highest load seen since reset
+    max_load: f64,
+}
+
+impl CpuMeter {
+    pub(crate) const fn new() -> Self {
+        Self {
+            block_start_ms: None,
+            last_block_frames: 0,
+            last_process_ms: 0.0,
+            average_load: 0.0,
+            max_load: 0.0,
+        }
+    }
+
+    /// Record the size of the block just rendered
+    #[inline(always)]
+    pub(crate) fn record_block(&mut self, frames: usize) {
+        self.last_block_frames = frames;
+    }
+
+    /// Close the open measurement, returning the block's load if one was taken
+    fn finish(&mut self, now_ms: f64, sample_rate: f32) -> Option<f64> {
+        let start = self.block_start_ms.take()?;
+        if self.last_block_frames == 0 || sample_rate <= 0.0 {
+            return None;
+        }
+
+        let elapsed = (now_ms - start).max(0.0);
+        let budget_ms = self.last_block_frames as f64 * 1000.0 / sample_rate as f64;
+        let load = elapsed / budget_ms;
+
+        self.last_process_ms = elapsed;
+        self.average_load += (load - self.average_load) * LOAD_AVERAGE_COEFF;
+        self.max_load = self.max_load.max(load);
+        Some(load)
+    }
+}
+
+// ============================================================================
+// HEALTH COUNTERS - Conditions that are likely to be audible
+// ============================================================================
+
+pub(crate) struct HealthCounters {
+    /// Blocks whose processing exceeded the real-time budget
+    pub(crate) overloaded_blocks: u32,
+    /// note_on calls that found no free voice
+    pub(crate) voice_pool_exhausted: u32,
+}
+
+impl HealthCounters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            overloaded_blocks: 0,
+            voice_pool_exhausted: 0,
+        }
+    }
+}
+
+// ============================================================================
+// VOICE STATS - Polyphony usage since the last reset
+// ============================================================================
+
+pub(crate) struct VoiceStats {
+    /// Most voices active at once
+    pub(crate) peak_voices: u32,
+    /// Voices taken over because the pool was full
+    pub(crate) steals: u32,
+    /// note_on count per key code
+    pub(crate) key_triggers: [u32; 256],
+}
+
+impl VoiceStats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            peak_voices: 0,
+            steals: 0,
+            key_triggers: [0; 256],
+        }
+    }
+}
+
+// ============================================================================
+// ENGINE API
+// ============================================================================
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Mark the start of a `process()` call for load measurement
+    ///
+    /// # Arguments
+    /// * `now_ms` - High-resolution timestamp in milliseconds
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn begin_block_timing(&mut self, now_ms: f64) {
+        self.cpu_meter.block_start_ms = Some(now_ms);
+    }
+
+    /// Mark the end of a `process()` call and update the load statistics
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn end_block_timing(&mut self, now_ms: f64) {
+        let Some(load) = self.cpu_meter.finish(now_ms, self.sample_rate) else {
+            return;
+        };
+        if load > 1.0 {
+            self.health.overloaded_blocks = self.health.overloaded_blocks.saturating_add(1);
+            let permille = (load * 1000.0).min(u32::MAX as f64) as u32;
+            self.events.push(EngineEventKind::Overload, 0, permille, self.global_sample_position);
+        }
+    }
+
+    /// Average process load (1.0 = the full real-time budget of a block)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_cpu_load_average(&self) -> f32 {
+        self.cpu_meter.average_load as f32
+    }
+
+    /// Highest process load seen since the last reset
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_cpu_load_max(&self) -> f32 {
+        self.cpu_meter.max_load as f32
+    }
+
+    /// Wall time of the most recently measured block in milliseconds
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_last_process_time_ms(&self) -> f64 {
+        self.cpu_meter.last_process_ms
+    }
+
+    /// Reset the average and maximum load
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn reset_cpu_load(&mut self) {
+        self.cpu_meter.average_load = 0.0;
+        self.cpu_meter.max_load = 0.0;
+    }
+
+    /// Number of blocks that exceeded their real-time budget
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_overload_count(&self) -> u32 {
+        self.health.overloaded_blocks
+    }
+
+    /// Number of note_on calls that found the voice pool exhausted
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_voice_exhausted_count(&self) -> u32 {
+        self.health.voice_pool_exhausted
+    }
+
+    /// Most voices that have been active simultaneously since reset
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_peak_voice_count(&self) -> u32 {
+        self.voice_stats.peak_voices
+    }
+
+    /// Number of voices stolen since reset
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_voice_steal_count(&self) -> u32 {
+        self.voice_stats.steals
+    }
+
+    /// Number of times a key has triggered a voice since reset
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_trigger_count(&self, key_code: u8) -> u32 {
+        self.voice_stats.key_triggers[key_code as usize]
+    }
+
+    /// Reset peak voices, steal count and per-key trigger counts
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn reset_voice_stats(&mut self) {
+        self.voice_stats = VoiceStats::new();
+    }
+
+    /// Report memory used by loaded sounds and the engine as a whole
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_memory_stats(&self) -> MemoryStats {
+        let mut sound_bytes = 0;
+        let mut reserved_sound_bytes = 0;
+        let mut free_sound_slots = 0;
+        for sound in self.sounds.iter() {
+            reserved_sound_bytes += sound.heap_bytes();
+            if sound.loaded {
+                sound_bytes += sound.length * std::mem::size_of::<f32>();
+            } else {
+                free_sound_slots += 1;
+            }
+        }
+        let upload_bytes = self.upload.as_ref().map_or(0, |upload| upload.heap_bytes());
+        let total_bytes = std::mem::size_of::<Self>()
+            + std::mem::size_of_val(&*self.sounds)
+            + std::mem::size_of_val(&*self.voices)
+            + reserved_sound_bytes
+            + upload_bytes
+            + self.recorder.heap_bytes()
+            + self.performance.heap_bytes()
+            + self.looper.heap_bytes()
+            + self.swap_heap_bytes()
+            + self.history.heap_bytes();
+
+        MemoryStats {
+            sound_bytes: sound_bytes as u32,
+            reserved_sound_bytes: reserved_sound_bytes as u32,
+            free_sound_slots,
+            total_bytes: total_bytes as u32,
+        }
+    }
+
+    /// Reset the overload and voice-exhaustion counters
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn reset_health_counters(&mut self) {
+        self.health = HealthCounters::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_meter_load() {
+        let mut meter = CpuMeter::new();
+        meter.block_start_ms = Some(10.0);
+        meter.record_block(128);
+
+        // 128 frames at 48 kHz is a 2.667 ms budget
+        let load = meter.finish(11.0, 48000.0).unwrap();
+        assert!((load - 0.375).abs() < 1e-9);
+        assert_eq!(meter.max_load, load);
+
+        // Without a begin timestamp nothing is measured
+        assert!(meter.finish(20.0, 48000.0).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+//! Timestamped key input
+//!
+//! Key events handled between blocks all start at the beginning of the
+//! next block, so their timing is quantized to the block length (about
+//! 3 ms at 128 frames). Events passed with the time they happened instead
+//! keep their spacing: each is played a fixed delay after its timestamp,
+//! on the exact sample. The delay must cover the time an event can wait
+//! before reaching the engine; one block is enough when input is handled
+//! on the audio thread.
+//!
+//! Times are in seconds on the engine clock (`get_engine_time`).
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::DspEngine;
+
+/// Most events waiting at once; further events play immediately
+const MAX_TIMED_EVENTS: usize = 256;
+
+/// A key event waiting for its sample
+#[derive(Clone, Copy)]
+struct TimedEvent {
+    at: u64,
+    key_code: u8,
+    on: bool,
+}
+
+pub(crate) struct TimedInput {
+    /// Sorted by sample; events on the same sample keep their order
+    events: Vec<TimedEvent>,
+    /// Samples between an event's timestamp and when it plays
+    delay: u64,
+}
+
+impl TimedInput {
+    pub(crate) fn new() -> Self {
+        Self { events: Vec::with_capacity(MAX_TIMED_EVENTS), delay: 128 }
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Whether an event plays at sample `now`
+    #[inline]
+    pub(crate) fn is_due(&self, now: u64) -> bool {
+        self.events.first().is_some_and(|event| event.at <= now)
+    }
+
+    /// Drop every waiting event
+    pub(crate) fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+impl DspEngine {
+    /// Play the timed events due at the current sample (called once per
+    /// frame from `process()` while any are waiting)
+    #[inline]
+    pub(crate) fn fire_timed_events(&mut self) {
+        while let Some(&event) = self.timed_input.events.first() {
+            if event.at > self.global_sample_position {
+                break;
+            }
+            self.timed_input.events.remove(0);
+            if event.on {
+                self.note_on(event.key_code);
+            } else {
+                self.note_off(event.key_code);
+            }
+        }
+    }
+
+    fn schedule_key_event(&mut self, key_code: u8, on: bool, time: f64) {
+        let stamped = if time.is_finite() { (time.max(0.0) * self.sample_rate as f64) as u64 } else { 0 };
+        let at = stamped.saturating_add(self.timed_input.delay);
+        let events = &mut self.timed_input.events;
+        if at <= self.global_sample_position || events.len() >= MAX_TIMED_EVENTS {
+            if on {
+                self.note_on(key_code);
+            } else {
+                self.note_off(key_code);
+            }
+            return;
+        }
+        let index = events.partition_point(|event| event.at <= at);
+        events.insert(index, TimedEvent { at, key_code, on });
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Current time on the engine clock in seconds (samples processed so
+    /// far over the sample rate)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_engine_time(&self) -> f64 {
+        self.global_sample_position as f64 / self.sample_rate as f64
+    }
+
+    /// Key down that happened at `time` (engine clock seconds)
+    ///
+    /// The key plays the timestamp delay after `time`, or at once if that
+    /// moment has already passed.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn note_on_timestamped(&mut self, key_code: u8, time: f64) {
+        self.schedule_key_event(key_code, true, time);
+    }
+
+    /// Key up that happened at `time` (engine clock seconds)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn note_off_timestamped(&mut self, key_code: u8, time: f64) {
+        self.schedule_key_event(key_code, false, time);
+    }
+
+    /// Set how many samples after its timestamp a timed event plays
+    /// (default 128, one Web Audio render quantum)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_timestamp_delay(&mut self, samples: u32) {
+        self.timed_input.delay = samples as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_timestamped_notes_keep_their_spacing() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.load_sound(0, &[0.5; 4000]);
+        engine.set_key_mapping(65, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_key_mapping(66, 0, PlaybackMode::SingleShot, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        engine.set_timestamp_delay(100);
+        engine.process(&mut [0.0; 2 * 100]);
+
+        // Both happened during the last block, 30 ms apart
+        engine.note_on_timestamped(65, 0.040);
+        engine.note_on_timestamped(66, 0.070);
+        engine.process(&mut [0.0; 2 * 100]);
+        // Started at samples 140 and 170, so 60 and 30 samples played
+        let positions = (engine.voices[0].position, engine.voices[1].position);
+        assert_eq!(positions, (60.0, 30.0));
+
+        // Too late to honour: plays at once
+        engine.note_on_timestamped(65, 0.0);
+        assert_eq!(engine.get_active_voice_count(), 3);
+    }
+}
@@ -0,0 +1,166 @@
+//! Extended trigger IDs
+//!
+//! Triggers are identified by a u16. IDs 0-255 are the keyboard key codes
+//! and use the key mappings (and their banks); the `note_on`/`note_off`
+//! key code API is a shim over these IDs. IDs from 256 up address a
+//! registry of extra mappings, so several input devices can each get their
+//! own range of triggers without colliding on the 256 key codes.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::envelope::Envelope;
+use crate::errors::ErrorCode;
+use crate::history::ConfigChange;
+use crate::performance::Performed;
+use crate::release::DEFAULT_RELEASE_VELOCITY;
+use crate::velocity::VelocityResponse;
+use crate::{DspEngine, KeyMapping, OverlapMode, PlaybackMode, VoiceSource};
+
+/// Most extended triggers mapped at once
+const MAX_EXTENDED_TRIGGERS: usize = 4096;
+
+impl DspEngine {
+    /// Mapping of a trigger, if it has one
+    pub(crate) fn trigger_mapping(&self, trigger: u16) -> Option<&KeyMapping> {
+        match u8::try_from(trigger) {
+            Ok(key_code) => Some(&self.key_mappings[key_code as usize]),
+            Err(_) => {
+                let index = self.trigger_mappings.binary_search_by_key(&trigger, |&(id, _)| id).ok()?;
+                Some(&self.trigger_mappings[index].1)
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Map a trigger ID to a sound with settings
+    ///
+    /// IDs below 256 are key codes and behave exactly like
+    /// `set_key_mapping`. Returns false if the registry of extended
+    /// triggers (4096) is full.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_trigger_mapping(
+        &mut self,
+        trigger: u16,
+        sound_index: usize,
+        mode: PlaybackMode,
+        overlap_mode: OverlapMode,
+        group_id: u8,
+        volume: f32,
+        pitch_semitones: i8,
+        modulation_enabled: bool,
+    ) -> bool {
+        if let Ok(key_code) = u8::try_from(trigger) {
+            self.set_key_mapping(key_code, sound_index, mode, overlap_mode, group_id, volume, pitch_semitones, modulation_enabled);
+            return true;
+        }
+        if sound_index >= self.sounds.len() {
+            self.bad_sound_index(sound_index);
+        }
+        let mapping = KeyMapping {
+            has_sound: sound_index < self.sounds.len() && self.sounds[sound_index].loaded,
+            sound_index,
+            mode,
+            overlap_mode,
+            group_id,
+            volume: volume.clamp(0.0, 1.0),
+            pitch_semitones: pitch_semitones.clamp(-24, 24),
+            modulation_enabled,
+            slice: None,
+            velocity: VelocityResponse::LINEAR,
+            aftertouch_depth: 0.0,
+            release_ms: 0.0,
+            envelope: Envelope::NONE,
+        };
+        match self.trigger_mappings.binary_search_by_key(&trigger, |&(id, _)| id) {
+            Ok(index) => self.trigger_mappings[index].1 = mapping,
+            Err(_) if self.trigger_mappings.len() >= MAX_EXTENDED_TRIGGERS => return false,
+            Err(index) => self.trigger_mappings.insert(index, (trigger, mapping)),
+        }
+        true
+    }
+
+    /// Remove a trigger's mapping (a key code's is reset to unmapped)
+    ///
+    /// Returns false if the trigger was not mapped.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn clear_trigger_mapping(&mut self, trigger: u16) -> bool {
+        if let Ok(key_code) = u8::try_from(trigger) {
+            let mapped = self.key_mappings[key_code as usize].has_sound;
+            self.record_change(ConfigChange::KeyMapping(key_code));
+            self.key_mappings[key_code as usize] = KeyMapping::new();
+            return mapped;
+        }
+        let count = self.trigger_mappings.len();
+        self.trigger_mappings.retain(|&(id, _)| id != trigger);
+        self.trigger_mappings.len() != count
+    }
+
+    /// Number of mapped extended triggers (IDs 256 and up)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_extended_trigger_count(&self) -> usize {
+        self.trigger_mappings.len()
+    }
+
+    /// Trigger a sound by trigger ID (key down)
+    ///
+    /// If every voice is busy, the oldest voice is stolen.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn trigger_on(&mut self, trigger: u16) {
+        self.record_performance(Performed::TriggerOn(trigger));
+        // The note plays with the settings made before it
+        self.apply_commands();
+        let Some(&mapping) = self.trigger_mapping(trigger).filter(|mapping| mapping.has_sound) else {
+            self.report_error(ErrorCode::KeyNotMapped, trigger as u32);
+            return;
+        };
+        self.trigger_voice(&mapping, trigger, VoiceSource::Key);
+        if let Ok(key_code) = u8::try_from(trigger) {
+            self.record_step(key_code);
+        }
+    }
+
+    /// Release a trigger (key up)
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn trigger_off(&mut self, trigger: u16) {
+        // For SingleShot mode, sound continues playing after key release
+        // For Loop mode, sound stops (or starts its release) on key release
+        self.record_performance(Performed::TriggerOff(trigger));
+        self.release_voices(VoiceSource::Key, trigger, DEFAULT_RELEASE_VELOCITY);
+    }
+
+    /// Whether a trigger has an active voice
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn is_trigger_playing(&self, trigger: u16) -> bool {
+        self.voices.iter().any(|v| v.active && v.is_trigger(trigger))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DspEngine, OverlapMode, PlaybackMode};
+
+    #[test]
+    fn test_extended_triggers_do_not_collide_with_keys() {
+        let mut engine = DspEngine::new(48000.0);
+        engine.load_sound(0, &[0.5; 4800]);
+        engine.load_sound(1, &[0.25; 4800]);
+        engine.set_key_mapping(65, 0, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false);
+        // Same low byte as key 65
+        assert!(engine.set_trigger_mapping(256 + 65, 1, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false));
+        assert!(engine.set_trigger_mapping(7, 1, PlaybackMode::Loop, OverlapMode::Polyphonic, 0, 1.0, 0, false));
+        assert!(engine.key_mappings[7].has_sound, "IDs below 256 are key codes");
+
+        engine.trigger_on(256 + 65);
+        engine.note_on(65);
+        assert_eq!(engine.voices[0].sound_index, 1);
+        engine.note_off(65);
+        assert!(engine.is_trigger_playing(256 + 65) && !engine.is_key_playing(65));
+
+        assert!(engine.clear_trigger_mapping(256 + 65));
+        assert_eq!(engine.get_extended_trigger_count(), 0);
+    }
+}
@@ -0,0 +1,174 @@
+//! Chunked and direct sound upload
+//!
+//! Backing tracks and stems can run to minutes of audio. Passing one huge
+//! array across the JS boundary doubles peak memory on both sides, so long
+//! sounds can instead be streamed in pieces, or written straight into an
+//! engine-owned buffer, and committed once complete.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::config::SAMPLE_SECONDS_LIMIT;
+use crate::resample::is_supported_rate;
+use crate::DspEngine;
+
+/// A sound being assembled from chunks
+pub(crate) struct SoundUpload {
+    sound_index: usize,
+    source_rate: f32,
+    samples: Vec<f32>,
+    /// Most samples the upload may reach
+    max_samples: usize,
+}
+
+impl SoundUpload {
+    /// Heap bytes held by the staging buffer
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.samples.capacity() * std::mem::size_of::<f32>()
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Change the longest sound (seconds) accepted by later loads
+    ///
+    /// Sounds already loaded keep their length.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_max_sample_seconds(&mut self, seconds: f32) {
+        if seconds.is_nan() || seconds <= 0.0 {
+            return;
+        }
+        self.max_sample_length = (seconds.min(SAMPLE_SECONDS_LIMIT) * self.sample_rate) as usize;
+    }
+
+    /// Longest sound (seconds) that loads will currently accept
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_max_sample_seconds(&self) -> f32 {
+        self.max_sample_length as f32 / self.sample_rate
+    }
+
+    /// Start uploading a sound in chunks
+    ///
+    /// `total_samples` is a capacity hint (0 if unknown). Any upload that was
+    /// not finished is discarded.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn begin_sound_upload(&mut self, sound_index: usize, total_samples: usize, source_rate: f32) {
+        if sound_index >= self.sounds.len() {
+            self.bad_sound_index(sound_index);
+            self.upload = None;
+            return;
+        }
+        let max_samples = self.max_upload_samples(source_rate);
+        self.upload = Some(SoundUpload {
+            sound_index,
+            source_rate,
+            samples: Vec::with_capacity(total_samples.min(max_samples)),
+            max_samples,
+        });
+    }
+
+    /// Reserve `len` samples inside the engine for JS to write into directly
+    ///
+    /// Returns the address of the buffer in WASM memory (null if the slot is
+    /// invalid or `len` exceeds the maximum sound length). JS must create
+    /// its `Float32Array` view *after* this call, since the allocation may
+    /// grow memory, fill it with mono samples at the engine rate, then call
+    /// `finish_sound_upload`. Any other upload in progress is discarded.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_sound_write_ptr(&mut self, sound_index: usize, len: usize) -> *mut f32 {
+        self.upload = None;
+        if sound_index >= self.sounds.len() {
+            self.bad_sound_index(sound_index);
+            return std::ptr::null_mut();
+        }
+        if len > self.max_upload_samples(self.sample_rate) {
+            return std::ptr::null_mut();
+        }
+        let upload = self.upload.insert(SoundUpload {
+            sound_index,
+            source_rate: self.sample_rate,
+            samples: vec![0.0; len],
+            max_samples: len,
+        });
+        upload.samples.as_mut_ptr()
+    }
+
+    /// Append mono samples to the upload started by `begin_sound_upload`
+    ///
+    /// Returns false if no upload is in progress, or if the chunk would take
+    /// the upload past the maximum sound length; the chunk is then dropped
+    /// and the samples appended so far can still be finished.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn append_sound_chunk(&mut self, samples: &[f32]) -> bool {
+        match self.upload.as_mut() {
+            Some(upload) if upload.samples.len() + samples.len() <= upload.max_samples => {
+                upload.samples.extend_from_slice(samples);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Finish the upload and store the sound (trim, resample, analysis as usual)
+    ///
+    /// Returns false if no upload is in progress.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn finish_sound_upload(&mut self) -> bool {
+        let Some(upload) = self.upload.take() else {
+            return false;
+        };
+        self.store_sound(upload.sound_index, &upload.samples, upload.source_rate);
+        true
+    }
+}
+
+impl DspEngine {
+    /// Most samples at `source_rate` that still fit the maximum sound length
+    ///
+    /// A rate loads will reject counts as the engine rate.
+    fn max_upload_samples(&self, source_rate: f32) -> usize {
+        let rate = if is_supported_rate(source_rate) { source_rate.max(self.sample_rate) } else { self.sample_rate };
+        (self.max_sample_length as f64 * rate as f64 / self.sample_rate as f64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DspEngine;
+
+    #[test]
+    fn test_chunked_upload_beyond_default_limit() {
+        let mut engine = DspEngine::new(1000.0);
+        engine.set_max_sample_seconds(30.0);
+        assert_eq!(engine.get_max_sample_seconds(), 30.0);
+
+        engine.begin_sound_upload(0, 20_000, 1000.0);
+        for _ in 0..20 {
+            assert!(engine.append_sound_chunk(&[0.5; 1000]));
+        }
+        assert!(engine.finish_sound_upload());
+        assert_eq!(engine.get_sound_length_samples(0), 20_000);
+        assert!(!engine.append_sound_chunk(&[0.5; 10]));
+
+        // Chunks past the maximum length are refused
+        engine.begin_sound_upload(1, 0, 1000.0);
+        assert!(engine.append_sound_chunk(&[0.5; 29_000]));
+        assert!(!engine.append_sound_chunk(&[0.5; 1001]));
+        assert!(engine.append_sound_chunk(&[0.5; 1000]));
+        assert!(engine.finish_sound_upload());
+        assert_eq!(engine.get_sound_length_samples(1), 30_000);
+    }
+
+    #[test]
+    fn test_direct_write_upload() {
+        let mut engine = DspEngine::new(1000.0);
+        let ptr = engine.get_sound_write_ptr(2, 4);
+        assert!(!ptr.is_null());
+        // Stand-in for JS writing through a Float32Array view
+        unsafe { std::slice::from_raw_parts_mut(ptr, 4) }.copy_from_slice(&[0.1, 0.2, 0.3, 0.4]);
+        assert!(engine.finish_sound_upload());
+        assert_eq!(engine.loaded_samples(2), Some(&[0.1, 0.2, 0.3, 0.4][..]));
+
+        assert!(engine.get_sound_write_ptr(2, 20_000).is_null(), "longer than 10 s");
+    }
+}
@@ -0,0 +1,99 @@
+//! Per-key velocity response
+//!
+//! Each key mapping shapes incoming note velocity before it scales the
+//! voice volume, so a pad that is hard to hit evenly can be flattened or
+//! made fixed without changing how the other pads respond. Velocity only
+//! arrives with MIDI notes; the computer keyboard always plays at full
+//! velocity.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::history::ConfigChange;
+use crate::DspEngine;
+
+/// How velocity maps to volume
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum VelocityCurve {
+    /// Every hit plays at full volume
+    Fixed = 0,
+    /// Volume follows velocity
+    Linear = 1,
+    /// Light hits come out louder (square root)
+    Soft = 2,
+    /// Only firm hits reach full volume (squared)
+    Hard = 3,
+    /// Exponent set by an amount from -1.0 (softest) to 1.0 (hardest)
+    Custom = 4,
+}
+
+/// Velocity curve of a key mapping
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct VelocityResponse {
+    pub(crate) curve: VelocityCurve,
+    /// Custom curve amount (-1.0 to 1.0, 0.0 = linear)
+    pub(crate) amount: f32,
+}
+
+impl VelocityResponse {
+    pub(crate) const LINEAR: Self = Self { curve: VelocityCurve::Linear, amount: 0.0 };
+
+    /// Volume factor for a note velocity (1-127)
+    #[inline]
+    pub(crate) fn gain(&self, velocity: u8) -> f32 {
+        let x = velocity.min(127) as f32 / 127.0;
+        match self.curve {
+            VelocityCurve::Fixed => 1.0,
+            VelocityCurve::Linear => x,
+            VelocityCurve::Soft => x.sqrt(),
+            VelocityCurve::Hard => x * x,
+            // Amount -1..1 spans exponents 1/4..4
+            VelocityCurve::Custom => x.powf(4.0_f32.powf(self.amount)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DspEngine {
+    /// Set how a key responds to note velocity
+    ///
+    /// `amount` (-1.0 to 1.0) is only used by the custom curve: negative
+    /// values lift light hits, positive values favour firm ones.
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn set_key_velocity_curve(&mut self, key_code: u8, curve: VelocityCurve, amount: f32) {
+        self.record_change(ConfigChange::KeyMapping(key_code));
+        let amount = if amount.is_finite() { amount.clamp(-1.0, 1.0) } else { 0.0 };
+        self.key_mappings[key_code as usize].velocity = VelocityResponse { curve, amount };
+    }
+
+    /// Velocity curve of a key
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_velocity_curve(&self, key_code: u8) -> VelocityCurve {
+        self.key_mappings[key_code as usize].velocity.curve
+    }
+
+    /// Custom velocity curve amount of a key
+    #[cfg_attr(feature = "wasm", wasm_bindgen)]
+    pub fn get_key_velocity_amount(&self, key_code: u8) -> f32 {
+        self.key_mappings[key_code as usize].velocity.amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_velocity_curves() {
+        let linear = VelocityResponse::LINEAR;
+        let fixed = VelocityResponse { curve: VelocityCurve::Fixed, amount: 0.0 };
+        let soft = VelocityResponse { curve: VelocityCurve::Soft, amount: 0.0 };
+        let custom = VelocityResponse { curve: VelocityCurve::Custom, amount: 0.5 };
+        assert_eq!(fixed.gain(1), 1.0);
+        assert!(soft.gain(32) > linear.gain(32));
+        assert!((custom.gain(64) - linear.gain(64).powi(2)).abs() < 1e-6, "amount 0.5 matches the hard curve");
+        assert_eq!(custom.gain(127), 1.0);
+    }
+}